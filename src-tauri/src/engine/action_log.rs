@@ -0,0 +1,396 @@
+//! Audit log of automated block mutations.
+//!
+//! Every change the engine makes on its own (as opposed to one the user
+//! made directly) is recorded here with enough detail to undo it.
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tracing::info;
+use uuid::Uuid;
+
+use super::block::Block;
+use super::memory::{self, MemoryState};
+use super::types::{CompressionLevel, PinPosition, Zone};
+use crate::events::timeline::now_ms;
+
+/// Why an automated action was taken.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ActionReason {
+    /// The projected prompt exceeded the budget threshold.
+    BudgetPressure,
+    /// A duplicate-content suggestion was applied.
+    Deduplicated,
+    /// A memory lifecycle transition was requested.
+    Lifecycle,
+    /// A cold or archived block was recalled into the context.
+    Recall,
+    /// The model asked for it through the memory tool.
+    ModelCommand,
+    /// A user-defined rule fired.
+    Rule,
+    /// A whole-topic operation was applied.
+    Cluster,
+}
+
+/// What an action changed on its block.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BlockChange {
+    CompressionLevel {
+        from: CompressionLevel,
+        to: CompressionLevel,
+    },
+    /// Live content replaced by a pointer to another block; the stored
+    /// versions are untouched.
+    Collapsed {
+        duplicate_of: String,
+        stub: String,
+    },
+    /// Memory lifecycle transition, with the level change it implied.
+    Memory {
+        from: MemoryState,
+        to: MemoryState,
+        level_from: CompressionLevel,
+        level_to: CompressionLevel,
+    },
+    Zone {
+        from: Zone,
+        to: Zone,
+    },
+    Pin {
+        from: Option<PinPosition>,
+        to: Option<PinPosition>,
+    },
+    /// Block taken out of the context, e.g. into the trash; undo puts this
+    /// copy back.
+    Removed {
+        block: Box<Block>,
+        /// Index it had.
+        index: usize,
+        /// Block that preceded it, to put it back next to the same one.
+        after_block_id: Option<String>,
+    },
+}
+
+/// One recorded action.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LoggedAction {
+    pub id: String,
+    /// Unix epoch milliseconds.
+    pub at_ms: u64,
+    pub block_id: String,
+    pub change: BlockChange,
+    pub reason: ActionReason,
+    pub tokens_before: u32,
+    pub tokens_after: u32,
+    /// Human-readable detail, e.g. the staleness score that picked the block.
+    pub detail: String,
+    pub undone: bool,
+}
+
+/// Errors from undoing a logged action.
+#[derive(Debug, Error)]
+pub enum ActionLogError {
+    #[error("Action not found: {0}")]
+    ActionNotFound(String),
+
+    #[error("Action already undone: {0}")]
+    AlreadyUndone(String),
+
+    #[error("Block not found: {0}")]
+    BlockNotFound(String),
+
+    /// The block was changed again after the action, so undoing it would
+    /// clobber the later change.
+    #[error("Block {0} changed since the action was taken")]
+    Conflict(String),
+}
+
+impl serde::Serialize for ActionLogError {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+/// Append-only list of automated actions, oldest first.
+#[derive(Debug, Clone, Default)]
+pub struct ActionLog {
+    entries: Vec<LoggedAction>,
+}
+
+impl ActionLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A log holding `entries`, e.g. as saved in a checkpoint.
+    pub fn from_entries(entries: Vec<LoggedAction>) -> Self {
+        Self { entries }
+    }
+
+    /// Record a compression level change already applied to `block`.
+    pub fn record_level_change(
+        &mut self,
+        block: &Block,
+        from: CompressionLevel,
+        tokens_before: u32,
+        reason: ActionReason,
+        detail: String,
+    ) -> &LoggedAction {
+        let change = BlockChange::CompressionLevel {
+            from,
+            to: block.compression_level,
+        };
+        self.record(block, change, tokens_before, reason, detail)
+    }
+
+    /// Record `change`, already applied to `block`.
+    pub fn record(
+        &mut self,
+        block: &Block,
+        change: BlockChange,
+        tokens_before: u32,
+        reason: ActionReason,
+        detail: String,
+    ) -> &LoggedAction {
+        info!(
+            "Action {:?} on {}: {:?} ({} -> {} tokens), {}",
+            reason, block.id, change, tokens_before, block.tokens, detail
+        );
+        self.push(
+            &block.id,
+            change,
+            reason,
+            tokens_before,
+            block.tokens,
+            detail,
+        )
+    }
+
+    /// Record that `block` was taken out of the context at `index`, after
+    /// `after_block_id`.
+    pub fn record_removal(
+        &mut self,
+        block: Block,
+        index: usize,
+        after_block_id: Option<String>,
+        reason: ActionReason,
+        detail: String,
+    ) -> &LoggedAction {
+        info!(
+            "Action {:?} on {}: removed ({} tokens), {}",
+            reason, block.id, block.tokens, detail
+        );
+        let (block_id, tokens) = (block.id.clone(), block.tokens);
+        let change = BlockChange::Removed {
+            block: Box::new(block),
+            index,
+            after_block_id,
+        };
+        self.push(&block_id, change, reason, tokens, 0, detail)
+    }
+
+    fn push(
+        &mut self,
+        block_id: &str,
+        change: BlockChange,
+        reason: ActionReason,
+        tokens_before: u32,
+        tokens_after: u32,
+        detail: String,
+    ) -> &LoggedAction {
+        self.entries.push(LoggedAction {
+            id: Uuid::new_v4().to_string(),
+            at_ms: now_ms(),
+            block_id: block_id.to_string(),
+            change,
+            reason,
+            tokens_before,
+            tokens_after,
+            detail,
+            undone: false,
+        });
+        self.entries.last().expect("just pushed")
+    }
+
+    pub fn entries(&self) -> &[LoggedAction] {
+        &self.entries
+    }
+
+    pub fn get(&self, action_id: &str) -> Option<&LoggedAction> {
+        self.entries.iter().find(|a| a.id == action_id)
+    }
+
+    /// Revert `action_id` on the matching block in `blocks`, or put a
+    /// removed block back.
+    ///
+    /// Fails with [`ActionLogError::Conflict`] if the block no longer has
+    /// the state the action left it in, or a removed block is back already.
+    pub fn undo(&mut self, action_id: &str, blocks: &mut Vec<Block>) -> Result<(), ActionLogError> {
+        let action = self
+            .entries
+            .iter_mut()
+            .find(|a| a.id == action_id)
+            .ok_or_else(|| ActionLogError::ActionNotFound(action_id.to_string()))?;
+        if action.undone {
+            return Err(ActionLogError::AlreadyUndone(action_id.to_string()));
+        }
+        let reverted = match &action.change {
+            BlockChange::Removed {
+                block,
+                index,
+                after_block_id,
+            } => reinsert(blocks, block, *index, after_block_id.as_deref()),
+            change => {
+                let block = blocks
+                    .iter_mut()
+                    .find(|b| b.id == action.block_id)
+                    .ok_or_else(|| ActionLogError::BlockNotFound(action.block_id.clone()))?;
+                revert(change, block)
+            }
+        };
+        if !reverted {
+            return Err(ActionLogError::Conflict(action.block_id.clone()));
+        }
+        action.undone = true;
+        info!("Undid action {} on {}", action.id, action.block_id);
+        Ok(())
+    }
+
+    /// Undo every action still in effect, newest first. Returns how many
+    /// were reverted; conflicting ones are skipped.
+    pub fn undo_all(&mut self, blocks: &mut Vec<Block>) -> usize {
+        let ids: Vec<String> = self
+            .entries
+            .iter()
+            .rev()
+            .filter(|a| !a.undone)
+            .map(|a| a.id.clone())
+            .collect();
+        ids.iter()
+            .filter(|id| self.undo(id, blocks).is_ok())
+            .count()
+    }
+}
+
+/// Undo `change` on `block`; `false` if the block has moved on since.
+fn revert(change: &BlockChange, block: &mut Block) -> bool {
+    match change {
+        BlockChange::CompressionLevel { from, to } => {
+            block.compression_level == *to && block.set_compression_level(*from)
+        }
+        BlockChange::Collapsed { stub, .. } => {
+            block.content == *stub && block.set_compression_level(block.compression_level)
+        }
+        BlockChange::Memory {
+            from,
+            to,
+            level_from,
+            level_to,
+        } => {
+            let current = block.memory_state == *to
+                && block.compression_level == *level_to
+                && !block.is_rewritten();
+            let reverted = current && block.set_compression_level(*level_from);
+            if reverted {
+                memory::set_state(block, *from);
+            }
+            reverted
+        }
+        BlockChange::Zone { from, to } => {
+            let current = block.zone == *to;
+            if current {
+                block.zone = from.clone();
+            }
+            current
+        }
+        BlockChange::Pin { from, to } => {
+            let current = block.pinned == *to;
+            if current {
+                block.pinned = *from;
+            }
+            current
+        }
+        // The block is gone; see `reinsert`.
+        BlockChange::Removed { .. } => false,
+    }
+}
+
+/// Put a removed `block` back after `after_block_id`, else at `index`;
+/// `false` if a block with its id is already there.
+fn reinsert(blocks: &mut Vec<Block>, block: &Block, index: usize, after: Option<&str>) -> bool {
+    if blocks.iter().any(|b| b.id == block.id) {
+        return false;
+    }
+    let at = after
+        .and_then(|prev| blocks.iter().position(|b| b.id == prev))
+        .map_or(index.min(blocks.len()), |i| i + 1);
+    blocks.insert(at, block.clone());
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::block::{test_block, CompressionVersion};
+    use crate::engine::types::Role;
+
+    fn trimmed_block(id: &str) -> Block {
+        let mut block = test_block(id, Role::ToolResult, "line one\n\n\n\nline two");
+        block.compressed_versions.trimmed =
+            Some(CompressionVersion::new("line one\n\nline two".to_string()));
+        block
+    }
+
+    fn compress(log: &mut ActionLog, block: &mut Block) -> String {
+        let before = block.tokens;
+        assert!(block.set_compression_level(CompressionLevel::Trimmed));
+        log.record_level_change(
+            block,
+            CompressionLevel::Original,
+            before,
+            ActionReason::BudgetPressure,
+            "test".to_string(),
+        )
+        .id
+        .clone()
+    }
+
+    #[test]
+    fn test_undo_restores_previous_level() {
+        let mut log = ActionLog::new();
+        let mut blocks = vec![trimmed_block("a")];
+        let id = compress(&mut log, &mut blocks[0]);
+        assert_eq!(blocks[0].content, "line one\n\nline two");
+
+        log.undo(&id, &mut blocks).expect("undo");
+        assert_eq!(blocks[0].compression_level, CompressionLevel::Original);
+        assert_eq!(blocks[0].content, "line one\n\n\n\nline two");
+        assert!(log.get(&id).expect("entry").undone);
+        assert!(matches!(
+            log.undo(&id, &mut blocks),
+            Err(ActionLogError::AlreadyUndone(_))
+        ));
+    }
+
+    #[test]
+    fn test_undo_refuses_when_block_changed_since() {
+        let mut log = ActionLog::new();
+        let mut blocks = vec![trimmed_block("a"), trimmed_block("b")];
+        let stale = compress(&mut log, &mut blocks[0]);
+        compress(&mut log, &mut blocks[1]);
+        blocks[0].set_compression_level(CompressionLevel::Original);
+
+        assert!(matches!(
+            log.undo(&stale, &mut blocks),
+            Err(ActionLogError::Conflict(_))
+        ));
+        assert_eq!(log.undo_all(&mut blocks), 1);
+        assert_eq!(blocks[1].compression_level, CompressionLevel::Original);
+    }
+}
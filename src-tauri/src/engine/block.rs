@@ -6,13 +6,34 @@
 
 use serde::{Deserialize, Serialize};
 
-use super::types::{CompressionLevel, PinPosition, Role, Zone};
+use super::compression::quality::{QualityConfig, QualityReport};
+use super::history::BlockEdit;
+use super::memory::{MemoryState, RecallMetadata};
+use super::tokens::count_tokens;
+use super::types::{BuiltInZone, CompressionLevel, PinPosition, Role, Zone};
+
+/// `metadata.provider` of blocks the engine generated.
+pub const GENERATED_PROVIDER: &str = "aperture";
 
 /// A single compressed version of block content.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CompressionVersion {
     pub content: String,
     pub tokens: u32,
+    /// Verifier score; `None` for the original and unverified versions.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quality: Option<QualityReport>,
+}
+
+impl CompressionVersion {
+    /// An unverified version with its token count filled in.
+    pub fn new(content: String) -> Self {
+        Self {
+            tokens: count_tokens(&content),
+            content,
+            quality: None,
+        }
+    }
 }
 
 /// All compression versions of a block.
@@ -27,6 +48,34 @@ pub struct CompressionVersions {
     pub minimal: Option<CompressionVersion>,
 }
 
+impl CompressionVersions {
+    pub fn get(&self, level: CompressionLevel) -> Option<&CompressionVersion> {
+        match level {
+            CompressionLevel::Original => Some(&self.original),
+            CompressionLevel::Trimmed => self.trimmed.as_ref(),
+            CompressionLevel::Summarized => self.summarized.as_ref(),
+            CompressionLevel::Minimal => self.minimal.as_ref(),
+        }
+    }
+
+    /// The version at `level` if it may be applied without user review.
+    ///
+    /// Versions the verifier scored below `min_confidence` are withheld;
+    /// unverified versions (e.g. user edits) are allowed.
+    pub fn auto_usable(
+        &self,
+        level: CompressionLevel,
+        config: &QualityConfig,
+    ) -> Option<&CompressionVersion> {
+        self.get(level).filter(|version| {
+            version
+                .quality
+                .as_ref()
+                .is_none_or(|quality| quality.is_auto_usable(config))
+        })
+    }
+}
+
 /// Provider-specific metadata for a block.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BlockMetadata {
@@ -36,6 +85,9 @@ pub struct BlockMetadata {
     pub tool_name: Option<String>,
     #[serde(default)]
     pub file_paths: Vec<String>,
+    /// Provider id linking a `tool_use` block to its `tool_result`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_use_id: Option<String>,
 }
 
 /// A universal context block.
@@ -69,6 +121,162 @@ pub struct Block {
     #[serde(default)]
     pub topic_keywords: Vec<String>,
 
+    // Memory lifecycle
+    #[serde(default)]
+    pub memory_state: MemoryState,
+    /// Set while the block is cold or archived.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recall: Option<RecallMetadata>,
+
+    // Edit history
+    /// User and automated hot-patches of the original content, oldest first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub edits: Vec<BlockEdit>,
+
     // Metadata
     pub metadata: BlockMetadata,
 }
+
+impl Block {
+    /// A block the engine generated rather than the provider sent: a
+    /// system block at its original level, with no heat or topic.
+    pub(crate) fn generated(
+        id: String,
+        block_type: &str,
+        content: String,
+        zone: Zone,
+        turn: u32,
+    ) -> Self {
+        let mut block = Self::from_request(
+            id,
+            Role::System,
+            block_type,
+            content,
+            GENERATED_PROVIDER,
+            turn,
+        );
+        block.zone = zone;
+        block
+    }
+
+    /// A block for one part of a request message from `provider`: Middle
+    /// zone, original level, no heat or topic.
+    pub(crate) fn from_request(
+        id: String,
+        role: Role,
+        block_type: &str,
+        content: String,
+        provider: &str,
+        turn: u32,
+    ) -> Self {
+        let original = CompressionVersion::new(content);
+        Self {
+            id,
+            role,
+            block_type: Some(block_type.to_string()),
+            content: original.content.clone(),
+            tokens: original.tokens,
+            timestamp: String::new(),
+            zone: Zone::BuiltIn(BuiltInZone::Middle),
+            pinned: None,
+            compression_level: CompressionLevel::Original,
+            compressed_versions: CompressionVersions {
+                original,
+                trimmed: None,
+                summarized: None,
+                minimal: None,
+            },
+            usage_heat: 0.0,
+            position_relevance: 0.0,
+            last_referenced_turn: turn,
+            reference_count: 0,
+            topic_cluster: None,
+            topic_keywords: Vec::new(),
+            memory_state: MemoryState::Hot,
+            recall: None,
+            edits: Vec::new(),
+            metadata: BlockMetadata {
+                provider: provider.to_string(),
+                turn_index: turn,
+                tool_name: None,
+                file_paths: Vec::new(),
+                tool_use_id: None,
+            },
+        }
+    }
+
+    /// Switch the block's live content to the stored version at `level`.
+    ///
+    /// Returns `false`, leaving the block unchanged, if that version has
+    /// not been generated.
+    pub fn set_compression_level(&mut self, level: CompressionLevel) -> bool {
+        let Some(version) = self.compressed_versions.get(level) else {
+            return false;
+        };
+        self.content = version.content.clone();
+        self.tokens = version.tokens;
+        self.compression_level = level;
+        true
+    }
+
+    /// Whether the live content was replaced with something other than the
+    /// version at the block's level, e.g. a duplicate stub.
+    pub fn is_rewritten(&self) -> bool {
+        self.compressed_versions
+            .get(self.compression_level)
+            .is_none_or(|version| version.content != self.content)
+    }
+
+    pub fn is_in_zone(&self, zone: BuiltInZone) -> bool {
+        self.zone == Zone::BuiltIn(zone)
+    }
+
+    /// Whether the engine generated the block rather than the provider
+    /// sending it.
+    pub fn is_generated(&self) -> bool {
+        self.metadata.provider == GENERATED_PROVIDER
+    }
+}
+
+/// Minimal block for unit tests across the engine.
+#[cfg(test)]
+pub(crate) fn test_block(id: &str, role: Role, content: &str) -> Block {
+    let tokens = count_tokens(content);
+    Block {
+        id: id.to_string(),
+        role,
+        block_type: None,
+        content: content.to_string(),
+        tokens,
+        timestamp: "2026-01-01T00:00:00Z".to_string(),
+        zone: Zone::BuiltIn(BuiltInZone::Middle),
+        pinned: None,
+        compression_level: CompressionLevel::Original,
+        compressed_versions: CompressionVersions {
+            original: CompressionVersion {
+                content: content.to_string(),
+                tokens,
+                quality: None,
+            },
+            trimmed: None,
+            summarized: None,
+            minimal: None,
+        },
+        usage_heat: 0.0,
+        position_relevance: 0.0,
+        last_referenced_turn: 0,
+        reference_count: 0,
+        topic_cluster: None,
+        topic_keywords: Vec::new(),
+        memory_state: MemoryState::Hot,
+        recall: None,
+        edits: Vec::new(),
+        metadata: BlockMetadata {
+            provider: "anthropic".to_string(),
+            turn_index: 0,
+            tool_name: None,
+            file_paths: Vec::new(),
+            tool_use_id: None,
+        },
+    }
+}
@@ -0,0 +1,428 @@
+//! Budget-pressure auto-compression policy.
+//!
+//! When a session's projected prompt would fill more than
+//! [`BudgetConfig::pressure_threshold`] of the model's context window, the
+//! stalest Middle-zone blocks are stepped down one compression level at a
+//! time until the prompt fits. Each step goes through the [`ActionLog`] so it
+//! can be undone, and only versions that pass the quality verifier are
+//! applied automatically.
+
+use std::collections::HashSet;
+
+use serde::Serialize;
+use tracing::{debug, warn};
+
+use super::action_log::{ActionLog, ActionReason};
+use super::block::Block;
+use super::compression::preserve::PreserveKeys;
+use super::compression::quality::QualityConfig;
+use super::compression::{compress_rule_based, CompressionPriority, CompressionQueue};
+use super::outbound::sent_mask;
+use super::staleness::calculate_staleness;
+use super::types::{BuiltInZone, CompressionLevel};
+
+/// Budget policy settings for one session.
+#[derive(Debug, Clone)]
+pub struct BudgetConfig {
+    /// The model's context window, in tokens.
+    pub context_window: u32,
+    /// Fraction of the window the projected prompt may fill before the
+    /// policy starts compressing.
+    pub pressure_threshold: f32,
+    /// Which generated versions may be applied without review.
+    pub quality: QualityConfig,
+    /// Blocks from this many latest turns are never compressed.
+    pub recency_turns: u32,
+}
+
+impl BudgetConfig {
+    pub fn new(context_window: u32) -> Self {
+        Self {
+            context_window,
+            pressure_threshold: 0.9,
+            quality: QualityConfig::default(),
+            recency_turns: 0,
+        }
+    }
+
+    /// Largest projected prompt, in tokens, that needs no action.
+    pub fn limit(&self) -> u32 {
+        (self.context_window as f64 * self.pressure_threshold.clamp(0.0, 1.0) as f64) as u32
+    }
+}
+
+/// A compression level the policy wanted but that has not been generated.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestedCompression {
+    pub block_id: String,
+    pub level: CompressionLevel,
+}
+
+/// What one policy pass did.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PressureReport {
+    pub limit: u32,
+    /// Projected prompt tokens before and after the pass.
+    pub projected_before: u32,
+    pub projected_after: u32,
+    /// Ids of the [`ActionLog`] entries recorded by this pass.
+    pub action_ids: Vec<String>,
+    /// Model-generated levels queued for blocks that ran out of steps.
+    pub requested: Vec<RequestedCompression>,
+}
+
+impl PressureReport {
+    pub fn fits(&self) -> bool {
+        self.projected_after <= self.limit
+    }
+}
+
+/// Tokens the next request will send: the blocks the outbound rewrite
+/// carries, generated ones included, plus the new input.
+pub fn projected_tokens(blocks: &[Block], incoming_tokens: u32) -> u32 {
+    blocks
+        .iter()
+        .zip(sent_mask(blocks))
+        .filter(|(block, sent)| *sent || (block.is_generated() && block.memory_state.is_active()))
+        .fold(incoming_tokens, |total, (block, _)| {
+            total.saturating_add(block.tokens)
+        })
+}
+
+/// Step the stalest Middle-zone blocks down compression levels until the
+/// projected prompt fits under the configured limit. Only blocks the
+/// outbound rewrite sends are counted and compressed.
+///
+/// Missing `trimmed` versions are generated on the spot. A block with no
+/// usable deeper version is skipped, and its next model-generated level is
+/// queued at [`CompressionPriority::Pressure`] when a `queue` is given, so
+/// a later pass can use it.
+pub fn relieve_pressure(
+    blocks: &mut [Block],
+    incoming_tokens: u32,
+    current_turn: u32,
+    config: &BudgetConfig,
+    keys: &PreserveKeys,
+    log: &mut ActionLog,
+    queue: Option<&CompressionQueue>,
+) -> PressureReport {
+    let limit = config.limit();
+    let projected_before = projected_tokens(blocks, incoming_tokens);
+    let mut report = PressureReport {
+        limit,
+        projected_before,
+        projected_after: projected_before,
+        action_ids: Vec::new(),
+        requested: Vec::new(),
+    };
+
+    let sent = sent_mask(blocks);
+    let mut exhausted = HashSet::new();
+    while report.projected_after > limit {
+        let Some((index, staleness)) = blocks
+            .iter()
+            .enumerate()
+            .filter(|(i, block)| {
+                sent[*i] && !exhausted.contains(i) && is_candidate(block, current_turn, config)
+            })
+            .map(|(i, block)| (i, calculate_staleness(block, current_turn)))
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+        else {
+            break;
+        };
+
+        let block = &mut blocks[index];
+        match next_step(block, keys, &config.quality) {
+            Step::Apply(level) => {
+                let from = block.compression_level;
+                let before = block.tokens;
+                let projected = report.projected_after;
+                block.set_compression_level(level);
+                report.projected_after -= before - block.tokens;
+                let action = log.record_level_change(
+                    block,
+                    from,
+                    before,
+                    ActionReason::BudgetPressure,
+                    format!("staleness {staleness:.0}, projected {projected} of {limit} tokens"),
+                );
+                report.action_ids.push(action.id.clone());
+            }
+            Step::Missing(level) => {
+                exhausted.insert(index);
+                if let Some(queue) = queue {
+                    queue.enqueue(block.clone(), level, CompressionPriority::Pressure);
+                }
+                report.requested.push(RequestedCompression {
+                    block_id: block.id.clone(),
+                    level,
+                });
+            }
+            Step::None => {
+                exhausted.insert(index);
+            }
+        }
+    }
+
+    if report.fits() {
+        debug!(
+            "Budget pass: {} -> {} of {} tokens, {} actions",
+            projected_before,
+            report.projected_after,
+            limit,
+            report.action_ids.len()
+        );
+    } else {
+        warn!(
+            "Budget pass could not fit prompt: {} of {} tokens after {} actions, {} compressions requested",
+            report.projected_after,
+            limit,
+            report.action_ids.len(),
+            report.requested.len()
+        );
+    }
+    report
+}
+
+/// Unpinned Middle-zone blocks that can still be compressed further.
+///
+/// Rewritten blocks (e.g. collapsed duplicates) are already as small as
+/// they will get, and switching their level would undo the rewrite.
+fn is_candidate(block: &Block, current_turn: u32, config: &BudgetConfig) -> bool {
+    block.is_in_zone(BuiltInZone::Middle)
+        && current_turn.saturating_sub(block.metadata.turn_index) >= config.recency_turns
+        && block.memory_state.is_active()
+        && block.pinned.is_none()
+        && !block.is_rewritten()
+        && block.compression_level.deeper().is_some()
+}
+
+enum Step {
+    /// Switch to this level.
+    Apply(CompressionLevel),
+    /// Nothing usable yet; this model-generated level would help.
+    Missing(CompressionLevel),
+    /// No deeper level can help.
+    None,
+}
+
+/// The shallowest deeper level that is auto-usable and actually smaller.
+fn next_step(block: &mut Block, keys: &PreserveKeys, quality: &QualityConfig) -> Step {
+    let mut missing = None;
+    let mut level = block.compression_level;
+    while let Some(deeper) = level.deeper() {
+        level = deeper;
+        if level == CompressionLevel::Trimmed && block.compressed_versions.trimmed.is_none() {
+            compress_rule_based(block, keys);
+        }
+        match block.compressed_versions.auto_usable(level, quality) {
+            Some(version) if version.tokens < block.tokens => return Step::Apply(level),
+            Some(_) => {}
+            None if block.compressed_versions.get(level).is_none() => {
+                missing.get_or_insert(level);
+            }
+            None => {}
+        }
+    }
+    missing.map_or(Step::None, Step::Missing)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::engine::block::{test_block, CompressionVersion};
+    use crate::engine::compression::queue::QueueConfig;
+    use crate::engine::types::{PinPosition, Role, Zone};
+
+    /// A noisy log block from `turn` whose trimmed version is much smaller.
+    fn log_block(id: &str, turn: u32) -> Block {
+        let content = format!("{}Build finished.", "INFO compiling module\n".repeat(80));
+        let mut block = test_block(id, Role::ToolResult, &content);
+        block.metadata.tool_name = Some("Bash".to_string());
+        block.metadata.turn_index = turn;
+        block
+    }
+
+    fn config_for(blocks: &[Block], headroom: u32) -> BudgetConfig {
+        let mut config = BudgetConfig::new(projected_tokens(blocks, 0) - headroom);
+        config.pressure_threshold = 1.0;
+        config
+    }
+
+    #[test]
+    fn test_relieve_pressure_noop_under_limit() {
+        let mut blocks = vec![log_block("a", 1)];
+        let config = BudgetConfig::new(200_000);
+        let mut log = ActionLog::new();
+
+        let report = relieve_pressure(
+            &mut blocks,
+            100,
+            5,
+            &config,
+            &PreserveKeys::default(),
+            &mut log,
+            None,
+        );
+        assert!(report.fits());
+        assert!(report.action_ids.is_empty());
+        assert_eq!(blocks[0].compression_level, CompressionLevel::Original);
+    }
+
+    #[test]
+    fn test_relieve_pressure_compresses_stalest_middle_block_first() {
+        let mut primacy = log_block("system", 0);
+        primacy.zone = Zone::BuiltIn(BuiltInZone::Primacy);
+        let mut pinned = log_block("pinned", 0);
+        pinned.pinned = Some(PinPosition::Top);
+        let mut blocks = vec![primacy, pinned, log_block("old", 1), log_block("new", 8)];
+        let config = config_for(&blocks, 10);
+        let mut log = ActionLog::new();
+
+        let report = relieve_pressure(
+            &mut blocks,
+            0,
+            10,
+            &config,
+            &PreserveKeys::default(),
+            &mut log,
+            None,
+        );
+
+        assert!(report.fits());
+        assert_eq!(report.action_ids.len(), 1);
+        let levels: Vec<_> = blocks.iter().map(|b| b.compression_level).collect();
+        assert_eq!(
+            levels,
+            vec![
+                CompressionLevel::Original,
+                CompressionLevel::Original,
+                CompressionLevel::Trimmed,
+                CompressionLevel::Original,
+            ]
+        );
+        assert_eq!(log.entries()[0].block_id, "old");
+        assert_eq!(report.projected_after, projected_tokens(&blocks, 0));
+    }
+
+    #[test]
+    fn test_relieve_pressure_ignores_blocks_not_sent() {
+        // A tool result whose call is gone is left out of the request.
+        let mut orphan = log_block("orphan", 0);
+        orphan.metadata.tool_use_id = Some("toolu_gone".to_string());
+        let mut blocks = vec![orphan, log_block("sent", 1)];
+        assert_eq!(projected_tokens(&blocks, 0), blocks[1].tokens);
+        let config = config_for(&blocks, 10);
+        let mut log = ActionLog::new();
+
+        let report = relieve_pressure(
+            &mut blocks,
+            0,
+            10,
+            &config,
+            &PreserveKeys::default(),
+            &mut log,
+            None,
+        );
+        assert!(report.fits());
+        assert_eq!(blocks[0].compression_level, CompressionLevel::Original);
+        assert_eq!(blocks[1].compression_level, CompressionLevel::Trimmed);
+    }
+
+    #[test]
+    fn test_relieve_pressure_spares_recent_turns() {
+        let mut blocks = vec![log_block("old", 1), log_block("new", 8)];
+        let mut config = BudgetConfig::new(1);
+        config.recency_turns = 5;
+        let mut log = ActionLog::new();
+
+        let report = relieve_pressure(
+            &mut blocks,
+            0,
+            10,
+            &config,
+            &PreserveKeys::default(),
+            &mut log,
+            None,
+        );
+        assert!(!report.fits());
+        assert_eq!(blocks[0].compression_level, CompressionLevel::Trimmed);
+        assert_eq!(blocks[1].compression_level, CompressionLevel::Original);
+    }
+
+    #[test]
+    fn test_relieve_pressure_steps_down_levels_and_is_reversible() {
+        let mut block = log_block("a", 1);
+        block.compressed_versions.summarized = Some(CompressionVersion::new(
+            "Build of 80 modules finished.".to_string(),
+        ));
+        let original = block.content.clone();
+        let mut blocks = vec![block];
+        let config = BudgetConfig {
+            context_window: 10,
+            pressure_threshold: 1.0,
+            quality: QualityConfig::default(),
+            recency_turns: 0,
+        };
+        let mut log = ActionLog::new();
+
+        let report = relieve_pressure(
+            &mut blocks,
+            0,
+            10,
+            &config,
+            &PreserveKeys::default(),
+            &mut log,
+            None,
+        );
+        assert!(report.fits());
+        assert_eq!(report.action_ids.len(), 2);
+        assert_eq!(blocks[0].compression_level, CompressionLevel::Summarized);
+
+        assert_eq!(log.undo_all(&mut blocks), 2);
+        assert_eq!(blocks[0].compression_level, CompressionLevel::Original);
+        assert_eq!(blocks[0].content, original);
+    }
+
+    #[tokio::test]
+    async fn test_relieve_pressure_skips_low_confidence_and_queues_missing_level() {
+        let mut block = log_block("a", 1);
+        block.compressed_versions.trimmed = Some(CompressionVersion {
+            quality: Some(crate::engine::compression::quality::QualityReport {
+                confidence: 0.2,
+                ratio: 40.0,
+                issues: Vec::new(),
+            }),
+            ..CompressionVersion::new("Done.".to_string())
+        });
+        let mut blocks = vec![block];
+        let config = config_for(&blocks, 10);
+        let queue = CompressionQueue::new(QueueConfig::default(), Arc::new(|_| {}));
+        let mut log = ActionLog::new();
+
+        let report = relieve_pressure(
+            &mut blocks,
+            0,
+            10,
+            &config,
+            &PreserveKeys::default(),
+            &mut log,
+            Some(&queue),
+        );
+
+        assert!(!report.fits());
+        assert!(log.entries().is_empty());
+        assert_eq!(
+            report.requested,
+            vec![RequestedCompression {
+                block_id: "a".to_string(),
+                level: CompressionLevel::Summarized,
+            }]
+        );
+        assert_eq!(queue.depth(), (1, 0));
+    }
+}
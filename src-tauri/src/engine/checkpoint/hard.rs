@@ -0,0 +1,460 @@
+//! Hard checkpoints: exact snapshots of a session's engine state.
+//!
+//! Every block is stored as its own JSON object named by the SHA-256 of its
+//! bytes, under `objects/`; a checkpoint record under `hard/` lists the
+//! object hashes in order, with the session's rules, action log and rule
+//! audit. A block unchanged between checkpoints is written once.
+//!
+//! A checkpoint holds the active branch's state only. Other fork branches,
+//! staged items, the loaded profile and active preset, and the manifest
+//! and memory tool settings are session configuration: they are not saved,
+//! and a restore leaves them as they are.
+//!
+//! ```text
+//! checkpoints/
+//! ├── objects/ab/cdef…json   # one serialized Block
+//! └── hard/<id>.json         # HardCheckpoint record
+//! ```
+
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::info;
+use uuid::Uuid;
+
+use super::CheckpointError;
+use crate::engine::action_log::{ActionLog, LoggedAction};
+use crate::engine::block::Block;
+use crate::engine::rules::{Rule, RuleFiring};
+use crate::engine::session::EngineSession;
+use crate::events::timeline::now_ms;
+use crate::paths;
+
+/// Stored checkpoint record.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HardCheckpoint {
+    pub id: String,
+    pub name: String,
+    /// Unix epoch milliseconds.
+    pub created_at_ms: u64,
+    /// Object hashes of the session's blocks, in order.
+    pub block_hashes: Vec<String>,
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+    /// Action log entries, so actions taken before the checkpoint can still
+    /// be undone after a restore.
+    #[serde(default)]
+    pub actions: Vec<LoggedAction>,
+    #[serde(default)]
+    pub rule_audit: Vec<RuleFiring>,
+    pub total_tokens: u32,
+    /// Serialized size of every block in the checkpoint.
+    pub size_bytes: u64,
+    /// Bytes this checkpoint added to the object store.
+    pub stored_bytes: u64,
+}
+
+/// A checkpoint as listed to the user.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CheckpointSummary {
+    pub id: String,
+    pub name: String,
+    pub created_at_ms: u64,
+    pub block_count: usize,
+    pub total_tokens: u32,
+    pub size_bytes: u64,
+    pub stored_bytes: u64,
+}
+
+impl From<&HardCheckpoint> for CheckpointSummary {
+    fn from(checkpoint: &HardCheckpoint) -> Self {
+        Self {
+            id: checkpoint.id.clone(),
+            name: checkpoint.name.clone(),
+            created_at_ms: checkpoint.created_at_ms,
+            block_count: checkpoint.block_hashes.len(),
+            total_tokens: checkpoint.total_tokens,
+            size_bytes: checkpoint.size_bytes,
+            stored_bytes: checkpoint.stored_bytes,
+        }
+    }
+}
+
+pub(super) fn storage_error(e: impl std::fmt::Display) -> CheckpointError {
+    CheckpointError::StorageFailed(e.to_string())
+}
+
+fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// Write `bytes` to `path` via a temporary file, so readers never see a
+/// partial file.
+pub(super) fn write_atomic(path: &Path, bytes: &[u8]) -> Result<(), CheckpointError> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(storage_error)?;
+    }
+    let tmp = path.with_extension(format!("tmp-{}", Uuid::new_v4()));
+    fs::write(&tmp, bytes).map_err(storage_error)?;
+    fs::rename(&tmp, path).map_err(storage_error)
+}
+
+/// On-disk checkpoint store.
+#[derive(Debug, Clone)]
+pub struct CheckpointStore {
+    pub(super) root: PathBuf,
+}
+
+impl Default for CheckpointStore {
+    fn default() -> Self {
+        Self::new(paths::checkpoints_dir())
+    }
+}
+
+impl CheckpointStore {
+    pub fn new(root: PathBuf) -> Self {
+        Self { root }
+    }
+
+    fn object_path(&self, hash: &str) -> PathBuf {
+        let (fan, rest) = hash.split_at(2.min(hash.len()));
+        self.root
+            .join("objects")
+            .join(fan)
+            .join(format!("{rest}.json"))
+    }
+
+    fn record_path(&self, id: &str) -> PathBuf {
+        self.root.join("hard").join(format!("{id}.json"))
+    }
+
+    /// Store `bytes`, returning the hash and whether it was new.
+    fn put_object(&self, bytes: &[u8]) -> Result<(String, bool), CheckpointError> {
+        let hash = sha256_hex(bytes);
+        let path = self.object_path(&hash);
+        if path.exists() {
+            return Ok((hash, false));
+        }
+        write_atomic(&path, bytes)?;
+        Ok((hash, true))
+    }
+
+    fn get_object(&self, hash: &str) -> Result<Vec<u8>, CheckpointError> {
+        let bytes = fs::read(self.object_path(hash))
+            .map_err(|e| CheckpointError::Corrupt(format!("object {hash}: {e}")))?;
+        if sha256_hex(&bytes) != hash {
+            return Err(CheckpointError::Corrupt(format!(
+                "object {hash} does not match its hash"
+            )));
+        }
+        Ok(bytes)
+    }
+
+    /// Save the blocks, rules, action log and rule audit of `session` under
+    /// `name`.
+    pub fn create(
+        &self,
+        name: &str,
+        session: &EngineSession,
+    ) -> Result<CheckpointSummary, CheckpointError> {
+        let mut block_hashes = Vec::with_capacity(session.blocks.len());
+        let mut size_bytes = 0;
+        let mut stored_bytes = 0;
+        for block in &session.blocks {
+            let bytes = serde_json::to_vec(block).map_err(storage_error)?;
+            let (hash, new) = self.put_object(&bytes)?;
+            size_bytes += bytes.len() as u64;
+            if new {
+                stored_bytes += bytes.len() as u64;
+            }
+            block_hashes.push(hash);
+        }
+
+        let checkpoint = HardCheckpoint {
+            id: Uuid::new_v4().to_string(),
+            name: name.to_string(),
+            created_at_ms: now_ms(),
+            block_hashes,
+            rules: session.rules.clone(),
+            actions: session.log.entries().to_vec(),
+            rule_audit: session.rule_audit.entries(),
+            total_tokens: session
+                .blocks
+                .iter()
+                .fold(0, |total, b| total.saturating_add(b.tokens)),
+            size_bytes,
+            stored_bytes,
+        };
+        let record = serde_json::to_vec_pretty(&checkpoint).map_err(storage_error)?;
+        write_atomic(&self.record_path(&checkpoint.id), &record)?;
+        info!(
+            "Created checkpoint {} ({} blocks, {} of {} bytes new)",
+            checkpoint.id,
+            checkpoint.block_hashes.len(),
+            stored_bytes,
+            size_bytes
+        );
+        Ok(CheckpointSummary::from(&checkpoint))
+    }
+
+    fn record(&self, id: &str) -> Result<HardCheckpoint, CheckpointError> {
+        // Ids are generated UUIDs; anything else could escape the store.
+        if Uuid::parse_str(id).is_err() {
+            return Err(CheckpointError::NotFound(id.to_string()));
+        }
+        let bytes = match fs::read(self.record_path(id)) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                return Err(CheckpointError::NotFound(id.to_string()))
+            }
+            Err(e) => return Err(storage_error(e)),
+        };
+        serde_json::from_slice(&bytes)
+            .map_err(|e| CheckpointError::Corrupt(format!("checkpoint {id}: {e}")))
+    }
+
+    /// The checkpoint record and its blocks, verified against their hashes.
+    pub fn load(&self, id: &str) -> Result<(HardCheckpoint, Vec<Block>), CheckpointError> {
+        let checkpoint = self.record(id)?;
+        let blocks = checkpoint
+            .block_hashes
+            .iter()
+            .map(|hash| {
+                let bytes = self.get_object(hash)?;
+                serde_json::from_slice(&bytes)
+                    .map_err(|e| CheckpointError::Corrupt(format!("object {hash}: {e}")))
+            })
+            .collect::<Result<Vec<Block>, _>>()?;
+        Ok((checkpoint, blocks))
+    }
+
+    /// Replace the blocks, rules, action log and rule audit of `session`'s
+    /// active branch with the checkpoint's.
+    ///
+    /// Nothing changes unless every block loads. Blocks keep the topic
+    /// assignments they were saved with, and the topics are rebuilt from
+    /// them. Messages the client sent since the checkpoint stay ingested,
+    /// so later requests carry the restored blocks plus only the messages
+    /// that are new to the session.
+    pub fn restore(
+        &self,
+        id: &str,
+        session: &mut EngineSession,
+    ) -> Result<CheckpointSummary, CheckpointError> {
+        let (checkpoint, blocks) = self.load(id)?;
+        session.blocks = blocks;
+        session.rules = checkpoint.rules.clone();
+        session.log = ActionLog::from_entries(checkpoint.actions.clone());
+        session.rule_audit.replace(checkpoint.rule_audit.clone());
+        session.clusters.rebuild(&session.blocks);
+        info!(
+            "Restored checkpoint {} ({})",
+            checkpoint.id, checkpoint.name
+        );
+        Ok(CheckpointSummary::from(&checkpoint))
+    }
+
+    /// Every readable checkpoint, newest first.
+    pub fn list(&self) -> Result<Vec<CheckpointSummary>, CheckpointError> {
+        let dir = self.root.join("hard");
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(storage_error(e)),
+        };
+        let mut summaries: Vec<CheckpointSummary> = entries
+            .filter_map(Result::ok)
+            .filter_map(|entry| {
+                let name = entry.file_name();
+                let id = name.to_str()?.strip_suffix(".json")?;
+                self.record(id).ok().map(|c| CheckpointSummary::from(&c))
+            })
+            .collect();
+        summaries.sort_by_key(|c| std::cmp::Reverse(c.created_at_ms));
+        Ok(summaries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::action_log::ActionReason;
+    use crate::engine::block::{test_block, CompressionVersion};
+    use crate::engine::memory::MemoryState;
+    use crate::engine::types::{BuiltInZone, CompressionLevel, PinPosition, Role, Zone};
+    use crate::paths::TestDir;
+    use serde_json::{json, Value};
+
+    fn store(name: &str) -> (TestDir, CheckpointStore) {
+        let dir = TestDir::new(&format!("checkpoint-{name}"));
+        let store = CheckpointStore::new(dir.path().to_path_buf());
+        (dir, store)
+    }
+
+    fn session() -> EngineSession {
+        let mut blocks = vec![
+            test_block("a", Role::System, "You are helpful."),
+            test_block("b", Role::ToolResult, "line one\n\n\n\nline two"),
+        ];
+        blocks[0].zone = Zone::BuiltIn(BuiltInZone::Primacy);
+        blocks[0].pinned = Some(PinPosition::Top);
+        blocks[1].compressed_versions.trimmed =
+            Some(CompressionVersion::new("line one\n\nline two".to_string()));
+        blocks[1].set_compression_level(CompressionLevel::Trimmed);
+        blocks[1].memory_state = MemoryState::Warm;
+        EngineSession {
+            blocks,
+            rules: vec![serde_json::from_value(json!({
+                "id": "r1",
+                "name": "pin errors",
+                "trigger": { "field": "content", "operator": "contains", "value": "error" },
+                "action": "pin",
+            }))
+            .expect("rule")],
+            ..EngineSession::default()
+        }
+    }
+
+    fn snapshot(session: &EngineSession) -> Value {
+        json!({
+            "blocks": session.blocks,
+            "rules": session.rules,
+            "log": session.log.entries(),
+            "audit": session.rule_audit.entries(),
+            "topics": session.clusters.clusters(),
+        })
+    }
+
+    #[test]
+    fn test_restore_returns_exact_state() {
+        let (_dir, store) = store("restore");
+        let mut session = session();
+        session.clusters.update(&mut session.blocks);
+        let before = snapshot(&session);
+        let saved = store.create("before refactor", &session).expect("create");
+
+        session.blocks[1].set_compression_level(CompressionLevel::Original);
+        session.log.record_level_change(
+            &session.blocks[1],
+            CompressionLevel::Trimmed,
+            0,
+            ActionReason::Lifecycle,
+            String::new(),
+        );
+        session.blocks.remove(0);
+        session.clusters.update(&mut session.blocks);
+        session.rules.clear();
+
+        let restored = store.restore(&saved.id, &mut session).expect("restore");
+        assert_eq!(restored, saved);
+        assert_eq!(snapshot(&session), before);
+    }
+
+    #[test]
+    fn test_requests_after_restore_carry_restored_blocks() {
+        let (_dir, store) = store("outbound");
+        let mut session = EngineSession::default();
+        let turn = |texts: &[&str]| {
+            let messages: Vec<Value> = texts
+                .iter()
+                .enumerate()
+                .map(|(i, text)| {
+                    let role = if i % 2 == 0 { "user" } else { "assistant" };
+                    json!({ "role": role, "content": text })
+                })
+                .collect();
+            json!({ "messages": messages })
+        };
+        let ingest = |session: &mut EngineSession, body: &Value| {
+            session
+                .transcript
+                .ingest(&mut session.blocks, body, "anthropic");
+        };
+
+        ingest(&mut session, &turn(&["Add caching", "Use an LRU."]));
+        let saved = store.create("plan", &session).expect("create");
+        ingest(
+            &mut session,
+            &turn(&["Add caching", "Use an LRU.", "Make it TTL"]),
+        );
+        session.blocks[1].content = "LRU.".to_string();
+
+        store.restore(&saved.id, &mut session).expect("restore");
+        let mut body = turn(&[
+            "Add caching",
+            "Use an LRU.",
+            "Make it TTL",
+            "Done.",
+            "Now add metrics",
+        ]);
+        ingest(&mut session, &body);
+        session
+            .transcript
+            .rebuild(&session.blocks, &mut body, "anthropic");
+        let sent = body["messages"].to_string();
+        assert!(sent.contains("Use an LRU."));
+        assert!(!sent.contains("Make it TTL"));
+        assert!(sent.contains("Now add metrics"));
+    }
+
+    #[test]
+    fn test_unchanged_blocks_are_stored_once() {
+        let (_dir, store) = store("dedup");
+        let mut session = session();
+        let first = store.create("one", &session).expect("create");
+        assert_eq!(first.stored_bytes, first.size_bytes);
+
+        let second = store.create("two", &session).expect("create");
+        assert_eq!(second.stored_bytes, 0);
+
+        session.blocks[0].content.push('!');
+        let third = store.create("three", &session).expect("create");
+        assert!(third.stored_bytes > 0 && third.stored_bytes < third.size_bytes);
+
+        let listed: Vec<String> = store
+            .list()
+            .expect("list")
+            .into_iter()
+            .map(|c| c.name)
+            .collect();
+        assert_eq!(listed.len(), 3);
+        assert!(listed.contains(&"two".to_string()));
+    }
+
+    #[test]
+    fn test_corrupt_object_fails_restore_without_changes() {
+        let (_dir, store) = store("corrupt");
+        let mut session = session();
+        let saved = store.create("x", &session).expect("create");
+        let (checkpoint, _) = store.load(&saved.id).expect("load");
+        fs::write(store.object_path(&checkpoint.block_hashes[1]), b"{}").expect("tamper");
+
+        session.blocks.clear();
+        assert!(matches!(
+            store.restore(&saved.id, &mut session),
+            Err(CheckpointError::Corrupt(_))
+        ));
+        assert!(session.blocks.is_empty());
+        assert!(matches!(
+            store.restore("../escape", &mut session),
+            Err(CheckpointError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_total_tokens_saturate() {
+        let (_dir, store) = store("tokens");
+        let mut session = session();
+        session.blocks[0].tokens = u32::MAX;
+        let saved = store.create("huge", &session).expect("create");
+        assert_eq!(saved.total_tokens, u32::MAX);
+    }
+}
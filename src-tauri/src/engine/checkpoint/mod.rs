@@ -0,0 +1,112 @@
+//! Checkpoints: saved session state that can be restored later.
+//!
+//! Hard checkpoints ([`hard`]) are exact copies of the engine session,
+//! stored content-addressed so blocks shared between checkpoints are kept
+//! once. Soft checkpoints ([`soft`]) are condensed handover notes that can
+//! seed a fresh session.
+
+pub mod hard;
+pub mod soft;
+
+use tauri::State;
+use thiserror::Error;
+
+use self::hard::{CheckpointStore, CheckpointSummary};
+use self::soft::{SoftCheckpoint, SoftCheckpointFormat};
+use super::compression::SharedBackend;
+use super::session::SharedSession;
+
+#[derive(Debug, Error)]
+pub enum CheckpointError {
+    #[error("Checkpoint not found: {0}")]
+    NotFound(String),
+
+    #[error("Checkpoint data is corrupt: {0}")]
+    Corrupt(String),
+
+    #[error("Failed to access checkpoint storage: {0}")]
+    StorageFailed(String),
+}
+
+impl serde::Serialize for CheckpointError {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+/// Save the current session as a hard checkpoint.
+#[tauri::command]
+pub fn checkpoint_create(
+    store: State<'_, CheckpointStore>,
+    session: State<'_, SharedSession>,
+    name: String,
+) -> Result<CheckpointSummary, CheckpointError> {
+    let session = session.lock();
+    store.create(&name, &session)
+}
+
+/// Replace the active branch's blocks, rules, action log and rule audit
+/// with a checkpoint's. Later requests are rebuilt from the restored blocks
+/// plus the messages the client sends that are new to the session; forks,
+/// staged items and the active profile and preset are not part of a
+/// checkpoint and stay as they are.
+#[tauri::command]
+pub fn checkpoint_restore(
+    store: State<'_, CheckpointStore>,
+    session: State<'_, SharedSession>,
+    checkpoint_id: String,
+) -> Result<CheckpointSummary, CheckpointError> {
+    let mut session = session.lock();
+    store.restore(&checkpoint_id, &mut session)
+}
+
+/// Saved checkpoints, newest first.
+#[tauri::command]
+pub fn checkpoint_list(
+    store: State<'_, CheckpointStore>,
+) -> Result<Vec<CheckpointSummary>, CheckpointError> {
+    store.list()
+}
+
+/// Summarize the current session as a soft checkpoint and save it.
+///
+/// Uses the configured compression backend and falls back to the
+/// deterministic extraction when none is set or the model call fails.
+#[tauri::command]
+pub async fn soft_checkpoint_create(
+    store: State<'_, CheckpointStore>,
+    session: State<'_, SharedSession>,
+    backend: State<'_, SharedBackend>,
+    name: String,
+) -> Result<SoftCheckpoint, CheckpointError> {
+    let blocks = session.lock().blocks.clone();
+    let checkpoint = soft::generate_soft_checkpoint(backend.as_deref(), &blocks, &name).await;
+    store.save_soft(&checkpoint)?;
+    Ok(checkpoint)
+}
+
+/// A saved soft checkpoint as Markdown or JSON.
+#[tauri::command]
+pub fn soft_checkpoint_export(
+    store: State<'_, CheckpointStore>,
+    checkpoint_id: String,
+    format: SoftCheckpointFormat,
+) -> Result<String, CheckpointError> {
+    Ok(store.load_soft(&checkpoint_id)?.export(format))
+}
+
+/// Inject a saved soft checkpoint into the Primacy zone of the current
+/// session; later requests lead their first user message with it. Returns
+/// the id of the injected block.
+#[tauri::command]
+pub fn soft_checkpoint_inject(
+    store: State<'_, CheckpointStore>,
+    session: State<'_, SharedSession>,
+    checkpoint_id: String,
+) -> Result<String, CheckpointError> {
+    let checkpoint = store.load_soft(&checkpoint_id)?;
+    Ok(soft::inject(&mut session.lock(), &checkpoint))
+}
@@ -0,0 +1,512 @@
+//! Incremental topic clustering.
+//!
+//! Each new block gets TF-IDF keywords from the session's
+//! [`KeywordIndex`] and joins the existing topic whose keywords it overlaps
+//! most, or starts a new one. Blocks never move between topics on later
+//! turns and ids are never reused, so `topic_cluster` stays stable while
+//! labels follow each topic's strongest keywords.
+//!
+//! Whole topics can then be compressed, archived or moved between zones;
+//! each block those operations change is recorded in the [`ActionLog`].
+
+use std::collections::{HashMap, HashSet};
+
+use serde::Serialize;
+
+use super::action_log::{ActionLog, ActionReason, BlockChange};
+use super::block::Block;
+use super::budget::RequestedCompression;
+use super::compression::preserve::PreserveKeys;
+use super::compression::{compress_rule_based, CompressionPriority, CompressionQueue};
+use super::keywords::{KeywordIndex, KEYWORDS_PER_BLOCK};
+use super::manifest::is_manifest;
+use super::memory::{self, MemoryState};
+use super::types::{CompressionLevel, Zone};
+
+/// Clustering thresholds.
+#[derive(Debug, Clone)]
+pub struct ClusterConfig {
+    /// Share of a block's keywords that must appear among a topic's top
+    /// keywords for the block to join it.
+    pub min_overlap: f64,
+    /// How many of a topic's keywords new blocks are compared against.
+    pub topic_keywords: usize,
+    /// Keywords joined into an auto-generated label.
+    pub label_keywords: usize,
+}
+
+impl Default for ClusterConfig {
+    fn default() -> Self {
+        Self {
+            min_overlap: 0.25,
+            topic_keywords: 20,
+            label_keywords: 3,
+        }
+    }
+}
+
+/// A topic and the blocks in it.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TopicCluster {
+    pub id: String,
+    pub label: String,
+    /// Set when the user renamed the topic; replaces the generated label.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub custom_label: Option<String>,
+    /// Strongest keywords, best first.
+    pub keywords: Vec<String>,
+    pub block_ids: Vec<String>,
+    #[serde(skip)]
+    weights: HashMap<String, f64>,
+}
+
+impl TopicCluster {
+    pub fn display_label(&self) -> &str {
+        self.custom_label.as_deref().unwrap_or(&self.label)
+    }
+
+    fn absorb(&mut self, block_id: &str, keywords: &[(String, f64)], config: &ClusterConfig) {
+        self.block_ids.push(block_id.to_string());
+        for (keyword, score) in keywords {
+            *self.weights.entry(keyword.clone()).or_default() += score;
+        }
+        let mut ranked: Vec<(&String, &f64)> = self.weights.iter().collect();
+        ranked.sort_by(|a, b| b.1.total_cmp(a.1).then_with(|| a.0.cmp(b.0)));
+        self.keywords = ranked
+            .into_iter()
+            .take(config.topic_keywords)
+            .map(|(k, _)| k.clone())
+            .collect();
+        self.label = self
+            .keywords
+            .iter()
+            .take(config.label_keywords)
+            .cloned()
+            .collect::<Vec<_>>()
+            .join(" / ");
+    }
+
+    /// Share of `keywords` found among this topic's keywords.
+    fn overlap(&self, keywords: &[(String, f64)]) -> f64 {
+        let shared = keywords
+            .iter()
+            .filter(|(k, _)| self.keywords.contains(k))
+            .count();
+        shared as f64 / keywords.len() as f64
+    }
+}
+
+/// Topic state for one session.
+#[derive(Debug, Clone, Default)]
+pub struct TopicClusters {
+    config: ClusterConfig,
+    index: KeywordIndex,
+    clusters: Vec<TopicCluster>,
+    seen: HashSet<String>,
+    next_id: u32,
+}
+
+impl TopicClusters {
+    pub fn new(config: ClusterConfig) -> Self {
+        Self {
+            config,
+            ..Self::default()
+        }
+    }
+
+    pub fn clusters(&self) -> &[TopicCluster] {
+        &self.clusters
+    }
+
+    pub fn get(&self, cluster_id: &str) -> Option<&TopicCluster> {
+        self.clusters.iter().find(|c| c.id == cluster_id)
+    }
+
+    /// Replace a topic's generated label; `None` restores it.
+    pub fn set_label(&mut self, cluster_id: &str, label: Option<String>) -> bool {
+        match self.clusters.iter_mut().find(|c| c.id == cluster_id) {
+            Some(cluster) => {
+                cluster.custom_label = label;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Rebuild topic state from the assignments `blocks` already carry, e.g.
+    /// after a checkpoint restore. Custom labels are dropped; blocks without
+    /// a topic are clustered on the next [`update`](Self::update).
+    pub fn rebuild(&mut self, blocks: &[Block]) {
+        *self = Self::new(self.config.clone());
+        let assigned: Vec<(&Block, &String)> = blocks
+            .iter()
+            .filter_map(|b| Some((b, b.topic_cluster.as_ref()?)))
+            .collect();
+        for (block, _) in &assigned {
+            self.seen.insert(block.id.clone());
+            self.index
+                .add_document(&block.compressed_versions.original.content);
+        }
+        for (block, cluster_id) in assigned {
+            let keywords = self.index.keywords(
+                &block.compressed_versions.original.content,
+                KEYWORDS_PER_BLOCK,
+            );
+            let index = match self.clusters.iter().position(|c| c.id == *cluster_id) {
+                Some(index) => index,
+                None => {
+                    if let Some(n) = cluster_id
+                        .strip_prefix("topic-")
+                        .and_then(|n| n.parse::<u32>().ok())
+                    {
+                        self.next_id = self.next_id.max(n);
+                    }
+                    self.clusters.push(TopicCluster {
+                        id: cluster_id.clone(),
+                        label: String::new(),
+                        custom_label: None,
+                        keywords: Vec::new(),
+                        block_ids: Vec::new(),
+                        weights: HashMap::new(),
+                    });
+                    self.clusters.len() - 1
+                }
+            };
+            self.clusters[index].absorb(&block.id, &keywords, &self.config);
+        }
+    }
+
+    /// Cluster blocks not seen before and drop blocks no longer present.
+    ///
+    /// Sets `topic_keywords` and `topic_cluster` on each new block. Returns
+    /// the ids of the blocks that were assigned a topic.
+    pub fn update(&mut self, blocks: &mut [Block]) -> Vec<String> {
+        let present: HashSet<&str> = blocks.iter().map(|b| b.id.as_str()).collect();
+        for cluster in &mut self.clusters {
+            cluster.block_ids.retain(|id| present.contains(id.as_str()));
+        }
+        self.clusters.retain(|c| !c.block_ids.is_empty());
+
+        // Document frequencies first, so a turn's blocks are weighed against
+        // each other as well as against earlier turns.
+        let new: Vec<usize> = blocks
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| !self.seen.contains(&b.id) && !is_manifest(b))
+            .map(|(i, _)| i)
+            .collect();
+        for &i in &new {
+            let block = &blocks[i];
+            self.seen.insert(block.id.clone());
+            self.index
+                .add_document(&block.compressed_versions.original.content);
+        }
+
+        let mut assigned = Vec::new();
+        for i in new {
+            let block = &mut blocks[i];
+            let keywords = self.index.keywords(
+                &block.compressed_versions.original.content,
+                KEYWORDS_PER_BLOCK,
+            );
+            block.topic_keywords = keywords.iter().map(|(k, _)| k.clone()).collect();
+            if keywords.is_empty() {
+                block.topic_cluster = None;
+                continue;
+            }
+
+            let best = self
+                .clusters
+                .iter_mut()
+                .map(|c| {
+                    let overlap = c.overlap(&keywords);
+                    (c, overlap)
+                })
+                .filter(|(_, overlap)| *overlap >= self.config.min_overlap)
+                .max_by(|a, b| a.1.total_cmp(&b.1));
+            let cluster = match best {
+                Some((cluster, _)) => cluster,
+                None => {
+                    self.next_id += 1;
+                    self.clusters.push(TopicCluster {
+                        id: format!("topic-{}", self.next_id),
+                        label: String::new(),
+                        custom_label: None,
+                        keywords: Vec::new(),
+                        block_ids: Vec::new(),
+                        weights: HashMap::new(),
+                    });
+                    self.clusters.last_mut().expect("just pushed")
+                }
+            };
+            cluster.absorb(&block.id, &keywords, &self.config);
+            block.topic_cluster = Some(cluster.id.clone());
+            assigned.push(block.id.clone());
+        }
+        assigned
+    }
+}
+
+/// Result of a whole-topic operation.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClusterOpReport {
+    /// Blocks the operation changed.
+    pub changed: Vec<String>,
+    /// Model-generated levels queued because they did not exist yet.
+    pub requested: Vec<RequestedCompression>,
+    /// Blocks left as they were, e.g. pinned blocks on archive.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub skipped: Vec<String>,
+}
+
+fn in_cluster<'a>(
+    blocks: &'a mut [Block],
+    cluster_id: &'a str,
+) -> impl Iterator<Item = &'a mut Block> {
+    blocks
+        .iter_mut()
+        .filter(move |b| b.topic_cluster.as_deref() == Some(cluster_id))
+}
+
+/// Switch every block in the topic to `level`.
+///
+/// Trimmed versions are generated on the spot. Blocks missing a summarized
+/// or minimal version keep their level, and the version is queued at
+/// [`CompressionPriority::User`] when a `queue` is given.
+pub fn compress_cluster(
+    blocks: &mut [Block],
+    cluster_id: &str,
+    level: CompressionLevel,
+    keys: &PreserveKeys,
+    queue: Option<&CompressionQueue>,
+    log: &mut ActionLog,
+) -> ClusterOpReport {
+    let mut report = ClusterOpReport::default();
+    for block in in_cluster(blocks, cluster_id) {
+        if block.compression_level == level {
+            continue;
+        }
+        if level == CompressionLevel::Trimmed && block.compressed_versions.trimmed.is_none() {
+            compress_rule_based(block, keys);
+        }
+        let (from, tokens_before) = (block.compression_level, block.tokens);
+        if block.set_compression_level(level) {
+            log.record_level_change(
+                block,
+                from,
+                tokens_before,
+                ActionReason::Cluster,
+                format!("topic {cluster_id}"),
+            );
+            report.changed.push(block.id.clone());
+        } else {
+            if let Some(queue) = queue {
+                queue.enqueue(block.clone(), level, CompressionPriority::User);
+            }
+            report.requested.push(RequestedCompression {
+                block_id: block.id.clone(),
+                level,
+            });
+        }
+    }
+    report
+}
+
+/// Move every block in the topic to `zone`.
+pub fn move_cluster(
+    blocks: &mut [Block],
+    cluster_id: &str,
+    zone: &Zone,
+    log: &mut ActionLog,
+) -> ClusterOpReport {
+    let mut report = ClusterOpReport::default();
+    for block in in_cluster(blocks, cluster_id) {
+        if block.zone != *zone {
+            let from = std::mem::replace(&mut block.zone, zone.clone());
+            let change = BlockChange::Zone {
+                from,
+                to: zone.clone(),
+            };
+            log.record(
+                block,
+                change,
+                block.tokens,
+                ActionReason::Cluster,
+                format!("topic {cluster_id}"),
+            );
+            report.changed.push(block.id.clone());
+        }
+    }
+    report
+}
+
+/// Move every block in the topic to [`MemoryState::Archived`].
+///
+/// Archived blocks stay in `blocks` with recall metadata, so they can be
+/// recalled or undone from the log. Pinned blocks are skipped.
+pub fn archive_cluster(
+    blocks: &mut [Block],
+    cluster_id: &str,
+    log: &mut ActionLog,
+) -> ClusterOpReport {
+    let mut report = ClusterOpReport::default();
+    for block in in_cluster(blocks, cluster_id) {
+        match memory::transition(
+            block,
+            MemoryState::Archived,
+            None,
+            ActionReason::Cluster,
+            log,
+        ) {
+            Ok(Some(_)) => report.changed.push(block.id.clone()),
+            Ok(None) => {}
+            Err(_) => report.skipped.push(block.id.clone()),
+        }
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::block::{test_block, CompressionVersion};
+    use crate::engine::types::{BuiltInZone, PinPosition, Role};
+
+    fn session() -> Vec<Block> {
+        vec![
+            test_block(
+                "a1",
+                Role::User,
+                "Add token refresh to the AuthClient login flow",
+            ),
+            test_block(
+                "a2",
+                Role::ToolResult,
+                "impl AuthClient { fn login() {} fn refresh_token() {} }",
+            ),
+            test_block(
+                "c1",
+                Role::User,
+                "The chart renderer draws candlesticks too slowly",
+            ),
+        ]
+    }
+
+    #[test]
+    fn test_update_groups_by_keyword_overlap() {
+        let mut topics = TopicClusters::new(ClusterConfig::default());
+        let mut blocks = session();
+        topics.update(&mut blocks);
+
+        let auth = blocks[0].topic_cluster.clone().expect("clustered");
+        assert_eq!(blocks[1].topic_cluster.as_deref(), Some(auth.as_str()));
+        assert_ne!(blocks[2].topic_cluster.as_deref(), Some(auth.as_str()));
+        assert!(blocks[1].topic_keywords.contains(&"authclient".to_string()));
+
+        let cluster = topics.get(&auth).expect("cluster");
+        assert_eq!(cluster.block_ids, vec!["a1", "a2"]);
+        assert!(!cluster.label.is_empty());
+    }
+
+    #[test]
+    fn test_update_keeps_ids_stable_across_turns() {
+        let mut topics = TopicClusters::new(ClusterConfig::default());
+        let mut blocks = session();
+        topics.update(&mut blocks);
+        let before: Vec<_> = blocks.iter().map(|b| b.topic_cluster.clone()).collect();
+
+        blocks.push(test_block(
+            "c2",
+            Role::Assistant,
+            "Batch the candlesticks so the chart renderer draws once per frame",
+        ));
+        blocks.remove(0);
+        let assigned = topics.update(&mut blocks);
+
+        assert_eq!(assigned, vec!["c2"]);
+        assert_eq!(blocks[0].topic_cluster, before[1]);
+        assert_eq!(blocks[1].topic_cluster, before[2]);
+        assert_eq!(blocks[2].topic_cluster, before[2]);
+        let auth = before[1].clone().expect("clustered");
+        assert_eq!(topics.get(&auth).expect("cluster").block_ids, vec!["a2"]);
+
+        topics.set_label(&auth, Some("Auth".to_string()));
+        assert_eq!(topics.get(&auth).expect("cluster").display_label(), "Auth");
+    }
+
+    #[test]
+    fn test_cluster_operations_apply_to_members_only() {
+        let mut topics = TopicClusters::new(ClusterConfig::default());
+        let mut blocks = session();
+        topics.update(&mut blocks);
+        let auth = blocks[0].topic_cluster.clone().expect("clustered");
+        blocks[1].compressed_versions.minimal =
+            Some(CompressionVersion::new("AuthClient impl".to_string()));
+
+        let mut log = ActionLog::new();
+        let report = compress_cluster(
+            &mut blocks,
+            &auth,
+            CompressionLevel::Minimal,
+            &PreserveKeys::default(),
+            None,
+            &mut log,
+        );
+        assert_eq!(report.changed, vec!["a2"]);
+        assert_eq!(report.requested[0].block_id, "a1");
+        assert_eq!(blocks[1].content, "AuthClient impl");
+
+        let recency = Zone::BuiltIn(BuiltInZone::Recency);
+        let moved = move_cluster(&mut blocks, &auth, &recency, &mut log);
+        assert_eq!(moved.changed.len(), 2);
+        assert_eq!(blocks[2].zone, Zone::BuiltIn(BuiltInZone::Middle));
+
+        blocks[0].pinned = Some(PinPosition::Top);
+        let archived = archive_cluster(&mut blocks, &auth, &mut log);
+        assert_eq!(archived.changed, vec!["a2"]);
+        assert_eq!(archived.skipped, vec!["a1"]);
+        assert_eq!(blocks.len(), 3);
+        assert_eq!(blocks[1].memory_state, MemoryState::Archived);
+        assert!(blocks[1].recall.is_some());
+        assert_eq!(blocks[2].memory_state, MemoryState::Hot);
+    }
+
+    #[test]
+    fn test_cluster_operations_are_logged_and_undoable() {
+        let mut topics = TopicClusters::new(ClusterConfig::default());
+        let mut blocks = session();
+        topics.update(&mut blocks);
+        let auth = blocks[0].topic_cluster.clone().expect("clustered");
+        let mut log = ActionLog::new();
+
+        compress_cluster(
+            &mut blocks,
+            &auth,
+            CompressionLevel::Trimmed,
+            &PreserveKeys::default(),
+            None,
+            &mut log,
+        );
+        move_cluster(
+            &mut blocks,
+            &auth,
+            &Zone::BuiltIn(BuiltInZone::Recency),
+            &mut log,
+        );
+        archive_cluster(&mut blocks, &auth, &mut log);
+        assert!(log
+            .entries()
+            .iter()
+            .all(|entry| entry.reason == ActionReason::Cluster));
+        assert_eq!(log.entries().len(), 6);
+
+        assert_eq!(log.undo_all(&mut blocks), 6);
+        for block in &blocks[..2] {
+            assert_eq!(block.compression_level, CompressionLevel::Original);
+            assert_eq!(block.zone, Zone::BuiltIn(BuiltInZone::Middle));
+            assert_eq!(block.memory_state, MemoryState::Hot);
+        }
+    }
+}
@@ -0,0 +1,196 @@
+//! Anthropic Messages API backend.
+
+use std::time::Duration;
+
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::Deserialize;
+use serde_json::json;
+
+use super::backend::{
+    build_client, check_status, endpoint, CompressionBackend, CompressionPrompt,
+    DEFAULT_BACKEND_TIMEOUT,
+};
+use super::error::CompressionError;
+
+/// Default Anthropic API base URL.
+pub const DEFAULT_ANTHROPIC_URL: &str = "https://api.anthropic.com";
+
+/// API version header sent with every request.
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+
+/// Backend for the Anthropic Messages API.
+#[derive(Debug, Clone)]
+pub struct AnthropicBackend {
+    client: Client,
+    base_url: String,
+    model: String,
+    api_key: String,
+}
+
+impl AnthropicBackend {
+    pub fn new(
+        api_key: impl Into<String>,
+        model: impl Into<String>,
+    ) -> Result<Self, CompressionError> {
+        Self::with_base_url(
+            DEFAULT_ANTHROPIC_URL,
+            api_key,
+            model,
+            DEFAULT_BACKEND_TIMEOUT,
+        )
+    }
+
+    pub fn with_base_url(
+        base_url: impl Into<String>,
+        api_key: impl Into<String>,
+        model: impl Into<String>,
+        timeout: Duration,
+    ) -> Result<Self, CompressionError> {
+        Ok(Self {
+            client: build_client(timeout)?,
+            base_url: base_url.into(),
+            model: model.into(),
+            api_key: api_key.into(),
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct MessagesResponse {
+    content: Vec<ContentBlock>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ContentBlock {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    text: String,
+}
+
+#[async_trait]
+impl CompressionBackend for AnthropicBackend {
+    fn name(&self) -> &str {
+        "anthropic"
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    async fn complete(&self, prompt: &CompressionPrompt) -> Result<String, CompressionError> {
+        let body = json!({
+            "model": self.model,
+            "max_tokens": prompt.max_tokens,
+            "temperature": 0,
+            "system": prompt.system,
+            "messages": [{ "role": "user", "content": prompt.user }],
+        });
+
+        let response = self
+            .client
+            .post(endpoint(&self.base_url, "/v1/messages"))
+            .header("x-api-key", &self.api_key)
+            .header("anthropic-version", ANTHROPIC_VERSION)
+            .json(&body)
+            .send()
+            .await?;
+        let parsed: MessagesResponse = check_status(response)
+            .await?
+            .json()
+            .await
+            .map_err(|e| CompressionError::InvalidResponse(e.to_string()))?;
+
+        let text: String = parsed
+            .content
+            .into_iter()
+            .filter(|block| block.kind == "text")
+            .map(|block| block.text)
+            .collect();
+        if text.is_empty() {
+            return Err(CompressionError::InvalidResponse(
+                "no text content blocks in response".into(),
+            ));
+        }
+        Ok(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::compression::backend::stub::StubServer;
+
+    fn prompt() -> CompressionPrompt {
+        CompressionPrompt {
+            system: "You compress context.".to_string(),
+            user: "Summarize this.".to_string(),
+            max_tokens: 128,
+        }
+    }
+
+    fn backend(base_url: &str) -> AnthropicBackend {
+        AnthropicBackend::with_base_url(
+            base_url,
+            "sk-ant-test",
+            "claude-haiku",
+            Duration::from_secs(5),
+        )
+        .expect("backend")
+    }
+
+    #[tokio::test]
+    async fn test_complete_sends_messages_request_and_joins_text() {
+        let server = StubServer::start(
+            "/v1/messages",
+            200,
+            json!({
+                "content": [
+                    { "type": "text", "text": "Key " },
+                    { "type": "thinking", "thinking": "..." },
+                    { "type": "text", "text": "points." }
+                ]
+            }),
+        )
+        .await;
+
+        let text = backend(&server.base_url)
+            .complete(&prompt())
+            .await
+            .expect("completion");
+        assert_eq!(text, "Key points.");
+
+        let requests = server.requests();
+        let request = &requests[0];
+        assert_eq!(request.headers["x-api-key"], "sk-ant-test");
+        assert_eq!(request.headers["anthropic-version"], ANTHROPIC_VERSION);
+        assert_eq!(request.body["system"], "You compress context.");
+        assert_eq!(request.body["messages"][0]["role"], "user");
+        assert_eq!(request.body["max_tokens"], 128);
+    }
+
+    #[tokio::test]
+    async fn test_complete_maps_error_status() {
+        let server = StubServer::start(
+            "/v1/messages",
+            401,
+            json!({ "type": "error", "error": { "message": "invalid x-api-key" } }),
+        )
+        .await;
+
+        let result = backend(&server.base_url).complete(&prompt()).await;
+        assert!(matches!(
+            result,
+            Err(CompressionError::BackendStatus { status: 401, .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_complete_rejects_response_without_text() {
+        let server = StubServer::start("/v1/messages", 200, json!({ "content": [] })).await;
+
+        let result = backend(&server.base_url).complete(&prompt()).await;
+        assert!(matches!(result, Err(CompressionError::InvalidResponse(_))));
+    }
+}
@@ -0,0 +1,167 @@
+//! Pluggable model backends for summarized and minimal compression.
+
+use std::time::Duration;
+
+use async_trait::async_trait;
+use reqwest::Client;
+
+use super::error::CompressionError;
+
+/// Default request timeout for compression calls.
+pub const DEFAULT_BACKEND_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Error bodies are cut to this many bytes before being surfaced.
+const MAX_ERROR_BODY: usize = 512;
+
+/// A single-turn completion request for a compression model.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompressionPrompt {
+    pub system: String,
+    pub user: String,
+    pub max_tokens: u32,
+}
+
+/// A model that can turn a compression prompt into text.
+///
+/// Implementations only transport the prompt; templating and output
+/// validation live in [`super::llm`] so every backend behaves the same.
+#[async_trait]
+pub trait CompressionBackend: Send + Sync {
+    /// Short backend identifier, e.g. `"anthropic"`.
+    fn name(&self) -> &str;
+
+    /// Model the backend sends requests to.
+    fn model(&self) -> &str;
+
+    /// Run `prompt` and return the model's raw text output.
+    async fn complete(&self, prompt: &CompressionPrompt) -> Result<String, CompressionError>;
+}
+
+pub(crate) fn build_client(timeout: Duration) -> Result<Client, CompressionError> {
+    Client::builder()
+        .timeout(timeout)
+        .build()
+        .map_err(CompressionError::ClientBuildFailed)
+}
+
+/// Join a base URL and an API path without doubling slashes.
+pub(crate) fn endpoint(base_url: &str, path: &str) -> String {
+    format!(
+        "{}/{}",
+        base_url.trim_end_matches('/'),
+        path.trim_start_matches('/')
+    )
+}
+
+/// Turn a non-success response into [`CompressionError::BackendStatus`].
+pub(crate) async fn check_status(
+    response: reqwest::Response,
+) -> Result<reqwest::Response, CompressionError> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let mut body = response.text().await.unwrap_or_default();
+    let mut end = body.len().min(MAX_ERROR_BODY);
+    while !body.is_char_boundary(end) {
+        end -= 1;
+    }
+    body.truncate(end);
+    Err(CompressionError::BackendStatus {
+        status: status.as_u16(),
+        body,
+    })
+}
+
+#[cfg(test)]
+pub(crate) mod stub {
+    //! Local HTTP stub standing in for a model server.
+
+    use std::sync::{Arc, Mutex};
+
+    use axum::{
+        extract::State,
+        http::{HeaderMap, StatusCode},
+        routing::post,
+        Json, Router,
+    };
+    use serde_json::Value;
+    use tokio::net::TcpListener;
+
+    /// A request the stub received.
+    #[derive(Debug, Clone)]
+    pub struct Captured {
+        pub headers: HeaderMap,
+        pub body: Value,
+    }
+
+    #[derive(Clone)]
+    struct StubState {
+        status: StatusCode,
+        reply: Value,
+        captured: Arc<Mutex<Vec<Captured>>>,
+    }
+
+    pub struct StubServer {
+        pub base_url: String,
+        captured: Arc<Mutex<Vec<Captured>>>,
+    }
+
+    impl StubServer {
+        /// Serve `reply` with `status` on `path` from an ephemeral port.
+        pub async fn start(path: &str, status: u16, reply: Value) -> Self {
+            let captured = Arc::new(Mutex::new(Vec::new()));
+            let state = StubState {
+                status: StatusCode::from_u16(status).expect("valid status"),
+                reply,
+                captured: Arc::clone(&captured),
+            };
+            let app = Router::new().route(path, post(respond)).with_state(state);
+
+            let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind stub");
+            let addr = listener.local_addr().expect("stub address");
+            tokio::spawn(async move {
+                let _ = axum::serve(listener, app).await;
+            });
+
+            Self {
+                base_url: format!("http://{addr}"),
+                captured,
+            }
+        }
+
+        pub fn requests(&self) -> Vec<Captured> {
+            self.captured.lock().expect("captured lock").clone()
+        }
+    }
+
+    async fn respond(
+        State(state): State<StubState>,
+        headers: HeaderMap,
+        Json(body): Json<Value>,
+    ) -> (StatusCode, Json<Value>) {
+        state
+            .captured
+            .lock()
+            .expect("captured lock")
+            .push(Captured { headers, body });
+        (state.status, Json(state.reply))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_endpoint_joins_without_double_slash() {
+        assert_eq!(
+            endpoint("http://localhost:11434/", "/v1/chat/completions"),
+            "http://localhost:11434/v1/chat/completions"
+        );
+        assert_eq!(
+            endpoint("https://api.anthropic.com", "v1/messages"),
+            "https://api.anthropic.com/v1/messages"
+        );
+    }
+}
@@ -0,0 +1,191 @@
+//! Compression backend configuration.
+//!
+//! The app does not need a model to run; summarized and minimal levels are
+//! only generated when a backend is configured through the environment:
+//!
+//! - `APERTURE_COMPRESSION_BACKEND`: `anthropic` or `openai-compatible`
+//! - `APERTURE_COMPRESSION_MODEL`: model to call (required)
+//! - `APERTURE_COMPRESSION_URL`: base URL override
+//! - `APERTURE_COMPRESSION_API_KEY`: API key (required for `anthropic`)
+
+use std::sync::Arc;
+
+use super::anthropic::{AnthropicBackend, DEFAULT_ANTHROPIC_URL};
+use super::backend::{CompressionBackend, DEFAULT_BACKEND_TIMEOUT};
+use super::error::CompressionError;
+use super::openai::{OpenAiCompatibleBackend, DEFAULT_OPENAI_COMPATIBLE_URL};
+
+pub const BACKEND_ENV: &str = "APERTURE_COMPRESSION_BACKEND";
+pub const MODEL_ENV: &str = "APERTURE_COMPRESSION_MODEL";
+pub const URL_ENV: &str = "APERTURE_COMPRESSION_URL";
+pub const API_KEY_ENV: &str = "APERTURE_COMPRESSION_API_KEY";
+
+/// The backend built at startup, if one is configured. Managed as Tauri
+/// state and shared with the compression worker.
+pub type SharedBackend = Option<Arc<dyn CompressionBackend>>;
+
+/// Which API the compression backend speaks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackendKind {
+    Anthropic,
+    OpenAiCompatible,
+}
+
+impl BackendKind {
+    fn parse(raw: &str) -> Result<Self, CompressionError> {
+        match raw.trim().to_ascii_lowercase().as_str() {
+            "anthropic" => Ok(Self::Anthropic),
+            "openai-compatible" | "openai" => Ok(Self::OpenAiCompatible),
+            other => Err(CompressionError::Config(format!(
+                "unknown backend {other:?} in {BACKEND_ENV}; expected \"anthropic\" or \"openai-compatible\""
+            ))),
+        }
+    }
+
+    fn default_url(self) -> &'static str {
+        match self {
+            Self::Anthropic => DEFAULT_ANTHROPIC_URL,
+            Self::OpenAiCompatible => DEFAULT_OPENAI_COMPATIBLE_URL,
+        }
+    }
+}
+
+/// Settings for the compression backend.
+#[derive(Clone, PartialEq, Eq)]
+pub struct BackendConfig {
+    pub kind: BackendKind,
+    pub model: String,
+    pub base_url: String,
+    pub api_key: Option<String>,
+}
+
+impl std::fmt::Debug for BackendConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BackendConfig")
+            .field("kind", &self.kind)
+            .field("model", &self.model)
+            .field("base_url", &self.base_url)
+            .field("api_key", &self.api_key.as_ref().map(|_| "<redacted>"))
+            .finish()
+    }
+}
+
+impl BackendConfig {
+    /// Read the configuration from the process environment.
+    ///
+    /// `Ok(None)` when no backend is selected.
+    pub fn from_env() -> Result<Option<Self>, CompressionError> {
+        Self::from_vars(|name| std::env::var(name).ok())
+    }
+
+    /// Read the configuration through `var`, which looks up one variable.
+    pub fn from_vars(
+        var: impl Fn(&str) -> Option<String>,
+    ) -> Result<Option<Self>, CompressionError> {
+        let value = |name: &str| {
+            var(name)
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty())
+        };
+        let Some(kind) = value(BACKEND_ENV) else {
+            return Ok(None);
+        };
+        let kind = BackendKind::parse(&kind)?;
+        let model = value(MODEL_ENV)
+            .ok_or_else(|| CompressionError::Config(format!("{MODEL_ENV} is not set")))?;
+        let api_key = value(API_KEY_ENV);
+        if kind == BackendKind::Anthropic && api_key.is_none() {
+            return Err(CompressionError::Config(format!(
+                "{API_KEY_ENV} is required for the anthropic backend"
+            )));
+        }
+        Ok(Some(Self {
+            kind,
+            model,
+            base_url: value(URL_ENV).unwrap_or_else(|| kind.default_url().to_string()),
+            api_key,
+        }))
+    }
+
+    /// Construct the configured backend.
+    pub fn build(&self) -> Result<Arc<dyn CompressionBackend>, CompressionError> {
+        Ok(match self.kind {
+            BackendKind::Anthropic => Arc::new(AnthropicBackend::with_base_url(
+                &self.base_url,
+                self.api_key.clone().unwrap_or_default(),
+                &self.model,
+                DEFAULT_BACKEND_TIMEOUT,
+            )?),
+            BackendKind::OpenAiCompatible => {
+                let backend = OpenAiCompatibleBackend::new(&self.base_url, &self.model)?;
+                Arc::new(match &self.api_key {
+                    Some(key) => backend.with_api_key(key),
+                    None => backend,
+                })
+            }
+        })
+    }
+}
+
+/// Build the backend configured in the environment, if any.
+pub fn backend_from_env() -> Result<SharedBackend, CompressionError> {
+    BackendConfig::from_env()?
+        .map(|config| config.build())
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn config(vars: &[(&str, &str)]) -> Result<Option<BackendConfig>, CompressionError> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        BackendConfig::from_vars(|name| vars.get(name).cloned())
+    }
+
+    #[test]
+    fn test_backend_config_from_vars() {
+        assert!(config(&[]).unwrap().is_none());
+        assert!(config(&[(BACKEND_ENV, " ")]).unwrap().is_none());
+
+        let local = config(&[(BACKEND_ENV, "openai-compatible"), (MODEL_ENV, "qwen2.5")])
+            .unwrap()
+            .unwrap();
+        assert_eq!(local.kind, BackendKind::OpenAiCompatible);
+        assert_eq!(local.base_url, DEFAULT_OPENAI_COMPATIBLE_URL);
+        assert_eq!(local.api_key, None);
+        let backend = local.build().unwrap();
+        assert_eq!(backend.name(), "openai-compatible");
+        assert_eq!(backend.model(), "qwen2.5");
+
+        let anthropic = config(&[
+            (BACKEND_ENV, "Anthropic"),
+            (MODEL_ENV, "claude-haiku"),
+            (API_KEY_ENV, "sk-test"),
+            (URL_ENV, "http://127.0.0.1:9"),
+        ])
+        .unwrap()
+        .unwrap();
+        assert_eq!(anthropic.kind, BackendKind::Anthropic);
+        assert_eq!(anthropic.base_url, "http://127.0.0.1:9");
+        assert!(!format!("{anthropic:?}").contains("sk-test"));
+        assert_eq!(anthropic.build().unwrap().name(), "anthropic");
+    }
+
+    #[test]
+    fn test_backend_config_rejects_incomplete_settings() {
+        for vars in [
+            vec![(BACKEND_ENV, "gemini"), (MODEL_ENV, "m")],
+            vec![(BACKEND_ENV, "openai")],
+            vec![(BACKEND_ENV, "anthropic"), (MODEL_ENV, "m")],
+        ] {
+            let err = config(&vars).unwrap_err();
+            assert!(matches!(err, CompressionError::Config(_)), "{err}");
+            assert!(!err.is_retryable());
+        }
+    }
+}
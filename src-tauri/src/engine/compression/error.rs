@@ -0,0 +1,52 @@
+//! Compression error types.
+
+use thiserror::Error;
+
+use crate::engine::types::CompressionLevel;
+
+/// Errors that can occur while generating a compressed version.
+#[derive(Debug, Error)]
+pub enum CompressionError {
+    /// Failed to construct the HTTP client.
+    #[error("failed to build HTTP client: {0}")]
+    ClientBuildFailed(#[source] reqwest::Error),
+
+    /// The backend could not be reached or the request failed in transit.
+    #[error("compression backend request failed: {0}")]
+    RequestFailed(#[from] reqwest::Error),
+
+    /// The backend answered with a non-success status.
+    #[error("compression backend returned {status}: {body}")]
+    BackendStatus { status: u16, body: String },
+
+    /// The backend's response did not have the expected shape.
+    #[error("invalid compression backend response: {0}")]
+    InvalidResponse(String),
+
+    /// The model's output failed validation.
+    #[error("compression output rejected: {0}")]
+    Rejected(String),
+
+    /// The level is not produced by this generator.
+    #[error("compression level {0:?} is not generated by a model")]
+    UnsupportedLevel(CompressionLevel),
+
+    /// The backend settings are missing or invalid.
+    #[error("invalid compression backend configuration: {0}")]
+    Config(String),
+}
+
+impl CompressionError {
+    /// Whether a later attempt might succeed: transport failures, rate
+    /// limits, server errors and malformed responses.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::RequestFailed(_) | Self::InvalidResponse(_) => true,
+            Self::BackendStatus { status, .. } => *status == 429 || *status >= 500,
+            Self::ClientBuildFailed(_)
+            | Self::Rejected(_)
+            | Self::UnsupportedLevel(_)
+            | Self::Config(_) => false,
+        }
+    }
+}
@@ -0,0 +1,345 @@
+//! Model-generated `summarized` and `minimal` compression levels.
+
+use tracing::warn;
+
+use super::backend::CompressionBackend;
+use super::error::CompressionError;
+use super::preserve::PreserveKeys;
+use super::prompts::{build_prompt, source_text};
+use super::quality::{self, QualityConfig};
+use crate::engine::block::{Block, CompressionVersion};
+use crate::engine::tokens::count_tokens;
+use crate::engine::types::CompressionLevel;
+
+/// Longest accepted minimal description, in characters.
+pub const MAX_MINIMAL_CHARS: usize = 200;
+
+/// Openings that mean the model declined instead of compressing.
+const REFUSAL_PREFIXES: &[&str] = &[
+    "i'm sorry",
+    "i am sorry",
+    "i cannot",
+    "i can't",
+    "i'm unable",
+    "i am unable",
+    "as an ai",
+];
+
+/// Labels models like to put in front of the answer.
+const LABEL_PREFIXES: &[&str] = &["summary:", "description:", "minimal:", "compressed:"];
+
+/// Generate `level` for `block` with `backend` and store it in the block's
+/// compression versions.
+///
+/// The version is scored against the original with [`quality::verify`]
+/// (plus a self-check when `quality.self_check` is set) before it is
+/// stored. The original is left untouched, and a rejected output leaves any
+/// previous version in place.
+pub async fn compress_with_llm(
+    backend: &dyn CompressionBackend,
+    block: &mut Block,
+    level: CompressionLevel,
+    keys: &PreserveKeys,
+    quality: &QualityConfig,
+) -> Result<CompressionVersion, CompressionError> {
+    if !matches!(
+        level,
+        CompressionLevel::Summarized | CompressionLevel::Minimal
+    ) {
+        return Err(CompressionError::UnsupportedLevel(level));
+    }
+
+    let source_tokens = count_tokens(source_text(block));
+    let prompt = build_prompt(block, level, keys, source_tokens);
+    let raw = backend.complete(&prompt).await?;
+    let content = validate_output(&raw, level, source_tokens)?;
+
+    let mut version = CompressionVersion::new(content);
+    let original = &block.compressed_versions.original;
+    let mut report = quality::verify(
+        &original.content,
+        original.tokens,
+        level,
+        &version,
+        keys,
+        quality,
+    );
+    if quality.self_check {
+        match quality::self_check(backend, &original.content, &version.content).await {
+            Ok(score) => quality::apply_self_check(&mut report, score),
+            Err(e) => warn!("Compression self-check of {} failed: {e}", block.id),
+        }
+    }
+    version.quality = Some(report);
+
+    let versions = &mut block.compressed_versions;
+    match level {
+        CompressionLevel::Minimal => versions.minimal = Some(version.clone()),
+        _ => versions.summarized = Some(version.clone()),
+    }
+    Ok(version)
+}
+
+/// Clean up a model's output and reject it if it is unusable.
+///
+/// Strips wrapping code fences, quotes and labels. A minimal description is
+/// cut to its first line; a summary must come out shorter than its source.
+pub fn validate_output(
+    raw: &str,
+    level: CompressionLevel,
+    source_tokens: u32,
+) -> Result<String, CompressionError> {
+    let mut text = strip_fences(raw.trim()).trim();
+    for label in LABEL_PREFIXES {
+        if text
+            .get(..label.len())
+            .is_some_and(|prefix| prefix.eq_ignore_ascii_case(label))
+        {
+            text = text[label.len()..].trim_start();
+            break;
+        }
+    }
+
+    if text.is_empty() {
+        return Err(CompressionError::Rejected("empty output".into()));
+    }
+    let lower = text.to_lowercase();
+    if REFUSAL_PREFIXES.iter().any(|p| lower.starts_with(p)) {
+        return Err(CompressionError::Rejected(
+            "model refused to compress".into(),
+        ));
+    }
+
+    match level {
+        CompressionLevel::Minimal => {
+            let line = text
+                .lines()
+                .map(str::trim)
+                .find(|line| !line.is_empty())
+                .unwrap_or_default();
+            let line = line
+                .trim_matches(|c| c == '"' || c == '\'' || c == '`')
+                .trim();
+            if line.chars().count() > MAX_MINIMAL_CHARS {
+                return Err(CompressionError::Rejected(format!(
+                    "minimal description exceeds {MAX_MINIMAL_CHARS} characters"
+                )));
+            }
+            Ok(line.to_string())
+        }
+        _ => {
+            let tokens = count_tokens(text);
+            if tokens >= source_tokens {
+                return Err(CompressionError::Rejected(format!(
+                    "summary is {tokens} tokens, not shorter than the {source_tokens} token source"
+                )));
+            }
+            Ok(text.to_string())
+        }
+    }
+}
+
+/// Remove a code fence wrapping the whole output.
+pub(crate) fn strip_fences(text: &str) -> &str {
+    let Some(inner) = text.strip_prefix("```").and_then(|t| t.strip_suffix("```")) else {
+        return text;
+    };
+    // Drop an info string such as ```text.
+    match inner.split_once('\n') {
+        Some((info, body)) if !info.trim().contains(' ') => body,
+        _ => inner,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use async_trait::async_trait;
+    use serde_json::json;
+
+    use super::*;
+    use crate::engine::block::test_block;
+    use crate::engine::compression::anthropic::AnthropicBackend;
+    use crate::engine::compression::backend::stub::StubServer;
+    use crate::engine::compression::backend::CompressionPrompt;
+    use crate::engine::types::Role;
+
+    /// Backend that replies with canned text and records prompts.
+    struct CannedBackend {
+        reply: String,
+        prompts: Mutex<Vec<CompressionPrompt>>,
+    }
+
+    impl CannedBackend {
+        fn new(reply: &str) -> Self {
+            Self {
+                reply: reply.to_string(),
+                prompts: Mutex::new(Vec::new()),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl CompressionBackend for CannedBackend {
+        fn name(&self) -> &str {
+            "canned"
+        }
+
+        fn model(&self) -> &str {
+            "canned-1"
+        }
+
+        async fn complete(&self, prompt: &CompressionPrompt) -> Result<String, CompressionError> {
+            self.prompts.lock().expect("prompts").push(prompt.clone());
+            Ok(self.reply.clone())
+        }
+    }
+
+    fn long_block() -> Block {
+        let content =
+            "The MarketStream class connects to the exchange websocket and retries. ".repeat(20);
+        test_block("b", Role::Assistant, &content)
+    }
+
+    #[tokio::test]
+    async fn test_compress_with_llm_stores_summary() {
+        let backend = CannedBackend::new("```\nMarketStream wraps the exchange websocket.\n```");
+        let mut block = long_block();
+
+        let version = compress_with_llm(
+            &backend,
+            &mut block,
+            CompressionLevel::Summarized,
+            &PreserveKeys::default(),
+            &QualityConfig::default(),
+        )
+        .await
+        .expect("summary");
+
+        assert_eq!(
+            version.content,
+            "MarketStream wraps the exchange websocket."
+        );
+        assert_eq!(version.tokens, count_tokens(&version.content));
+        let quality = version.quality.as_ref().expect("quality report");
+        assert!(quality.is_auto_usable(&QualityConfig::default()));
+        assert_eq!(
+            block.compressed_versions.summarized.map(|v| v.content),
+            Some(version.content)
+        );
+        assert!(block.compressed_versions.minimal.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_compress_with_llm_minimal_keeps_first_line() {
+        let backend =
+            CannedBackend::new("Description: \"Market data websocket client\"\n\nIt also retries.");
+        let mut block = long_block();
+
+        let version = compress_with_llm(
+            &backend,
+            &mut block,
+            CompressionLevel::Minimal,
+            &PreserveKeys::default(),
+            &QualityConfig::default(),
+        )
+        .await
+        .expect("minimal");
+
+        assert_eq!(version.content, "Market data websocket client");
+        let prompts = backend.prompts.lock().expect("prompts");
+        assert!(prompts[0].user.contains("one line"));
+    }
+
+    #[tokio::test]
+    async fn test_compress_with_llm_rejects_unsupported_level() {
+        let backend = CannedBackend::new("unused");
+        let mut block = long_block();
+
+        let result = compress_with_llm(
+            &backend,
+            &mut block,
+            CompressionLevel::Trimmed,
+            &PreserveKeys::default(),
+            &QualityConfig::default(),
+        )
+        .await;
+        assert!(matches!(
+            result,
+            Err(CompressionError::UnsupportedLevel(
+                CompressionLevel::Trimmed
+            ))
+        ));
+        assert!(backend.prompts.lock().expect("prompts").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_compress_with_llm_rejection_keeps_previous_version() {
+        let backend = CannedBackend::new("I'm sorry, I can't help with that.");
+        let mut block = long_block();
+        block.compressed_versions.summarized =
+            Some(CompressionVersion::new("earlier summary".to_string()));
+
+        let result = compress_with_llm(
+            &backend,
+            &mut block,
+            CompressionLevel::Summarized,
+            &PreserveKeys::default(),
+            &QualityConfig::default(),
+        )
+        .await;
+        assert!(matches!(result, Err(CompressionError::Rejected(_))));
+        assert_eq!(
+            block.compressed_versions.summarized.map(|v| v.content),
+            Some("earlier summary".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_compress_with_llm_through_stub_server() {
+        let server = StubServer::start(
+            "/v1/messages",
+            200,
+            json!({ "content": [{ "type": "text", "text": "Websocket market data client." }] }),
+        )
+        .await;
+        let backend = AnthropicBackend::with_base_url(
+            &server.base_url,
+            "sk-ant-test",
+            "claude-haiku",
+            std::time::Duration::from_secs(5),
+        )
+        .expect("backend");
+        let mut block = long_block();
+
+        compress_with_llm(
+            &backend,
+            &mut block,
+            CompressionLevel::Minimal,
+            &PreserveKeys::default(),
+            &QualityConfig::default(),
+        )
+        .await
+        .expect("minimal");
+
+        assert_eq!(
+            block.compressed_versions.minimal.map(|v| v.content),
+            Some("Websocket market data client.".to_string())
+        );
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[test]
+    fn test_validate_output_rejects_summary_not_shorter() {
+        let result = validate_output("the same long text", CompressionLevel::Summarized, 2);
+        assert!(matches!(result, Err(CompressionError::Rejected(_))));
+    }
+
+    #[test]
+    fn test_validate_output_rejects_empty_and_overlong_minimal() {
+        assert!(validate_output("```\n```", CompressionLevel::Minimal, 100).is_err());
+        let long = "word ".repeat(60);
+        assert!(validate_output(&long, CompressionLevel::Minimal, 1000).is_err());
+    }
+}
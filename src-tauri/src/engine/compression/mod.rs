@@ -0,0 +1,155 @@
+//! Block compression.
+//!
+//! Produces the `trimmed`, `summarized` and `minimal` entries of a block's
+//! [`CompressionVersions`](super::block::CompressionVersions). The original is
+//! never modified, so every level stays reversible.
+//!
+//! `trimmed` is rule-based ([`compress_rule_based`]); `summarized` and
+//! `minimal` come from a model through a [`CompressionBackend`]
+//! ([`compress_with_llm`]). Every generated version carries a
+//! [`QualityReport`](quality::QualityReport) from [`quality::verify`].
+
+pub mod anthropic;
+pub mod backend;
+pub mod config;
+pub mod error;
+pub mod llm;
+pub mod openai;
+pub mod preserve;
+pub mod prompts;
+pub mod quality;
+pub mod queue;
+pub mod rules;
+
+use std::sync::Arc;
+
+use serde::Serialize;
+
+use super::block::{Block, CompressionVersion};
+use super::session::SharedSession;
+use super::types::CompressionLevel;
+use preserve::{PreserveKeys, PreservedKey};
+use quality::{QualityConfig, QualityReport};
+use rules::{TrimKind, TrimOptions};
+
+pub use backend::CompressionBackend;
+pub use config::SharedBackend;
+pub use error::CompressionError;
+pub use llm::compress_with_llm;
+pub use queue::{CompressionPriority, CompressionQueue};
+
+/// Run `queue`'s worker against `backend` on the current tokio runtime and
+/// store each finished compression on the matching block of `session`.
+pub fn start_worker(
+    queue: &CompressionQueue,
+    backend: Arc<dyn CompressionBackend>,
+    session: SharedSession,
+) {
+    let mut outcomes = queue.spawn_worker(backend, PreserveKeys::default());
+    tokio::spawn(async move {
+        while let Some(outcome) = outcomes.recv().await {
+            outcome.apply(&mut session.lock().blocks);
+        }
+    });
+}
+
+/// Outcome of rule-based trimming for one block.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrimReport {
+    pub block_id: String,
+    pub kind: TrimKind,
+    pub original_tokens: u32,
+    pub trimmed_tokens: u32,
+    /// Keys the trimmer was required to keep verbatim.
+    pub preserved: Vec<PreservedKey>,
+    pub quality: QualityReport,
+}
+
+/// Generate `block`'s trimmed version from its original content.
+///
+/// Deterministic and LLM-free; replaces any previous trimmed version.
+pub fn compress_rule_based(block: &mut Block, keys: &PreserveKeys) -> TrimReport {
+    compress_rule_based_with(block, keys, &TrimOptions::default())
+}
+
+/// [`compress_rule_based`] with explicit thresholds.
+pub fn compress_rule_based_with(
+    block: &mut Block,
+    keys: &PreserveKeys,
+    options: &TrimOptions,
+) -> TrimReport {
+    let kind = TrimKind::of(block);
+    let original = &block.compressed_versions.original;
+    let trimmed = rules::trim(&original.content, kind, keys, options);
+    let mut version = CompressionVersion::new(trimmed.content);
+    let quality = quality::verify(
+        &original.content,
+        original.tokens,
+        CompressionLevel::Trimmed,
+        &version,
+        keys,
+        &QualityConfig::default(),
+    );
+    version.quality = Some(quality.clone());
+
+    let report = TrimReport {
+        block_id: block.id.clone(),
+        kind,
+        original_tokens: original.tokens,
+        trimmed_tokens: version.tokens,
+        preserved: trimmed.preserved,
+        quality,
+    };
+    block.compressed_versions.trimmed = Some(version);
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::block::test_block;
+    use crate::engine::tokens::count_tokens;
+    use crate::engine::types::Role;
+
+    #[test]
+    fn test_compress_rule_based_fills_trimmed_version() {
+        let content = format!(
+            "{}\nError: disk full at src/io.rs:9",
+            "INFO ok\n".repeat(60)
+        );
+        let mut block = test_block("b1", Role::ToolResult, &content);
+        block.metadata.tool_name = Some("Bash".to_string());
+
+        let report = compress_rule_based(&mut block, &PreserveKeys::default());
+        let trimmed = block.compressed_versions.trimmed.as_ref().expect("trimmed");
+
+        assert_eq!(report.kind, TrimKind::Log);
+        assert_eq!(trimmed.tokens, count_tokens(&trimmed.content));
+        assert_eq!(report.trimmed_tokens, trimmed.tokens);
+        assert!(report.trimmed_tokens < report.original_tokens);
+        assert!(trimmed.content.ends_with("Error: disk full at src/io.rs:9"));
+        assert!(report.preserved.iter().any(|k| k.text == "src/io.rs"));
+        assert_eq!(trimmed.quality.as_ref(), Some(&report.quality));
+        assert!(
+            report.quality.issues.is_empty(),
+            "{:?}",
+            report.quality.issues
+        );
+    }
+
+    #[test]
+    fn test_compress_rule_based_leaves_original_untouched() {
+        let mut block = test_block("b2", Role::Assistant, "Done.\n\n\n\nNext step.");
+        compress_rule_based(&mut block, &PreserveKeys::default());
+
+        assert_eq!(
+            block.compressed_versions.original.content,
+            "Done.\n\n\n\nNext step."
+        );
+        assert_eq!(
+            block.compressed_versions.trimmed.map(|v| v.content),
+            Some("Done.\n\nNext step.".to_string())
+        );
+    }
+}
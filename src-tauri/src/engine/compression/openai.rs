@@ -0,0 +1,185 @@
+//! OpenAI-compatible chat completions backend.
+//!
+//! Speaks `POST /v1/chat/completions`, which covers OpenAI itself as well as
+//! local servers such as Ollama and llama.cpp.
+
+use std::time::Duration;
+
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::Deserialize;
+use serde_json::json;
+
+use super::backend::{
+    build_client, check_status, endpoint, CompressionBackend, CompressionPrompt,
+    DEFAULT_BACKEND_TIMEOUT,
+};
+use super::error::CompressionError;
+
+/// Default base URL: a local Ollama server.
+pub const DEFAULT_OPENAI_COMPATIBLE_URL: &str = "http://localhost:11434";
+
+/// Backend for any server implementing the OpenAI chat completions API.
+#[derive(Debug, Clone)]
+pub struct OpenAiCompatibleBackend {
+    client: Client,
+    /// Base URL without the `/v1` suffix, like `UpstreamConfig`.
+    base_url: String,
+    model: String,
+    api_key: Option<String>,
+}
+
+impl OpenAiCompatibleBackend {
+    pub fn new(
+        base_url: impl Into<String>,
+        model: impl Into<String>,
+    ) -> Result<Self, CompressionError> {
+        Self::with_timeout(base_url, model, DEFAULT_BACKEND_TIMEOUT)
+    }
+
+    pub fn with_timeout(
+        base_url: impl Into<String>,
+        model: impl Into<String>,
+        timeout: Duration,
+    ) -> Result<Self, CompressionError> {
+        Ok(Self {
+            client: build_client(timeout)?,
+            base_url: base_url.into(),
+            model: model.into(),
+            api_key: None,
+        })
+    }
+
+    /// Send `Authorization: Bearer <key>`; local servers usually need none.
+    pub fn with_api_key(mut self, api_key: impl Into<String>) -> Self {
+        self.api_key = Some(api_key.into());
+        self
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatResponse {
+    choices: Vec<ChatChoice>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatChoice {
+    message: ChatMessage,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatMessage {
+    content: Option<String>,
+}
+
+#[async_trait]
+impl CompressionBackend for OpenAiCompatibleBackend {
+    fn name(&self) -> &str {
+        "openai-compatible"
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    async fn complete(&self, prompt: &CompressionPrompt) -> Result<String, CompressionError> {
+        let body = json!({
+            "model": self.model,
+            "max_tokens": prompt.max_tokens,
+            "temperature": 0,
+            "stream": false,
+            "messages": [
+                { "role": "system", "content": prompt.system },
+                { "role": "user", "content": prompt.user },
+            ],
+        });
+
+        let mut request = self
+            .client
+            .post(endpoint(&self.base_url, "/v1/chat/completions"))
+            .json(&body);
+        if let Some(key) = &self.api_key {
+            request = request.bearer_auth(key);
+        }
+
+        let response = check_status(request.send().await?).await?;
+        let parsed: ChatResponse = response
+            .json()
+            .await
+            .map_err(|e| CompressionError::InvalidResponse(e.to_string()))?;
+
+        parsed
+            .choices
+            .into_iter()
+            .next()
+            .and_then(|choice| choice.message.content)
+            .ok_or_else(|| {
+                CompressionError::InvalidResponse("no message content in choices".into())
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::compression::backend::stub::StubServer;
+
+    fn prompt() -> CompressionPrompt {
+        CompressionPrompt {
+            system: "You compress context.".to_string(),
+            user: "Summarize this.".to_string(),
+            max_tokens: 64,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_complete_sends_chat_request_and_reads_content() {
+        let server = StubServer::start(
+            "/v1/chat/completions",
+            200,
+            json!({ "choices": [{ "message": { "role": "assistant", "content": "short" } }] }),
+        )
+        .await;
+        let backend = OpenAiCompatibleBackend::new(&server.base_url, "llama3.2")
+            .expect("backend")
+            .with_api_key("sk-local");
+
+        let text = backend.complete(&prompt()).await.expect("completion");
+        assert_eq!(text, "short");
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        let body = &requests[0].body;
+        assert_eq!(body["model"], "llama3.2");
+        assert_eq!(body["max_tokens"], 64);
+        assert_eq!(body["messages"][0]["role"], "system");
+        assert_eq!(body["messages"][1]["content"], "Summarize this.");
+        assert_eq!(requests[0].headers["authorization"], "Bearer sk-local");
+    }
+
+    #[tokio::test]
+    async fn test_complete_maps_error_status() {
+        let server = StubServer::start(
+            "/v1/chat/completions",
+            500,
+            json!({ "error": "model not loaded" }),
+        )
+        .await;
+        let backend = OpenAiCompatibleBackend::new(&server.base_url, "llama3.2").expect("backend");
+
+        let result = backend.complete(&prompt()).await;
+        assert!(matches!(
+            result,
+            Err(CompressionError::BackendStatus { status: 500, ref body }) if body.contains("model not loaded")
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_complete_rejects_missing_choices() {
+        let server = StubServer::start("/v1/chat/completions", 200, json!({ "choices": [] })).await;
+        let backend = OpenAiCompatibleBackend::new(&server.base_url, "llama3.2").expect("backend");
+
+        let result = backend.complete(&prompt()).await;
+        assert!(matches!(result, Err(CompressionError::InvalidResponse(_))));
+    }
+}
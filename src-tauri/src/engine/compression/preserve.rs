@@ -0,0 +1,207 @@
+//! Preserve-keys: content that compression must keep verbatim.
+//!
+//! Keys are detected per line. Rule-based trimming keeps every line that
+//! carries a key, so file paths, line references, error messages, stack
+//! frames and user keywords always survive the trimmed version.
+
+use std::sync::OnceLock;
+
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+
+/// What kind of content a preserved key is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PreserveKind {
+    FilePath,
+    LineNumber,
+    ErrorMessage,
+    StackFrame,
+    Keyword,
+}
+
+/// A span of content protected from compression.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct PreservedKey {
+    pub kind: PreserveKind,
+    pub text: String,
+}
+
+struct Patterns {
+    file_path: Regex,
+    line_number: Regex,
+    error: Regex,
+    stack_frame: Regex,
+}
+
+fn patterns() -> &'static Patterns {
+    static PATTERNS: OnceLock<Patterns> = OnceLock::new();
+    PATTERNS.get_or_init(|| Patterns {
+        // Anything with a directory separator and a file name, or a bare
+        // file name with a short extension.
+        file_path: Regex::new(
+            r"(?:[A-Za-z]:\\|~/|\.{1,2}/|/)?(?:[\w.@-]+[/\\])+[\w.@-]+\.\w{1,8}\b|\b[\w-]+\.(?:rs|ts|tsx|js|jsx|svelte|py|go|java|kt|rb|c|h|cc|cpp|hpp|cs|swift|toml|json|ya?ml|md|sh|sql|html|css)\b",
+        )
+        .expect("valid file path pattern"),
+        // `file.rs:12`, `file.rs:12:5`, `line 12`, `lines 10-20`.
+        line_number: Regex::new(r"(?i)[\w.-]+\.\w{1,8}:\d+(?::\d+)?\b|\blines? \d+(?:\s*[-–]\s*\d+)?\b")
+            .expect("valid line number pattern"),
+        error: Regex::new(
+            r"(?i)\b(?:error|errors|exception|panic|panicked|fatal|failed|failure|traceback)\b",
+        )
+        .expect("valid error pattern"),
+        // JS/Java `at fn (file:1:2)`, Python `File "x", line 3`, Rust
+        // backtrace `  3: crate::fn`, gdb/C++ `#3 0x...`.
+        stack_frame: Regex::new(
+            r#"^\s+at \S|^\s*File ".+", line \d+|^\s*\d+: [\w<][\w:<>]*::|^\s*#\d+\s+\S"#,
+        )
+        .expect("valid stack frame pattern"),
+    })
+}
+
+/// Preserve-key detector, configured with optional user keywords.
+#[derive(Debug, Clone, Default)]
+pub struct PreserveKeys {
+    keywords: Vec<String>,
+    keyword_pattern: Option<Regex>,
+}
+
+impl PreserveKeys {
+    /// Detector that also protects `keywords` (matched case-insensitively).
+    pub fn new<I, S>(keywords: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let keywords: Vec<String> = keywords
+            .into_iter()
+            .map(Into::into)
+            .filter(|k| !k.trim().is_empty())
+            .collect();
+        let keyword_pattern = (!keywords.is_empty()).then(|| {
+            let alternation = keywords
+                .iter()
+                .map(|k| regex::escape(k.trim()))
+                .collect::<Vec<_>>()
+                .join("|");
+            RegexBuilder::new(&alternation)
+                .case_insensitive(true)
+                .build()
+                .expect("escaped keywords form a valid pattern")
+        });
+        Self {
+            keywords,
+            keyword_pattern,
+        }
+    }
+
+    pub fn keywords(&self) -> &[String] {
+        &self.keywords
+    }
+
+    /// Keys found in a single line, in detection order.
+    pub fn scan_line(&self, line: &str) -> Vec<PreservedKey> {
+        let p = patterns();
+        let mut keys = Vec::new();
+        let mut push = |kind, text: &str| {
+            keys.push(PreservedKey {
+                kind,
+                text: text.trim().to_string(),
+            })
+        };
+
+        if p.stack_frame.is_match(line) {
+            push(PreserveKind::StackFrame, line);
+        } else if p.error.is_match(line) {
+            push(PreserveKind::ErrorMessage, line);
+        }
+        for m in p.line_number.find_iter(line) {
+            push(PreserveKind::LineNumber, m.as_str());
+        }
+        for m in p.file_path.find_iter(line) {
+            push(PreserveKind::FilePath, m.as_str());
+        }
+        if let Some(pattern) = &self.keyword_pattern {
+            for m in pattern.find_iter(line) {
+                push(PreserveKind::Keyword, m.as_str());
+            }
+        }
+        keys
+    }
+
+    /// Whether `line` carries any key and must be kept.
+    pub fn is_protected(&self, line: &str) -> bool {
+        let p = patterns();
+        p.stack_frame.is_match(line)
+            || p.error.is_match(line)
+            || p.line_number.is_match(line)
+            || p.file_path.is_match(line)
+            || self
+                .keyword_pattern
+                .as_ref()
+                .is_some_and(|pattern| pattern.is_match(line))
+    }
+
+    /// Distinct keys in `text`, in first-seen order.
+    pub fn scan(&self, text: &str) -> Vec<PreservedKey> {
+        let mut seen = std::collections::HashSet::new();
+        text.lines()
+            .flat_map(|line| self.scan_line(line))
+            .filter(|key| seen.insert(key.clone()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(keys: &[PreservedKey]) -> Vec<PreserveKind> {
+        keys.iter().map(|k| k.kind).collect()
+    }
+
+    #[test]
+    fn test_scan_line_detects_error_path_and_line() {
+        let keys =
+            PreserveKeys::default().scan_line("error[E0308]: mismatched types at src/main.rs:42:7");
+
+        assert!(kinds(&keys).contains(&PreserveKind::ErrorMessage));
+        assert!(keys
+            .iter()
+            .any(|k| k.kind == PreserveKind::LineNumber && k.text == "main.rs:42:7"));
+        assert!(keys
+            .iter()
+            .any(|k| k.kind == PreserveKind::FilePath && k.text == "src/main.rs"));
+    }
+
+    #[test]
+    fn test_scan_line_detects_stack_frames() {
+        let keys = PreserveKeys::default();
+        for frame in [
+            "    at handler (server/index.js:10:3)",
+            "  File \"app.py\", line 12, in main",
+            "   3: aperture_lib::proxy::handler",
+        ] {
+            assert!(
+                kinds(&keys.scan_line(frame)).contains(&PreserveKind::StackFrame),
+                "{frame}"
+            );
+        }
+        assert!(!keys.is_protected("plain prose with nothing special"));
+    }
+
+    #[test]
+    fn test_keywords_match_case_insensitively() {
+        let keys = PreserveKeys::new(["MarketStream", " "]);
+        assert_eq!(keys.keywords(), ["MarketStream"]);
+
+        let found = keys.scan("the marketstream client reconnects\nunrelated");
+        assert_eq!(
+            found,
+            vec![PreservedKey {
+                kind: PreserveKind::Keyword,
+                text: "marketstream".to_string(),
+            }]
+        );
+    }
+}
@@ -0,0 +1,167 @@
+//! Prompt templates for model-generated compression levels.
+//!
+//! The template is chosen by the block's role and, for tool results, the
+//! same [`TrimKind`] the rule-based trimmer uses, so a file read is described
+//! by its API and a log by its outcome.
+
+use super::backend::CompressionPrompt;
+use super::preserve::PreserveKeys;
+use super::rules::TrimKind;
+use crate::engine::block::Block;
+use crate::engine::types::{CompressionLevel, Role};
+
+/// Preserve keys listed in a summary prompt before the rest are elided.
+const MAX_PROMPT_KEYS: usize = 20;
+
+/// Output budget for a minimal one-line description.
+pub const MINIMAL_MAX_TOKENS: u32 = 48;
+
+/// Lower and upper bounds on a summary's output budget.
+const SUMMARY_MIN_TOKENS: u32 = 64;
+const SUMMARY_MAX_TOKENS: u32 = 1024;
+
+const SYSTEM_PROMPT: &str = "You compress conversation context for another AI model. \
+Reply with the compressed text only: no preamble, no labels, no code fences. \
+Never invent details that are not in the input.";
+
+/// What a block is, and what a summary of it must keep.
+struct Template {
+    subject: &'static str,
+    focus: &'static str,
+}
+
+fn template(block: &Block) -> Template {
+    match (block.role, TrimKind::of(block)) {
+        (_, TrimKind::Log) => Template {
+            subject: "log output",
+            focus: "the final outcome, every error and warning, and the steps that led to them",
+        },
+        (Role::ToolResult, TrimKind::FileRead) => Template {
+            subject: "file contents",
+            focus: "the file's purpose and its public types, functions and their signatures",
+        },
+        (Role::ToolResult, _) => Template {
+            subject: "tool output",
+            focus: "the outcome, key facts and numbers, and anything that failed",
+        },
+        (Role::ToolUse, _) => Template {
+            subject: "tool call",
+            focus: "which tool was called and the arguments that matter",
+        },
+        (Role::System, _) => Template {
+            subject: "system prompt",
+            focus: "every instruction, constraint and rule",
+        },
+        (Role::User, _) => Template {
+            subject: "user message",
+            focus: "what the user asked for, their constraints and any specifics they gave",
+        },
+        (Role::Assistant, _) => Template {
+            subject: "assistant message",
+            focus: "decisions made, conclusions reached, changes performed and open questions",
+        },
+    }
+}
+
+/// The text a model compresses: the trimmed version when available.
+pub fn source_text(block: &Block) -> &str {
+    let versions = &block.compressed_versions;
+    versions
+        .trimmed
+        .as_ref()
+        .unwrap_or(&versions.original)
+        .content
+        .as_str()
+}
+
+/// Output token budget for `level` given the source size.
+pub fn max_tokens(level: CompressionLevel, source_tokens: u32) -> u32 {
+    match level {
+        CompressionLevel::Minimal => MINIMAL_MAX_TOKENS,
+        _ => (source_tokens / 4).clamp(SUMMARY_MIN_TOKENS, SUMMARY_MAX_TOKENS),
+    }
+}
+
+/// Build the prompt that produces `level` for `block`.
+///
+/// Only `Summarized` and `Minimal` are model-generated; other levels are
+/// rejected by [`super::llm::compress_with_llm`] before reaching here.
+pub fn build_prompt(
+    block: &Block,
+    level: CompressionLevel,
+    keys: &PreserveKeys,
+    source_tokens: u32,
+) -> CompressionPrompt {
+    let Template { subject, focus } = template(block);
+    let source = source_text(block);
+
+    let user = match level {
+        CompressionLevel::Minimal => format!(
+            "Describe this {subject} in one line of at most 15 words.\n\n<content>\n{source}\n</content>"
+        ),
+        _ => {
+            let mut instructions = format!(
+                "Summarize this {subject} in at most a quarter of its length. Keep {focus}."
+            );
+            let preserved = keys.scan(source);
+            if !preserved.is_empty() {
+                instructions.push_str("\nCopy these verbatim wherever they are relevant:");
+                for key in preserved.iter().take(MAX_PROMPT_KEYS) {
+                    instructions.push_str("\n- ");
+                    instructions.push_str(&key.text);
+                }
+            }
+            format!("{instructions}\n\n<content>\n{source}\n</content>")
+        }
+    };
+
+    CompressionPrompt {
+        system: SYSTEM_PROMPT.to_string(),
+        user,
+        max_tokens: max_tokens(level, source_tokens),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::block::{test_block, CompressionVersion};
+
+    #[test]
+    fn test_build_prompt_picks_template_by_role_and_tool() {
+        let mut read = test_block("r", Role::ToolResult, "pub fn connect() {}");
+        read.metadata.tool_name = Some("Read".to_string());
+        let user = test_block("u", Role::User, "please add retries");
+
+        let keys = PreserveKeys::default();
+        let read_prompt = build_prompt(&read, CompressionLevel::Summarized, &keys, 400);
+        let user_prompt = build_prompt(&user, CompressionLevel::Minimal, &keys, 10);
+
+        assert!(read_prompt.user.contains("file contents"));
+        assert!(read_prompt.user.contains("signatures"));
+        assert_eq!(read_prompt.max_tokens, 100);
+        assert!(user_prompt
+            .user
+            .starts_with("Describe this user message in one line"));
+        assert_eq!(user_prompt.max_tokens, MINIMAL_MAX_TOKENS);
+    }
+
+    #[test]
+    fn test_build_prompt_lists_preserved_keys_and_uses_trimmed_source() {
+        let mut block = test_block("t", Role::ToolResult, "noisy original");
+        block.compressed_versions.trimmed = Some(CompressionVersion::new(
+            "error: timeout in src/net.rs:40".to_string(),
+        ));
+
+        let prompt = build_prompt(
+            &block,
+            CompressionLevel::Summarized,
+            &PreserveKeys::default(),
+            10,
+        );
+        assert!(prompt.user.contains("- src/net.rs"));
+        assert!(prompt.user.contains("<content>\nerror: timeout"));
+        assert!(!prompt.user.contains("noisy original"));
+        assert_eq!(prompt.max_tokens, SUMMARY_MIN_TOKENS);
+    }
+}
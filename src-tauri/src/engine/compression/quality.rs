@@ -0,0 +1,442 @@
+//! Compression quality verification.
+//!
+//! Every generated version is scored before it is stored. Heuristics check
+//! that preserved keys survived, that the compression ratio is plausible and
+//! that code fences and lists were not mangled; a model self-check can lower
+//! the score further. Versions below [`QualityConfig::min_confidence`] are
+//! kept for the user to review but never picked automatically.
+
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+
+use super::backend::{CompressionBackend, CompressionPrompt};
+use super::error::CompressionError;
+use super::preserve::{PreserveKeys, PreserveKind, PreservedKey};
+use super::rules::visible_text;
+use crate::engine::block::CompressionVersion;
+use crate::engine::types::CompressionLevel;
+
+/// Confidence ceiling when an error message or stack frame was lost.
+const LOST_ERROR_CEILING: f32 = 0.4;
+
+/// Share of an error line's distinctive words a summary must repeat for the
+/// error to count as kept.
+const PARAPHRASE_COVERAGE: f32 = 0.6;
+
+const MISSING_KEYS_PENALTY: f32 = 0.5;
+const HIGH_RATIO_PENALTY: f32 = 0.15;
+const BROKEN_FENCE_PENALTY: f32 = 0.3;
+const STRUCTURE_LOST_PENALTY: f32 = 0.2;
+
+/// Verifier thresholds.
+#[derive(Debug, Clone)]
+pub struct QualityConfig {
+    /// Compression ratios above this are flagged.
+    pub max_ratio: f32,
+    /// Versions scoring below this are blocked from automatic use.
+    pub min_confidence: f32,
+    /// Also ask the compression model to rate its own output.
+    pub self_check: bool,
+}
+
+impl Default for QualityConfig {
+    fn default() -> Self {
+        Self {
+            max_ratio: 15.0,
+            min_confidence: 0.6,
+            self_check: false,
+        }
+    }
+}
+
+/// A problem the verifier found with a compressed version.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum QualityIssue {
+    /// Preserved keys that do not appear in the compressed text.
+    MissingKeys { keys: Vec<PreservedKey> },
+    /// Original-to-compressed token ratio above the configured maximum.
+    HighRatio { ratio: f32 },
+    /// An odd number of code fence lines.
+    BrokenCodeFence,
+    /// A structural element present in the source disappeared.
+    StructureLost { element: String },
+    /// The model's self-check rated the version poorly.
+    SelfCheck { score: f32 },
+}
+
+/// Verifier result stored on a [`CompressionVersion`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QualityReport {
+    /// 0.0 (unusable) to 1.0 (no issues found).
+    pub confidence: f32,
+    /// Original tokens per compressed token.
+    pub ratio: f32,
+    #[serde(default)]
+    pub issues: Vec<QualityIssue>,
+}
+
+impl QualityReport {
+    /// Whether a version with this report may be used without review.
+    pub fn is_auto_usable(&self, config: &QualityConfig) -> bool {
+        self.confidence >= config.min_confidence
+    }
+}
+
+/// Score `version` as a compression of `original` at `level`.
+///
+/// Minimal versions are one-line descriptions, so only their code fences
+/// are checked; keys, ratio and structure apply to trimmed and summarized.
+pub fn verify(
+    original: &str,
+    original_tokens: u32,
+    level: CompressionLevel,
+    version: &CompressionVersion,
+    keys: &PreserveKeys,
+    config: &QualityConfig,
+) -> QualityReport {
+    let ratio = original_tokens as f32 / version.tokens.max(1) as f32;
+    let mut confidence = 1.0f32;
+    let mut issues = Vec::new();
+
+    if !fence_count(&version.content).is_multiple_of(2) {
+        confidence -= BROKEN_FENCE_PENALTY;
+        issues.push(QualityIssue::BrokenCodeFence);
+    }
+
+    if level != CompressionLevel::Minimal {
+        let source = visible_text(original, keys);
+        let expected = keys.scan(&source);
+        let missing: Vec<PreservedKey> = expected
+            .iter()
+            .filter(|key| !key_survives(key, level, &version.content))
+            .cloned()
+            .collect();
+        if !missing.is_empty() {
+            let weight = |k: &PreservedKey| key_weight(k.kind);
+            let total: f32 = expected.iter().map(weight).sum();
+            let lost: f32 = missing.iter().map(weight).sum();
+            confidence -= MISSING_KEYS_PENALTY * lost / total;
+            if missing.iter().any(|k| {
+                matches!(
+                    k.kind,
+                    PreserveKind::ErrorMessage | PreserveKind::StackFrame
+                )
+            }) {
+                confidence = confidence.min(LOST_ERROR_CEILING);
+            }
+            issues.push(QualityIssue::MissingKeys { keys: missing });
+        }
+
+        if ratio > config.max_ratio {
+            confidence -= HIGH_RATIO_PENALTY;
+            issues.push(QualityIssue::HighRatio { ratio });
+        }
+
+        for element in lost_structure(&source, &version.content, level) {
+            confidence -= STRUCTURE_LOST_PENALTY;
+            issues.push(QualityIssue::StructureLost {
+                element: element.to_string(),
+            });
+        }
+    }
+
+    QualityReport {
+        confidence: confidence.clamp(0.0, 1.0),
+        ratio,
+        issues,
+    }
+}
+
+/// Fold a model self-check score (0.0-1.0) into `report`.
+///
+/// The lower of the two scores wins; a self-check can only lower confidence.
+pub fn apply_self_check(report: &mut QualityReport, score: f32) {
+    let score = score.clamp(0.0, 1.0);
+    if score < report.confidence {
+        report.confidence = score;
+        report.issues.push(QualityIssue::SelfCheck { score });
+    }
+}
+
+/// Ask `backend` how well `compressed` preserves `original`, as 0.0-1.0.
+pub async fn self_check(
+    backend: &dyn CompressionBackend,
+    original: &str,
+    compressed: &str,
+) -> Result<f32, CompressionError> {
+    let prompt = CompressionPrompt {
+        system: "You grade compressions of conversation context. Reply with a single \
+integer from 0 to 100 and nothing else."
+            .to_string(),
+        user: format!(
+            "How completely does the compressed text preserve the key information of the \
+original, especially errors, file paths and decisions?\n\n<original>\n{original}\n</original>\n\n\
+<compressed>\n{compressed}\n</compressed>"
+        ),
+        max_tokens: 8,
+    };
+    let reply = backend.complete(&prompt).await?;
+    parse_score(&reply).ok_or_else(|| {
+        CompressionError::InvalidResponse(format!("self-check reply has no score: {reply:?}"))
+    })
+}
+
+/// First integer in `reply`, read as a percentage.
+fn parse_score(reply: &str) -> Option<f32> {
+    let digits: String = reply
+        .chars()
+        .skip_while(|c| !c.is_ascii_digit())
+        .take_while(char::is_ascii_digit)
+        .collect();
+    let value: u32 = digits.parse().ok()?;
+    (value <= 100).then(|| value as f32 / 100.0)
+}
+
+fn key_weight(kind: PreserveKind) -> f32 {
+    match kind {
+        PreserveKind::ErrorMessage => 3.0,
+        PreserveKind::StackFrame | PreserveKind::Keyword => 2.0,
+        PreserveKind::FilePath | PreserveKind::LineNumber => 1.0,
+    }
+}
+
+/// Whether `key` is still present in `compressed`.
+///
+/// Trimmed text must keep every key verbatim. A summary may paraphrase an
+/// error line or stack frame as long as most of its distinctive words stay.
+fn key_survives(key: &PreservedKey, level: CompressionLevel, compressed: &str) -> bool {
+    if compressed.contains(&key.text) {
+        return true;
+    }
+    if level == CompressionLevel::Trimmed
+        || !matches!(
+            key.kind,
+            PreserveKind::ErrorMessage | PreserveKind::StackFrame
+        )
+    {
+        return false;
+    }
+
+    let words: HashSet<String> = distinctive_words(&key.text).collect();
+    if words.is_empty() {
+        return false;
+    }
+    let present: HashSet<String> = distinctive_words(compressed).collect();
+    let kept = words.iter().filter(|w| present.contains(*w)).count();
+    kept as f32 / words.len() as f32 >= PARAPHRASE_COVERAGE
+}
+
+fn distinctive_words(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric() && c != '_')
+        .filter(|w| w.len() >= 4)
+        .map(str::to_lowercase)
+}
+
+fn fence_count(text: &str) -> usize {
+    text.lines()
+        .filter(|line| line.trim_start().starts_with("```"))
+        .count()
+}
+
+fn list_items(text: &str) -> usize {
+    text.lines()
+        .map(str::trim_start)
+        .filter(|line| {
+            line.starts_with("- ")
+                || line.starts_with("* ")
+                || line
+                    .split_once(". ")
+                    .is_some_and(|(n, _)| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()))
+        })
+        .count()
+}
+
+/// Code blocks and lists present in `source` but gone from `compressed`.
+///
+/// Trimming must keep them; a summary may drop code but should keep at
+/// least some list structure when the source was mostly a list.
+fn lost_structure(source: &str, compressed: &str, level: CompressionLevel) -> Vec<&'static str> {
+    let mut lost = Vec::new();
+    let source_fences = fence_count(source);
+    if level == CompressionLevel::Trimmed
+        && source_fences >= 2
+        && fence_count(compressed) < source_fences
+    {
+        lost.push("code block");
+    }
+    let source_items = list_items(source);
+    if source_items >= 3 && list_items(compressed) == 0 {
+        lost.push("list");
+    }
+    lost
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(content: &str) -> CompressionVersion {
+        CompressionVersion::new(content.to_string())
+    }
+
+    fn verify_default(original: &str, level: CompressionLevel, compressed: &str) -> QualityReport {
+        let original_tokens = crate::engine::tokens::count_tokens(original);
+        verify(
+            original,
+            original_tokens,
+            level,
+            &version(compressed),
+            &PreserveKeys::default(),
+            &QualityConfig::default(),
+        )
+    }
+
+    #[test]
+    fn test_verify_clean_summary_scores_full_confidence() {
+        let original = "Ran the migration against staging. It applied cleanly and \
+the new index sped up the dashboard query from 800ms to 40ms."
+            .repeat(3);
+        let report = verify_default(
+            &original,
+            CompressionLevel::Summarized,
+            "Migration applied; index cut query to 40ms.",
+        );
+
+        assert_eq!(report.issues, vec![]);
+        assert!((report.confidence - 1.0).abs() < f32::EPSILON);
+        assert!(report.ratio > 1.0);
+    }
+
+    #[test]
+    fn test_verify_dropped_error_line_blocks_auto_use() {
+        let original = format!(
+            "{}\nError: connection refused (os error 111)\n{}",
+            "compiling crate\n".repeat(10),
+            "done\n".repeat(5)
+        );
+        let report = verify_default(
+            &original,
+            CompressionLevel::Summarized,
+            "Build compiled and finished.",
+        );
+
+        assert!(report.confidence <= LOST_ERROR_CEILING);
+        assert!(!report.is_auto_usable(&QualityConfig::default()));
+        assert!(matches!(
+            &report.issues[0],
+            QualityIssue::MissingKeys { keys } if keys[0].kind == PreserveKind::ErrorMessage
+        ));
+    }
+
+    #[test]
+    fn test_verify_accepts_paraphrased_error_in_summary_only() {
+        let original = "step one\nError: connection refused by upstream proxy\nstep two";
+        let summary = "The upstream proxy refused the connection during step one.";
+
+        let summarized = verify_default(original, CompressionLevel::Summarized, summary);
+        assert!(summarized.issues.is_empty(), "{:?}", summarized.issues);
+
+        let trimmed = verify_default(original, CompressionLevel::Trimmed, summary);
+        assert!(!trimmed.is_auto_usable(&QualityConfig::default()));
+    }
+
+    #[test]
+    fn test_verify_flags_high_ratio() {
+        let original = "The service reads configuration from disk on startup. ".repeat(40);
+        let report = verify_default(&original, CompressionLevel::Summarized, "Reads config.");
+
+        assert!(matches!(
+            report.issues[..],
+            [QualityIssue::HighRatio { .. }]
+        ));
+        assert!(report.is_auto_usable(&QualityConfig::default()));
+    }
+
+    #[test]
+    fn test_verify_flags_broken_fence_and_lost_list() {
+        let original = "Steps:\n- build the image\n- push it\n- deploy\n```sh\nmake\n```";
+        let report = verify_default(
+            original,
+            CompressionLevel::Trimmed,
+            "Steps: build, push, deploy\n```sh\nmake",
+        );
+
+        assert!(report.issues.contains(&QualityIssue::BrokenCodeFence));
+        assert!(report.issues.contains(&QualityIssue::StructureLost {
+            element: "list".to_string()
+        }));
+        assert!(report.issues.contains(&QualityIssue::StructureLost {
+            element: "code block".to_string()
+        }));
+        assert!(!report.is_auto_usable(&QualityConfig::default()));
+    }
+
+    #[test]
+    fn test_verify_minimal_skips_key_checks() {
+        let original = "Error: disk full at src/io.rs:9\n".repeat(5);
+        let report = verify_default(
+            &original,
+            CompressionLevel::Minimal,
+            "Disk-full failure in IO module",
+        );
+        assert!(report.issues.is_empty());
+    }
+
+    #[test]
+    fn test_auto_usable_withholds_low_confidence_versions() {
+        let mut block = crate::engine::block::test_block(
+            "b",
+            crate::engine::types::Role::ToolResult,
+            "Error: disk full at src/io.rs:9",
+        );
+        let mut summary = version("Ran out of space.");
+        summary.quality = Some(verify_default(
+            "Error: disk full at src/io.rs:9",
+            CompressionLevel::Summarized,
+            &summary.content,
+        ));
+        block.compressed_versions.summarized = Some(summary);
+        block.compressed_versions.minimal = Some(version("Disk-full error"));
+
+        let versions = &block.compressed_versions;
+        let config = QualityConfig::default();
+        assert!(versions
+            .auto_usable(CompressionLevel::Summarized, &config)
+            .is_none());
+        assert!(versions
+            .auto_usable(CompressionLevel::Minimal, &config)
+            .is_some());
+        assert!(versions
+            .auto_usable(CompressionLevel::Original, &config)
+            .is_some());
+        assert!(versions
+            .auto_usable(CompressionLevel::Trimmed, &config)
+            .is_none());
+    }
+
+    #[test]
+    fn test_apply_self_check_only_lowers_confidence() {
+        let mut report = QualityReport {
+            confidence: 0.8,
+            ratio: 4.0,
+            issues: Vec::new(),
+        };
+        apply_self_check(&mut report, 0.95);
+        assert!((report.confidence - 0.8).abs() < f32::EPSILON);
+
+        apply_self_check(&mut report, 0.3);
+        assert!((report.confidence - 0.3).abs() < f32::EPSILON);
+        assert_eq!(report.issues, vec![QualityIssue::SelfCheck { score: 0.3 }]);
+    }
+
+    #[test]
+    fn test_parse_score_reads_first_integer() {
+        assert_eq!(parse_score("85"), Some(0.85));
+        assert_eq!(parse_score("Score: 40/100"), Some(0.4));
+        assert_eq!(parse_score("none"), None);
+        assert_eq!(parse_score("250"), None);
+    }
+}
@@ -0,0 +1,997 @@
+//! Background compression queue.
+//!
+//! Model compression is slow and must never block the proxy or the UI, so
+//! requests are queued and run on a tokio worker. Tasks are keyed by block id
+//! and level: enqueueing the same pair again only refreshes the snapshot and
+//! raises the priority, and a pair enqueued while it runs is compressed again
+//! from the newer snapshot once the current run finishes. New tasks wait out
+//! a short batch window so a burst of requests is dispatched in priority
+//! order. The worker runs up to `concurrency` tasks at a time, retries
+//! transient failures with exponential backoff, and reports every step as an
+//! [`ApertureEvent`]. Events are emitted after the queue lock is released.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, Notify};
+use tokio::task::{AbortHandle, JoinSet};
+use tokio::time::Instant;
+use tracing::{debug, warn};
+
+use super::backend::CompressionBackend;
+use super::error::CompressionError;
+use super::llm::compress_with_llm;
+use super::preserve::PreserveKeys;
+use super::quality::QualityConfig;
+use crate::engine::block::{Block, CompressionVersion};
+use crate::engine::types::CompressionLevel;
+use crate::events::types::ApertureEvent;
+use crate::events::EventSink;
+
+/// Why a compression was requested; higher runs first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CompressionPriority {
+    /// Precomputed ahead of need.
+    Speculative,
+    /// Requested by the budget policy to relieve context pressure.
+    Pressure,
+    /// Requested explicitly by the user.
+    User,
+}
+
+/// Lifecycle of a queued compression task.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CompressionTaskStatus {
+    Queued,
+    Running,
+    /// Failed transiently; queued again after a backoff.
+    Retrying,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+/// Worker tuning.
+#[derive(Debug, Clone)]
+pub struct QueueConfig {
+    /// Tasks sent to the backend at once.
+    pub concurrency: usize,
+    /// Attempts per task, including the first.
+    pub max_attempts: u32,
+    /// Delay before the first retry; doubled for each further retry.
+    pub base_backoff: Duration,
+    pub max_backoff: Duration,
+    /// How long a new task waits so requests arriving together are
+    /// dispatched together, highest priority first.
+    pub batch_window: Duration,
+    /// Verifier thresholds applied to every generated version.
+    pub quality: QualityConfig,
+}
+
+impl Default for QueueConfig {
+    fn default() -> Self {
+        Self {
+            concurrency: 4,
+            max_attempts: 3,
+            base_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            batch_window: Duration::from_millis(50),
+            quality: QualityConfig::default(),
+        }
+    }
+}
+
+impl QueueConfig {
+    /// Backoff after `attempt` (1-based) failed.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        self.base_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+}
+
+/// A finished compression, ready to be stored on the live block.
+#[derive(Debug, Clone)]
+pub struct CompressionOutcome {
+    pub block_id: String,
+    pub level: CompressionLevel,
+    pub version: CompressionVersion,
+    /// Original content the version was generated from.
+    pub source: String,
+}
+
+impl CompressionOutcome {
+    /// Store the version on the matching block in `blocks`.
+    ///
+    /// Skipped, returning `false`, if the block is gone or its original
+    /// changed after the snapshot was taken.
+    pub fn apply(self, blocks: &mut [Block]) -> bool {
+        let Some(block) = blocks.iter_mut().find(|b| b.id == self.block_id) else {
+            return false;
+        };
+        let versions = &mut block.compressed_versions;
+        if versions.original.content != self.source {
+            debug!(
+                "Dropping stale {:?} compression of {}",
+                self.level, self.block_id
+            );
+            return false;
+        }
+        match self.level {
+            CompressionLevel::Minimal => versions.minimal = Some(self.version),
+            _ => versions.summarized = Some(self.version),
+        }
+        true
+    }
+}
+
+type TaskKey = (String, CompressionLevel);
+
+#[derive(Debug)]
+struct PendingTask {
+    block: Block,
+    priority: CompressionPriority,
+    /// Insertion order, so equal priorities run first come, first served.
+    seq: u64,
+    /// Attempts already made.
+    attempts: u32,
+    not_before: Instant,
+}
+
+#[derive(Debug)]
+struct RunningTask {
+    priority: CompressionPriority,
+    attempt: u32,
+    abort: AbortHandle,
+    /// Newer snapshot enqueued while this task ran, with its priority.
+    resubmit: Option<(Block, CompressionPriority)>,
+}
+
+#[derive(Debug, Default)]
+struct QueueState {
+    pending: HashMap<TaskKey, PendingTask>,
+    running: HashMap<TaskKey, RunningTask>,
+    next_seq: u64,
+    /// When the current batch window closes; tasks queued before then are
+    /// released together.
+    batch_until: Option<Instant>,
+    last_depth: Option<(u32, u32)>,
+    shutdown: bool,
+}
+
+impl QueueState {
+    /// Highest-priority task whose backoff has elapsed.
+    fn take_ready(&mut self, now: Instant) -> Option<(TaskKey, PendingTask)> {
+        let key = self
+            .pending
+            .iter()
+            .filter(|(_, task)| task.not_before <= now)
+            .max_by(|(_, a), (_, b)| a.priority.cmp(&b.priority).then(b.seq.cmp(&a.seq)))
+            .map(|(key, _)| key.clone())?;
+        self.pending.remove_entry(&key)
+    }
+
+    fn next_ready_at(&self) -> Option<Instant> {
+        self.pending.values().map(|task| task.not_before).min()
+    }
+
+    fn depth(&self) -> (u32, u32) {
+        let count = |n: usize| u32::try_from(n).unwrap_or(u32::MAX);
+        (count(self.pending.len()), count(self.running.len()))
+    }
+
+    fn push(
+        &mut self,
+        key: TaskKey,
+        block: Block,
+        priority: CompressionPriority,
+        attempts: u32,
+        not_before: Instant,
+    ) {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.pending.insert(
+            key,
+            PendingTask {
+                block,
+                priority,
+                seq,
+                attempts,
+                not_before,
+            },
+        );
+    }
+
+    /// Queue depth event, if the depth changed since the last one.
+    fn depth_event(&mut self) -> Option<ApertureEvent> {
+        let depth = self.depth();
+        if self.last_depth == Some(depth) {
+            return None;
+        }
+        self.last_depth = Some(depth);
+        Some(ApertureEvent::CompressionQueueChanged {
+            pending: depth.0,
+            running: depth.1,
+        })
+    }
+}
+
+struct Inner {
+    state: Mutex<QueueState>,
+    wake: Notify,
+    config: QueueConfig,
+    sink: EventSink,
+}
+
+/// Handle to the compression queue; clones share the same queue.
+#[derive(Clone)]
+pub struct CompressionQueue {
+    inner: Arc<Inner>,
+}
+
+impl CompressionQueue {
+    pub fn new(config: QueueConfig, sink: EventSink) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                state: Mutex::new(QueueState::default()),
+                wake: Notify::new(),
+                config,
+                sink,
+            }),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, QueueState> {
+        self.inner
+            .state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Queue `level` for a snapshot of `block`.
+    ///
+    /// Returns `false` if the same block and level was already queued or
+    /// running. A queued duplicate takes the newer snapshot and the higher
+    /// of the two priorities; a running one keeps them and is queued again
+    /// when the current run finishes.
+    pub fn enqueue(
+        &self,
+        block: Block,
+        level: CompressionLevel,
+        priority: CompressionPriority,
+    ) -> bool {
+        let key = (block.id.clone(), level);
+        let mut state = self.state();
+        if let Some(task) = state.running.get_mut(&key) {
+            let priority = match task.resubmit.take() {
+                Some((_, queued)) => queued.max(priority),
+                None => priority,
+            };
+            task.resubmit = Some((block, priority));
+            return false;
+        }
+        if let Some(task) = state.pending.get_mut(&key) {
+            task.block = block;
+            task.priority = task.priority.max(priority);
+            return false;
+        }
+
+        let now = Instant::now();
+        let not_before = match state.batch_until {
+            Some(until) if until > now => until,
+            _ => now + self.inner.config.batch_window,
+        };
+        state.batch_until = Some(not_before);
+        state.push(key.clone(), block, priority, 0, not_before);
+        let events = [
+            Some(progress(
+                &key,
+                priority,
+                CompressionTaskStatus::Queued,
+                1,
+                None,
+            )),
+            state.depth_event(),
+        ];
+        drop(state);
+
+        self.emit(events.into_iter().flatten());
+        self.inner.wake.notify_one();
+        true
+    }
+
+    /// Drop every queued or running task for a removed block.
+    ///
+    /// Running backend calls are aborted and their results discarded.
+    /// Returns how many tasks were cancelled.
+    pub fn cancel_block(&self, block_id: &str) -> usize {
+        let mut events = Vec::new();
+        let mut state = self.state();
+        let pending: Vec<_> = state
+            .pending
+            .keys()
+            .filter(|(id, _)| id == block_id)
+            .cloned()
+            .collect();
+        let running: Vec<_> = state
+            .running
+            .keys()
+            .filter(|(id, _)| id == block_id)
+            .cloned()
+            .collect();
+
+        for key in &pending {
+            if let Some(task) = state.pending.remove(key) {
+                events.push(progress(
+                    key,
+                    task.priority,
+                    CompressionTaskStatus::Cancelled,
+                    task.attempts + 1,
+                    None,
+                ));
+            }
+        }
+        for key in &running {
+            if let Some(task) = state.running.remove(key) {
+                task.abort.abort();
+                events.push(progress(
+                    key,
+                    task.priority,
+                    CompressionTaskStatus::Cancelled,
+                    task.attempt,
+                    None,
+                ));
+            }
+        }
+
+        let cancelled = pending.len() + running.len();
+        if cancelled > 0 {
+            debug!("Cancelled {cancelled} compression task(s) for block {block_id}");
+            events.extend(state.depth_event());
+        }
+        drop(state);
+
+        self.emit(events);
+        cancelled
+    }
+
+    /// Number of queued and running tasks.
+    pub fn depth(&self) -> (u32, u32) {
+        self.state().depth()
+    }
+
+    /// Stop the worker; running tasks are aborted.
+    pub fn shutdown(&self) {
+        self.state().shutdown = true;
+        self.inner.wake.notify_one();
+    }
+
+    /// Start the worker on the current tokio runtime.
+    ///
+    /// Finished compressions arrive on the returned channel; the owner of the
+    /// live blocks applies them.
+    pub fn spawn_worker(
+        &self,
+        backend: Arc<dyn CompressionBackend>,
+        keys: PreserveKeys,
+    ) -> mpsc::UnboundedReceiver<CompressionOutcome> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let queue = self.clone();
+        tokio::spawn(async move { queue.run(backend, Arc::new(keys), tx).await });
+        rx
+    }
+
+    async fn run(
+        self,
+        backend: Arc<dyn CompressionBackend>,
+        keys: Arc<PreserveKeys>,
+        outcomes: mpsc::UnboundedSender<CompressionOutcome>,
+    ) {
+        type TaskResult = (
+            TaskKey,
+            Block,
+            u32,
+            Result<CompressionVersion, CompressionError>,
+        );
+        let mut tasks: JoinSet<TaskResult> = JoinSet::new();
+
+        loop {
+            let mut events = Vec::new();
+            let next_ready_at = {
+                let mut state = self.state();
+                if state.shutdown {
+                    break;
+                }
+                while tasks.len() < self.inner.config.concurrency.max(1) {
+                    let Some((key, task)) = state.take_ready(Instant::now()) else {
+                        break;
+                    };
+                    let attempt = task.attempts + 1;
+                    events.push(progress(
+                        &key,
+                        task.priority,
+                        CompressionTaskStatus::Running,
+                        attempt,
+                        None,
+                    ));
+
+                    let backend = Arc::clone(&backend);
+                    let keys = Arc::clone(&keys);
+                    let quality = self.inner.config.quality.clone();
+                    let task_key = key.clone();
+                    let mut block = task.block;
+                    let abort = tasks.spawn(async move {
+                        let snapshot = block.clone();
+                        let result = compress_with_llm(
+                            backend.as_ref(),
+                            &mut block,
+                            task_key.1,
+                            &keys,
+                            &quality,
+                        )
+                        .await;
+                        (task_key, snapshot, attempt, result)
+                    });
+                    state.running.insert(
+                        key,
+                        RunningTask {
+                            priority: task.priority,
+                            attempt,
+                            abort,
+                            resubmit: None,
+                        },
+                    );
+                }
+                events.extend(state.depth_event());
+                state.next_ready_at()
+            };
+            self.emit(events);
+
+            let backoff = async {
+                match next_ready_at {
+                    Some(at) => tokio::time::sleep_until(at).await,
+                    None => std::future::pending().await,
+                }
+            };
+
+            tokio::select! {
+                Some(joined) = tasks.join_next(), if !tasks.is_empty() => {
+                    // Aborted tasks were already reported as cancelled.
+                    if let Ok((key, block, attempt, result)) = joined {
+                        self.finish(key, block, attempt, result, &outcomes);
+                    }
+                }
+                _ = self.inner.wake.notified() => {}
+                _ = backoff => {}
+            }
+        }
+
+        tasks.abort_all();
+        debug!("Compression queue worker stopped");
+    }
+
+    fn finish(
+        &self,
+        key: TaskKey,
+        block: Block,
+        attempt: u32,
+        result: Result<CompressionVersion, CompressionError>,
+        outcomes: &mpsc::UnboundedSender<CompressionOutcome>,
+    ) {
+        let mut events = Vec::new();
+        let mut state = self.state();
+        // Cancelled while the backend call was completing.
+        let Some(running) = state.running.remove(&key) else {
+            return;
+        };
+        let priority = running.priority;
+        let mut resubmit = running.resubmit;
+
+        match result {
+            Ok(version) => {
+                events.push(progress(
+                    &key,
+                    priority,
+                    CompressionTaskStatus::Completed,
+                    attempt,
+                    None,
+                ));
+                let _ = outcomes.send(CompressionOutcome {
+                    block_id: key.0.clone(),
+                    level: key.1,
+                    version,
+                    source: block.compressed_versions.original.content,
+                });
+            }
+            Err(e) if e.is_retryable() && attempt < self.inner.config.max_attempts => {
+                let delay = self.inner.config.backoff(attempt);
+                debug!(
+                    "Compression of {} {:?} failed (attempt {attempt}), retrying in {delay:?}: {e}",
+                    key.0, key.1
+                );
+                events.push(progress(
+                    &key,
+                    priority,
+                    CompressionTaskStatus::Retrying,
+                    attempt,
+                    Some(e.to_string()),
+                ));
+                // Retry with the freshest snapshot rather than running twice.
+                let (block, priority) = match resubmit.take() {
+                    Some((newer, queued)) => (newer, queued.max(priority)),
+                    None => (block, priority),
+                };
+                state.push(
+                    key.clone(),
+                    block,
+                    priority,
+                    attempt,
+                    Instant::now() + delay,
+                );
+            }
+            Err(e) => {
+                warn!("Compression of {} {:?} failed: {e}", key.0, key.1);
+                events.push(progress(
+                    &key,
+                    priority,
+                    CompressionTaskStatus::Failed,
+                    attempt,
+                    Some(e.to_string()),
+                ));
+            }
+        }
+
+        if let Some((block, priority)) = resubmit {
+            state.push(key.clone(), block, priority, 0, Instant::now());
+            events.push(progress(
+                &key,
+                priority,
+                CompressionTaskStatus::Queued,
+                1,
+                None,
+            ));
+        }
+        events.extend(state.depth_event());
+        drop(state);
+
+        self.emit(events);
+    }
+
+    fn emit(&self, events: impl IntoIterator<Item = ApertureEvent>) {
+        for event in events {
+            (self.inner.sink)(event);
+        }
+    }
+}
+
+fn progress(
+    key: &TaskKey,
+    priority: CompressionPriority,
+    status: CompressionTaskStatus,
+    attempt: u32,
+    error: Option<String>,
+) -> ApertureEvent {
+    ApertureEvent::CompressionProgress {
+        block_id: key.0.clone(),
+        level: key.1,
+        priority,
+        status,
+        attempt,
+        error,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use async_trait::async_trait;
+
+    use super::*;
+    use crate::engine::block::test_block;
+    use crate::engine::compression::backend::CompressionPrompt;
+    use crate::engine::types::Role;
+
+    /// Backend that fails a set number of times, optionally hangs or waits
+    /// for a release, and records the order in which blocks were compressed.
+    struct ScriptedBackend {
+        failures_left: AtomicU32,
+        failure_status: u16,
+        hang: bool,
+        gate: Option<Notify>,
+        calls: Mutex<Vec<String>>,
+    }
+
+    impl ScriptedBackend {
+        fn new() -> Self {
+            Self {
+                failures_left: AtomicU32::new(0),
+                failure_status: 503,
+                hang: false,
+                gate: None,
+                calls: Mutex::new(Vec::new()),
+            }
+        }
+
+        fn calls(&self) -> Vec<String> {
+            self.calls.lock().expect("calls").clone()
+        }
+    }
+
+    #[async_trait]
+    impl CompressionBackend for ScriptedBackend {
+        fn name(&self) -> &str {
+            "scripted"
+        }
+
+        fn model(&self) -> &str {
+            "scripted-1"
+        }
+
+        async fn complete(&self, prompt: &CompressionPrompt) -> Result<String, CompressionError> {
+            let id = prompt
+                .user
+                .split("<content>\n")
+                .nth(1)
+                .and_then(|rest| rest.split_whitespace().next())
+                .unwrap_or_default()
+                .to_string();
+            self.calls.lock().expect("calls").push(id.clone());
+
+            if self.hang {
+                std::future::pending::<()>().await;
+            }
+            if let Some(gate) = &self.gate {
+                gate.notified().await;
+            }
+            if self
+                .failures_left
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+                .is_ok()
+            {
+                return Err(CompressionError::BackendStatus {
+                    status: self.failure_status,
+                    body: "busy".to_string(),
+                });
+            }
+            Ok(format!("{id} summary"))
+        }
+    }
+
+    fn collector() -> (EventSink, Arc<Mutex<Vec<ApertureEvent>>>) {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink_events = Arc::clone(&events);
+        let sink: EventSink =
+            Arc::new(move |event| sink_events.lock().expect("events").push(event));
+        (sink, events)
+    }
+
+    fn statuses(events: &Mutex<Vec<ApertureEvent>>, block_id: &str) -> Vec<CompressionTaskStatus> {
+        events
+            .lock()
+            .expect("events")
+            .iter()
+            .filter_map(|event| match event {
+                ApertureEvent::CompressionProgress {
+                    block_id: id,
+                    status,
+                    ..
+                } if id == block_id => Some(*status),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// A block whose content starts with its id, long enough to summarize.
+    fn block(id: &str) -> Block {
+        test_block(
+            id,
+            Role::Assistant,
+            &format!("{id} {}", "detail ".repeat(200)),
+        )
+    }
+
+    fn fast_config(concurrency: usize) -> QueueConfig {
+        QueueConfig {
+            concurrency,
+            max_attempts: 3,
+            base_backoff: Duration::from_millis(5),
+            max_backoff: Duration::from_millis(20),
+            batch_window: Duration::from_millis(20),
+            quality: QualityConfig::default(),
+        }
+    }
+
+    async fn recv(rx: &mut mpsc::UnboundedReceiver<CompressionOutcome>) -> CompressionOutcome {
+        tokio::time::timeout(Duration::from_secs(5), rx.recv())
+            .await
+            .expect("outcome in time")
+            .expect("worker running")
+    }
+
+    #[tokio::test]
+    async fn test_queue_runs_highest_priority_first() {
+        let (sink, _) = collector();
+        let queue = CompressionQueue::new(fast_config(1), sink);
+        queue.enqueue(
+            block("spec"),
+            CompressionLevel::Summarized,
+            CompressionPriority::Speculative,
+        );
+        queue.enqueue(
+            block("press"),
+            CompressionLevel::Summarized,
+            CompressionPriority::Pressure,
+        );
+        queue.enqueue(
+            block("user"),
+            CompressionLevel::Summarized,
+            CompressionPriority::User,
+        );
+
+        let backend = Arc::new(ScriptedBackend::new());
+        let mut rx = queue.spawn_worker(backend.clone(), PreserveKeys::default());
+        for _ in 0..3 {
+            recv(&mut rx).await;
+        }
+
+        assert_eq!(backend.calls(), vec!["user", "press", "spec"]);
+        queue.shutdown();
+    }
+
+    #[tokio::test]
+    async fn test_outcome_applies_only_to_unchanged_block() {
+        let (sink, _) = collector();
+        let queue = CompressionQueue::new(fast_config(1), sink);
+        queue.enqueue(
+            block("a"),
+            CompressionLevel::Summarized,
+            CompressionPriority::Pressure,
+        );
+        let mut rx = queue.spawn_worker(Arc::new(ScriptedBackend::new()), PreserveKeys::default());
+        let outcome = recv(&mut rx).await;
+        queue.shutdown();
+
+        let mut edited = vec![test_block("a", Role::Assistant, "rewritten by hand")];
+        assert!(!outcome.clone().apply(&mut edited));
+        assert!(edited[0].compressed_versions.summarized.is_none());
+        assert!(!outcome.clone().apply(&mut []));
+
+        let mut blocks = vec![block("a")];
+        assert!(outcome.apply(&mut blocks));
+        let summary = blocks[0].compressed_versions.summarized.as_ref();
+        assert_eq!(summary.map(|v| v.content.as_str()), Some("a summary"));
+        assert_eq!(blocks[0].compression_level, CompressionLevel::Original);
+    }
+
+    #[tokio::test]
+    async fn test_enqueue_dedupes_and_raises_priority() {
+        let (sink, _) = collector();
+        let queue = CompressionQueue::new(fast_config(1), sink);
+
+        assert!(queue.enqueue(
+            block("a"),
+            CompressionLevel::Minimal,
+            CompressionPriority::Speculative
+        ));
+        assert!(!queue.enqueue(
+            block("a"),
+            CompressionLevel::Minimal,
+            CompressionPriority::User
+        ));
+        assert!(queue.enqueue(
+            block("a"),
+            CompressionLevel::Summarized,
+            CompressionPriority::Speculative
+        ));
+
+        let state = queue.state();
+        assert_eq!(state.depth(), (2, 0));
+        let task = &state.pending[&("a".to_string(), CompressionLevel::Minimal)];
+        assert_eq!(task.priority, CompressionPriority::User);
+    }
+
+    #[tokio::test]
+    async fn test_transient_failure_retries_with_backoff() {
+        let (sink, events) = collector();
+        let queue = CompressionQueue::new(fast_config(2), sink);
+        let backend = Arc::new(ScriptedBackend::new());
+        backend.failures_left.store(2, Ordering::SeqCst);
+
+        let mut rx = queue.spawn_worker(backend.clone(), PreserveKeys::default());
+        queue.enqueue(
+            block("r"),
+            CompressionLevel::Summarized,
+            CompressionPriority::User,
+        );
+        let outcome = recv(&mut rx).await;
+
+        assert_eq!(outcome.version.content, "r summary");
+        assert_eq!(backend.calls().len(), 3);
+        assert_eq!(
+            statuses(&events, "r"),
+            vec![
+                CompressionTaskStatus::Queued,
+                CompressionTaskStatus::Running,
+                CompressionTaskStatus::Retrying,
+                CompressionTaskStatus::Running,
+                CompressionTaskStatus::Retrying,
+                CompressionTaskStatus::Running,
+                CompressionTaskStatus::Completed,
+            ]
+        );
+        queue.shutdown();
+    }
+
+    #[tokio::test]
+    async fn test_permanent_failure_is_not_retried() {
+        let (sink, events) = collector();
+        let queue = CompressionQueue::new(fast_config(1), sink);
+        let mut backend = ScriptedBackend::new();
+        backend.failures_left.store(5, Ordering::SeqCst);
+        backend.failure_status = 400;
+        let backend = Arc::new(backend);
+
+        let _rx = queue.spawn_worker(backend.clone(), PreserveKeys::default());
+        queue.enqueue(
+            block("bad"),
+            CompressionLevel::Minimal,
+            CompressionPriority::User,
+        );
+
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !statuses(&events, "bad").contains(&CompressionTaskStatus::Failed) {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .expect("task failed in time");
+        assert_eq!(backend.calls().len(), 1);
+        assert_eq!(queue.depth(), (0, 0));
+        queue.shutdown();
+    }
+
+    #[tokio::test]
+    async fn test_cancel_block_aborts_running_and_pending() {
+        let (sink, events) = collector();
+        let queue = CompressionQueue::new(fast_config(1), sink);
+        let mut backend = ScriptedBackend::new();
+        backend.hang = true;
+        let backend = Arc::new(backend);
+
+        let _rx = queue.spawn_worker(backend.clone(), PreserveKeys::default());
+        queue.enqueue(
+            block("gone"),
+            CompressionLevel::Summarized,
+            CompressionPriority::User,
+        );
+        queue.enqueue(
+            block("gone"),
+            CompressionLevel::Minimal,
+            CompressionPriority::Speculative,
+        );
+
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while queue.depth() != (1, 1) {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .expect("task started in time");
+
+        assert_eq!(queue.cancel_block("gone"), 2);
+        assert_eq!(queue.depth(), (0, 0));
+        let cancelled = statuses(&events, "gone")
+            .into_iter()
+            .filter(|s| *s == CompressionTaskStatus::Cancelled)
+            .count();
+        assert_eq!(cancelled, 2);
+
+        let last_depth = events
+            .lock()
+            .expect("events")
+            .iter()
+            .rev()
+            .find_map(|e| match e {
+                ApertureEvent::CompressionQueueChanged { pending, running } => {
+                    Some((*pending, *running))
+                }
+                _ => None,
+            });
+        assert_eq!(last_depth, Some((0, 0)));
+        queue.shutdown();
+    }
+
+    #[tokio::test]
+    async fn test_enqueue_while_running_reruns_with_newer_snapshot() {
+        let (sink, events) = collector();
+        let queue = CompressionQueue::new(fast_config(1), sink);
+        let mut backend = ScriptedBackend::new();
+        backend.gate = Some(Notify::new());
+        let backend = Arc::new(backend);
+
+        let mut rx = queue.spawn_worker(backend.clone(), PreserveKeys::default());
+        queue.enqueue(
+            block("a"),
+            CompressionLevel::Summarized,
+            CompressionPriority::Speculative,
+        );
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while queue.depth() != (0, 1) {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .expect("task started in time");
+
+        let newer = test_block(
+            "a",
+            Role::Assistant,
+            &format!("a2 {}", "detail ".repeat(200)),
+        );
+        assert!(!queue.enqueue(
+            newer,
+            CompressionLevel::Summarized,
+            CompressionPriority::User
+        ));
+        assert_eq!(queue.depth(), (0, 1));
+
+        let gate = backend.gate.as_ref().expect("gate");
+        gate.notify_one();
+        recv(&mut rx).await;
+        gate.notify_one();
+        recv(&mut rx).await;
+
+        assert_eq!(backend.calls(), vec!["a", "a2"]);
+        let queued = statuses(&events, "a")
+            .into_iter()
+            .filter(|s| *s == CompressionTaskStatus::Queued)
+            .count();
+        assert_eq!(queued, 2);
+        queue.shutdown();
+    }
+
+    #[test]
+    fn test_sink_may_call_back_into_queue() {
+        let handle: Arc<std::sync::OnceLock<CompressionQueue>> = Arc::default();
+        let sink_handle = Arc::clone(&handle);
+        let sink: EventSink = Arc::new(move |_| {
+            if let Some(queue) = sink_handle.get() {
+                queue.depth();
+            }
+        });
+        let queue = CompressionQueue::new(fast_config(1), sink);
+        let _ = handle.set(queue.clone());
+
+        let (done_tx, done_rx) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            queue.enqueue(
+                block("x"),
+                CompressionLevel::Minimal,
+                CompressionPriority::User,
+            );
+            queue.cancel_block("x");
+            let _ = done_tx.send(());
+        });
+        done_rx
+            .recv_timeout(Duration::from_secs(5))
+            .expect("sink re-entered the queue without deadlocking");
+    }
+
+    #[test]
+    fn test_backoff_doubles_and_caps() {
+        let config = QueueConfig {
+            base_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(350),
+            ..QueueConfig::default()
+        };
+        assert_eq!(config.backoff(1), Duration::from_millis(100));
+        assert_eq!(config.backoff(2), Duration::from_millis(200));
+        assert_eq!(config.backoff(3), Duration::from_millis(350));
+    }
+}
@@ -0,0 +1,400 @@
+//! Rule-based trimming — the deterministic, LLM-free `trimmed` level.
+//!
+//! Every block kind gets the same cleanup (ANSI codes, carriage-return
+//! redraws, progress bars, repeated and blank lines), then a kind-specific
+//! cut: file reads shrink to their signatures and logs to head, tail and
+//! errors. Lines carrying a preserve key are never dropped.
+
+use std::sync::OnceLock;
+
+use regex::Regex;
+use serde::Serialize;
+
+use super::preserve::{PreserveKeys, PreservedKey};
+use crate::engine::block::Block;
+use crate::engine::types::Role;
+
+/// Tool names whose results are file contents.
+const FILE_READ_TOOLS: &[&str] = &["read", "read_file", "readfile", "view", "cat", "open_file"];
+
+/// Consecutive identical lines collapsed once a run reaches this length.
+const MIN_REPEAT_RUN: usize = 3;
+
+/// How a block's content is trimmed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TrimKind {
+    /// Messages and anything else: cleanup only.
+    Prose,
+    /// Generic tool output: cleanup, then head/tail if very long.
+    ToolResult,
+    /// File contents: cleanup, then signatures only if long.
+    FileRead,
+    /// Log output: cleanup, then head, tail and errors.
+    Log,
+}
+
+impl TrimKind {
+    /// Pick a trim strategy from a block's role, type and tool name.
+    pub fn of(block: &Block) -> Self {
+        let block_type = block.block_type.as_deref().unwrap_or_default();
+        if block_type == "log" {
+            return Self::Log;
+        }
+        if block.role != Role::ToolResult {
+            return Self::Prose;
+        }
+
+        let tool = block
+            .metadata
+            .tool_name
+            .as_deref()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        if block_type == "file_read" || FILE_READ_TOOLS.contains(&tool.as_str()) {
+            Self::FileRead
+        } else if looks_like_log(&block.content) {
+            Self::Log
+        } else {
+            Self::ToolResult
+        }
+    }
+}
+
+/// Thresholds for the kind-specific cuts.
+#[derive(Debug, Clone)]
+pub struct TrimOptions {
+    /// File reads longer than this are reduced to signatures.
+    pub max_file_lines: usize,
+    /// Generic tool output longer than this is cut like a log.
+    pub max_tool_lines: usize,
+    /// Lines kept from the start of a cut log.
+    pub log_head: usize,
+    /// Lines kept from the end of a cut log.
+    pub log_tail: usize,
+}
+
+impl Default for TrimOptions {
+    fn default() -> Self {
+        Self {
+            max_file_lines: 80,
+            max_tool_lines: 200,
+            log_head: 20,
+            log_tail: 20,
+        }
+    }
+}
+
+/// Trimmed content and the keys it was required to keep.
+#[derive(Debug, Clone)]
+pub struct Trimmed {
+    pub content: String,
+    pub preserved: Vec<PreservedKey>,
+}
+
+/// Trim `content` for `kind`, never dropping a line that carries a key.
+///
+/// Keys are detected after ANSI codes and carriage-return redraws are
+/// resolved, i.e. on the text a terminal would actually have shown.
+pub fn trim(content: &str, kind: TrimKind, keys: &PreserveKeys, options: &TrimOptions) -> Trimmed {
+    let lines = clean_lines(content, keys);
+    let preserved = keys.scan(&lines.join("\n"));
+
+    let lines = match kind {
+        TrimKind::Prose => lines,
+        TrimKind::FileRead if lines.len() > options.max_file_lines => {
+            keep_matching(&lines, |i, line| {
+                i == 0 || is_signature(line) || keys.is_protected(line)
+            })
+        }
+        TrimKind::Log if lines.len() > options.log_head + options.log_tail => {
+            head_tail(&lines, keys, options)
+        }
+        TrimKind::ToolResult if lines.len() > options.max_tool_lines => {
+            head_tail(&lines, keys, options)
+        }
+        _ => lines,
+    };
+
+    let mut content = lines.join("\n");
+    // Line-level keeping already guarantees this; the check guards future
+    // rules that rewrite within a line.
+    let missing: Vec<&PreservedKey> = preserved
+        .iter()
+        .filter(|key| !content.contains(&key.text))
+        .collect();
+    if !missing.is_empty() {
+        content.push_str("\n[preserved]");
+        for key in missing {
+            content.push('\n');
+            content.push_str(&key.text);
+        }
+    }
+
+    Trimmed { content, preserved }
+}
+
+fn ansi_pattern() -> &'static Regex {
+    static ANSI: OnceLock<Regex> = OnceLock::new();
+    ANSI.get_or_init(|| {
+        // CSI sequences, OSC strings (BEL or ST terminated), and two-byte escapes.
+        Regex::new(r"\x1b\[[0-?]*[ -/]*[@-~]|\x1b\][^\x07\x1b]*(?:\x07|\x1b\\)|\x1b[@-Z\\-_]")
+            .expect("valid ANSI pattern")
+    })
+}
+
+fn progress_pattern() -> &'static Regex {
+    static PROGRESS: OnceLock<Regex> = OnceLock::new();
+    PROGRESS.get_or_init(|| {
+        // A bar of fill characters next to a percentage, a bare
+        // block-character bar, or a braille spinner frame.
+        Regex::new(
+            r"[=#>█▉▊▋▌▍▎▏▓▒░━]{3,}[ >.\-]*[\]|]?\s*\d{1,3}(?:\.\d+)?\s?%|\d{1,3}(?:\.\d+)?\s?%\s*[\[|]?[=#>█▉▊▋▌▍▎▏▓▒░━]{3,}|[█▓▒░━]{10,}|^\s*[⠋⠙⠹⠸⠼⠴⠦⠧⠇⠏]\s",
+        )
+        .expect("valid progress pattern")
+    })
+}
+
+/// `content` as a terminal would have shown it, with noise lines collapsed.
+///
+/// This is the text preserve keys are scanned in, so the quality verifier
+/// uses it too.
+pub(crate) fn visible_text(content: &str, keys: &PreserveKeys) -> String {
+    clean_lines(content, keys).join("\n")
+}
+
+/// Strip terminal noise and collapse repeated and blank lines.
+fn clean_lines(content: &str, keys: &PreserveKeys) -> Vec<String> {
+    let stripped = ansi_pattern().replace_all(content, "");
+    let mut lines: Vec<String> = Vec::new();
+    let mut run = 0usize;
+
+    for raw in stripped.lines() {
+        // A carriage return redraws the line; only the last frame was visible.
+        let line = raw
+            .rsplit('\r')
+            .find(|frame| !frame.is_empty())
+            .unwrap_or("");
+        let line = line.trim_end();
+
+        if progress_pattern().is_match(line) && !keys.is_protected(line) {
+            continue;
+        }
+        if line.is_empty() && lines.last().is_some_and(String::is_empty) {
+            continue;
+        }
+
+        if lines.last().is_some_and(|last| last == line) && !line.is_empty() {
+            run += 1;
+            continue;
+        }
+        flush_repeats(&mut lines, &mut run);
+        lines.push(line.to_string());
+    }
+    flush_repeats(&mut lines, &mut run);
+
+    while lines.last().is_some_and(String::is_empty) {
+        lines.pop();
+    }
+    lines
+}
+
+/// Re-emit a short run of duplicates, or note a long one as a single line.
+fn flush_repeats(lines: &mut Vec<String>, run: &mut usize) {
+    if *run == 0 {
+        return;
+    }
+    if *run + 1 >= MIN_REPEAT_RUN {
+        lines.push(format!("[previous line repeated {} more times]", *run));
+    } else if let Some(last) = lines.last().cloned() {
+        for _ in 0..*run {
+            lines.push(last.clone());
+        }
+    }
+    *run = 0;
+}
+
+/// Keep `head`/`tail` lines plus every protected line in between.
+fn head_tail(lines: &[String], keys: &PreserveKeys, options: &TrimOptions) -> Vec<String> {
+    let tail_start = lines.len().saturating_sub(options.log_tail);
+    keep_matching(lines, |i, line| {
+        i < options.log_head || i >= tail_start || keys.is_protected(line)
+    })
+}
+
+/// Keep lines for which `keep` holds, replacing each gap with a marker.
+fn keep_matching(lines: &[String], keep: impl Fn(usize, &str) -> bool) -> Vec<String> {
+    let mut kept = Vec::new();
+    let mut omitted = 0usize;
+    for (i, line) in lines.iter().enumerate() {
+        if keep(i, line) {
+            push_gap(&mut kept, &mut omitted);
+            kept.push(line.clone());
+        } else {
+            omitted += 1;
+        }
+    }
+    push_gap(&mut kept, &mut omitted);
+    kept
+}
+
+fn push_gap(kept: &mut Vec<String>, omitted: &mut usize) {
+    match *omitted {
+        0 => {}
+        1 => kept.push("[… 1 line omitted …]".to_string()),
+        n => kept.push(format!("[… {n} lines omitted …]")),
+    }
+    *omitted = 0;
+}
+
+fn signature_pattern() -> &'static Regex {
+    static SIGNATURE: OnceLock<Regex> = OnceLock::new();
+    SIGNATURE.get_or_init(|| {
+        // Optional `cat -n` style gutter, then a declaration keyword.
+        Regex::new(
+            r"^\s*(?:\d+\s*(?:→|\t|\||:)\s*)?(?:#\[|@\w|(?:pub(?:\([\w:]+\))?\s+)?(?:async\s+|const\s+|unsafe\s+|export\s+(?:default\s+)?)*(?:fn|struct|enum|trait|impl|mod|type|use|class|interface|def|function|import|from|const)\b)",
+        )
+        .expect("valid signature pattern")
+    })
+}
+
+fn is_signature(line: &str) -> bool {
+    signature_pattern().is_match(line)
+}
+
+fn log_line_pattern() -> &'static Regex {
+    static LOG_LINE: OnceLock<Regex> = OnceLock::new();
+    LOG_LINE.get_or_init(|| {
+        Regex::new(
+            r"^\s*(?:\[?\d{4}-\d{2}-\d{2}[T ]\d{2}:\d{2}|\[?\d{2}:\d{2}:\d{2}|\[?(?:TRACE|DEBUG|INFO|WARN|WARNING|ERROR|FATAL)\b)",
+        )
+        .expect("valid log line pattern")
+    })
+}
+
+/// At least half of the non-empty lines start with a timestamp or level.
+fn looks_like_log(content: &str) -> bool {
+    let (total, matching) = content.lines().filter(|line| !line.trim().is_empty()).fold(
+        (0usize, 0usize),
+        |(total, matching), line| {
+            (
+                total + 1,
+                matching + usize::from(log_line_pattern().is_match(line)),
+            )
+        },
+    );
+    total >= 5 && matching * 2 >= total
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trim_default(content: &str, kind: TrimKind) -> Trimmed {
+        trim(
+            content,
+            kind,
+            &PreserveKeys::default(),
+            &TrimOptions::default(),
+        )
+    }
+
+    #[test]
+    fn test_trim_strips_ansi_and_redraws() {
+        let out = trim_default(
+            "\x1b[32mok\x1b[0m\nstep 1\rstep 2\rdone\n",
+            TrimKind::ToolResult,
+        );
+        assert_eq!(out.content, "ok\ndone");
+    }
+
+    #[test]
+    fn test_trim_drops_progress_bars() {
+        let content =
+            "Downloading\n[=====>     ] 45%\n 87% |██████████▉ | 87/100\n⠙ building\nfinished";
+        let out = trim_default(content, TrimKind::ToolResult);
+        assert_eq!(out.content, "Downloading\nfinished");
+    }
+
+    #[test]
+    fn test_trim_collapses_repeated_and_blank_lines() {
+        let content = "start\nretry\nretry\nretry\nretry\n\n\n\nok\nok\nend\n\n";
+        let out = trim_default(content, TrimKind::Prose);
+        assert_eq!(
+            out.content,
+            "start\nretry\n[previous line repeated 3 more times]\n\nok\nok\nend"
+        );
+    }
+
+    #[test]
+    fn test_trim_file_read_keeps_signatures_and_gutter() {
+        let mut content = String::from("     1→use std::io;\n");
+        for n in 2..=100 {
+            if n == 50 {
+                content.push_str("    50→pub fn connect(url: &str) -> Result<()> {\n");
+            } else {
+                content.push_str(&format!("{n:>6}→    let x{n} = {n};\n"));
+            }
+        }
+        let out = trim_default(&content, TrimKind::FileRead);
+
+        assert!(out.content.contains("     1→use std::io;"));
+        assert!(out.content.contains("    50→pub fn connect"));
+        assert!(out.content.contains("[… 48 lines omitted …]"));
+        assert!(!out.content.contains("x20"));
+    }
+
+    #[test]
+    fn test_trim_short_file_read_is_untouched() {
+        let content = "fn main() {\n    println!(\"hi\");\n}";
+        assert_eq!(trim_default(content, TrimKind::FileRead).content, content);
+    }
+
+    #[test]
+    fn test_trim_log_keeps_head_tail_and_errors() {
+        let mut lines: Vec<String> = (0..100).map(|n| format!("INFO tick {n}")).collect();
+        lines[60] = "ERROR connection refused by upstream".to_string();
+        let out = trim_default(&lines.join("\n"), TrimKind::Log);
+
+        assert!(out.content.starts_with("INFO tick 0\n"));
+        assert!(out
+            .content
+            .contains("INFO tick 19\n[… 40 lines omitted …]\nERROR connection refused"));
+        assert!(out.content.ends_with("INFO tick 99"));
+        assert!(!out.content.contains("INFO tick 50"));
+    }
+
+    #[test]
+    fn test_trim_never_drops_preserved_keys() {
+        let keys = PreserveKeys::new(["MarketStream"]);
+        let options = TrimOptions {
+            max_tool_lines: 4,
+            log_head: 1,
+            log_tail: 1,
+            ..TrimOptions::default()
+        };
+        let content = "first\nfiller\nsee src/stream.rs:88\nfiller two\nMarketStream reconnect\n    at connect (lib/net.js:4:2)\nfiller three\nlast";
+        let out = trim(content, TrimKind::ToolResult, &keys, &options);
+
+        assert!(!out.content.contains("filler"));
+        for key in &out.preserved {
+            assert!(out.content.contains(&key.text), "lost {key:?}");
+        }
+        assert!(out.preserved.len() >= 5);
+    }
+
+    #[test]
+    fn test_trim_protected_progress_line_is_kept() {
+        let content = "[=====>     ] 45% error: checksum mismatch";
+        assert_eq!(trim_default(content, TrimKind::ToolResult).content, content);
+    }
+
+    #[test]
+    fn test_looks_like_log_requires_majority() {
+        let log = (0..6)
+            .map(|n| format!("2026-01-02 10:00:0{n} INFO ok"))
+            .collect::<Vec<_>>();
+        assert!(looks_like_log(&log.join("\n")));
+        assert!(!looks_like_log("a\nb\nc\nd\nINFO e\nf"));
+    }
+}
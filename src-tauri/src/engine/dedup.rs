@@ -0,0 +1,456 @@
+//! Duplicate and near-duplicate block detection.
+//!
+//! Three detectors, run in order so each block lands in one suggestion:
+//!
+//! - **Exact** — identical original content, by hash.
+//! - **Superseded read** — earlier reads of the same file by the same tool.
+//! - **Near duplicate** — MinHash over word shingles, for a file read again
+//!   after a small edit or a log re-run with a few different lines.
+//!
+//! Each suggestion keeps the newest copy. [`apply_suggestion`] replaces the
+//! older copies' live content with a pointer stub through the
+//! [`ActionLog`], so it can be undone.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+
+use serde::Serialize;
+
+use super::action_log::{ActionLog, ActionReason, BlockChange};
+use super::block::Block;
+use super::compression::rules::TrimKind;
+use super::tokens::count_tokens;
+
+/// Words per shingle.
+const SHINGLE_WORDS: usize = 5;
+
+/// MinHash signature length.
+const SIGNATURE_LEN: usize = 64;
+
+/// Detection thresholds.
+#[derive(Debug, Clone)]
+pub struct DedupConfig {
+    /// Blocks smaller than this are never collapsed.
+    pub min_tokens: u32,
+    /// Estimated Jaccard similarity at which blocks count as near duplicates.
+    pub near_threshold: f64,
+}
+
+impl Default for DedupConfig {
+    fn default() -> Self {
+        Self {
+            min_tokens: 32,
+            near_threshold: 0.8,
+        }
+    }
+}
+
+/// How the collapsed blocks relate to the kept one.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum DuplicateKind {
+    Exact,
+    NearDuplicate { similarity: f64 },
+    SupersededRead { path: String },
+}
+
+/// A group of redundant blocks and what collapsing them would save.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DedupSuggestion {
+    #[serde(flatten)]
+    pub kind: DuplicateKind,
+    /// The newest copy, left as is.
+    pub keep: String,
+    /// Older copies to replace with [`Self::stub`], oldest first.
+    pub collapse: Vec<String>,
+    pub stub: String,
+    pub tokens_saved: u32,
+    /// e.g. "Collapse 3 reads of handler.rs, save 12k tokens".
+    pub message: String,
+}
+
+/// Find redundant blocks in `blocks` (oldest first).
+///
+/// Pinned blocks and blocks already collapsed are left out.
+pub fn find_duplicates(blocks: &[Block], config: &DedupConfig) -> Vec<DedupSuggestion> {
+    let candidates: Vec<&Block> = blocks
+        .iter()
+        .filter(|b| b.pinned.is_none() && !b.is_rewritten() && b.tokens >= config.min_tokens)
+        .collect();
+    let mut claimed: HashSet<&str> = HashSet::new();
+    let mut suggestions = Vec::new();
+
+    // Exact: group by content hash, confirming equality against collisions.
+    let mut by_hash: HashMap<u64, Vec<&Block>> = HashMap::new();
+    for &block in &candidates {
+        by_hash
+            .entry(hash_of(original(block)))
+            .or_default()
+            .push(block);
+    }
+    let mut exact: Vec<Vec<&Block>> = by_hash
+        .into_values()
+        .flat_map(split_identical)
+        .filter(|group| group.len() > 1)
+        .collect();
+    exact.sort_by_key(|group| position(blocks, group[0]));
+    for group in exact {
+        suggestions.push(suggest(DuplicateKind::Exact, &group));
+        claimed.extend(group.iter().map(|b| b.id.as_str()));
+    }
+
+    // Superseded reads: same tool, same path.
+    let mut reads: Vec<((String, String), Vec<&Block>)> = Vec::new();
+    for block in candidates
+        .iter()
+        .copied()
+        .filter(|b| !claimed.contains(b.id.as_str()))
+    {
+        let Some(key) = read_key(block) else {
+            continue;
+        };
+        match reads.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, group)) => group.push(block),
+            None => reads.push((key, vec![block])),
+        }
+    }
+    for ((_, path), group) in reads.into_iter().filter(|(_, g)| g.len() > 1) {
+        suggestions.push(suggest(DuplicateKind::SupersededRead { path }, &group));
+        claimed.extend(group.iter().map(|b| b.id.as_str()));
+    }
+
+    // Near duplicates: newest block first, gathering older similar ones.
+    let rest: Vec<(&Block, [u64; SIGNATURE_LEN])> = candidates
+        .iter()
+        .filter(|b| !claimed.contains(b.id.as_str()))
+        .map(|b| (*b, minhash(original(b))))
+        .collect();
+    let mut grouped = vec![false; rest.len()];
+    for newest in (0..rest.len()).rev() {
+        if grouped[newest] {
+            continue;
+        }
+        let mut group = Vec::new();
+        let mut lowest = 1.0f64;
+        for older in 0..newest {
+            if grouped[older] {
+                continue;
+            }
+            let similarity = similarity(&rest[older].1, &rest[newest].1);
+            if similarity >= config.near_threshold {
+                grouped[older] = true;
+                group.push(rest[older].0);
+                lowest = lowest.min(similarity);
+            }
+        }
+        if !group.is_empty() {
+            grouped[newest] = true;
+            group.push(rest[newest].0);
+            suggestions.push(suggest(
+                DuplicateKind::NearDuplicate { similarity: lowest },
+                &group,
+            ));
+        }
+    }
+
+    suggestions
+}
+
+/// Replace the live content of each block in `suggestion.collapse` with the
+/// stub, logging one undoable action per block. Returns the action ids.
+///
+/// Blocks that are missing, pinned or already rewritten are skipped.
+pub fn apply_suggestion(
+    blocks: &mut [Block],
+    suggestion: &DedupSuggestion,
+    log: &mut ActionLog,
+) -> Vec<String> {
+    let tokens = count_tokens(&suggestion.stub);
+    let mut actions = Vec::new();
+    for block in blocks
+        .iter_mut()
+        .filter(|b| suggestion.collapse.contains(&b.id))
+    {
+        if block.pinned.is_some() || block.is_rewritten() {
+            continue;
+        }
+        let before = block.tokens;
+        block.content = suggestion.stub.clone();
+        block.tokens = tokens;
+        let change = BlockChange::Collapsed {
+            duplicate_of: suggestion.keep.clone(),
+            stub: suggestion.stub.clone(),
+        };
+        let action = log.record(
+            block,
+            change,
+            before,
+            ActionReason::Deduplicated,
+            suggestion.message.clone(),
+        );
+        actions.push(action.id.clone());
+    }
+    actions
+}
+
+fn original(block: &Block) -> &str {
+    &block.compressed_versions.original.content
+}
+
+fn position(blocks: &[Block], block: &Block) -> usize {
+    blocks
+        .iter()
+        .position(|b| b.id == block.id)
+        .unwrap_or(usize::MAX)
+}
+
+fn hash_of<T: Hash + ?Sized>(value: &T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Split a same-hash group into runs of truly identical content.
+fn split_identical(group: Vec<&Block>) -> Vec<Vec<&Block>> {
+    let mut runs: Vec<Vec<&Block>> = Vec::new();
+    for block in group {
+        match runs
+            .iter_mut()
+            .find(|run| original(run[0]) == original(block))
+        {
+            Some(run) => run.push(block),
+            None => runs.push(vec![block]),
+        }
+    }
+    runs
+}
+
+/// `(tool, path)` for a single-file read.
+fn read_key(block: &Block) -> Option<(String, String)> {
+    if TrimKind::of(block) != TrimKind::FileRead {
+        return None;
+    }
+    let [path] = block.metadata.file_paths.as_slice() else {
+        return None;
+    };
+    let tool = block
+        .metadata
+        .tool_name
+        .clone()
+        .or_else(|| block.block_type.clone())
+        .unwrap_or_default()
+        .to_lowercase();
+    Some((tool, path.clone()))
+}
+
+/// MinHash signature of `text`'s word shingles.
+fn minhash(text: &str) -> [u64; SIGNATURE_LEN] {
+    let words: Vec<&str> = text.split_whitespace().collect();
+    let mut signature = [u64::MAX; SIGNATURE_LEN];
+    let mut add = |shingle: &[&str]| {
+        let base = hash_of(shingle);
+        for (i, slot) in signature.iter_mut().enumerate() {
+            *slot = (*slot).min(mix(base ^ (i as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)));
+        }
+    };
+    if words.len() < SHINGLE_WORDS {
+        add(&words);
+    } else {
+        words.windows(SHINGLE_WORDS).for_each(&mut add);
+    }
+    signature
+}
+
+/// splitmix64 finalizer, to derive independent hash functions from one.
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    x ^ (x >> 31)
+}
+
+/// Estimated Jaccard similarity of two signatures.
+fn similarity(a: &[u64; SIGNATURE_LEN], b: &[u64; SIGNATURE_LEN]) -> f64 {
+    let equal = a.iter().zip(b).filter(|(x, y)| x == y).count();
+    equal as f64 / SIGNATURE_LEN as f64
+}
+
+/// Build the suggestion for `group` (oldest first; the last is kept).
+fn suggest(kind: DuplicateKind, group: &[&Block]) -> DedupSuggestion {
+    let (keep, older) = group.split_last().expect("non-empty group");
+    let subject = match (&kind, keep.metadata.file_paths.as_slice()) {
+        (DuplicateKind::SupersededRead { path }, _) | (_, [path]) => {
+            format!("reads of {}", file_name(path))
+        }
+        _ => "blocks".to_string(),
+    };
+    let stub = match &kind {
+        DuplicateKind::Exact => format!("[Duplicate of block {}]", keep.id),
+        DuplicateKind::NearDuplicate { similarity } => format!(
+            "[Near-duplicate ({:.0}% similar) of block {}]",
+            similarity * 100.0,
+            keep.id
+        ),
+        DuplicateKind::SupersededRead { path } => {
+            format!("[Earlier read of {path}; superseded by block {}]", keep.id)
+        }
+    };
+    let stub_tokens = count_tokens(&stub);
+    let tokens_saved = older
+        .iter()
+        .map(|b| b.tokens.saturating_sub(stub_tokens))
+        .sum();
+    let qualifier = match &kind {
+        DuplicateKind::Exact => "identical ",
+        DuplicateKind::NearDuplicate { .. } => "near-identical ",
+        DuplicateKind::SupersededRead { .. } => "",
+    };
+    let message = format!(
+        "Collapse {} {qualifier}{subject}, save {} tokens",
+        group.len(),
+        format_tokens(tokens_saved)
+    );
+
+    DedupSuggestion {
+        kind,
+        keep: keep.id.clone(),
+        collapse: older.iter().map(|b| b.id.clone()).collect(),
+        stub,
+        tokens_saved,
+        message,
+    }
+}
+
+fn file_name(path: &str) -> &str {
+    path.rsplit(['/', '\\']).next().unwrap_or(path)
+}
+
+/// `850`, `1.5k`, `12k`.
+pub(crate) fn format_tokens(tokens: u32) -> String {
+    if tokens < 1000 {
+        return tokens.to_string();
+    }
+    let thousands = format!("{:.1}", tokens as f64 / 1000.0);
+    format!("{}k", thousands.trim_end_matches(".0"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::block::test_block;
+    use crate::engine::types::Role;
+
+    fn source(n: usize) -> String {
+        (0..n)
+            .map(|i| format!("pub fn handler_{i}(request: Request) -> Response {{ route({i}) }}\n"))
+            .collect()
+    }
+
+    fn read(id: &str, path: &str, content: &str) -> Block {
+        let mut block = test_block(id, Role::ToolResult, content);
+        block.metadata.tool_name = Some("Read".to_string());
+        block.metadata.file_paths = vec![path.to_string()];
+        block
+    }
+
+    #[test]
+    fn test_find_duplicates_exact_keeps_newest() {
+        let content = source(20);
+        let blocks = vec![
+            test_block("a", Role::ToolResult, &content),
+            test_block("b", Role::User, "unrelated request that is short"),
+            test_block("c", Role::ToolResult, &content),
+        ];
+
+        let suggestions = find_duplicates(&blocks, &DedupConfig::default());
+        assert_eq!(suggestions.len(), 1);
+        assert_eq!(suggestions[0].kind, DuplicateKind::Exact);
+        assert_eq!(suggestions[0].keep, "c");
+        assert_eq!(suggestions[0].collapse, vec!["a"]);
+        assert!(suggestions[0].tokens_saved > 0);
+    }
+
+    #[test]
+    fn test_find_duplicates_superseded_reads_of_same_file() {
+        let blocks = vec![
+            read("r1", "src/proxy/handler.rs", &source(40)),
+            read(
+                "r2",
+                "src/proxy/handler.rs",
+                &format!("// edited\n{}", source(10)),
+            ),
+            read("other", "src/main.rs", &source(30)),
+            read("r3", "src/proxy/handler.rs", &source(50)),
+        ];
+
+        let suggestions = find_duplicates(&blocks, &DedupConfig::default());
+        let read = &suggestions[0];
+        assert_eq!(
+            read.kind,
+            DuplicateKind::SupersededRead {
+                path: "src/proxy/handler.rs".to_string()
+            }
+        );
+        assert_eq!(read.keep, "r3");
+        assert_eq!(read.collapse, vec!["r1", "r2"]);
+        assert!(read
+            .message
+            .starts_with("Collapse 3 reads of handler.rs, save "));
+        assert!(!suggestions
+            .iter()
+            .any(|s| s.collapse.contains(&"other".to_string())));
+    }
+
+    #[test]
+    fn test_find_duplicates_near_duplicate_logs() {
+        let run = |failing: &str| {
+            format!(
+                "{}test {failing} ... FAILED\ntest result: FAILED. 39 passed; 1 failed",
+                (0..40)
+                    .map(|i| format!("test engine::module_{i}::test_case ... ok\n"))
+                    .collect::<String>()
+            )
+        };
+        let blocks = vec![
+            test_block("run1", Role::ToolResult, &run("parse_empty")),
+            test_block("run2", Role::ToolResult, &run("parse_nested")),
+            test_block("diff", Role::ToolResult, &source(40)),
+        ];
+
+        let suggestions = find_duplicates(&blocks, &DedupConfig::default());
+        assert_eq!(suggestions.len(), 1);
+        assert!(matches!(
+            suggestions[0].kind,
+            DuplicateKind::NearDuplicate { similarity } if similarity >= 0.8
+        ));
+        assert_eq!(suggestions[0].collapse, vec!["run1"]);
+    }
+
+    #[test]
+    fn test_apply_suggestion_stubs_older_copies_reversibly() {
+        let content = source(20);
+        let mut blocks = vec![
+            test_block("a", Role::ToolResult, &content),
+            test_block("b", Role::ToolResult, &content),
+        ];
+        let suggestion = find_duplicates(&blocks, &DedupConfig::default()).remove(0);
+        let mut log = ActionLog::new();
+
+        let actions = apply_suggestion(&mut blocks, &suggestion, &mut log);
+        assert_eq!(actions.len(), 1);
+        assert_eq!(blocks[0].content, "[Duplicate of block b]");
+        assert!(blocks[0].tokens < blocks[1].tokens);
+        assert!(find_duplicates(&blocks, &DedupConfig::default()).is_empty());
+
+        log.undo(&actions[0], &mut blocks).expect("undo");
+        assert_eq!(blocks[0].content, content);
+    }
+
+    #[test]
+    fn test_format_tokens() {
+        assert_eq!(format_tokens(850), "850");
+        assert_eq!(format_tokens(1500), "1.5k");
+        assert_eq!(format_tokens(12_040), "12k");
+    }
+}
@@ -0,0 +1,388 @@
+//! Block dependency graph.
+//!
+//! Structural edges are ones the provider API enforces or the conversation
+//! needs to make sense: a `tool_use` and its `tool_result` (linked by
+//! `metadata.tool_use_id`), and an assistant turn and the user message it
+//! answers. Derived edges come from content: a later block mentions a file
+//! an earlier block read, or quotes an earlier block.
+//!
+//! [`DependencyGraph::removal_impact`] answers "what breaks if I remove X",
+//! and [`DependencyGraph::plan_removal`] turns that into a refusal or a
+//! cascade for whoever rewrites the context.
+
+use std::collections::{BTreeSet, HashSet};
+
+use serde::Serialize;
+use thiserror::Error;
+
+use super::block::Block;
+use super::compression::preserve::{PreserveKeys, PreserveKind};
+use super::compression::rules::TrimKind;
+use super::heat::{quoted_spans, same_file};
+use super::types::Role;
+
+/// Shortest quoted span that creates a quotation edge, in characters.
+const MIN_QUOTE_CHARS: usize = 16;
+
+/// Why one block depends on another.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum DependencyKind {
+    /// A `tool_result` and the `tool_use` it answers.
+    ToolCall { tool_use_id: String },
+    /// An assistant turn and the user message it answers.
+    TurnPair,
+    /// A later block mentions a file an earlier block read.
+    FileChain { path: String },
+    /// A later block quotes an earlier one.
+    Quotation,
+}
+
+impl DependencyKind {
+    /// Whether the API rejects a request that keeps only one side.
+    pub fn is_breaking(&self) -> bool {
+        matches!(self, Self::ToolCall { .. })
+    }
+}
+
+/// `from` depends on `to`, which comes earlier in the conversation.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Dependency {
+    pub from: String,
+    pub to: String,
+    #[serde(flatten)]
+    pub kind: DependencyKind,
+}
+
+/// What removing a set of blocks would do.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RemovalImpact {
+    /// Further blocks that must go too, or the API rejects the request.
+    pub cascade: Vec<String>,
+    /// Breaking edges cut by the removal as requested.
+    pub breaking: Vec<Dependency>,
+    /// Kept blocks that lose a block they build on.
+    pub degraded: Vec<Dependency>,
+}
+
+impl RemovalImpact {
+    pub fn is_safe(&self) -> bool {
+        self.cascade.is_empty()
+    }
+}
+
+/// What to do with a removal that would break the request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RemovalPolicy {
+    Refuse,
+    Cascade,
+}
+
+#[derive(Debug, Error)]
+pub enum DependencyError {
+    #[error("Removing {block_id} would break {required_by}")]
+    UnsafeRemoval {
+        block_id: String,
+        required_by: String,
+    },
+}
+
+impl serde::Serialize for DependencyError {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+/// Dependencies between the blocks of one context.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DependencyGraph {
+    edges: Vec<Dependency>,
+}
+
+impl DependencyGraph {
+    /// Build the graph for `blocks`, oldest first.
+    pub fn build(blocks: &[Block]) -> Self {
+        let mut edges = Vec::new();
+        let mut seen = HashSet::new();
+        let mut push = |edge: Dependency| {
+            if edge.from != edge.to && seen.insert(edge.clone()) {
+                edges.push(edge);
+            }
+        };
+        let keys = PreserveKeys::default();
+        let mut last_user: Option<&Block> = None;
+
+        for (i, block) in blocks.iter().enumerate() {
+            let earlier = &blocks[..i];
+
+            if let (Role::ToolResult, Some(id)) = (block.role, &block.metadata.tool_use_id) {
+                if let Some(tool_use) = earlier.iter().rev().find(|b| {
+                    b.role == Role::ToolUse && b.metadata.tool_use_id.as_ref() == Some(id)
+                }) {
+                    push(Dependency {
+                        from: block.id.clone(),
+                        to: tool_use.id.clone(),
+                        kind: DependencyKind::ToolCall {
+                            tool_use_id: id.clone(),
+                        },
+                    });
+                }
+            }
+
+            match block.role {
+                Role::User => last_user = Some(block),
+                Role::Assistant | Role::ToolUse => {
+                    if let Some(user) = last_user {
+                        push(Dependency {
+                            from: block.id.clone(),
+                            to: user.id.clone(),
+                            kind: DependencyKind::TurnPair,
+                        });
+                    }
+                }
+                Role::System | Role::ToolResult => {}
+            }
+
+            for path in mentioned_paths(block, &keys) {
+                let source = earlier.iter().rev().find(|b| {
+                    TrimKind::of(b) == TrimKind::FileRead
+                        && b.metadata.file_paths.iter().any(|p| same_file(p, &path))
+                });
+                if let Some(source) = source {
+                    push(Dependency {
+                        from: block.id.clone(),
+                        to: source.id.clone(),
+                        kind: DependencyKind::FileChain { path },
+                    });
+                }
+            }
+
+            for quote in quoted_spans(&block.content, MIN_QUOTE_CHARS) {
+                if let Some(source) = earlier.iter().rev().find(|b| b.content.contains(quote)) {
+                    push(Dependency {
+                        from: block.id.clone(),
+                        to: source.id.clone(),
+                        kind: DependencyKind::Quotation,
+                    });
+                }
+            }
+        }
+
+        Self { edges }
+    }
+
+    pub fn edges(&self) -> &[Dependency] {
+        &self.edges
+    }
+
+    /// Blocks `block_id` depends on.
+    pub fn dependencies_of(&self, block_id: &str) -> impl Iterator<Item = &Dependency> {
+        let block_id = block_id.to_string();
+        self.edges.iter().filter(move |e| e.from == block_id)
+    }
+
+    /// Blocks that depend on `block_id`.
+    pub fn dependents_of(&self, block_id: &str) -> impl Iterator<Item = &Dependency> {
+        let block_id = block_id.to_string();
+        self.edges.iter().filter(move |e| e.to == block_id)
+    }
+
+    /// What breaks if `block_ids` are removed.
+    pub fn removal_impact(&self, block_ids: &[&str]) -> RemovalImpact {
+        let requested: HashSet<&str> = block_ids.iter().copied().collect();
+        let mut removed = requested.clone();
+        let mut cascade = Vec::new();
+        let mut breaking = Vec::new();
+
+        // Breaking edges hold both ends together, whichever side goes.
+        let mut frontier: Vec<&str> = block_ids.to_vec();
+        while let Some(id) = frontier.pop() {
+            for edge in self.edges.iter().filter(|e| e.kind.is_breaking()) {
+                let partner = if edge.from == id {
+                    edge.to.as_str()
+                } else if edge.to == id {
+                    edge.from.as_str()
+                } else {
+                    continue;
+                };
+                if requested.contains(id) && !requested.contains(partner) {
+                    breaking.push(edge.clone());
+                }
+                if removed.insert(partner) {
+                    cascade.push(partner.to_string());
+                    frontier.push(partner);
+                }
+            }
+        }
+
+        let degraded = self
+            .edges
+            .iter()
+            .filter(|e| {
+                !e.kind.is_breaking()
+                    && removed.contains(e.to.as_str())
+                    && !removed.contains(e.from.as_str())
+            })
+            .cloned()
+            .collect();
+
+        RemovalImpact {
+            cascade,
+            breaking,
+            degraded,
+        }
+    }
+
+    /// The full set of blocks to remove for `block_ids` under `policy`.
+    ///
+    /// `Refuse` fails if any breaking dependency would be cut; `Cascade`
+    /// adds the blocks needed to keep the request valid.
+    pub fn plan_removal(
+        &self,
+        block_ids: &[&str],
+        policy: RemovalPolicy,
+    ) -> Result<Vec<String>, DependencyError> {
+        let impact = self.removal_impact(block_ids);
+        if policy == RemovalPolicy::Refuse {
+            if let Some(edge) = impact.breaking.first() {
+                let (block_id, required_by) = if block_ids.contains(&edge.from.as_str()) {
+                    (edge.from.clone(), edge.to.clone())
+                } else {
+                    (edge.to.clone(), edge.from.clone())
+                };
+                return Err(DependencyError::UnsafeRemoval {
+                    block_id,
+                    required_by,
+                });
+            }
+        }
+        let mut ids: Vec<String> = block_ids.iter().map(|id| id.to_string()).collect();
+        ids.extend(impact.cascade);
+        Ok(ids)
+    }
+}
+
+/// Files `block` refers to without reading them itself.
+fn mentioned_paths(block: &Block, keys: &PreserveKeys) -> Vec<String> {
+    if TrimKind::of(block) == TrimKind::FileRead {
+        return Vec::new();
+    }
+    let mut paths: BTreeSet<String> = block.metadata.file_paths.iter().cloned().collect();
+    paths.extend(
+        keys.scan(&block.content)
+            .into_iter()
+            .filter(|key| key.kind == PreserveKind::FilePath)
+            .map(|key| key.text),
+    );
+    paths.into_iter().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::block::test_block;
+
+    fn tool_pair(use_id: &str, result_id: &str, call: &str) -> [Block; 2] {
+        let mut tool_use = test_block(use_id, Role::ToolUse, "Read src/proxy/handler.rs");
+        tool_use.metadata.tool_use_id = Some(call.to_string());
+        let mut result = test_block(
+            result_id,
+            Role::ToolResult,
+            "pub(crate) async fn proxy_handler(state: ProxyState) -> Response",
+        );
+        result.metadata.tool_use_id = Some(call.to_string());
+        result.metadata.tool_name = Some("Read".to_string());
+        result.metadata.file_paths = vec!["src/proxy/handler.rs".to_string()];
+        [tool_use, result]
+    }
+
+    fn conversation() -> Vec<Block> {
+        let [tool_use, result] = tool_pair("use", "result", "toolu_1");
+        vec![
+            test_block("ask", Role::User, "Why does the proxy drop headers?"),
+            tool_use,
+            result,
+            test_block(
+                "answer",
+                Role::Assistant,
+                "In handler.rs, `async fn proxy_handler(state: ProxyState)` never copies them.",
+            ),
+        ]
+    }
+
+    fn kinds_from(graph: &DependencyGraph, id: &str) -> Vec<(String, DependencyKind)> {
+        graph
+            .dependencies_of(id)
+            .map(|e| (e.to.clone(), e.kind.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn test_build_structural_edges() {
+        let graph = DependencyGraph::build(&conversation());
+
+        assert_eq!(
+            kinds_from(&graph, "result"),
+            vec![(
+                "use".to_string(),
+                DependencyKind::ToolCall {
+                    tool_use_id: "toolu_1".to_string()
+                }
+            )]
+        );
+        assert!(kinds_from(&graph, "use").contains(&("ask".to_string(), DependencyKind::TurnPair)));
+    }
+
+    #[test]
+    fn test_build_derived_file_and_quote_edges() {
+        let graph = DependencyGraph::build(&conversation());
+        let answer = kinds_from(&graph, "answer");
+
+        assert!(answer.contains(&(
+            "result".to_string(),
+            DependencyKind::FileChain {
+                path: "handler.rs".to_string()
+            }
+        )));
+        assert!(answer.contains(&("result".to_string(), DependencyKind::Quotation)));
+        assert!(answer.contains(&("ask".to_string(), DependencyKind::TurnPair)));
+    }
+
+    #[test]
+    fn test_removal_impact_cascades_tool_pairs() {
+        let graph = DependencyGraph::build(&conversation());
+        let impact = graph.removal_impact(&["result"]);
+
+        assert!(!impact.is_safe());
+        assert_eq!(impact.cascade, vec!["use"]);
+        assert_eq!(impact.breaking.len(), 1);
+        assert!(impact.degraded.iter().all(|e| e.from == "answer"));
+        assert!(!impact.degraded.is_empty());
+
+        assert!(graph.removal_impact(&["use", "result"]).breaking.is_empty());
+        assert!(graph.removal_impact(&["answer"]).is_safe());
+    }
+
+    #[test]
+    fn test_plan_removal_refuses_or_cascades() {
+        let graph = DependencyGraph::build(&conversation());
+
+        let refused = graph.plan_removal(&["use"], RemovalPolicy::Refuse);
+        assert!(matches!(
+            refused,
+            Err(DependencyError::UnsafeRemoval { ref block_id, ref required_by })
+                if block_id == "use" && required_by == "result"
+        ));
+        assert_eq!(
+            graph
+                .plan_removal(&["use"], RemovalPolicy::Cascade)
+                .expect("cascade"),
+            vec!["use", "result"]
+        );
+    }
+}
@@ -0,0 +1,749 @@
+//! Context forking: independent branches of a session's block state.
+//!
+//! The active branch's blocks are the session's blocks, and
+//! [`super::outbound`] rebuilds every outbound request from them, so the
+//! next request is always built from the active branch; messages the client
+//! sends while a branch is active are ingested into that branch. Inactive
+//! branches keep their blocks and action log here until switched to.
+//! Every branch remembers the blocks it was forked from; the fork point
+//! nearest two branches' lowest common ancestor is the base of the
+//! three-way [`merge`] between them.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use tauri::State;
+use thiserror::Error;
+use tracing::info;
+use uuid::Uuid;
+
+use super::action_log::ActionLog;
+use super::block::Block;
+use super::checkpoint::hard::CheckpointStore;
+use super::checkpoint::CheckpointError;
+use super::memory::MemoryState;
+use super::session::{EngineSession, SharedSession};
+use super::types::{CompressionLevel, PinPosition, Zone};
+use crate::events::timeline::now_ms;
+
+/// Id of the branch every session starts on.
+pub const MAIN_BRANCH: &str = "main";
+
+#[derive(Debug, Error)]
+pub enum ForkError {
+    #[error("Branch not found: {0}")]
+    BranchNotFound(String),
+
+    #[error("Cannot merge branch {0} into itself")]
+    SameBranch(String),
+
+    #[error("Cannot delete branch {0}")]
+    Protected(String),
+
+    #[error(transparent)]
+    Checkpoint(#[from] CheckpointError),
+}
+
+impl serde::Serialize for ForkError {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+/// One line of development.
+#[derive(Debug, Clone)]
+pub struct Branch {
+    pub id: String,
+    pub name: String,
+    pub parent: Option<String>,
+    pub created_at_ms: u64,
+    /// Blocks at the fork point.
+    pub base: Vec<Block>,
+    /// Stashed blocks and log; empty while the branch is active.
+    blocks: Vec<Block>,
+    log: ActionLog,
+}
+
+/// Every branch of a session and which one is active.
+#[derive(Debug, Clone)]
+pub struct ForkState {
+    branches: Vec<Branch>,
+    active: String,
+}
+
+impl Default for ForkState {
+    fn default() -> Self {
+        Self {
+            branches: vec![Branch {
+                id: MAIN_BRANCH.to_string(),
+                name: MAIN_BRANCH.to_string(),
+                parent: None,
+                created_at_ms: now_ms(),
+                base: Vec::new(),
+                blocks: Vec::new(),
+                log: ActionLog::default(),
+            }],
+            active: MAIN_BRANCH.to_string(),
+        }
+    }
+}
+
+impl ForkState {
+    pub fn active(&self) -> &str {
+        &self.active
+    }
+
+    fn index(&self, id: &str) -> Result<usize, ForkError> {
+        self.branches
+            .iter()
+            .position(|b| b.id == id)
+            .ok_or_else(|| ForkError::BranchNotFound(id.to_string()))
+    }
+
+    /// Indices of branch `id` and its ancestors, nearest first. Stops at a
+    /// deleted parent.
+    fn lineage(&self, id: &str) -> Vec<usize> {
+        let mut chain = Vec::new();
+        let mut next = Some(id);
+        while let Some(index) = next.and_then(|id| self.index(id).ok()) {
+            if chain.contains(&index) {
+                break;
+            }
+            chain.push(index);
+            next = self.branches[index].parent.as_deref();
+        }
+        chain
+    }
+
+    /// Base for merging branch `source` into the active branch.
+    ///
+    /// Walks both `parent` chains to their lowest common ancestor and takes
+    /// the fork point where a side left it; when both sides did, the older
+    /// fork point, which both sides descend from. Falls back to `source`'s
+    /// own fork point if the chains never meet.
+    fn merge_base(&self, source: usize) -> &[Block] {
+        let ours = self.lineage(&self.active);
+        let theirs = self.lineage(&self.branches[source].id);
+        let Some(their_pos) = theirs.iter().position(|i| ours.contains(i)) else {
+            return &self.branches[source].base;
+        };
+        let our_pos = ours
+            .iter()
+            .position(|&i| i == theirs[their_pos])
+            .expect("common ancestor is in both chains");
+        let their_fork = their_pos.checked_sub(1).map(|p| theirs[p]);
+        let our_fork = our_pos.checked_sub(1).map(|p| ours[p]);
+        // Branches are appended as they are created, so a lower index is
+        // an older fork.
+        let fork = match (their_fork, our_fork) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (fork, None) | (None, fork) => fork,
+        };
+        fork.map_or(&[], |i| &self.branches[i].base)
+    }
+}
+
+/// A branch as listed to the user.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BranchSummary {
+    pub id: String,
+    pub name: String,
+    pub parent: Option<String>,
+    pub created_at_ms: u64,
+    pub active: bool,
+    pub block_count: usize,
+    pub total_tokens: u32,
+}
+
+/// How a block differs between two branches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiffStatus {
+    Removed,
+    Modified,
+    Added,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlockDiff {
+    pub block_id: String,
+    pub status: DiffStatus,
+    /// What changed on a modified block, e.g. `"content"` or
+    /// `"compression: original → trimmed"`.
+    pub changes: Vec<String>,
+}
+
+/// Differences from one branch to another.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BranchDiff {
+    pub from: String,
+    pub to: String,
+    /// Removed first, then modified, then added.
+    pub entries: Vec<BlockDiff>,
+    pub token_delta: i64,
+}
+
+/// Which side wins when a block was edited differently on both branches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MergeResolution {
+    Ours,
+    Theirs,
+}
+
+/// A block changed on both sides of a merge.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MergeConflict {
+    pub block_id: String,
+    /// `None` when the side removed the block.
+    pub ours: Option<String>,
+    pub theirs: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MergeReport {
+    /// Blocks taken from the source branch.
+    pub merged: Vec<String>,
+    /// Blocks removed because the source branch removed them.
+    pub removed: Vec<String>,
+    /// Conflicts left unresolved; those blocks were not changed.
+    pub conflicts: Vec<MergeConflict>,
+}
+
+/// The parts of a block a user edits. Heat and reference counts differ
+/// between branches without anyone editing anything.
+#[derive(PartialEq)]
+struct EditState<'a> {
+    content: &'a str,
+    original: &'a str,
+    level: CompressionLevel,
+    zone: &'a Zone,
+    pinned: Option<PinPosition>,
+    memory_state: MemoryState,
+}
+
+fn edit_state(block: &Block) -> EditState<'_> {
+    EditState {
+        content: &block.content,
+        original: &block.compressed_versions.original.content,
+        level: block.compression_level,
+        zone: &block.zone,
+        pinned: block.pinned,
+        memory_state: block.memory_state,
+    }
+}
+
+fn same_edit(a: Option<&Block>, b: Option<&Block>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => edit_state(a) == edit_state(b),
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+/// Serialized name of a unit enum or untagged zone.
+fn label(value: &impl Serialize) -> String {
+    match serde_json::to_value(value) {
+        Ok(serde_json::Value::String(s)) => s,
+        Ok(other) => other.to_string(),
+        Err(_) => String::new(),
+    }
+}
+
+fn changes(from: &Block, to: &Block) -> Vec<String> {
+    let mut changes = Vec::new();
+    if from.content != to.content {
+        changes.push("content".to_string());
+    }
+    if from.zone != to.zone {
+        changes.push(format!("zone: {} → {}", label(&from.zone), label(&to.zone)));
+    }
+    if from.compression_level != to.compression_level {
+        changes.push(format!(
+            "compression: {} → {}",
+            label(&from.compression_level),
+            label(&to.compression_level)
+        ));
+    }
+    if from.tokens != to.tokens {
+        changes.push(format!(
+            "tokens: {:+}",
+            i64::from(to.tokens) - i64::from(from.tokens)
+        ));
+    }
+    if from.pinned != to.pinned {
+        changes.push("pin changed".to_string());
+    }
+    if from.memory_state != to.memory_state {
+        changes.push(format!(
+            "memory: {} → {}",
+            label(&from.memory_state),
+            label(&to.memory_state)
+        ));
+    }
+    changes
+}
+
+/// The blocks of branch `id`: the session's for the active branch.
+fn branch_blocks<'a>(session: &'a EngineSession, id: &str) -> Result<&'a [Block], ForkError> {
+    let forks = &session.forks;
+    let branch = &forks.branches[forks.index(id)?];
+    Ok(if branch.id == forks.active {
+        &session.blocks
+    } else {
+        &branch.blocks
+    })
+}
+
+fn summary(session: &EngineSession, branch: &Branch) -> BranchSummary {
+    let active = branch.id == session.forks.active;
+    let blocks = if active {
+        &session.blocks
+    } else {
+        &branch.blocks
+    };
+    BranchSummary {
+        id: branch.id.clone(),
+        name: branch.name.clone(),
+        parent: branch.parent.clone(),
+        created_at_ms: branch.created_at_ms,
+        active,
+        block_count: blocks.len(),
+        total_tokens: blocks.iter().map(|b| b.tokens).sum(),
+    }
+}
+
+/// Every branch, oldest first.
+pub fn list(session: &EngineSession) -> Vec<BranchSummary> {
+    session
+        .forks
+        .branches
+        .iter()
+        .map(|b| summary(session, b))
+        .collect()
+}
+
+/// Fork a new branch from `from` (a checkpoint's blocks) or, when `None`,
+/// from the active branch's current blocks, and switch to it.
+pub fn fork(session: &mut EngineSession, name: &str, from: Option<Vec<Block>>) -> BranchSummary {
+    let base = from.unwrap_or_else(|| session.blocks.clone());
+    let parent = session.forks.active.clone();
+    let id = Uuid::new_v4().to_string();
+    session.forks.branches.push(Branch {
+        id: id.clone(),
+        name: name.to_string(),
+        parent: Some(parent),
+        created_at_ms: now_ms(),
+        blocks: base.clone(),
+        base,
+        log: ActionLog::default(),
+    });
+    switch(session, &id).expect("branch just created");
+    info!("Forked branch {id} ({name})");
+    summary(
+        session,
+        session.forks.branches.last().expect("branch just created"),
+    )
+}
+
+/// Make `id` the active branch. Its blocks become the session's; the
+/// previous branch's blocks and log are stashed.
+pub fn switch(session: &mut EngineSession, id: &str) -> Result<BranchSummary, ForkError> {
+    let to = session.forks.index(id)?;
+    let from = session.forks.index(&session.forks.active)?;
+    if to != from {
+        let forks = &mut session.forks;
+        std::mem::swap(&mut forks.branches[from].blocks, &mut session.blocks);
+        std::mem::swap(&mut forks.branches[from].log, &mut session.log);
+        std::mem::swap(&mut forks.branches[to].blocks, &mut session.blocks);
+        std::mem::swap(&mut forks.branches[to].log, &mut session.log);
+        forks.active = id.to_string();
+    }
+    Ok(summary(session, &session.forks.branches[to]))
+}
+
+/// Delete an inactive branch other than main.
+pub fn delete(session: &mut EngineSession, id: &str) -> Result<(), ForkError> {
+    if id == MAIN_BRANCH || id == session.forks.active {
+        return Err(ForkError::Protected(id.to_string()));
+    }
+    let index = session.forks.index(id)?;
+    session.forks.branches.remove(index);
+    Ok(())
+}
+
+/// Block-level differences going from branch `from` to branch `to`.
+pub fn diff(session: &EngineSession, from: &str, to: &str) -> Result<BranchDiff, ForkError> {
+    let from_blocks = branch_blocks(session, from)?;
+    let to_blocks = branch_blocks(session, to)?;
+    let from_map: HashMap<&str, &Block> = from_blocks.iter().map(|b| (b.id.as_str(), b)).collect();
+    let to_map: HashMap<&str, &Block> = to_blocks.iter().map(|b| (b.id.as_str(), b)).collect();
+
+    let mut entries = Vec::new();
+    for block in from_blocks {
+        match to_map.get(block.id.as_str()) {
+            None => entries.push(BlockDiff {
+                block_id: block.id.clone(),
+                status: DiffStatus::Removed,
+                changes: Vec::new(),
+            }),
+            Some(other) => {
+                let changes = changes(block, other);
+                if !changes.is_empty() {
+                    entries.push(BlockDiff {
+                        block_id: block.id.clone(),
+                        status: DiffStatus::Modified,
+                        changes,
+                    });
+                }
+            }
+        }
+    }
+    entries.extend(
+        to_blocks
+            .iter()
+            .filter(|b| !from_map.contains_key(b.id.as_str()))
+            .map(|b| BlockDiff {
+                block_id: b.id.clone(),
+                status: DiffStatus::Added,
+                changes: Vec::new(),
+            }),
+    );
+    // Stable, so blocks keep their order within each status.
+    entries.sort_by_key(|e| e.status as u8);
+
+    let tokens = |blocks: &[Block]| blocks.iter().map(|b| i64::from(b.tokens)).sum::<i64>();
+    Ok(BranchDiff {
+        from: from.to_string(),
+        to: to.to_string(),
+        entries,
+        token_delta: tokens(to_blocks) - tokens(from_blocks),
+    })
+}
+
+/// Carry `block_ids` from branch `source` into the active branch.
+///
+/// The base is the fork point at the branches' lowest common ancestor (see
+/// [`ForkState::merge_base`]). A block the source left unchanged
+/// is skipped; one the active branch left unchanged takes the source's
+/// state, including removal. A block edited differently on both sides is
+/// a conflict: it is settled by `resolution` when given, and otherwise
+/// reported and left alone.
+pub fn merge(
+    session: &mut EngineSession,
+    source: &str,
+    block_ids: &[String],
+    resolution: Option<MergeResolution>,
+) -> Result<MergeReport, ForkError> {
+    if source == session.forks.active {
+        return Err(ForkError::SameBranch(source.to_string()));
+    }
+    let source_index = session.forks.index(source)?;
+    let branch = &session.forks.branches[source_index];
+    let base_blocks = session.forks.merge_base(source_index);
+    let mut report = MergeReport::default();
+
+    for id in block_ids {
+        let base = base_blocks.iter().find(|b| &b.id == id);
+        let theirs = branch.blocks.iter().find(|b| &b.id == id);
+        let ours_index = session.blocks.iter().position(|b| &b.id == id);
+        let ours = ours_index.map(|i| &session.blocks[i]);
+
+        if same_edit(theirs, base) || same_edit(ours, theirs) {
+            continue;
+        }
+        if !same_edit(ours, base) {
+            match resolution {
+                Some(MergeResolution::Theirs) => {}
+                Some(MergeResolution::Ours) => continue,
+                None => {
+                    report.conflicts.push(MergeConflict {
+                        block_id: id.clone(),
+                        ours: ours.map(|b| b.content.clone()),
+                        theirs: theirs.map(|b| b.content.clone()),
+                    });
+                    continue;
+                }
+            }
+        }
+
+        match (theirs, ours_index) {
+            (Some(theirs), Some(i)) => {
+                session.blocks[i] = theirs.clone();
+                report.merged.push(id.clone());
+            }
+            (Some(theirs), None) => {
+                // After the nearest preceding source block the active
+                // branch has, else at the end.
+                let position = branch.blocks.iter().position(|b| &b.id == id);
+                let at = branch.blocks[..position.unwrap_or(0)]
+                    .iter()
+                    .rev()
+                    .find_map(|prev| session.blocks.iter().position(|b| b.id == prev.id))
+                    .map_or(session.blocks.len(), |i| i + 1);
+                session.blocks.insert(at, theirs.clone());
+                report.merged.push(id.clone());
+            }
+            (None, Some(i)) => {
+                session.blocks.remove(i);
+                report.removed.push(id.clone());
+            }
+            (None, None) => {}
+        }
+    }
+    info!(
+        "Merged {} blocks from branch {source}, {} conflicts",
+        report.merged.len() + report.removed.len(),
+        report.conflicts.len()
+    );
+    Ok(report)
+}
+
+/// Fork the session, from a hard checkpoint when `checkpoint_id` is given.
+#[tauri::command]
+pub fn fork_create(
+    store: State<'_, CheckpointStore>,
+    session: State<'_, SharedSession>,
+    name: String,
+    checkpoint_id: Option<String>,
+) -> Result<BranchSummary, ForkError> {
+    let from = match checkpoint_id {
+        Some(id) => Some(store.load(&id)?.1),
+        None => None,
+    };
+    Ok(fork(&mut session.lock(), &name, from))
+}
+
+#[tauri::command]
+pub fn fork_list(session: State<'_, SharedSession>) -> Vec<BranchSummary> {
+    list(&session.lock())
+}
+
+/// Switch the active branch. The next outbound request uses its blocks.
+#[tauri::command]
+pub fn fork_switch(
+    session: State<'_, SharedSession>,
+    branch_id: String,
+) -> Result<BranchSummary, ForkError> {
+    switch(&mut session.lock(), &branch_id)
+}
+
+#[tauri::command]
+pub fn fork_delete(session: State<'_, SharedSession>, branch_id: String) -> Result<(), ForkError> {
+    delete(&mut session.lock(), &branch_id)
+}
+
+#[tauri::command]
+pub fn fork_diff(
+    session: State<'_, SharedSession>,
+    from: String,
+    to: String,
+) -> Result<BranchDiff, ForkError> {
+    diff(&session.lock(), &from, &to)
+}
+
+/// Merge selected blocks from `source` into the active branch.
+#[tauri::command]
+pub fn fork_merge(
+    session: State<'_, SharedSession>,
+    source: String,
+    block_ids: Vec<String>,
+    resolution: Option<MergeResolution>,
+) -> Result<MergeReport, ForkError> {
+    merge(&mut session.lock(), &source, &block_ids, resolution)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::engine::block::test_block;
+    use crate::engine::types::Role;
+
+    fn session() -> EngineSession {
+        EngineSession {
+            blocks: vec![
+                test_block("a", Role::User, "Should we use WebSockets or SSE?"),
+                test_block("b", Role::Assistant, "Both work; let's compare."),
+                test_block("c", Role::ToolResult, "server.rs: 120 lines"),
+            ],
+            ..EngineSession::default()
+        }
+    }
+
+    fn edit(session: &mut EngineSession, id: &str, content: &str) {
+        let block = session
+            .blocks
+            .iter_mut()
+            .find(|b| b.id == id)
+            .expect("block");
+        block.content = content.to_string();
+    }
+
+    #[test]
+    fn test_fork_and_switch_keep_branches_independent() {
+        let mut session = session();
+        let branch = fork(&mut session, "sse", None);
+        assert!(branch.active);
+        edit(&mut session, "b", "Going with SSE.");
+        session.blocks.remove(2);
+
+        switch(&mut session, MAIN_BRANCH).expect("switch");
+        assert_eq!(session.blocks.len(), 3);
+        assert_eq!(session.blocks[1].content, "Both work; let's compare.");
+
+        let listed = list(&session);
+        assert_eq!(listed.len(), 2);
+        assert!(listed[0].active);
+        assert_eq!(listed[1].block_count, 2);
+        assert_eq!(listed[1].parent.as_deref(), Some(MAIN_BRANCH));
+
+        let diff = diff(&session, MAIN_BRANCH, &branch.id).expect("diff");
+        let statuses: Vec<_> = diff
+            .entries
+            .iter()
+            .map(|e| (e.block_id.as_str(), e.status))
+            .collect();
+        assert_eq!(
+            statuses,
+            vec![("c", DiffStatus::Removed), ("b", DiffStatus::Modified)]
+        );
+        assert_eq!(diff.entries[1].changes, vec!["content"]);
+        assert!(diff.token_delta < 0);
+    }
+
+    #[test]
+    fn test_requests_are_rebuilt_from_the_active_branch() {
+        let mut session = EngineSession::default();
+        let mut body = json!({ "messages": [
+            { "role": "user", "content": "Should we use WebSockets or SSE?" },
+            { "role": "assistant", "content": "Both work; let's compare." },
+        ] });
+        session
+            .transcript
+            .ingest(&mut session.blocks, &body, "anthropic");
+        fork(&mut session, "sse", None);
+        session.blocks[1].content = "Going with SSE.".to_string();
+
+        body["messages"]
+            .as_array_mut()
+            .expect("messages")
+            .push(json!({ "role": "user", "content": "Write the handler." }));
+        session
+            .transcript
+            .ingest(&mut session.blocks, &body, "anthropic");
+        let mut outbound = body.clone();
+        session
+            .transcript
+            .rebuild(&session.blocks, &mut outbound, "anthropic");
+        assert_eq!(outbound["messages"][1]["content"], "Going with SSE.");
+        assert_eq!(outbound["messages"][2]["content"], "Write the handler.");
+
+        switch(&mut session, MAIN_BRANCH).expect("switch");
+        session
+            .transcript
+            .rebuild(&session.blocks, &mut outbound, "anthropic");
+        assert_eq!(
+            outbound["messages"][1]["content"],
+            "Both work; let's compare."
+        );
+        assert_eq!(outbound["messages"].as_array().map(Vec::len), Some(2));
+    }
+
+    #[test]
+    fn test_merge_carries_selected_blocks_and_reports_conflicts() {
+        let mut session = session();
+        let branch = fork(&mut session, "sse", None);
+        edit(&mut session, "a", "SSE it is.");
+        edit(&mut session, "b", "Going with SSE.");
+        session
+            .blocks
+            .push(test_block("d", Role::Assistant, "SSE handler added"));
+        session.blocks.remove(2);
+
+        switch(&mut session, MAIN_BRANCH).expect("switch");
+        edit(&mut session, "b", "Going with WebSockets.");
+
+        let ids: Vec<String> = ["a", "b", "c", "d"].map(String::from).to_vec();
+        let report = merge(&mut session, &branch.id, &ids, None).expect("merge");
+        assert_eq!(report.merged, vec!["a", "d"]);
+        assert_eq!(report.removed, vec!["c"]);
+        assert_eq!(report.conflicts.len(), 1);
+        assert_eq!(report.conflicts[0].block_id, "b");
+        assert_eq!(session.blocks[1].content, "Going with WebSockets.");
+        let ids_now: Vec<&str> = session.blocks.iter().map(|b| b.id.as_str()).collect();
+        assert_eq!(ids_now, vec!["a", "b", "d"]);
+
+        let report = merge(
+            &mut session,
+            &branch.id,
+            &["b".to_string()],
+            Some(MergeResolution::Theirs),
+        )
+        .expect("merge");
+        assert_eq!(report.merged, vec!["b"]);
+        assert_eq!(session.blocks[1].content, "Going with SSE.");
+
+        assert!(matches!(
+            merge(&mut session, MAIN_BRANCH, &ids, None),
+            Err(ForkError::SameBranch(_))
+        ));
+        assert!(matches!(
+            delete(&mut session, MAIN_BRANCH),
+            Err(ForkError::Protected(_))
+        ));
+        delete(&mut session, &branch.id).expect("delete");
+        assert_eq!(list(&session).len(), 1);
+    }
+
+    #[test]
+    fn test_merge_main_into_child_uses_child_fork_point() {
+        let mut session = session();
+        let child = fork(&mut session, "child", None);
+        edit(&mut session, "c", "server.rs: 80 lines");
+
+        switch(&mut session, MAIN_BRANCH).expect("switch");
+        edit(&mut session, "a", "Use SSE.");
+        switch(&mut session, &child.id).expect("switch");
+
+        let ids: Vec<String> = ["a", "c"].map(String::from).to_vec();
+        let report = merge(&mut session, MAIN_BRANCH, &ids, None).expect("merge");
+        assert_eq!(report.merged, vec!["a"]);
+        assert!(report.conflicts.is_empty());
+        assert_eq!(session.blocks[0].content, "Use SSE.");
+        assert_eq!(session.blocks[2].content, "server.rs: 80 lines");
+    }
+
+    #[test]
+    fn test_merge_between_siblings_uses_older_fork_point() {
+        let mut session = session();
+        let early = fork(&mut session, "early", None);
+        edit(&mut session, "a", "Early idea.");
+
+        switch(&mut session, MAIN_BRANCH).expect("switch");
+        edit(&mut session, "c", "server.rs: 200 lines");
+        let late = fork(&mut session, "late", None);
+        edit(&mut session, "b", "Late idea.");
+
+        // `late` carries main's change to `c` since the common ancestor.
+        switch(&mut session, &early.id).expect("switch");
+        let ids: Vec<String> = ["a", "b", "c"].map(String::from).to_vec();
+        let report = merge(&mut session, &late.id, &ids, None).expect("merge");
+        assert_eq!(report.merged, vec!["b", "c"]);
+        assert!(report.conflicts.is_empty());
+        let contents: Vec<&str> = session.blocks.iter().map(|b| b.content.as_str()).collect();
+        assert_eq!(
+            contents,
+            vec!["Early idea.", "Late idea.", "server.rs: 200 lines"]
+        );
+    }
+}
@@ -0,0 +1,118 @@
+//! Ghost blocks: minimal placeholders for removed blocks.
+//!
+//! A ghost keeps what a reader needs to notice something was here and to
+//! put it back: a one-line summary, the token cost and where the block sat.
+//!
+//! ```text
+//! [Removed: src/config.py read by read_file — 847 tokens]
+//! ```
+
+use serde::{Deserialize, Serialize};
+
+use super::block::Block;
+use super::types::{Role, Zone};
+
+/// Longest ghost summary, in characters.
+const GHOST_SUMMARY_MAX_CHARS: usize = 60;
+
+/// Placeholder for a removed block.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Ghost {
+    pub block_id: String,
+    pub role: Role,
+    pub zone: Zone,
+    pub tokens: u32,
+    pub summary: String,
+    /// Index the block had when it was removed.
+    pub index: usize,
+    /// Block that preceded it, to restore it next to the same neighbour.
+    pub after_block_id: Option<String>,
+    /// Unix epoch milliseconds.
+    pub removed_at_ms: u64,
+}
+
+impl Ghost {
+    /// The one-line placeholder shown in the block's old position.
+    pub fn text(&self) -> String {
+        format!("[Removed: {} — {} tokens]", self.summary, self.tokens)
+    }
+}
+
+fn clip(line: &str) -> String {
+    match line.char_indices().nth(GHOST_SUMMARY_MAX_CHARS) {
+        Some((end, _)) => format!("{}…", &line[..end]),
+        None => line.to_string(),
+    }
+}
+
+/// One-line summary of `block`: the file a tool touched when known, else
+/// the block's first non-empty line.
+pub fn ghost_summary(block: &Block) -> String {
+    let tool = block.metadata.tool_name.as_deref();
+    if let Some(path) = block.metadata.file_paths.first() {
+        return match (block.role, tool) {
+            (Role::ToolResult, Some(tool)) => format!("{path} read by {tool}"),
+            (Role::ToolUse, Some(tool)) => format!("{tool} on {path}"),
+            _ => format!("{:?} message about {path}", block.role),
+        };
+    }
+    let line = block
+        .compressed_versions
+        .original
+        .content
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .unwrap_or_default();
+    match (tool, line.is_empty()) {
+        (Some(tool), true) => format!("{tool} output"),
+        (_, true) => format!("empty {:?} block", block.role),
+        (_, false) => clip(line),
+    }
+}
+
+/// Ghost of `blocks[index]`, about to be removed at `removed_at_ms`.
+pub fn generate_ghost(blocks: &[Block], index: usize, removed_at_ms: u64) -> Ghost {
+    let block = &blocks[index];
+    Ghost {
+        block_id: block.id.clone(),
+        role: block.role,
+        zone: block.zone.clone(),
+        tokens: block.tokens,
+        summary: ghost_summary(block),
+        index,
+        after_block_id: index.checked_sub(1).map(|i| blocks[i].id.clone()),
+        removed_at_ms,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::block::test_block;
+
+    #[test]
+    fn test_ghost_summarizes_file_reads_and_first_lines() {
+        let mut read = test_block("r", Role::ToolResult, "import os\n...");
+        read.metadata.tool_name = Some("read_file".to_string());
+        read.metadata.file_paths = vec!["src/config.py".to_string()];
+        let note = test_block("n", Role::Assistant, &format!("\n  {}", "x".repeat(80)));
+        let blocks = vec![note, read];
+
+        let ghost = generate_ghost(&blocks, 1, 7);
+        assert_eq!(ghost.summary, "src/config.py read by read_file");
+        assert_eq!(ghost.after_block_id.as_deref(), Some("n"));
+        assert_eq!(
+            ghost.text(),
+            format!(
+                "[Removed: src/config.py read by read_file — {} tokens]",
+                ghost.tokens
+            )
+        );
+
+        let ghost = generate_ghost(&blocks, 0, 7);
+        assert_eq!(ghost.summary, format!("{}…", "x".repeat(60)));
+        assert_eq!(ghost.after_block_id, None);
+    }
+}
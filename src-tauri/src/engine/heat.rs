@@ -0,0 +1,376 @@
+//! Usage heat: which blocks the model actually uses.
+//!
+//! After each response, [`analyze_response`] matches the assistant's output
+//! back to the blocks it drew on — quoted spans, file paths and file names,
+//! distinctive identifiers and tool-result ids — and records a reference on
+//! each. `usage_heat` is then recomputed for every block from its reference
+//! count and how long ago it was last referenced, and `position_relevance`
+//! from its zone and age.
+
+use std::collections::HashSet;
+use std::sync::OnceLock;
+
+use regex::Regex;
+use serde::Serialize;
+
+use super::block::Block;
+use super::compression::preserve::{PreserveKeys, PreserveKind};
+use super::manifest::is_manifest;
+use super::types::{BuiltInZone, Role, Zone};
+
+/// Heat analysis tuning.
+#[derive(Debug, Clone)]
+pub struct HeatConfig {
+    /// Turns after which a reference counts half as much.
+    pub half_life_turns: f64,
+    /// Shortest quoted span matched against block content, in characters.
+    pub min_quote_chars: usize,
+    /// Turns after which a Middle-zone block's position relevance halves.
+    pub middle_half_life_turns: f64,
+}
+
+impl Default for HeatConfig {
+    fn default() -> Self {
+        Self {
+            half_life_turns: 5.0,
+            min_quote_chars: 12,
+            middle_half_life_turns: 10.0,
+        }
+    }
+}
+
+/// How a response referred to a block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReferenceKind {
+    /// A quoted or code span copied from the block.
+    Quote,
+    /// A file path or file name the block read or mentions.
+    FilePath,
+    /// A distinctive identifier defined or used in the block.
+    Identifier,
+    /// The block's tool-use / tool-result id.
+    ToolResultId,
+}
+
+/// A block the response referred to.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlockReference {
+    pub block_id: String,
+    pub kinds: Vec<ReferenceKind>,
+}
+
+/// Outcome of analyzing one response.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HeatReport {
+    pub turn: u32,
+    pub references: Vec<BlockReference>,
+}
+
+/// What in a response can point back at a block.
+struct ResponseSignals<'a> {
+    text: &'a str,
+    quotes: Vec<&'a str>,
+    paths: Vec<String>,
+    identifiers: HashSet<&'a str>,
+}
+
+impl<'a> ResponseSignals<'a> {
+    fn extract(text: &'a str, config: &HeatConfig) -> Self {
+        let quotes = quoted_spans(text, config.min_quote_chars);
+        let paths = PreserveKeys::default()
+            .scan(text)
+            .into_iter()
+            .filter(|key| key.kind == PreserveKind::FilePath)
+            .map(|key| key.text)
+            .collect();
+        let identifiers = identifier_pattern()
+            .find_iter(text)
+            .map(|m| m.as_str())
+            .filter(|word| is_distinctive(word))
+            .collect();
+        Self {
+            text,
+            quotes,
+            paths,
+            identifiers,
+        }
+    }
+
+    fn references(&self, block: &Block) -> Vec<ReferenceKind> {
+        let mut kinds = Vec::new();
+        let content = block.compressed_versions.original.content.as_str();
+
+        if matches!(block.role, Role::ToolUse | Role::ToolResult)
+            && block.id.len() >= 8
+            && self.text.contains(&block.id)
+        {
+            kinds.push(ReferenceKind::ToolResultId);
+        }
+        if self.quotes.iter().any(|q| content.contains(q)) {
+            kinds.push(ReferenceKind::Quote);
+        }
+        if self.paths.iter().any(|path| {
+            block
+                .metadata
+                .file_paths
+                .iter()
+                .any(|known| same_file(known, path))
+                || (block.role == Role::ToolResult && content.contains(path.as_str()))
+        }) {
+            kinds.push(ReferenceKind::FilePath);
+        }
+        if !self.identifiers.is_empty()
+            && identifier_pattern()
+                .find_iter(content)
+                .any(|m| self.identifiers.contains(m.as_str()))
+        {
+            kinds.push(ReferenceKind::Identifier);
+        }
+        kinds
+    }
+}
+
+fn quote_pattern() -> &'static Regex {
+    static QUOTE: OnceLock<Regex> = OnceLock::new();
+    QUOTE.get_or_init(|| {
+        Regex::new(r#"`([^`\n]+)`|"([^"\n]+)"|“([^”\n]+)”"#).expect("valid quote pattern")
+    })
+}
+
+fn identifier_pattern() -> &'static Regex {
+    static IDENTIFIER: OnceLock<Regex> = OnceLock::new();
+    IDENTIFIER.get_or_init(|| {
+        Regex::new(r"[A-Za-z_][A-Za-z0-9_]*(?:::[A-Za-z_][A-Za-z0-9_]*)*")
+            .expect("valid identifier pattern")
+    })
+}
+
+/// Quoted spans and fenced code lines of at least `min_chars` characters.
+pub(crate) fn quoted_spans(text: &str, min_chars: usize) -> Vec<&str> {
+    quote_pattern()
+        .captures_iter(text)
+        .filter_map(|c| c.iter().skip(1).flatten().next())
+        .map(|m| m.as_str().trim())
+        .chain(code_lines(text))
+        .filter(|q| q.chars().count() >= min_chars)
+        .collect()
+}
+
+/// Lines inside fenced code blocks.
+fn code_lines(text: &str) -> impl Iterator<Item = &str> {
+    let mut in_fence = false;
+    text.lines().filter_map(move |line| {
+        let trimmed = line.trim();
+        if trimmed.starts_with("```") {
+            in_fence = !in_fence;
+            return None;
+        }
+        in_fence.then_some(trimmed)
+    })
+}
+
+/// Identifiers unlikely to be ordinary prose: `snake_case`, `camelCase`,
+/// `PascalCase` with an inner capital, or `path::qualified`.
+fn is_distinctive(word: &str) -> bool {
+    if word.len() < 4 {
+        return false;
+    }
+    let inner_capital = word
+        .char_indices()
+        .skip(1)
+        .any(|(i, c)| c.is_ascii_uppercase() && word[..i].chars().any(|p| p.is_ascii_lowercase()));
+    word.contains("::") || word.trim_matches('_').contains('_') || inner_capital
+}
+
+/// Whether two paths name the same file: equal, or one is a suffix of the
+/// other at a path boundary (`src/main.rs` vs `/repo/src/main.rs`), or the
+/// mention is the bare file name.
+pub(crate) fn same_file(known: &str, mentioned: &str) -> bool {
+    let (long, short) = if known.len() >= mentioned.len() {
+        (known, mentioned)
+    } else {
+        (mentioned, known)
+    };
+    long.strip_suffix(short)
+        .is_some_and(|prefix| prefix.is_empty() || prefix.ends_with(['/', '\\']))
+}
+
+/// Find the blocks `response` refers to, record the references, and
+/// refresh every block's `usage_heat` and `position_relevance`. The
+/// manifest block is skipped and stays cold.
+///
+/// Call once per reassembled response with the turn it completes.
+pub fn analyze_response(
+    blocks: &mut [Block],
+    response: &str,
+    current_turn: u32,
+    config: &HeatConfig,
+) -> HeatReport {
+    let signals = ResponseSignals::extract(response, config);
+    let mut references = Vec::new();
+
+    for block in blocks.iter_mut().filter(|b| !is_manifest(b)) {
+        let kinds = signals.references(block);
+        if !kinds.is_empty() {
+            block.reference_count = block.reference_count.saturating_add(1);
+            block.last_referenced_turn = current_turn;
+            references.push(BlockReference {
+                block_id: block.id.clone(),
+                kinds,
+            });
+        }
+        block.usage_heat = usage_heat(block, current_turn, config);
+        block.position_relevance = position_relevance(block, current_turn, config);
+    }
+
+    HeatReport {
+        turn: current_turn,
+        references,
+    }
+}
+
+/// Recency-weighted heat in 0.0-1.0.
+///
+/// Each reference halves the remaining distance to 1.0, and the result
+/// halves every `half_life_turns` since the last reference.
+pub fn usage_heat(block: &Block, current_turn: u32, config: &HeatConfig) -> f64 {
+    if block.reference_count == 0 {
+        return 0.0;
+    }
+    let saturation = 1.0 - 0.5f64.powi(block.reference_count.min(32) as i32);
+    let since = current_turn.saturating_sub(block.last_referenced_turn) as f64;
+    saturation * 0.5f64.powf(since / config.half_life_turns.max(f64::EPSILON))
+}
+
+/// Predicted attention from position, in 0.0-1.0.
+///
+/// The model attends most to the start and end of its context; Middle-zone
+/// blocks start lower and decay with age.
+pub fn position_relevance(block: &Block, current_turn: u32, config: &HeatConfig) -> f64 {
+    match &block.zone {
+        Zone::BuiltIn(BuiltInZone::Primacy) if block.pinned.is_some() => 1.0,
+        Zone::BuiltIn(BuiltInZone::Primacy) => 0.9,
+        Zone::BuiltIn(BuiltInZone::Recency) => 0.8,
+        Zone::BuiltIn(BuiltInZone::Middle) | Zone::Custom(_) => {
+            let age = current_turn.saturating_sub(block.metadata.turn_index) as f64;
+            let decay = 0.5f64.powf(age / config.middle_half_life_turns.max(f64::EPSILON));
+            0.1 + 0.5 * decay
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::block::test_block;
+
+    fn analyze(blocks: &mut [Block], response: &str) -> Vec<(String, Vec<ReferenceKind>)> {
+        analyze_response(blocks, response, 10, &HeatConfig::default())
+            .references
+            .into_iter()
+            .map(|r| (r.block_id, r.kinds))
+            .collect()
+    }
+
+    #[test]
+    fn test_analyze_matches_file_paths_and_names() {
+        let mut read = test_block("read", Role::ToolResult, "fn main() {}");
+        read.metadata.file_paths = vec!["/repo/src/main.rs".to_string()];
+        let mut other = test_block("other", Role::ToolResult, "[package]");
+        other.metadata.file_paths = vec!["/repo/Cargo.toml".to_string()];
+        let mut blocks = vec![read, other];
+
+        let refs = analyze(&mut blocks, "I updated src/main.rs to call the parser.");
+        assert_eq!(
+            refs,
+            vec![("read".to_string(), vec![ReferenceKind::FilePath])]
+        );
+
+        let refs = analyze(&mut blocks, "The entry point lives in main.rs.");
+        assert_eq!(refs[0].0, "read");
+        assert_eq!(blocks[0].reference_count, 2);
+        assert_eq!(blocks[0].last_referenced_turn, 10);
+        assert_eq!(blocks[1].reference_count, 0);
+    }
+
+    #[test]
+    fn test_analyze_matches_quotes_and_identifiers() {
+        let log = test_block(
+            "log",
+            Role::ToolResult,
+            "thread 'main' panicked at 'index out of bounds'",
+        );
+        let code = test_block(
+            "code",
+            Role::Assistant,
+            "pub struct MarketStream { retry_limit: u32 }",
+        );
+        let prose = test_block(
+            "prose",
+            Role::User,
+            "Please make this work with the stream.",
+        );
+        let mut blocks = vec![log, code, prose];
+
+        let refs = analyze(
+            &mut blocks,
+            "The panic \"index out of bounds\" comes from MarketStream when \
+             retry_limit is zero. This should work with the stream.",
+        );
+        assert_eq!(
+            refs,
+            vec![
+                ("log".to_string(), vec![ReferenceKind::Quote]),
+                ("code".to_string(), vec![ReferenceKind::Identifier]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_analyze_matches_tool_result_id() {
+        let result = test_block("toolu_01AbCdEf", Role::ToolResult, "ok");
+        let mut blocks = vec![result];
+
+        let refs = analyze(&mut blocks, "Per toolu_01AbCdEf, the tests passed.");
+        assert_eq!(refs[0].1, vec![ReferenceKind::ToolResultId]);
+    }
+
+    #[test]
+    fn test_usage_heat_saturates_and_decays() {
+        let config = HeatConfig::default();
+        let mut block = test_block("b", Role::ToolResult, "x");
+        assert_eq!(usage_heat(&block, 10, &config), 0.0);
+
+        block.reference_count = 1;
+        block.last_referenced_turn = 10;
+        let once = usage_heat(&block, 10, &config);
+        block.reference_count = 40;
+        let often = usage_heat(&block, 10, &config);
+        let later = usage_heat(&block, 15, &config);
+
+        assert!((once - 0.5).abs() < 1e-9);
+        assert!(often > once && often <= 1.0);
+        assert!((later - often / 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_position_relevance_by_zone_and_age() {
+        let config = HeatConfig::default();
+        let mut block = test_block("b", Role::User, "x");
+        block.metadata.turn_index = 0;
+        let fresh_middle = position_relevance(&block, 0, &config);
+        let old_middle = position_relevance(&block, 30, &config);
+        block.zone = Zone::BuiltIn(BuiltInZone::Recency);
+        let recency = position_relevance(&block, 30, &config);
+        block.zone = Zone::BuiltIn(BuiltInZone::Primacy);
+        let primacy = position_relevance(&block, 30, &config);
+
+        assert!((fresh_middle - 0.6).abs() < 1e-9);
+        assert!(old_middle < fresh_middle && old_middle >= 0.1);
+        assert!(recency > fresh_middle);
+        assert!(primacy > recency);
+    }
+}
@@ -0,0 +1,561 @@
+//! Block edit history.
+//!
+//! Every hot-patch of a block's content is appended to the block's
+//! `edits`, with who made it, when and why. Version 0 is the content before
+//! the first edit and version `n` the content after edit `n`. Edits are
+//! never rewritten: reverting one appends a new edit that undoes only that
+//! edit's lines, keeping every later change.
+
+use serde::{Deserialize, Serialize};
+use tauri::State;
+use thiserror::Error;
+
+use super::block::{Block, CompressionVersion};
+use super::session::SharedSession;
+use super::types::CompressionLevel;
+use crate::events::timeline::now_ms;
+
+/// Who made an edit.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(
+    tag = "kind",
+    rename_all = "snake_case",
+    rename_all_fields = "camelCase"
+)]
+pub enum EditAuthor {
+    User,
+    Rule { rule_id: String },
+    Plugin { name: String },
+    ModelCommand,
+}
+
+/// One recorded change to a block's content.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlockEdit {
+    /// 1-based and sequential within the block; also the version it made.
+    pub id: u32,
+    pub author: EditAuthor,
+    /// Unix epoch milliseconds.
+    pub at_ms: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    pub before: String,
+    pub after: String,
+    /// The edit this one reverted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reverts: Option<u32>,
+}
+
+#[derive(Debug, Error)]
+pub enum HistoryError {
+    #[error("Block not found: {0}")]
+    BlockNotFound(String),
+
+    #[error("Block {block_id} has no version {version}")]
+    VersionNotFound { block_id: String, version: u32 },
+
+    #[error("Edit {edit} of block {block_id} was already reverted")]
+    NothingToRevert { block_id: String, edit: u32 },
+
+    #[error("Later edits changed the lines edit {edit} of block {block_id} touched")]
+    Conflict { block_id: String, edit: u32 },
+}
+
+impl serde::Serialize for HistoryError {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+/// Kind of a line in a version diff.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiffLineType {
+    Added,
+    Removed,
+    Unchanged,
+}
+
+/// A line of a version diff, in the shape of the frontend's `DiffLine`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiffLine {
+    #[serde(rename = "type")]
+    pub kind: DiffLineType,
+    pub content: String,
+    /// 1-based.
+    pub old_line_num: Option<usize>,
+    pub new_line_num: Option<usize>,
+}
+
+/// Lines left to align after trimming the common prefix and suffix above
+/// which a change is treated as replacing them wholesale; aligning costs
+/// time quadratic in this.
+const MAX_DIFF_LINES: usize = 4_000;
+
+/// Index pairs of a longest common subsequence of `a` and `b`, in order.
+///
+/// The common prefix and suffix always pair up. The rest is aligned in
+/// linear space (Hirschberg), or not at all past [`MAX_DIFF_LINES`].
+fn lcs_pairs(a: &[&str], b: &[&str]) -> Vec<(usize, usize)> {
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    let (a_mid, b_mid) = (&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix]);
+
+    let mut pairs: Vec<(usize, usize)> = (0..prefix).map(|i| (i, i)).collect();
+    if a_mid.len() <= MAX_DIFF_LINES && b_mid.len() <= MAX_DIFF_LINES {
+        align(a_mid, b_mid, (prefix, prefix), &mut pairs);
+    }
+    let (a_tail, b_tail) = (a.len() - suffix, b.len() - suffix);
+    pairs.extend((0..suffix).map(|k| (a_tail + k, b_tail + k)));
+    pairs
+}
+
+/// LCS lengths of `a` against each prefix of `b`.
+fn lcs_row<'a>(a: impl Iterator<Item = &'a str>, b: &[&'a str]) -> Vec<u32> {
+    let mut row = vec![0u32; b.len() + 1];
+    for x in a {
+        let mut diagonal = 0;
+        for (j, y) in b.iter().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = if x == *y {
+                diagonal + 1
+            } else {
+                above.max(row[j])
+            };
+            diagonal = above;
+        }
+    }
+    row
+}
+
+/// Append the LCS pairs of `a` and `b`, offset by `at`, splitting `a` in
+/// half and `b` where the two halves' LCS lengths sum highest.
+fn align(a: &[&str], b: &[&str], at: (usize, usize), pairs: &mut Vec<(usize, usize)>) {
+    if a.is_empty() || b.is_empty() {
+        return;
+    }
+    if a.len() == 1 {
+        if let Some(j) = b.iter().position(|y| *y == a[0]) {
+            pairs.push((at.0, at.1 + j));
+        }
+        return;
+    }
+    let mid = a.len() / 2;
+    let forward = lcs_row(a[..mid].iter().copied(), b);
+    let reversed: Vec<&str> = b.iter().rev().copied().collect();
+    let backward = lcs_row(a[mid..].iter().rev().copied(), &reversed);
+    let split = (0..=b.len())
+        .max_by_key(|&j| (forward[j] + backward[b.len() - j], std::cmp::Reverse(j)))
+        .unwrap_or(0);
+    align(&a[..mid], &b[..split], at, pairs);
+    align(&a[mid..], &b[split..], (at.0 + mid, at.1 + split), pairs);
+}
+
+/// Line diff from `before` to `after`; removals precede additions within
+/// a change.
+pub fn diff_lines(before: &str, after: &str) -> Vec<DiffLine> {
+    let old: Vec<&str> = before.split('\n').collect();
+    let new: Vec<&str> = after.split('\n').collect();
+    let mut lines = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    let sentinel = (old.len(), new.len());
+    for (mi, mj) in lcs_pairs(&old, &new).into_iter().chain([sentinel]) {
+        lines.extend((i..mi).map(|k| DiffLine {
+            kind: DiffLineType::Removed,
+            content: old[k].to_string(),
+            old_line_num: Some(k + 1),
+            new_line_num: None,
+        }));
+        lines.extend((j..mj).map(|k| DiffLine {
+            kind: DiffLineType::Added,
+            content: new[k].to_string(),
+            old_line_num: None,
+            new_line_num: Some(k + 1),
+        }));
+        if (mi, mj) != sentinel {
+            lines.push(DiffLine {
+                kind: DiffLineType::Unchanged,
+                content: old[mi].to_string(),
+                old_line_num: Some(mi + 1),
+                new_line_num: Some(mj + 1),
+            });
+        }
+        (i, j) = (mi + 1, mj + 1);
+    }
+    lines
+}
+
+/// Lines `start..end` of a text replaced by `replacement`.
+struct Hunk<'a> {
+    start: usize,
+    end: usize,
+    replacement: &'a [&'a str],
+}
+
+fn hunks<'a>(from: &[&str], to: &'a [&'a str]) -> Vec<Hunk<'a>> {
+    let mut hunks = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    let sentinel = (from.len(), to.len());
+    for (mi, mj) in lcs_pairs(from, to).into_iter().chain([sentinel]) {
+        if mi > i || mj > j {
+            hunks.push(Hunk {
+                start: i,
+                end: mi,
+                replacement: &to[j..mj],
+            });
+        }
+        (i, j) = (mi + 1, mj + 1);
+    }
+    hunks
+}
+
+/// `current` with `edit` undone, or `None` when later changes overlap the
+/// lines it touched.
+fn reverse_apply(edit: &BlockEdit, current: &str) -> Option<String> {
+    let after: Vec<&str> = edit.after.split('\n').collect();
+    let before: Vec<&str> = edit.before.split('\n').collect();
+    let mut lines: Vec<&str> = current.split('\n').collect();
+
+    // Where each line of the edit's result sits in the current content.
+    let mut position = vec![None; after.len()];
+    for (a, c) in lcs_pairs(&after, &lines) {
+        position[a] = Some(c);
+    }
+
+    let mut splices = Vec::new();
+    for hunk in hunks(&after, &before) {
+        let (start, end) = if hunk.start < hunk.end {
+            let first = position[hunk.start]?;
+            // The replaced lines must all survive, still adjacent.
+            for (offset, k) in (hunk.start..hunk.end).enumerate() {
+                if position[k]? != first + offset {
+                    return None;
+                }
+            }
+            (first, first + (hunk.end - hunk.start))
+        } else {
+            // Pure insertion: both neighbours must survive, still adjacent.
+            let prev = match hunk.start.checked_sub(1) {
+                Some(k) => Some(position[k]?),
+                None => None,
+            };
+            let next = match position.get(hunk.start) {
+                Some(p) => Some((*p)?),
+                None => None,
+            };
+            let at = match (prev, next) {
+                (Some(p), Some(n)) if n != p + 1 => return None,
+                (Some(p), _) => p + 1,
+                (None, Some(n)) => n,
+                (None, None) => lines.len(),
+            };
+            (at, at)
+        };
+        splices.push((start, end, hunk.replacement));
+    }
+    for (start, end, replacement) in splices.into_iter().rev() {
+        lines.splice(start..end, replacement.iter().copied());
+    }
+    Some(lines.join("\n"))
+}
+
+/// Content of `block` at `version`; the last version is the current
+/// original content.
+pub fn version_content(block: &Block, version: u32) -> Result<&str, HistoryError> {
+    if version == 0 {
+        return Ok(block
+            .edits
+            .first()
+            .map_or(&block.compressed_versions.original.content, |e| &e.before));
+    }
+    block
+        .edits
+        .get(version as usize - 1)
+        .map(|e| e.after.as_str())
+        .ok_or_else(|| HistoryError::VersionNotFound {
+            block_id: block.id.clone(),
+            version,
+        })
+}
+
+/// Replace the block's original content with `content` and record the
+/// edit. Compressed versions of the old content are dropped and the block
+/// shows the new original. Returns `None`, keeping the compressed versions,
+/// if `content` is what the block already shows or its original.
+pub fn edit<'a>(
+    block: &'a mut Block,
+    content: &str,
+    author: EditAuthor,
+    reason: Option<String>,
+) -> Option<&'a BlockEdit> {
+    if block.content == content {
+        return None;
+    }
+    record(block, content, author, reason, None)
+}
+
+fn record<'a>(
+    block: &'a mut Block,
+    content: &str,
+    author: EditAuthor,
+    reason: Option<String>,
+    reverts: Option<u32>,
+) -> Option<&'a BlockEdit> {
+    let before = &block.compressed_versions.original.content;
+    if before == content {
+        return None;
+    }
+    let edit = BlockEdit {
+        id: block.edits.len() as u32 + 1,
+        author,
+        at_ms: now_ms(),
+        reason,
+        before: before.clone(),
+        after: content.to_string(),
+        reverts,
+    };
+    let versions = &mut block.compressed_versions;
+    versions.original = CompressionVersion::new(content.to_string());
+    versions.trimmed = None;
+    versions.summarized = None;
+    versions.minimal = None;
+    block.set_compression_level(CompressionLevel::Original);
+    block.edits.push(edit);
+    block.edits.last()
+}
+
+/// Undo edit `edit_id` on the current content, as a new edit by `author`.
+pub fn revert(
+    block: &mut Block,
+    edit_id: u32,
+    author: EditAuthor,
+) -> Result<&BlockEdit, HistoryError> {
+    if block.edits.iter().any(|e| e.reverts == Some(edit_id)) {
+        return Err(HistoryError::NothingToRevert {
+            block_id: block.id.clone(),
+            edit: edit_id,
+        });
+    }
+    let target = edit_id
+        .checked_sub(1)
+        .and_then(|i| block.edits.get(i as usize))
+        .ok_or_else(|| HistoryError::VersionNotFound {
+            block_id: block.id.clone(),
+            version: edit_id,
+        })?;
+    let content =
+        reverse_apply(target, &block.compressed_versions.original.content).ok_or_else(|| {
+            HistoryError::Conflict {
+                block_id: block.id.clone(),
+                edit: edit_id,
+            }
+        })?;
+    let block_id = block.id.clone();
+    let reason = Some(format!("Revert edit {edit_id}"));
+    record(block, &content, author, reason, Some(edit_id)).ok_or(HistoryError::NothingToRevert {
+        block_id,
+        edit: edit_id,
+    })
+}
+
+fn find<'a>(blocks: &'a mut [Block], block_id: &str) -> Result<&'a mut Block, HistoryError> {
+    blocks
+        .iter_mut()
+        .find(|b| b.id == block_id)
+        .ok_or_else(|| HistoryError::BlockNotFound(block_id.to_string()))
+}
+
+/// Hot-patch a block's content as the user.
+#[tauri::command]
+pub fn block_edit(
+    session: State<'_, SharedSession>,
+    block_id: String,
+    content: String,
+    reason: Option<String>,
+) -> Result<Option<BlockEdit>, HistoryError> {
+    let mut session = session.lock();
+    let block = find(&mut session.blocks, &block_id)?;
+    Ok(edit(block, &content, EditAuthor::User, reason).cloned())
+}
+
+/// Every edit of a block, oldest first.
+#[tauri::command]
+pub fn block_history(
+    session: State<'_, SharedSession>,
+    block_id: String,
+) -> Result<Vec<BlockEdit>, HistoryError> {
+    let mut session = session.lock();
+    Ok(find(&mut session.blocks, &block_id)?.edits.clone())
+}
+
+#[tauri::command]
+pub fn block_version_diff(
+    session: State<'_, SharedSession>,
+    block_id: String,
+    from: u32,
+    to: u32,
+) -> Result<Vec<DiffLine>, HistoryError> {
+    let mut session = session.lock();
+    let block = find(&mut session.blocks, &block_id)?;
+    Ok(diff_lines(
+        version_content(block, from)?,
+        version_content(block, to)?,
+    ))
+}
+
+/// Revert one edit, keeping later ones.
+#[tauri::command]
+pub fn block_revert_edit(
+    session: State<'_, SharedSession>,
+    block_id: String,
+    edit_id: u32,
+) -> Result<BlockEdit, HistoryError> {
+    let mut session = session.lock();
+    let block = find(&mut session.blocks, &block_id)?;
+    revert(block, edit_id, EditAuthor::User).cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::block::test_block;
+    use crate::engine::types::Role;
+
+    fn block() -> Block {
+        let mut block = test_block("b", Role::System, "one\ntwo\nthree\nfour");
+        block.compressed_versions.trimmed = Some(CompressionVersion::new("one".to_string()));
+        block.set_compression_level(CompressionLevel::Trimmed);
+        block
+    }
+
+    #[test]
+    fn test_edit_appends_history_and_resets_compression() {
+        let mut block = block();
+        let first = edit(&mut block, "one\n2\nthree\nfour", EditAuthor::User, None)
+            .expect("edit")
+            .clone();
+        assert_eq!(first.id, 1);
+        assert_eq!(first.before, "one\ntwo\nthree\nfour");
+        assert!(edit(&mut block, "one\n2\nthree\nfour", EditAuthor::User, None).is_none());
+
+        block.compressed_versions.trimmed = Some(CompressionVersion::new("one".to_string()));
+        block.set_compression_level(CompressionLevel::Trimmed);
+        assert!(edit(&mut block, "one", EditAuthor::User, None).is_none());
+        assert_eq!(block.compression_level, CompressionLevel::Trimmed);
+        assert_eq!(block.edits.len(), 1);
+
+        let rule = EditAuthor::Rule {
+            rule_id: "r1".to_string(),
+        };
+        edit(
+            &mut block,
+            "one\n2\nthree",
+            rule.clone(),
+            Some("drop footer".into()),
+        );
+        assert_eq!(block.edits.len(), 2);
+        assert_eq!(block.edits[1].author, rule);
+        assert_eq!(
+            serde_json::to_value(&rule).unwrap(),
+            serde_json::json!({ "kind": "rule", "ruleId": "r1" })
+        );
+        assert_eq!(block.content, "one\n2\nthree");
+        assert_eq!(block.compression_level, CompressionLevel::Original);
+        assert!(block.compressed_versions.trimmed.is_none());
+
+        assert_eq!(
+            version_content(&block, 0).expect("v0"),
+            "one\ntwo\nthree\nfour"
+        );
+        assert_eq!(version_content(&block, 2).expect("v2"), "one\n2\nthree");
+        assert!(matches!(
+            version_content(&block, 3),
+            Err(HistoryError::VersionNotFound { version: 3, .. })
+        ));
+    }
+
+    #[test]
+    fn test_diff_lines_marks_changes_with_line_numbers() {
+        let diff = diff_lines("a\nb\nc", "a\nB\nc\nd");
+        let kinds: Vec<_> = diff.iter().map(|l| (l.kind, l.content.as_str())).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (DiffLineType::Unchanged, "a"),
+                (DiffLineType::Removed, "b"),
+                (DiffLineType::Added, "B"),
+                (DiffLineType::Unchanged, "c"),
+                (DiffLineType::Added, "d"),
+            ]
+        );
+        assert_eq!(diff[2].new_line_num, Some(2));
+        assert_eq!(diff[1].old_line_num, Some(2));
+    }
+
+    #[test]
+    fn test_lcs_pairs_align_in_linear_space_up_to_cap() {
+        let a = ["x", "a", "b", "c", "d", "y"];
+        let b = ["x", "b", "a", "c", "e", "d", "y"];
+        let pairs = lcs_pairs(&a, &b);
+        assert_eq!(pairs.len(), 5);
+        assert!(pairs.windows(2).all(|w| w[0].0 < w[1].0 && w[0].1 < w[1].1));
+        assert!(pairs.iter().all(|&(i, j)| a[i] == b[j]));
+
+        // Past the cap the middle is replaced wholesale.
+        let old: Vec<String> = (0..=MAX_DIFF_LINES).map(|i| i.to_string()).collect();
+        let mut new = old.clone();
+        new.rotate_left(1);
+        let mut old: Vec<&str> = old.iter().map(String::as_str).collect();
+        let mut new: Vec<&str> = new.iter().map(String::as_str).collect();
+        old.insert(0, "head");
+        new.insert(0, "head");
+        assert_eq!(lcs_pairs(&old, &new), vec![(0, 0)]);
+    }
+
+    #[test]
+    fn test_revert_undoes_one_edit_and_keeps_later_ones() {
+        let mut block = block();
+        edit(&mut block, "one\n2\nthree\nfour", EditAuthor::User, None);
+        edit(
+            &mut block,
+            "one\n2\nthree\nfour\nfive",
+            EditAuthor::ModelCommand,
+            None,
+        );
+        edit(&mut block, "one\n2\n3\nfour\nfive", EditAuthor::User, None);
+
+        let reverted = revert(&mut block, 1, EditAuthor::User)
+            .expect("revert")
+            .clone();
+        assert_eq!(reverted.id, 4);
+        assert_eq!(reverted.reverts, Some(1));
+        assert_eq!(block.content, "one\ntwo\n3\nfour\nfive");
+        assert_eq!(block.edits.len(), 4);
+
+        // Edit 3 changed "three"; after editing that line again it conflicts.
+        edit(
+            &mut block,
+            "one\ntwo\nTHREE\nfour\nfive",
+            EditAuthor::User,
+            None,
+        );
+        assert!(matches!(
+            revert(&mut block, 3, EditAuthor::User),
+            Err(HistoryError::Conflict { edit: 3, .. })
+        ));
+        assert!(matches!(
+            revert(&mut block, 1, EditAuthor::User),
+            Err(HistoryError::NothingToRevert { edit: 1, .. })
+        ));
+    }
+}
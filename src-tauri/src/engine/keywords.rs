@@ -0,0 +1,319 @@
+//! Local keyword extraction.
+//!
+//! Blocks are tokenized with code identifiers split into their words
+//! (`MarketStream` → `market`, `stream`, `marketstream`; `retry_limit` →
+//! `retry`, `limit`, `retry_limit`) and ranked by TF-IDF against the
+//! document frequencies of every block seen in the session.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::OnceLock;
+
+use regex::Regex;
+
+/// Keywords stored on a block.
+pub const KEYWORDS_PER_BLOCK: usize = 8;
+
+/// Shortest token kept, in characters.
+const MIN_TOKEN_LEN: usize = 3;
+
+/// English function words and common programming keywords.
+const STOPWORDS: &[&str] = &[
+    "about",
+    "above",
+    "after",
+    "again",
+    "all",
+    "also",
+    "and",
+    "any",
+    "are",
+    "because",
+    "been",
+    "before",
+    "being",
+    "below",
+    "between",
+    "both",
+    "but",
+    "can",
+    "could",
+    "did",
+    "does",
+    "doing",
+    "done",
+    "down",
+    "each",
+    "few",
+    "for",
+    "from",
+    "further",
+    "had",
+    "has",
+    "have",
+    "having",
+    "her",
+    "here",
+    "hers",
+    "him",
+    "his",
+    "how",
+    "into",
+    "its",
+    "itself",
+    "just",
+    "let",
+    "like",
+    "more",
+    "most",
+    "much",
+    "must",
+    "need",
+    "not",
+    "now",
+    "off",
+    "once",
+    "only",
+    "other",
+    "our",
+    "out",
+    "over",
+    "own",
+    "please",
+    "same",
+    "she",
+    "should",
+    "some",
+    "such",
+    "than",
+    "that",
+    "the",
+    "their",
+    "them",
+    "then",
+    "there",
+    "these",
+    "they",
+    "this",
+    "those",
+    "through",
+    "too",
+    "under",
+    "until",
+    "use",
+    "used",
+    "using",
+    "very",
+    "was",
+    "way",
+    "were",
+    "what",
+    "when",
+    "where",
+    "which",
+    "while",
+    "who",
+    "why",
+    "will",
+    "with",
+    "would",
+    "yes",
+    "you",
+    "your",
+    "async",
+    "await",
+    "bool",
+    "break",
+    "class",
+    "const",
+    "continue",
+    "def",
+    "else",
+    "enum",
+    "false",
+    "impl",
+    "import",
+    "mut",
+    "new",
+    "none",
+    "null",
+    "pub",
+    "return",
+    "self",
+    "static",
+    "str",
+    "string",
+    "struct",
+    "true",
+    "type",
+    "undefined",
+    "usize",
+    "var",
+    "void",
+    "fn",
+    "u32",
+    "u64",
+    "i32",
+    "i64",
+    "f32",
+    "f64",
+    "vec",
+    "option",
+    "result",
+    "err",
+    "ok",
+];
+
+fn stopwords() -> &'static HashSet<&'static str> {
+    static STOP: OnceLock<HashSet<&'static str>> = OnceLock::new();
+    STOP.get_or_init(|| STOPWORDS.iter().copied().collect())
+}
+
+fn word_pattern() -> &'static Regex {
+    static WORD: OnceLock<Regex> = OnceLock::new();
+    WORD.get_or_init(|| Regex::new(r"[A-Za-z_][A-Za-z0-9_]*").expect("valid word pattern"))
+}
+
+/// Split an identifier at underscores and lower-to-upper case changes.
+fn split_identifier(word: &str) -> Vec<String> {
+    let mut parts = Vec::new();
+    for piece in word.split('_').filter(|p| !p.is_empty()) {
+        let mut current = String::new();
+        let chars: Vec<char> = piece.chars().collect();
+        for (i, &c) in chars.iter().enumerate() {
+            let boundary = c.is_ascii_uppercase()
+                && i > 0
+                && (chars[i - 1].is_ascii_lowercase()
+                    || chars.get(i + 1).is_some_and(|n| n.is_ascii_lowercase()));
+            if boundary && !current.is_empty() {
+                parts.push(std::mem::take(&mut current));
+            }
+            current.push(c.to_ascii_lowercase());
+        }
+        if !current.is_empty() {
+            parts.push(current);
+        }
+    }
+    parts
+}
+
+fn keep(token: &str) -> bool {
+    token.len() >= MIN_TOKEN_LEN
+        && !token.chars().all(|c| c.is_ascii_digit() || c == '_')
+        && !stopwords().contains(token)
+}
+
+/// Lowercased tokens of `text`, with compound identifiers contributing both
+/// their parts and the whole.
+pub fn tokenize(text: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    for word in word_pattern().find_iter(text).map(|m| m.as_str()) {
+        let parts = split_identifier(word);
+        if parts.len() > 1 {
+            let whole = word.to_ascii_lowercase();
+            if keep(&whole) {
+                tokens.push(whole);
+            }
+        }
+        tokens.extend(parts.into_iter().filter(|p| keep(p)));
+    }
+    tokens
+}
+
+/// Session-wide document frequencies for TF-IDF.
+#[derive(Debug, Clone, Default)]
+pub struct KeywordIndex {
+    documents: u32,
+    document_frequency: HashMap<String, u32>,
+}
+
+impl KeywordIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count `text` as one more document in the session.
+    pub fn add_document(&mut self, text: &str) {
+        self.documents += 1;
+        let unique: HashSet<String> = tokenize(text).into_iter().collect();
+        for token in unique {
+            *self.document_frequency.entry(token).or_default() += 1;
+        }
+    }
+
+    pub fn documents(&self) -> u32 {
+        self.documents
+    }
+
+    /// Smoothed inverse document frequency of `token`.
+    fn idf(&self, token: &str) -> f64 {
+        let df = self.document_frequency.get(token).copied().unwrap_or(0);
+        ((1.0 + self.documents as f64) / (1.0 + df as f64)).ln() + 1.0
+    }
+
+    /// Up to `limit` keywords of `text` with their TF-IDF scores, best first.
+    pub fn keywords(&self, text: &str, limit: usize) -> Vec<(String, f64)> {
+        let tokens = tokenize(text);
+        if tokens.is_empty() {
+            return Vec::new();
+        }
+        let mut counts: HashMap<String, u32> = HashMap::new();
+        for token in tokens.iter() {
+            *counts.entry(token.clone()).or_default() += 1;
+        }
+
+        let total = tokens.len() as f64;
+        let mut scored: Vec<(String, f64)> = counts
+            .into_iter()
+            .map(|(token, count)| {
+                let score = (count as f64 / total) * self.idf(&token);
+                (token, score)
+            })
+            .collect();
+        scored.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        scored.truncate(limit);
+        scored
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokenize_splits_code_identifiers() {
+        let tokens = tokenize("impl MarketStream { fn retry_limit(&self) -> u32 } HTTPServer");
+
+        for expected in [
+            "marketstream",
+            "market",
+            "stream",
+            "retry_limit",
+            "retry",
+            "limit",
+            "httpserver",
+            "http",
+            "server",
+        ] {
+            assert!(tokens.contains(&expected.to_string()), "missing {expected}");
+        }
+        assert!(!tokens
+            .iter()
+            .any(|t| t == "impl" || t == "self" || t == "fn"));
+    }
+
+    #[test]
+    fn test_keywords_prefer_terms_rare_in_session() {
+        let mut index = KeywordIndex::new();
+        for text in [
+            "the websocket client reconnects",
+            "the config loader reads files",
+            "the websocket handshake fails with a TLS error",
+        ] {
+            index.add_document(text);
+        }
+
+        let keywords = index.keywords("websocket handshake with TLS handshake", 2);
+        let names: Vec<&str> = keywords.iter().map(|(k, _)| k.as_str()).collect();
+        assert_eq!(names, vec!["handshake", "tls"]);
+    }
+}
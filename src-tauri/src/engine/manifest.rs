@@ -0,0 +1,494 @@
+//! Context manifest: a compact description of the context, injected into
+//! each request so the model knows what it has, what was compressed, and
+//! what it can recall.
+//!
+//! The manifest is regenerated every turn from the blocks the request
+//! carries, never from the previous manifest. It is off by default. It is
+//! injected either into the request's system prompt or as a dedicated
+//! Primacy block leading the first user message; either way it is left
+//! out of heat and token accounting.
+
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+use serde_json::{json, Value};
+use tauri::State;
+
+use super::block::Block;
+use super::budget::BudgetConfig;
+use super::clustering::TopicClusters;
+use super::dedup::format_tokens;
+use super::memory::MemoryState;
+use super::outbound::sent_mask;
+use super::session::SharedSession;
+use super::tokens::count_tokens;
+use super::types::{BuiltInZone, CompressionLevel, PinPosition, Zone};
+
+/// `block_type` of the Primacy block carrying the manifest.
+pub const MANIFEST_BLOCK_TYPE: &str = "context_manifest";
+
+/// Id of the Primacy block carrying the manifest.
+pub const MANIFEST_BLOCK_ID: &str = "context-manifest";
+
+/// Where the manifest goes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ManifestPlacement {
+    /// Appended to the request's system prompt.
+    #[default]
+    SystemPrompt,
+    /// Kept as a pinned block at the top of the Primacy zone.
+    PrimacyBlock,
+}
+
+/// Per-session manifest settings.
+#[derive(Debug, Clone)]
+pub struct ManifestConfig {
+    pub enabled: bool,
+    pub placement: ManifestPlacement,
+    /// Hard cap on the rendered manifest. Topic and recall lines are
+    /// dropped, least important first, to stay under it.
+    pub max_tokens: u32,
+}
+
+impl Default for ManifestConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            placement: ManifestPlacement::default(),
+            max_tokens: 500,
+        }
+    }
+}
+
+/// A rendered manifest.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ContextManifest {
+    pub text: String,
+    pub tokens: u32,
+    /// Topic and recall lines left out to stay under the cap.
+    pub omitted_lines: usize,
+}
+
+/// Whether `block` is the manifest block, which heat and token accounting
+/// skip.
+pub fn is_manifest(block: &Block) -> bool {
+    block.block_type.as_deref() == Some(MANIFEST_BLOCK_TYPE)
+}
+
+#[derive(Default)]
+struct ZoneTally {
+    blocks: usize,
+    tokens: u32,
+    pinned: usize,
+    levels: BTreeMap<&'static str, usize>,
+}
+
+fn zone_name(zone: &Zone) -> String {
+    match zone {
+        Zone::BuiltIn(BuiltInZone::Primacy) => "PRIMACY".to_string(),
+        Zone::BuiltIn(BuiltInZone::Middle) => "MIDDLE".to_string(),
+        Zone::BuiltIn(BuiltInZone::Recency) => "RECENCY".to_string(),
+        Zone::Custom(id) => id.to_uppercase(),
+    }
+}
+
+fn level_name(level: CompressionLevel) -> Option<&'static str> {
+    match level {
+        CompressionLevel::Original => None,
+        CompressionLevel::Trimmed => Some("trimmed"),
+        CompressionLevel::Summarized => Some("summarized"),
+        CompressionLevel::Minimal => Some("minimal"),
+    }
+}
+
+/// Render the manifest for the blocks of `blocks` a request carries, or
+/// `None` when disabled. Cold and archived blocks are listed as
+/// recallable.
+pub fn build_manifest(
+    blocks: &[Block],
+    clusters: &TopicClusters,
+    budget: &BudgetConfig,
+    config: &ManifestConfig,
+) -> Option<ContextManifest> {
+    if !config.enabled {
+        return None;
+    }
+    let active: Vec<&Block> = blocks
+        .iter()
+        .zip(sent_mask(blocks))
+        .filter(|(b, sent)| {
+            !is_manifest(b) && (*sent || (b.is_generated() && b.memory_state.is_active()))
+        })
+        .map(|(b, _)| b)
+        .collect();
+    let inactive: Vec<&Block> = blocks
+        .iter()
+        .filter(|b| !b.memory_state.is_active())
+        .collect();
+
+    let used: u32 = active.iter().map(|b| b.tokens).sum();
+    let window = budget.context_window;
+    let percent = if window == 0 {
+        0
+    } else {
+        (used as u64 * 100 / window as u64) as u32
+    };
+
+    let mut lines = vec![format!(
+        "[CONTEXT MANIFEST — {} blocks, {}/{} tokens, {}% capacity]",
+        active.len(),
+        format_tokens(used),
+        format_tokens(window),
+        percent
+    )];
+
+    let mut zones: Vec<(Zone, ZoneTally)> = Vec::new();
+    for block in &active {
+        let index = match zones.iter().position(|(zone, _)| *zone == block.zone) {
+            Some(index) => index,
+            None => {
+                zones.push((block.zone.clone(), ZoneTally::default()));
+                zones.len() - 1
+            }
+        };
+        let tally = &mut zones[index].1;
+        tally.blocks += 1;
+        tally.tokens += block.tokens;
+        tally.pinned += usize::from(block.pinned.is_some());
+        if let Some(level) = level_name(block.compression_level) {
+            *tally.levels.entry(level).or_default() += 1;
+        }
+    }
+    zones.sort_by_key(|(zone, _)| match zone {
+        Zone::BuiltIn(BuiltInZone::Primacy) => 0,
+        Zone::BuiltIn(BuiltInZone::Middle) => 1,
+        Zone::Custom(_) => 2,
+        Zone::BuiltIn(BuiltInZone::Recency) => 3,
+    });
+    for (zone, tally) in &zones {
+        let mut line = format!(
+            "{} ({} blocks, {} tokens",
+            zone_name(zone),
+            tally.blocks,
+            format_tokens(tally.tokens)
+        );
+        if tally.pinned > 0 {
+            line.push_str(&format!(", {} pinned", tally.pinned));
+        }
+        line.push(')');
+        let compressed: Vec<String> = tally
+            .levels
+            .iter()
+            .map(|(level, count)| format!("{count} {level}"))
+            .collect();
+        if !compressed.is_empty() {
+            line.push_str(&format!(": compressed {}", compressed.join(", ")));
+        }
+        lines.push(line);
+    }
+
+    if !inactive.is_empty() {
+        let archived = inactive
+            .iter()
+            .filter(|b| b.memory_state == MemoryState::Archived)
+            .count();
+        lines.push(format!(
+            "COLD STORAGE ({} blocks, {} archived) — not in this prompt; recallable",
+            inactive.len(),
+            archived
+        ));
+    }
+    lines.push(format!(
+        "BUDGET: {} tokens remaining",
+        format_tokens(window.saturating_sub(used))
+    ));
+
+    // Topic and recall lines are added while they fit under the cap.
+    let mut topics = Vec::new();
+    for cluster in clusters.clusters() {
+        let live = cluster
+            .block_ids
+            .iter()
+            .filter(|id| active.iter().any(|b| b.id == **id))
+            .count();
+        if live > 0 {
+            topics.push(format!(
+                "  - topic {} \"{}\" ({} blocks)",
+                cluster.id,
+                cluster.display_label(),
+                live
+            ));
+        }
+    }
+    let mut recall_hints: Vec<String> = Vec::new();
+    for block in &inactive {
+        let Some(recall) = &block.recall else {
+            continue;
+        };
+        let hint = match &recall.topic {
+            Some(topic) => {
+                let label = clusters
+                    .get(topic)
+                    .map(|c| c.display_label().to_string())
+                    .unwrap_or_default();
+                format!("  - recall(\"topic:{topic}\") — {label}")
+            }
+            None => format!("  - recall(\"block:{}\") — {}", block.id, recall.summary),
+        };
+        if !recall_hints.contains(&hint) {
+            recall_hints.push(hint);
+        }
+    }
+
+    let mut text = lines.join("\n");
+    let mut omitted = 0;
+    let sections = [("TOPICS:", topics), ("RECALL:", recall_hints)];
+    for (heading, items) in sections {
+        if items.is_empty() {
+            continue;
+        }
+        let mut with_heading = format!("{text}\n{heading}");
+        let mut added = 0;
+        for item in &items {
+            let candidate = format!("{with_heading}\n{item}");
+            if count_tokens(&candidate) > config.max_tokens {
+                break;
+            }
+            with_heading = candidate;
+            added += 1;
+        }
+        omitted += items.len() - added;
+        if added > 0 {
+            text = with_heading;
+        }
+    }
+
+    let tokens = count_tokens(&text);
+    Some(ContextManifest {
+        text,
+        tokens,
+        omitted_lines: omitted,
+    })
+}
+
+/// Add `manifest` to an API request body's system prompt.
+///
+/// Returns `false` if the body is not a JSON object; see
+/// [`append_system_text`].
+pub fn inject_into_request(body: &mut Value, provider: &str, manifest: &ContextManifest) -> bool {
+    append_system_text(body, provider, &manifest.text)
+}
+
+/// Append `text` to an API request body's system prompt.
+///
+/// OpenAI-style bodies (`provider == "openai"`) get it appended to a
+/// leading system or developer message, or a new one; Anthropic bodies get
+/// it appended to `system`, whether that is a string or a list of content
+/// blocks. Returns `false` if the body is not a JSON object.
+pub(crate) fn append_system_text(body: &mut Value, provider: &str, text: &str) -> bool {
+    let Some(object) = body.as_object_mut() else {
+        return false;
+    };
+    if provider == "openai" {
+        let messages = object.entry("messages").or_insert_with(|| json!([]));
+        let Some(messages) = messages.as_array_mut() else {
+            return false;
+        };
+        let leading = messages.first_mut().filter(|m| {
+            matches!(m["role"].as_str(), Some("system" | "developer")) && m["content"].is_string()
+        });
+        match leading {
+            Some(message) => append_text(&mut message["content"], text),
+            None => messages.insert(0, json!({ "role": "system", "content": text })),
+        }
+        return true;
+    }
+
+    match object.get_mut("system") {
+        Some(Value::Array(parts)) => parts.push(json!({ "type": "text", "text": text })),
+        Some(system @ Value::String(_)) => append_text(system, text),
+        _ => {
+            object.insert("system".to_string(), Value::String(text.to_string()));
+        }
+    }
+    true
+}
+
+fn append_text(target: &mut Value, text: &str) {
+    let existing = target.as_str().unwrap_or_default();
+    *target = Value::String(if existing.is_empty() {
+        text.to_string()
+    } else {
+        format!("{existing}\n\n{text}")
+    });
+}
+
+/// Put `manifest` in a pinned block at the top of the Primacy zone,
+/// replacing the previous one, or remove that block when `manifest` is
+/// `None`.
+pub fn upsert_manifest_block(
+    blocks: &mut Vec<Block>,
+    manifest: Option<&ContextManifest>,
+    turn: u32,
+) {
+    blocks.retain(|b| !is_manifest(b));
+    let Some(manifest) = manifest else {
+        return;
+    };
+    let mut block = Block::generated(
+        MANIFEST_BLOCK_ID.to_string(),
+        MANIFEST_BLOCK_TYPE,
+        manifest.text.clone(),
+        Zone::BuiltIn(BuiltInZone::Primacy),
+        turn,
+    );
+    block.pinned = Some(PinPosition::Top);
+    blocks.insert(0, block);
+}
+
+/// Tokens charged to the session: active blocks other than the manifest.
+/// [`projected_tokens`](super::budget::projected_tokens) still counts the
+/// manifest, since it is sent.
+pub fn accounted_tokens(blocks: &[Block]) -> u32 {
+    blocks
+        .iter()
+        .filter(|b| b.memory_state.is_active() && !is_manifest(b))
+        .fold(0, |total, b| total.saturating_add(b.tokens))
+}
+
+/// Turn the manifest on or off for the next requests.
+#[tauri::command]
+pub fn manifest_set_enabled(session: State<'_, SharedSession>, enabled: bool) {
+    session.lock().manifest.enabled = enabled;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::action_log::{ActionLog, ActionReason};
+    use crate::engine::block::{test_block, CompressionVersion};
+    use crate::engine::clustering::ClusterConfig;
+    use crate::engine::memory::transition;
+    use crate::engine::types::Role;
+
+    fn session() -> (Vec<Block>, TopicClusters) {
+        let mut blocks = vec![
+            test_block("sys", Role::System, "You are a coding assistant."),
+            test_block("q", Role::User, "Why does the login token refresh fail?"),
+            test_block("a", Role::Assistant, "The refresh token expired early."),
+            test_block("old", Role::ToolResult, "npm install finished in 4s"),
+        ];
+        blocks[0].zone = Zone::BuiltIn(BuiltInZone::Primacy);
+        blocks[0].pinned = Some(PinPosition::Top);
+        blocks[2].compressed_versions.trimmed = Some(CompressionVersion::new(
+            "Refresh token expired.".to_string(),
+        ));
+        blocks[2].set_compression_level(CompressionLevel::Trimmed);
+        let mut clusters = TopicClusters::new(ClusterConfig::default());
+        clusters.update(&mut blocks);
+        let mut log = ActionLog::new();
+        transition(
+            &mut blocks[3],
+            MemoryState::Cold,
+            None,
+            ActionReason::Lifecycle,
+            &mut log,
+        )
+        .expect("cold");
+        (blocks, clusters)
+    }
+
+    fn enabled() -> ManifestConfig {
+        ManifestConfig {
+            enabled: true,
+            ..ManifestConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_manifest_summarizes_zones_compression_and_cold_storage() {
+        let (mut blocks, clusters) = session();
+        // Left out of the request: its tool call is gone.
+        let mut orphan = test_block("orphan", Role::ToolResult, "exit 0");
+        orphan.metadata.tool_use_id = Some("toolu_gone".to_string());
+        blocks.push(orphan);
+        let manifest = build_manifest(&blocks, &clusters, &BudgetConfig::new(200_000), &enabled())
+            .expect("enabled");
+
+        assert!(manifest.text.starts_with("[CONTEXT MANIFEST — 3 blocks, "));
+        assert!(manifest.text.contains("PRIMACY (1 blocks, "));
+        assert!(manifest.text.contains(", 1 pinned)"));
+        assert!(manifest.text.contains("MIDDLE (2 blocks, "));
+        assert!(manifest.text.contains(": compressed 1 trimmed"));
+        assert!(manifest
+            .text
+            .contains("COLD STORAGE (1 blocks, 0 archived)"));
+        assert!(manifest.text.contains("recall(\"topic:"));
+        assert_eq!(manifest.omitted_lines, 0);
+        assert!(manifest.tokens <= 500);
+    }
+
+    #[test]
+    fn test_manifest_respects_cap_and_toggle() {
+        let (blocks, clusters) = session();
+        let budget = BudgetConfig::new(200_000);
+        assert!(!ManifestConfig::default().enabled);
+        let disabled = ManifestConfig::default();
+        assert!(build_manifest(&blocks, &clusters, &budget, &disabled).is_none());
+
+        let tight = ManifestConfig {
+            max_tokens: 1,
+            ..enabled()
+        };
+        let manifest = build_manifest(&blocks, &clusters, &budget, &tight).expect("enabled");
+        assert!(!manifest.text.contains("RECALL:"));
+        assert!(manifest.omitted_lines > 0);
+    }
+
+    #[test]
+    fn test_inject_into_anthropic_and_openai_requests() {
+        let manifest = ContextManifest {
+            text: "[CONTEXT MANIFEST]".to_string(),
+            tokens: 3,
+            omitted_lines: 0,
+        };
+
+        let mut anthropic = json!({ "system": "Be brief.", "messages": [] });
+        assert!(inject_into_request(&mut anthropic, "anthropic", &manifest));
+        assert_eq!(anthropic["system"], "Be brief.\n\n[CONTEXT MANIFEST]");
+
+        let mut blocks = json!({ "system": [{ "type": "text", "text": "Be brief." }] });
+        inject_into_request(&mut blocks, "anthropic", &manifest);
+        assert_eq!(blocks["system"][1]["text"], "[CONTEXT MANIFEST]");
+
+        let mut openai = json!({ "messages": [{ "role": "user", "content": "hi" }] });
+        inject_into_request(&mut openai, "openai", &manifest);
+        assert_eq!(openai["messages"][0]["role"], "system");
+        assert_eq!(openai["messages"][1]["content"], "hi");
+
+        assert!(!inject_into_request(&mut json!([]), "anthropic", &manifest));
+    }
+
+    #[test]
+    fn test_manifest_block_is_replaced_and_not_accounted() {
+        let (mut blocks, clusters) = session();
+        let budget = BudgetConfig::new(200_000);
+        let before = accounted_tokens(&blocks);
+
+        for turn in 1..=2 {
+            let manifest = build_manifest(&blocks, &clusters, &budget, &enabled());
+            upsert_manifest_block(&mut blocks, manifest.as_ref(), turn);
+        }
+        assert_eq!(blocks.iter().filter(|b| is_manifest(b)).count(), 1);
+        assert_eq!(blocks[0].id, MANIFEST_BLOCK_ID);
+        assert!(blocks[0]
+            .content
+            .starts_with("[CONTEXT MANIFEST — 3 blocks"));
+        assert_eq!(accounted_tokens(&blocks), before);
+
+        upsert_manifest_block(&mut blocks, None, 3);
+        assert!(!blocks.iter().any(is_manifest));
+    }
+}
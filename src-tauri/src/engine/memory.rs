@@ -0,0 +1,290 @@
+//! Memory lifecycle: hot / warm / cold / archived.
+//!
+//! - **Hot** — original content, in the outbound prompt.
+//! - **Warm** — a compressed version, still in the prompt.
+//! - **Cold** — out of the prompt; recall metadata kept on the block.
+//! - **Archived** — out of the prompt and eligible to be offloaded from
+//!   memory; recallable like cold.
+//!
+//! [`super::outbound`] leaves cold and archived blocks out when it rebuilds
+//! a request. The original is never touched, so every state can return to
+//! any other.
+//! Each transition is recorded in the [`ActionLog`] and can be undone from
+//! there; [`super::recall`] brings cold and archived blocks back.
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::action_log::{ActionLog, ActionReason, BlockChange};
+use super::block::Block;
+use super::types::CompressionLevel;
+
+/// Longest generated recall summary, in characters.
+const SUMMARY_MAX_CHARS: usize = 120;
+
+/// Lifecycle state of a block.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MemoryState {
+    #[default]
+    Hot,
+    Warm,
+    Cold,
+    Archived,
+}
+
+impl MemoryState {
+    /// Whether blocks in this state are sent to the model.
+    pub fn is_active(self) -> bool {
+        matches!(self, Self::Hot | Self::Warm)
+    }
+}
+
+/// What is kept in view of a block that left the prompt.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecallMetadata {
+    pub topic: Option<String>,
+    #[serde(default)]
+    pub files: Vec<String>,
+    /// One line describing the block.
+    pub summary: String,
+}
+
+impl RecallMetadata {
+    /// Recall metadata for `block`: its topic and files, and the minimal
+    /// version or else the first line of the original as the summary.
+    pub fn for_block(block: &Block) -> Self {
+        let versions = &block.compressed_versions;
+        let summary = versions
+            .minimal
+            .as_ref()
+            .map(|v| v.content.trim().to_string())
+            .unwrap_or_else(|| {
+                let line = versions
+                    .original
+                    .content
+                    .lines()
+                    .map(str::trim)
+                    .find(|line| !line.is_empty())
+                    .unwrap_or_default();
+                match line.char_indices().nth(SUMMARY_MAX_CHARS) {
+                    Some((end, _)) => format!("{}…", &line[..end]),
+                    None => line.to_string(),
+                }
+            });
+        Self {
+            topic: block.topic_cluster.clone(),
+            files: block.metadata.file_paths.clone(),
+            summary,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum MemoryError {
+    #[error("Block not found: {0}")]
+    BlockNotFound(String),
+
+    #[error("Block {block_id} has no {level:?} version to warm to")]
+    VersionMissing {
+        block_id: String,
+        level: CompressionLevel,
+    },
+
+    #[error("Block {0} is pinned and cannot leave the context")]
+    Pinned(String),
+}
+
+impl serde::Serialize for MemoryError {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+/// Move `block` to `to`, recording the transition in `log`.
+///
+/// `Hot` restores the original. `Warm` switches to `warm_level`, or if none
+/// is given keeps the current compressed level or picks the shallowest
+/// compressed version available. `Cold` and `Archived` keep the level and
+/// fill in recall metadata. Returns the action id, or `None` if the block
+/// was already in that state.
+pub fn transition(
+    block: &mut Block,
+    to: MemoryState,
+    warm_level: Option<CompressionLevel>,
+    reason: ActionReason,
+    log: &mut ActionLog,
+) -> Result<Option<String>, MemoryError> {
+    let from = block.memory_state;
+    let level_from = block.compression_level;
+    let level_to = match to {
+        MemoryState::Hot => CompressionLevel::Original,
+        MemoryState::Warm => warm_level
+            .or_else(|| (level_from != CompressionLevel::Original).then_some(level_from))
+            .or_else(|| shallowest_compressed(block))
+            .unwrap_or(CompressionLevel::Trimmed),
+        MemoryState::Cold | MemoryState::Archived => {
+            if block.pinned.is_some() {
+                return Err(MemoryError::Pinned(block.id.clone()));
+            }
+            level_from
+        }
+    };
+    if from == to && level_from == level_to {
+        return Ok(None);
+    }
+    if to == MemoryState::Warm && level_to == CompressionLevel::Original {
+        return Err(MemoryError::VersionMissing {
+            block_id: block.id.clone(),
+            level: level_to,
+        });
+    }
+
+    let tokens_before = block.tokens;
+    if !block.set_compression_level(level_to) {
+        return Err(MemoryError::VersionMissing {
+            block_id: block.id.clone(),
+            level: level_to,
+        });
+    }
+    set_state(block, to);
+
+    let change = BlockChange::Memory {
+        from,
+        to,
+        level_from,
+        level_to,
+    };
+    let detail = format!("{from:?} -> {to:?}");
+    Ok(Some(
+        log.record(block, change, tokens_before, reason, detail)
+            .id
+            .clone(),
+    ))
+}
+
+/// Set the state and keep recall metadata in step with it.
+pub(crate) fn set_state(block: &mut Block, state: MemoryState) {
+    block.memory_state = state;
+    block.recall = (!state.is_active()).then(|| RecallMetadata::for_block(block));
+}
+
+fn shallowest_compressed(block: &Block) -> Option<CompressionLevel> {
+    let mut level = CompressionLevel::Original;
+    while let Some(deeper) = level.deeper() {
+        level = deeper;
+        if block.compressed_versions.get(level).is_some() {
+            return Some(level);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::block::{test_block, CompressionVersion};
+    use crate::engine::types::{PinPosition, Role};
+
+    fn block() -> Block {
+        let mut block = test_block(
+            "b",
+            Role::ToolResult,
+            "Compiling aperture v0.1.0\nerror: linker `cc` not found",
+        );
+        block.topic_cluster = Some("topic-2".to_string());
+        block.metadata.file_paths = vec!["src-tauri/Cargo.toml".to_string()];
+        block.compressed_versions.summarized = Some(CompressionVersion::new(
+            "Build failed: no linker".to_string(),
+        ));
+        block
+    }
+
+    fn go(block: &mut Block, to: MemoryState, log: &mut ActionLog) -> Option<String> {
+        transition(block, to, None, ActionReason::Lifecycle, log).expect("transition")
+    }
+
+    #[test]
+    fn test_transition_warm_picks_existing_compressed_version() {
+        let mut log = ActionLog::new();
+        let mut block = block();
+
+        go(&mut block, MemoryState::Warm, &mut log);
+        assert_eq!(block.memory_state, MemoryState::Warm);
+        assert_eq!(block.compression_level, CompressionLevel::Summarized);
+        assert_eq!(block.content, "Build failed: no linker");
+
+        go(&mut block, MemoryState::Hot, &mut log);
+        assert_eq!(block.compression_level, CompressionLevel::Original);
+        assert_eq!(log.entries().len(), 2);
+    }
+
+    #[test]
+    fn test_transition_warm_without_versions_fails() {
+        let mut log = ActionLog::new();
+        let mut block = test_block("b", Role::User, "hello");
+
+        let result = transition(
+            &mut block,
+            MemoryState::Warm,
+            None,
+            ActionReason::Lifecycle,
+            &mut log,
+        );
+        assert!(matches!(result, Err(MemoryError::VersionMissing { .. })));
+        assert_eq!(block.memory_state, MemoryState::Hot);
+        assert!(log.entries().is_empty());
+    }
+
+    #[test]
+    fn test_transition_cold_keeps_recall_metadata() {
+        let mut log = ActionLog::new();
+        let mut block = block();
+
+        go(&mut block, MemoryState::Cold, &mut log);
+        assert!(!block.memory_state.is_active());
+        let recall = block.recall.as_ref().expect("recall metadata");
+        assert_eq!(recall.topic.as_deref(), Some("topic-2"));
+        assert_eq!(recall.files, vec!["src-tauri/Cargo.toml"]);
+        assert_eq!(recall.summary, "Compiling aperture v0.1.0");
+        assert_eq!(
+            block.compressed_versions.original.content,
+            "Compiling aperture v0.1.0\nerror: linker `cc` not found"
+        );
+
+        assert_eq!(go(&mut block, MemoryState::Cold, &mut log), None);
+    }
+
+    #[test]
+    fn test_transition_refuses_to_evict_pinned_block() {
+        let mut log = ActionLog::new();
+        let mut block = block();
+        block.pinned = Some(PinPosition::Top);
+
+        let result = transition(
+            &mut block,
+            MemoryState::Archived,
+            None,
+            ActionReason::Lifecycle,
+            &mut log,
+        );
+        assert!(matches!(result, Err(MemoryError::Pinned(_))));
+    }
+
+    #[test]
+    fn test_transitions_undo_in_reverse() {
+        let mut log = ActionLog::new();
+        let mut blocks = vec![block()];
+
+        go(&mut blocks[0], MemoryState::Warm, &mut log);
+        go(&mut blocks[0], MemoryState::Archived, &mut log);
+        assert_eq!(log.undo_all(&mut blocks), 2);
+
+        assert_eq!(blocks[0].memory_state, MemoryState::Hot);
+        assert_eq!(blocks[0].compression_level, CompressionLevel::Original);
+        assert!(blocks[0].recall.is_none());
+    }
+}
@@ -0,0 +1,420 @@
+//! Memory commands the model issues through a synthetic tool.
+//!
+//! When enabled, the proxy adds the [`MEMORY_TOOL_NAME`] tool to outbound
+//! requests, intercepts the model's calls to it, and runs them here. Only
+//! allowlisted operations run, at most `max_calls_per_turn` per client
+//! request. Expanded and recalled blocks are returned in the tool result,
+//! since the model only sees the request it already started answering.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tauri::State;
+use thiserror::Error;
+
+use super::action_log::{ActionLog, ActionReason};
+use super::block::Block;
+use super::clustering::TopicClusters;
+use super::memory::{transition, MemoryError, MemoryState};
+use super::recall::{recall, RecallConfig, RecallQuery};
+use super::session::SharedSession;
+use super::types::CompressionLevel;
+
+/// Name of the synthetic tool. Calls to it never reach the client.
+pub const MEMORY_TOOL_NAME: &str = "aperture_memory";
+
+/// An operation the model can request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MemoryOperation {
+    /// Restore blocks to their original content.
+    Expand,
+    /// Bring cold or archived blocks back.
+    Recall,
+    /// Move blocks out of the prompt.
+    Archive,
+    /// Switch blocks to a compressed version.
+    Compress,
+}
+
+/// Memory tool settings.
+#[derive(Debug, Clone)]
+pub struct MemoryToolConfig {
+    /// Off by default: the tool is only injected when a session opts in.
+    pub enabled: bool,
+    pub allowed: Vec<MemoryOperation>,
+    pub max_calls_per_turn: u32,
+    /// Upstream requests the proxy makes on the client's behalf to return
+    /// tool results, per client request.
+    pub max_continuations: u32,
+}
+
+impl Default for MemoryToolConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            allowed: vec![
+                MemoryOperation::Expand,
+                MemoryOperation::Recall,
+                MemoryOperation::Archive,
+            ],
+            max_calls_per_turn: 3,
+            max_continuations: 2,
+        }
+    }
+}
+
+/// A parsed tool call.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "operation", rename_all = "snake_case")]
+pub enum MemoryCommand {
+    Expand {
+        target: String,
+    },
+    Recall {
+        query: String,
+    },
+    Archive {
+        target: String,
+    },
+    Compress {
+        target: String,
+        level: CompressionLevel,
+    },
+}
+
+impl MemoryCommand {
+    pub fn operation(&self) -> MemoryOperation {
+        match self {
+            Self::Expand { .. } => MemoryOperation::Expand,
+            Self::Recall { .. } => MemoryOperation::Recall,
+            Self::Archive { .. } => MemoryOperation::Archive,
+            Self::Compress { .. } => MemoryOperation::Compress,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum MemoryCommandError {
+    #[error("Invalid memory command: {0}")]
+    InvalidInput(String),
+
+    #[error("Memory operation {0:?} is not allowed")]
+    NotAllowed(MemoryOperation),
+
+    #[error("Memory command limit of {0} per turn reached")]
+    TurnLimit(u32),
+
+    #[error("No block or topic matches {0:?}")]
+    TargetNotFound(String),
+
+    #[error(transparent)]
+    Memory(#[from] MemoryError),
+}
+
+impl serde::Serialize for MemoryCommandError {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl MemoryToolConfig {
+    /// Anthropic tool definition exposing only the allowed operations.
+    pub fn tool_definition(&self) -> Value {
+        let operations: Vec<Value> = self
+            .allowed
+            .iter()
+            .map(|op| serde_json::to_value(op).expect("unit variant"))
+            .collect();
+        json!({
+            "name": MEMORY_TOOL_NAME,
+            "description": "Manage your own context memory. The context manifest lists \
+                blocks, topics and recall hints. `expand` restores a block or topic \
+                to full detail, `recall` brings archived blocks back by `block:<id>`, \
+                `topic:<id>` or free text; both return the restored content. `archive` moves a block or topic out of \
+                the prompt, `compress` switches it to a shorter version.",
+            "input_schema": {
+                "type": "object",
+                "properties": {
+                    "operation": { "type": "string", "enum": operations },
+                    "target": {
+                        "type": "string",
+                        "description": "Block id or topic, for expand/archive/compress"
+                    },
+                    "query": {
+                        "type": "string",
+                        "description": "What to recall, for recall"
+                    },
+                    "level": {
+                        "type": "string",
+                        "enum": ["trimmed", "summarized", "minimal"],
+                        "description": "For compress"
+                    }
+                },
+                "required": ["operation"]
+            }
+        })
+    }
+
+    /// Parse a tool call's input, given how many calls this turn already
+    /// made, rejecting disallowed operations and calls over the limit.
+    pub fn admit(
+        &self,
+        input: &Value,
+        calls_so_far: u32,
+    ) -> Result<MemoryCommand, MemoryCommandError> {
+        if calls_so_far >= self.max_calls_per_turn {
+            return Err(MemoryCommandError::TurnLimit(self.max_calls_per_turn));
+        }
+        let command: MemoryCommand = serde_json::from_value(input.clone())
+            .map_err(|e| MemoryCommandError::InvalidInput(e.to_string()))?;
+        if !self.allowed.contains(&command.operation()) {
+            return Err(MemoryCommandError::NotAllowed(command.operation()));
+        }
+        Ok(command)
+    }
+}
+
+/// Runs admitted commands against a session's blocks.
+pub trait MemoryToolExecutor: Send + Sync {
+    /// Run `command` and describe the outcome for the model.
+    fn execute(&self, command: &MemoryCommand) -> Result<String, MemoryCommandError>;
+}
+
+impl MemoryToolExecutor for SharedSession {
+    fn execute(&self, command: &MemoryCommand) -> Result<String, MemoryCommandError> {
+        let mut guard = self.lock();
+        let session = &mut *guard;
+        execute(
+            command,
+            &mut session.blocks,
+            &session.clusters,
+            &mut session.log,
+        )
+    }
+}
+
+/// Run `command`, logging every change as [`ActionReason::ModelCommand`]
+/// (recalls as [`ActionReason::Recall`]). The outcome of an expand or a
+/// recall carries the content of the blocks it brought back.
+pub fn execute(
+    command: &MemoryCommand,
+    blocks: &mut [Block],
+    clusters: &TopicClusters,
+    log: &mut ActionLog,
+) -> Result<String, MemoryCommandError> {
+    let (target, to, level) = match command {
+        MemoryCommand::Recall { query } => {
+            let report = recall(
+                blocks,
+                &RecallQuery::parse(query),
+                clusters,
+                &RecallConfig::default(),
+                log,
+            )?;
+            return Ok(if report.recalled.is_empty() {
+                format!("Nothing to recall for {query:?}.")
+            } else {
+                let outcome = format!("Recalled {}.", report.recalled.join(", "));
+                with_contents(outcome, blocks, &report.recalled)
+            });
+        }
+        MemoryCommand::Expand { target } => (target, MemoryState::Hot, None),
+        MemoryCommand::Archive { target } => (target, MemoryState::Archived, None),
+        MemoryCommand::Compress { target, level } => (target, MemoryState::Warm, Some(*level)),
+    };
+
+    let targets = resolve_targets(target, blocks, clusters);
+    if targets.is_empty() {
+        return Err(MemoryCommandError::TargetNotFound(target.clone()));
+    }
+    let mut changed = Vec::new();
+    let mut skipped = Vec::new();
+    for i in targets {
+        let block = &mut blocks[i];
+        match transition(block, to, level, ActionReason::ModelCommand, log) {
+            Ok(Some(_)) => changed.push(block.id.clone()),
+            Ok(None) => {}
+            Err(e) => skipped.push(e.to_string()),
+        }
+    }
+    let mut outcome = if changed.is_empty() {
+        format!("No change for {target:?}.")
+    } else {
+        format!("{:?} {}.", command.operation(), changed.join(", "))
+    };
+    if !skipped.is_empty() {
+        outcome.push_str(&format!(" Skipped: {}.", skipped.join("; ")));
+    }
+    if matches!(command, MemoryCommand::Expand { .. }) {
+        outcome = with_contents(outcome, blocks, &changed);
+    }
+    Ok(outcome)
+}
+
+/// `outcome` followed by the current content of the blocks named in `ids`.
+fn with_contents(mut outcome: String, blocks: &[Block], ids: &[String]) -> String {
+    for block in blocks.iter().filter(|b| ids.contains(&b.id)) {
+        outcome.push_str(&format!(
+            "\n\n<block id=\"{}\">\n{}\n</block>",
+            block.id, block.content
+        ));
+    }
+    outcome
+}
+
+/// Indices of the blocks `target` names: a block id, else a topic id or
+/// label. `block:` and `topic:` prefixes force one or the other.
+fn resolve_targets(target: &str, blocks: &[Block], clusters: &TopicClusters) -> Vec<usize> {
+    let (by_id, by_topic) = match RecallQuery::parse(target) {
+        RecallQuery::BlockId(id) => (Some(id), None),
+        RecallQuery::Topic(topic) => (None, Some(topic)),
+        RecallQuery::Query(text) => (Some(text.clone()), Some(text)),
+    };
+    if let Some(index) = by_id.and_then(|id| blocks.iter().position(|b| b.id == id)) {
+        return vec![index];
+    }
+    let Some(topic) = by_topic else {
+        return Vec::new();
+    };
+    let topic_id = clusters
+        .clusters()
+        .iter()
+        .find(|c| c.id == topic || c.display_label().eq_ignore_ascii_case(&topic))
+        .map(|c| c.id.clone())
+        .unwrap_or(topic);
+    blocks
+        .iter()
+        .enumerate()
+        .filter(|(_, b)| b.topic_cluster.as_deref() == Some(topic_id.as_str()))
+        .map(|(i, _)| i)
+        .collect()
+}
+
+/// Offer the memory tool on the next requests, or stop offering it.
+#[tauri::command]
+pub fn memory_tool_set_enabled(session: State<'_, SharedSession>, enabled: bool) {
+    session.lock().memory_tool.enabled = enabled;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::block::{test_block, CompressionVersion};
+    use crate::engine::session::EngineSession;
+    use crate::engine::types::Role;
+
+    fn session() -> EngineSession {
+        let mut blocks = vec![
+            test_block("a", Role::User, "Set up the database schema"),
+            test_block("b", Role::Assistant, "Created the users table"),
+            test_block("c", Role::ToolResult, "migration 001 applied"),
+        ];
+        blocks[0].topic_cluster = Some("topic-1".to_string());
+        blocks[1].topic_cluster = Some("topic-1".to_string());
+        blocks[2].compressed_versions.summarized =
+            Some(CompressionVersion::new("migrated".to_string()));
+        EngineSession {
+            blocks,
+            ..EngineSession::default()
+        }
+    }
+
+    #[test]
+    fn test_admit_enforces_allowlist_and_turn_limit() {
+        let config = MemoryToolConfig::default();
+        let archive = json!({ "operation": "archive", "target": "a" });
+
+        assert_eq!(
+            config.admit(&archive, 0).expect("allowed"),
+            MemoryCommand::Archive {
+                target: "a".to_string()
+            }
+        );
+        assert!(matches!(
+            config.admit(&archive, 3),
+            Err(MemoryCommandError::TurnLimit(3))
+        ));
+        assert!(matches!(
+            config.admit(
+                &json!({ "operation": "compress", "target": "a", "level": "minimal" }),
+                0
+            ),
+            Err(MemoryCommandError::NotAllowed(MemoryOperation::Compress))
+        ));
+        assert!(matches!(
+            config.admit(&json!({ "operation": "delete" }), 0),
+            Err(MemoryCommandError::InvalidInput(_))
+        ));
+
+        let definition = config.tool_definition();
+        assert_eq!(definition["name"], MEMORY_TOOL_NAME);
+        assert_eq!(
+            definition["input_schema"]["properties"]["operation"]["enum"],
+            json!(["expand", "recall", "archive"])
+        );
+    }
+
+    #[test]
+    fn test_execute_archive_topic_then_recall_it() {
+        let shared = SharedSession::new(session());
+
+        let outcome = shared
+            .execute(&MemoryCommand::Archive {
+                target: "topic:topic-1".to_string(),
+            })
+            .expect("archive");
+        assert_eq!(outcome, "Archive a, b.");
+        {
+            let state = shared.lock();
+            assert_eq!(state.blocks[0].memory_state, MemoryState::Archived);
+            assert_eq!(state.blocks[2].memory_state, MemoryState::Hot);
+            assert_eq!(state.log.entries()[0].reason, ActionReason::ModelCommand);
+        }
+
+        let outcome = shared
+            .execute(&MemoryCommand::Recall {
+                query: "block:b".to_string(),
+            })
+            .expect("recall");
+        assert_eq!(
+            outcome,
+            "Recalled b.\n\n<block id=\"b\">\nCreated the users table\n</block>"
+        );
+    }
+
+    #[test]
+    fn test_execute_compress_and_expand_block() {
+        let mut state = session();
+        let compress = MemoryCommand::Compress {
+            target: "c".to_string(),
+            level: CompressionLevel::Summarized,
+        };
+        execute(
+            &compress,
+            &mut state.blocks,
+            &state.clusters,
+            &mut state.log,
+        )
+        .expect("compress");
+        assert_eq!(state.blocks[2].content, "migrated");
+
+        let expand = MemoryCommand::Expand {
+            target: "c".to_string(),
+        };
+        let outcome =
+            execute(&expand, &mut state.blocks, &state.clusters, &mut state.log).expect("expand");
+        assert_eq!(state.blocks[2].content, "migration 001 applied");
+        assert!(outcome.ends_with("<block id=\"c\">\nmigration 001 applied\n</block>"));
+
+        let missing = MemoryCommand::Expand {
+            target: "nope".to_string(),
+        };
+        assert!(matches!(
+            execute(&missing, &mut state.blocks, &state.clusters, &mut state.log),
+            Err(MemoryCommandError::TargetNotFound(_))
+        ));
+    }
+}
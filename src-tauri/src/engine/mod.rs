@@ -4,5 +4,29 @@
 //! TypeScript types, enabling the backend to understand and manipulate
 //! context blocks independently of the UI.
 
+pub mod action_log;
 pub mod block;
+pub mod budget;
+pub mod checkpoint;
+pub mod clustering;
+pub mod compression;
+pub mod dedup;
+pub mod dependency;
+pub mod fork;
+pub mod ghost;
+pub mod heat;
+pub mod history;
+pub mod keywords;
+pub mod manifest;
+pub mod memory;
+pub mod memory_tool;
+pub mod outbound;
+pub mod profile;
+pub mod recall;
+pub mod rules;
+pub mod session;
+pub mod staging;
+pub mod staleness;
+pub mod tokens;
+pub mod trash;
 pub mod types;
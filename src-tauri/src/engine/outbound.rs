@@ -0,0 +1,796 @@
+//! Outbound rewrite: requests are rebuilt from the session's blocks.
+//!
+//! Clients resend their whole conversation with every request.
+//! [`Transcript::ingest`] turns the messages the session has not seen yet
+//! into blocks, one per content part, and [`Transcript::rebuild`] replaces
+//! the request's `messages` with the session's active blocks. Compression
+//! levels, edits, removals, memory states, the active fork branch and
+//! restored checkpoints therefore all reach the model, and a block the
+//! session dropped stays out even though the client keeps sending it.
+//!
+//! A message is known by a hash of its position and JSON, and a
+//! conversation by its first message. A request opening another
+//! conversation, e.g. a subagent's or a compacted history, parks the
+//! current one with its blocks and resumes the other if it was parked, so
+//! interleaved conversations do not overwrite each other. When a
+//! conversation's later history stops matching what was ingested, the
+//! blocks of the messages the client no longer sends are dropped.
+//!
+//! Every part is kept as a template: a block still holding the content it
+//! was ingested with is sent exactly as the client sent it, cache markers
+//! included, and a changed one keeps every field but its text. Blocks with
+//! no template, e.g. from a checkpoint saved by an earlier run, are rebuilt
+//! from their role and metadata. Tool calls and results are only sent in
+//! pairs.
+
+use std::collections::{HashMap, HashSet};
+
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+use super::block::Block;
+use super::staging::is_staged;
+use super::types::Role;
+
+/// Prefix of the ids of blocks ingested from requests.
+const MESSAGE_ID_PREFIX: &str = "msg-";
+
+/// Hex digits of a message hash kept in block ids.
+const MESSAGE_HASH_LEN: usize = 16;
+
+/// Conversations parked with their blocks, beyond the current one.
+const MAX_PARKED_CONVERSATIONS: usize = 8;
+
+/// Tool input fields read as file paths.
+const PATH_KEYS: [&str; 3] = ["file_path", "path", "notebook_path"];
+
+/// A part as the client sent it.
+#[derive(Debug, Clone)]
+struct Template {
+    part: Value,
+    /// Block content the part was ingested as.
+    content: String,
+}
+
+/// One conversation of the client.
+#[derive(Debug, Clone, Default)]
+struct Conversation {
+    /// Hashes of the messages ingested so far, in order.
+    messages: Vec<String>,
+    /// Parts as the client sent them, by block id.
+    templates: HashMap<String, Template>,
+}
+
+/// What the session has ingested from its client.
+#[derive(Debug, Clone, Default)]
+pub struct Transcript {
+    current: Conversation,
+    /// Other conversations and their blocks, least recently used first.
+    parked: Vec<(Conversation, Vec<Block>)>,
+}
+
+/// One content part of a request message, about to become a block.
+struct Part {
+    role: Role,
+    block_type: String,
+    content: String,
+    template: Value,
+    tool_name: Option<String>,
+    tool_use_id: Option<String>,
+    file_paths: Vec<String>,
+}
+
+impl Part {
+    fn new(role: Role, block_type: &str, content: String, template: Value) -> Self {
+        Self {
+            role,
+            block_type: block_type.to_string(),
+            content,
+            template,
+            tool_name: None,
+            tool_use_id: None,
+            file_paths: Vec::new(),
+        }
+    }
+}
+
+impl Transcript {
+    /// Add a block to `blocks` for every part of the messages of `body`
+    /// not ingested yet, switching conversations first if the request
+    /// belongs to another one, and dropping the blocks of ingested messages
+    /// the client no longer sends. OpenAI-style leading system messages
+    /// stay in the body. Returns the number of blocks added.
+    pub fn ingest(&mut self, blocks: &mut Vec<Block>, body: &Value, provider: &str) -> usize {
+        let Some(messages) = body["messages"].as_array() else {
+            return 0;
+        };
+        let messages = &messages[leading_system(messages, provider)..];
+        let hashes: Vec<String> = messages
+            .iter()
+            .enumerate()
+            .map(|(index, message)| message_hash(index, message))
+            .collect();
+        let Some(first) = hashes.first() else {
+            return 0;
+        };
+        if self
+            .current
+            .messages
+            .first()
+            .is_some_and(|key| key != first)
+        {
+            self.switch(blocks, first);
+        }
+        let conversation = &mut self.current;
+        let known = conversation
+            .messages
+            .iter()
+            .zip(&hashes)
+            .take_while(|(ingested, hash)| ingested == hash)
+            .count();
+        if known < conversation.messages.len() {
+            let dropped: HashSet<&str> = conversation.messages[known..]
+                .iter()
+                .map(String::as_str)
+                .collect();
+            let kept = |id: &str| message_of(id).is_none_or(|hash| !dropped.contains(hash));
+            blocks.retain(|b| kept(&b.id));
+            conversation.templates.retain(|id, _| kept(id));
+        }
+
+        let source = if provider == "openai" {
+            "openai"
+        } else {
+            "anthropic"
+        };
+        // Tool names and paths carry over to results, which lack them.
+        let mut calls: HashMap<String, (Option<String>, Vec<String>)> = HashMap::new();
+        let mut turn = 0;
+        let mut added = 0;
+        for (index, (message, hash)) in messages.iter().zip(&hashes).enumerate() {
+            let parts = match provider {
+                "openai" => openai_parts(message),
+                _ => anthropic_parts(message),
+            };
+            for (position, mut part) in parts.into_iter().enumerate() {
+                if let (Role::ToolUse, Some(id)) = (part.role, &part.tool_use_id) {
+                    calls.insert(
+                        id.clone(),
+                        (part.tool_name.clone(), part.file_paths.clone()),
+                    );
+                }
+                if index < known {
+                    continue;
+                }
+                if let (Role::ToolResult, Some(id)) = (part.role, &part.tool_use_id) {
+                    if let Some((name, paths)) = calls.get(id) {
+                        part.tool_name.clone_from(name);
+                        part.file_paths.clone_from(paths);
+                    }
+                }
+                let id = format!("{MESSAGE_ID_PREFIX}{hash}-{position}");
+                if blocks.iter().any(|b| b.id == id) {
+                    continue;
+                }
+                let mut block = Block::from_request(
+                    id.clone(),
+                    part.role,
+                    &part.block_type,
+                    part.content.clone(),
+                    source,
+                    turn,
+                );
+                block.metadata.tool_name = part.tool_name;
+                block.metadata.tool_use_id = part.tool_use_id;
+                block.metadata.file_paths = part.file_paths;
+                conversation.templates.insert(
+                    id,
+                    Template {
+                        part: part.template,
+                        content: part.content,
+                    },
+                );
+                blocks.push(block);
+                added += 1;
+            }
+            if message["role"] == "user" {
+                turn += 1;
+            }
+        }
+        conversation.messages = hashes;
+        added
+    }
+
+    /// Park the current conversation with its ingested blocks and resume
+    /// the one opening with message `first`, or start it.
+    fn switch(&mut self, blocks: &mut Vec<Block>, first: &str) {
+        let (ingested, rest) = std::mem::take(blocks)
+            .into_iter()
+            .partition(|b| message_of(&b.id).is_some());
+        *blocks = rest;
+        let current = std::mem::take(&mut self.current);
+        self.parked.push((current, ingested));
+        let resumed = self
+            .parked
+            .iter()
+            .position(|(c, _)| c.messages.first().is_some_and(|key| key == first));
+        if let Some(index) = resumed {
+            let (conversation, parked) = self.parked.remove(index);
+            self.current = conversation;
+            blocks.extend(parked);
+        }
+        if self.parked.len() > MAX_PARKED_CONVERSATIONS {
+            self.parked.remove(0);
+        }
+    }
+
+    /// Replace the messages of `body` with the sent blocks of `blocks`, in
+    /// order and at their current content. Engine-generated blocks such as
+    /// the manifest block and soft checkpoints lead the first user message;
+    /// staged items are left to the staging area. Returns `false` if the
+    /// body has no messages.
+    pub fn rebuild(&self, blocks: &[Block], body: &mut Value, provider: &str) -> bool {
+        let Some(messages) = body["messages"].as_array() else {
+            return false;
+        };
+        let mut rebuilt = messages[..leading_system(messages, provider)].to_vec();
+        let sent = sent_blocks(blocks);
+        match provider {
+            "openai" => self.openai_messages(&sent, &mut rebuilt),
+            _ => self.anthropic_messages(&sent, &mut rebuilt),
+        }
+        let lead: Vec<&str> = blocks
+            .iter()
+            .filter(|b| b.is_generated() && !is_staged(b) && b.memory_state.is_active())
+            .map(|b| b.content.as_str())
+            .collect();
+        if !lead.is_empty() {
+            lead_first_user(&mut rebuilt, &lead.join("\n\n"));
+        }
+        body["messages"] = Value::Array(rebuilt);
+        true
+    }
+
+    /// The part exactly as the client sent it, while `block` still holds
+    /// the content it was ingested with.
+    fn as_sent(&self, block: &Block) -> Option<Value> {
+        self.current
+            .templates
+            .get(&block.id)
+            .filter(|t| t.content == block.content)
+            .map(|t| t.part.clone())
+    }
+
+    /// The client's part for `block` to rewrite, or an empty object.
+    fn base(&self, block: &Block) -> Value {
+        self.current
+            .templates
+            .get(&block.id)
+            .map(|t| t.part.clone())
+            .filter(Value::is_object)
+            .unwrap_or_else(|| json!({}))
+    }
+
+    fn anthropic_messages(&self, blocks: &[&Block], out: &mut Vec<Value>) {
+        let mut current: Option<(&str, Vec<Value>)> = None;
+        for block in blocks {
+            let role = match block.role {
+                Role::User | Role::ToolResult => "user",
+                Role::Assistant | Role::ToolUse => "assistant",
+                // Anthropic takes system text only in `system`.
+                Role::System => continue,
+            };
+            let part = self.anthropic_part(block);
+            match &mut current {
+                Some((open, parts)) if *open == role => parts.push(part),
+                _ => {
+                    out.extend(current.take().map(|(role, parts)| message(role, parts)));
+                    current = Some((role, vec![part]));
+                }
+            }
+        }
+        out.extend(current.map(|(role, parts)| message(role, parts)));
+    }
+
+    fn anthropic_part(&self, block: &Block) -> Value {
+        if let Some(part) = self.as_sent(block) {
+            return part;
+        }
+        let mut part = self.base(block);
+        match (block.role, block.metadata.tool_use_id.as_deref()) {
+            (Role::ToolUse, Some(id)) => {
+                part["type"] = json!("tool_use");
+                part["id"] = json!(id);
+                part["name"] = json!(block.metadata.tool_name.as_deref().unwrap_or("tool"));
+                part["input"] = tool_input(&block.content);
+            }
+            (Role::ToolResult, Some(id)) => {
+                part["type"] = json!("tool_result");
+                part["tool_use_id"] = json!(id);
+                part["content"] = json!(block.content);
+            }
+            _ => return self.content_part(block, part),
+        }
+        part
+    }
+
+    /// A text part for `block`, keeping the fields of a text `part`, or
+    /// the raw part of an opaque block (an image, a thinking block) sent
+    /// back unchanged.
+    fn content_part(&self, block: &Block, mut part: Value) -> Value {
+        if !self.current.templates.contains_key(&block.id) {
+            if let Some(raw) = raw_part(block) {
+                return raw;
+            }
+        }
+        if part["type"] != "text" {
+            part = json!({});
+        }
+        part["type"] = json!("text");
+        part["text"] = json!(block.content);
+        part
+    }
+
+    fn openai_messages(&self, blocks: &[&Block], out: &mut Vec<Value>) {
+        let mut user: Vec<Value> = Vec::new();
+        let mut text: Vec<&str> = Vec::new();
+        let mut calls: Vec<Value> = Vec::new();
+        for block in blocks {
+            match (block.role, block.metadata.tool_use_id.as_deref()) {
+                (Role::User, _) | (Role::ToolResult, None) => {
+                    flush_assistant(&mut text, &mut calls, out);
+                    let part = match self.as_sent(block) {
+                        Some(part) => part,
+                        None => self.content_part(block, self.base(block)),
+                    };
+                    user.push(part);
+                }
+                (Role::Assistant, _) | (Role::ToolUse, None) => {
+                    flush_user(&mut user, out);
+                    text.push(&block.content);
+                }
+                (Role::ToolUse, Some(id)) => {
+                    flush_user(&mut user, out);
+                    calls.push(self.as_sent(block).unwrap_or_else(|| {
+                        let mut call = self.base(block);
+                        call["id"] = json!(id);
+                        call["type"] = json!("function");
+                        call["function"]["name"] =
+                            json!(block.metadata.tool_name.as_deref().unwrap_or("tool"));
+                        call["function"]["arguments"] = json!(block.content);
+                        call
+                    }));
+                }
+                (Role::ToolResult, Some(id)) => {
+                    flush_user(&mut user, out);
+                    flush_assistant(&mut text, &mut calls, out);
+                    out.push(self.as_sent(block).unwrap_or_else(|| {
+                        let mut message = self.base(block);
+                        message["role"] = json!("tool");
+                        message["tool_call_id"] = json!(id);
+                        message["content"] = json!(block.content);
+                        message
+                    }));
+                }
+                (Role::System, _) => {
+                    flush_user(&mut user, out);
+                    flush_assistant(&mut text, &mut calls, out);
+                    out.push(self.as_sent(block).unwrap_or_else(|| {
+                        let mut message = self.base(block);
+                        if message.get("role").is_none() {
+                            message["role"] = json!("system");
+                        }
+                        message["content"] = json!(block.content);
+                        message
+                    }));
+                }
+            }
+        }
+        flush_user(&mut user, out);
+        flush_assistant(&mut text, &mut calls, out);
+    }
+}
+
+/// For each of `blocks`, whether a rebuilt request carries it as a
+/// message part: active blocks the provider sent, leaving out tool calls
+/// and results whose counterpart is not sent. Generated blocks are sent
+/// separately and marked `false`.
+pub fn sent_mask(blocks: &[Block]) -> Vec<bool> {
+    let active = |b: &Block| !b.is_generated() && b.memory_state.is_active();
+    let ids = |role: Role| -> HashSet<&str> {
+        blocks
+            .iter()
+            .filter(|b| active(b) && b.role == role)
+            .filter_map(|b| b.metadata.tool_use_id.as_deref())
+            .collect()
+    };
+    let calls = ids(Role::ToolUse);
+    let results = ids(Role::ToolResult);
+    blocks
+        .iter()
+        .map(|b| {
+            active(b)
+                && match (b.role, b.metadata.tool_use_id.as_deref()) {
+                    (Role::ToolUse, Some(id)) => results.contains(id),
+                    (Role::ToolResult, Some(id)) => calls.contains(id),
+                    _ => true,
+                }
+        })
+        .collect()
+}
+
+/// Blocks the rebuilt request carries as message parts; see [`sent_mask`].
+fn sent_blocks(blocks: &[Block]) -> Vec<&Block> {
+    blocks
+        .iter()
+        .zip(sent_mask(blocks))
+        .filter_map(|(block, sent)| sent.then_some(block))
+        .collect()
+}
+
+/// Number of OpenAI-style system and developer messages opening the
+/// conversation; Anthropic bodies keep theirs in `system`.
+fn leading_system(messages: &[Value], provider: &str) -> usize {
+    if provider != "openai" {
+        return 0;
+    }
+    messages
+        .iter()
+        .take_while(|m| matches!(m["role"].as_str(), Some("system" | "developer")))
+        .count()
+}
+
+fn message_hash(index: usize, message: &Value) -> String {
+    let digest = Sha256::digest(format!("{index}:{message}").as_bytes());
+    let hex: String = digest.iter().map(|b| format!("{b:02x}")).collect();
+    hex[..MESSAGE_HASH_LEN].to_string()
+}
+
+/// Hash of the message an ingested block came from.
+fn message_of(id: &str) -> Option<&str> {
+    id.strip_prefix(MESSAGE_ID_PREFIX)?.get(..MESSAGE_HASH_LEN)
+}
+
+fn anthropic_parts(message: &Value) -> Vec<Part> {
+    let speaker = if message["role"] == "assistant" {
+        Role::Assistant
+    } else {
+        Role::User
+    };
+    let parts = match &message["content"] {
+        Value::String(text) => vec![json!({ "type": "text", "text": text })],
+        Value::Array(parts) => parts.clone(),
+        _ => Vec::new(),
+    };
+    parts
+        .into_iter()
+        .map(|part| match part["type"].as_str() {
+            Some("text") => {
+                let text = part["text"].as_str().unwrap_or_default().to_string();
+                Part::new(speaker, "text", text, part)
+            }
+            Some("tool_use") => {
+                let mut call = Part::new(
+                    Role::ToolUse,
+                    "tool_use",
+                    part["input"].to_string(),
+                    Value::Null,
+                );
+                call.tool_name = part["name"].as_str().map(str::to_string);
+                call.tool_use_id = part["id"].as_str().map(str::to_string);
+                call.file_paths = input_paths(&part["input"]);
+                call.template = part;
+                call
+            }
+            Some("tool_result") => {
+                let text = result_text(&part["content"]);
+                let mut result = Part::new(Role::ToolResult, "tool_result", text, Value::Null);
+                result.tool_use_id = part["tool_use_id"].as_str().map(str::to_string);
+                result.template = part;
+                result
+            }
+            kind => {
+                let kind = kind.unwrap_or("unknown").to_string();
+                Part::new(speaker, &kind, part.to_string(), part)
+            }
+        })
+        .collect()
+}
+
+fn openai_parts(message: &Value) -> Vec<Part> {
+    match message["role"].as_str() {
+        Some("user") => match &message["content"] {
+            Value::String(text) => vec![Part::new(
+                Role::User,
+                "text",
+                text.clone(),
+                json!({ "type": "text", "text": text }),
+            )],
+            Value::Array(parts) => parts
+                .iter()
+                .map(|part| match part["type"].as_str() {
+                    Some("text") => {
+                        let text = part["text"].as_str().unwrap_or_default().to_string();
+                        Part::new(Role::User, "text", text, part.clone())
+                    }
+                    kind => Part::new(
+                        Role::User,
+                        kind.unwrap_or("unknown"),
+                        part.to_string(),
+                        part.clone(),
+                    ),
+                })
+                .collect(),
+            _ => Vec::new(),
+        },
+        Some("assistant") => {
+            let mut parts: Vec<Part> = match &message["content"] {
+                Value::String(text) if !text.is_empty() => {
+                    vec![Part::new(
+                        Role::Assistant,
+                        "text",
+                        text.clone(),
+                        Value::Null,
+                    )]
+                }
+                Value::Array(parts) => parts
+                    .iter()
+                    .filter_map(|part| part["text"].as_str().or(part["refusal"].as_str()))
+                    .map(|text| Part::new(Role::Assistant, "text", text.to_string(), Value::Null))
+                    .collect(),
+                _ => Vec::new(),
+            };
+            for call in message["tool_calls"].as_array().into_iter().flatten() {
+                let arguments = call["function"]["arguments"].as_str().unwrap_or_default();
+                let mut part = Part::new(
+                    Role::ToolUse,
+                    "tool_use",
+                    arguments.to_string(),
+                    call.clone(),
+                );
+                part.tool_name = call["function"]["name"].as_str().map(str::to_string);
+                part.tool_use_id = call["id"].as_str().map(str::to_string);
+                part.file_paths = serde_json::from_str(arguments)
+                    .map(|input: Value| input_paths(&input))
+                    .unwrap_or_default();
+                parts.push(part);
+            }
+            parts
+        }
+        Some("tool") => {
+            let text = result_text(&message["content"]);
+            let mut part = Part::new(Role::ToolResult, "tool_result", text, message.clone());
+            part.tool_use_id = message["tool_call_id"].as_str().map(str::to_string);
+            vec![part]
+        }
+        _ => vec![Part::new(
+            Role::System,
+            "message",
+            result_text(&message["content"]),
+            message.clone(),
+        )],
+    }
+}
+
+/// Text of a tool result or message content: a string, or the text parts
+/// of a list joined by newlines.
+fn result_text(content: &Value) -> String {
+    match content {
+        Value::String(text) => text.clone(),
+        Value::Array(parts) => parts
+            .iter()
+            .filter_map(|p| p["text"].as_str())
+            .collect::<Vec<_>>()
+            .join("\n"),
+        _ => String::new(),
+    }
+}
+
+fn input_paths(input: &Value) -> Vec<String> {
+    PATH_KEYS
+        .iter()
+        .filter_map(|key| input[*key].as_str())
+        .map(str::to_string)
+        .collect()
+}
+
+/// A tool call's input from block content: the JSON object it was
+/// ingested as, or the compressed text wrapped in one.
+fn tool_input(content: &str) -> Value {
+    serde_json::from_str::<Value>(content)
+        .ok()
+        .filter(Value::is_object)
+        .unwrap_or_else(|| json!({ "summary": content }))
+}
+
+/// The raw part an opaque block holds, if its content still is one.
+fn raw_part(block: &Block) -> Option<Value> {
+    let kind = block.block_type.as_deref()?;
+    if matches!(kind, "text" | "tool_use" | "tool_result" | "message") {
+        return None;
+    }
+    serde_json::from_str::<Value>(&block.content)
+        .ok()
+        .filter(|part| part["type"] == kind)
+}
+
+/// A message of `parts`; a lone plain text part becomes string content.
+fn message(role: &str, parts: Vec<Value>) -> Value {
+    let content = match parts.as_slice() {
+        [part] if part["type"] == "text" && part.as_object().is_some_and(|o| o.len() == 2) => {
+            part["text"].clone()
+        }
+        _ => Value::Array(parts),
+    };
+    json!({ "role": role, "content": content })
+}
+
+fn flush_user(parts: &mut Vec<Value>, out: &mut Vec<Value>) {
+    if !parts.is_empty() {
+        out.push(message("user", std::mem::take(parts)));
+    }
+}
+
+fn flush_assistant(text: &mut Vec<&str>, calls: &mut Vec<Value>, out: &mut Vec<Value>) {
+    if text.is_empty() && calls.is_empty() {
+        return;
+    }
+    let content = if text.is_empty() {
+        Value::Null
+    } else {
+        json!(text.join("\n\n"))
+    };
+    let mut message = json!({ "role": "assistant", "content": content });
+    if !calls.is_empty() {
+        message["tool_calls"] = Value::Array(std::mem::take(calls));
+    }
+    text.clear();
+    out.push(message);
+}
+
+/// Put `text` ahead of the first user message's content.
+fn lead_first_user(messages: &mut [Value], text: &str) {
+    let Some(message) = messages.iter_mut().find(|m| m["role"] == "user") else {
+        return;
+    };
+    let content = &mut message["content"];
+    match content {
+        Value::String(existing) => *existing = format!("{text}\n\n{existing}"),
+        Value::Array(parts) => parts.insert(0, json!({ "type": "text", "text": text })),
+        _ => *content = json!(text),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::block::CompressionVersion;
+    use crate::engine::memory::MemoryState;
+    use crate::engine::types::{BuiltInZone, CompressionLevel, PinPosition, Zone};
+
+    fn anthropic_body() -> Value {
+        json!({
+            "system": "Be brief.",
+            "messages": [
+                { "role": "user", "content": "Why does the login fail?" },
+                { "role": "assistant", "content": [
+                    { "type": "text", "text": "Let me read it." },
+                    { "type": "tool_use", "id": "toolu_1", "name": "Read",
+                      "input": { "file_path": "src/auth.rs" } },
+                ] },
+                { "role": "user", "content": [
+                    { "type": "tool_result", "tool_use_id": "toolu_1",
+                      "content": "fn login() {}", "cache_control": { "type": "ephemeral" } },
+                ] },
+            ],
+        })
+    }
+
+    #[test]
+    fn test_ingest_once_and_rebuild_as_sent() {
+        let mut transcript = Transcript::default();
+        let mut blocks = Vec::new();
+        let body = anthropic_body();
+        assert_eq!(transcript.ingest(&mut blocks, &body, "anthropic"), 4);
+        assert_eq!(transcript.ingest(&mut blocks, &body, "anthropic"), 0);
+
+        let result = &blocks[3];
+        assert_eq!(result.role, Role::ToolResult);
+        assert_eq!(result.metadata.tool_name.as_deref(), Some("Read"));
+        assert_eq!(result.metadata.file_paths, ["src/auth.rs"]);
+
+        let mut rebuilt = body.clone();
+        assert!(transcript.rebuild(&blocks, &mut rebuilt, "anthropic"));
+        assert_eq!(rebuilt, body);
+    }
+
+    #[test]
+    fn test_rebuild_follows_block_state() {
+        let mut transcript = Transcript::default();
+        let mut blocks = Vec::new();
+        let mut body = anthropic_body();
+        transcript.ingest(&mut blocks, &body, "anthropic");
+
+        blocks[3].compressed_versions.trimmed =
+            Some(CompressionVersion::new("fn login()".to_string()));
+        blocks[3].set_compression_level(CompressionLevel::Trimmed);
+        blocks[1].memory_state = MemoryState::Cold;
+        let lead = Block::generated(
+            "note".to_string(),
+            "soft_checkpoint",
+            "Notes".to_string(),
+            Zone::BuiltIn(BuiltInZone::Primacy),
+            0,
+        );
+        blocks.insert(0, lead);
+        transcript.rebuild(&blocks, &mut body, "anthropic");
+
+        let messages = body["messages"].as_array().expect("messages");
+        assert_eq!(messages[0]["content"], "Notes\n\nWhy does the login fail?");
+        assert_eq!(messages[1]["content"][0]["type"], "tool_use");
+        let result = &messages[2]["content"][0];
+        assert_eq!(result["content"], "fn login()");
+        assert_eq!(result["cache_control"]["type"], "ephemeral");
+
+        // Without its result the call is not sent.
+        blocks.pop();
+        transcript.rebuild(&blocks, &mut body, "anthropic");
+        assert_eq!(body["messages"].as_array().map(Vec::len), Some(1));
+    }
+
+    #[test]
+    fn test_conversations_are_parked_and_diverged_history_dropped() {
+        let mut transcript = Transcript::default();
+        let mut blocks = Vec::new();
+        let body = anthropic_body();
+        transcript.ingest(&mut blocks, &body, "anthropic");
+        blocks[0].pinned = Some(PinPosition::Top);
+
+        let side = json!({ "messages": [{ "role": "user", "content": "Name this chat" }] });
+        assert_eq!(transcript.ingest(&mut blocks, &side, "anthropic"), 1);
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].content, "Name this chat");
+
+        // Back on the first conversation, its blocks come back as they were.
+        assert_eq!(transcript.ingest(&mut blocks, &body, "anthropic"), 0);
+        assert_eq!(blocks.len(), 4);
+        assert!(blocks[0].pinned.is_some());
+
+        let mut edited = body.clone();
+        edited["messages"][2]["content"] = json!("Never mind.");
+        assert_eq!(transcript.ingest(&mut blocks, &edited, "anthropic"), 1);
+        assert_eq!(blocks.len(), 4);
+        assert_eq!(blocks[3].content, "Never mind.");
+    }
+
+    #[test]
+    fn test_openai_round_trip() {
+        let body = json!({
+            "model": "gpt-test",
+            "messages": [
+                { "role": "system", "content": "Be brief." },
+                { "role": "user", "content": "Run the tests" },
+                { "role": "assistant", "content": null, "tool_calls": [
+                    { "id": "call_1", "type": "function",
+                      "function": { "name": "bash", "arguments": "{\"cmd\":\"cargo test\"}" } },
+                ] },
+                { "role": "tool", "tool_call_id": "call_1", "content": "ok" },
+                { "role": "assistant", "content": "All green." },
+            ],
+        });
+        let mut transcript = Transcript::default();
+        let mut blocks = Vec::new();
+        assert_eq!(transcript.ingest(&mut blocks, &body, "openai"), 4);
+
+        let mut rebuilt = body.clone();
+        transcript.rebuild(&blocks, &mut rebuilt, "openai");
+        assert_eq!(rebuilt, body);
+
+        blocks[1].content = "{\"cmd\":\"cargo test -q\"}".to_string();
+        transcript.rebuild(&blocks, &mut rebuilt, "openai");
+        assert_eq!(
+            rebuilt["messages"][2]["tool_calls"][0]["function"]["arguments"],
+            "{\"cmd\":\"cargo test -q\"}"
+        );
+    }
+}
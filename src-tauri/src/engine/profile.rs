@@ -0,0 +1,890 @@
+//! Project profiles: `.aperture.yml` and the named presets it defines.
+//!
+//! A profile lives in the session's working directory or any directory
+//! above it up to the repository root. Each preset sets zone thresholds,
+//! the recency window, the auto-compression policy, staged items, upstream
+//! routing and rules:
+//!
+//! ```yaml
+//! project: billing-service
+//! default_preset: focused
+//! presets:
+//!   base:
+//!     zones: { recency_turns: 8 }
+//!     compression: { auto: true, pressure_threshold: 0.85 }
+//!   focused:
+//!     extends: base
+//!     staging:
+//!       - file: docs/architecture.md
+//!         zone: primacy
+//!         session_start: true
+//! ```
+//!
+//! A preset takes every setting it leaves unset from the preset it
+//! `extends`. Lists (`staging`, `rules`) are replaced, not merged. Unknown
+//! keys are rejected with their path and line.
+//!
+//! A profile is picked up from whatever directory the user works in, so its
+//! routing only applies to upstream URLs the user approved in
+//! `APERTURE_ALLOWED_UPSTREAMS`, and staged files must stay inside the
+//! profile's directory.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use tauri::State;
+use thiserror::Error;
+use tracing::{info, warn};
+
+use super::budget::BudgetConfig;
+use super::checkpoint::hard::CheckpointStore;
+use super::compression::quality::QualityConfig;
+use super::rules::Rule;
+use super::session::{EngineSession, SharedSession};
+use super::staging::{InjectionCondition, NewStagedItem, StagedSource, StagingError};
+use super::types::Zone;
+use crate::proxy::UpstreamConfig;
+
+/// Profile file names looked up in each directory, in order.
+pub const PROFILE_FILE_NAMES: [&str; 2] = [".aperture.yml", ".aperture.yaml"];
+
+/// Environment variable listing the upstream base URLs profiles may route
+/// to, comma-separated.
+pub const ALLOWED_UPSTREAMS_ENV: &str = "APERTURE_ALLOWED_UPSTREAMS";
+
+/// Recency window when no preset sets one, in turns.
+const DEFAULT_RECENCY_TURNS: u32 = 5;
+
+/// Context window assumed when no preset sets one.
+const DEFAULT_CONTEXT_WINDOW: u32 = 200_000;
+
+#[derive(Debug, Error)]
+pub enum ProfileError {
+    #[error("Failed to read profile {path}: {message}")]
+    Read { path: String, message: String },
+
+    #[error("Invalid profile {path}: {message}")]
+    Invalid { path: String, message: String },
+
+    #[error("No project profile loaded")]
+    NotLoaded,
+
+    #[error("Preset not found: {0}")]
+    PresetNotFound(String),
+
+    #[error("Preset inheritance cycle: {}", .0.join(" -> "))]
+    InheritanceCycle(Vec<String>),
+
+    #[error("Preset {preset}: staged file {path} is outside the profile directory")]
+    OutsideProfile { preset: String, path: String },
+
+    #[error("Preset {preset}: upstream {url} is not listed in {ALLOWED_UPSTREAMS_ENV}")]
+    UpstreamNotAllowed { preset: String, url: String },
+
+    #[error("Preset {preset}: {source}")]
+    Staging {
+        preset: String,
+        source: StagingError,
+    },
+}
+
+impl serde::Serialize for ProfileError {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+/// Zone sizing.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ZoneSettings {
+    pub primacy_max_tokens: Option<u32>,
+    pub recency_max_tokens: Option<u32>,
+    /// Turns counted as recent.
+    pub recency_turns: Option<u32>,
+}
+
+impl ZoneSettings {
+    fn inherit(self, parent: &Self) -> Self {
+        Self {
+            primacy_max_tokens: self.primacy_max_tokens.or(parent.primacy_max_tokens),
+            recency_max_tokens: self.recency_max_tokens.or(parent.recency_max_tokens),
+            recency_turns: self.recency_turns.or(parent.recency_turns),
+        }
+    }
+}
+
+/// Auto-compression policy; see [`BudgetConfig`] and [`QualityConfig`].
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CompressionSettings {
+    /// Compress automatically under budget pressure.
+    pub auto: Option<bool>,
+    pub context_window: Option<u32>,
+    pub pressure_threshold: Option<f32>,
+    pub max_ratio: Option<f32>,
+    pub min_confidence: Option<f32>,
+    pub self_check: Option<bool>,
+}
+
+impl CompressionSettings {
+    fn inherit(self, parent: &Self) -> Self {
+        Self {
+            auto: self.auto.or(parent.auto),
+            context_window: self.context_window.or(parent.context_window),
+            pressure_threshold: self.pressure_threshold.or(parent.pressure_threshold),
+            max_ratio: self.max_ratio.or(parent.max_ratio),
+            min_confidence: self.min_confidence.or(parent.min_confidence),
+            self_check: self.self_check.or(parent.self_check),
+        }
+    }
+}
+
+/// Upstream base URLs; each must be approved in
+/// [`ALLOWED_UPSTREAMS_ENV`].
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RoutingSettings {
+    pub anthropic_url: Option<String>,
+    pub openai_url: Option<String>,
+}
+
+impl RoutingSettings {
+    fn inherit(self, parent: &Self) -> Self {
+        Self {
+            anthropic_url: self.anthropic_url.or_else(|| parent.anthropic_url.clone()),
+            openai_url: self.openai_url.or_else(|| parent.openai_url.clone()),
+        }
+    }
+
+    fn urls(&self) -> impl Iterator<Item = &String> {
+        self.anthropic_url.iter().chain(&self.openai_url)
+    }
+}
+
+/// A staged item as written in a profile. Exactly one of `file`, `text`
+/// and `soft_checkpoint` is set; with no condition the item is always
+/// injected.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StagingSpec {
+    /// Defaults to the file path or checkpoint id.
+    pub name: Option<String>,
+    /// Relative paths resolve against the profile's directory, which the
+    /// file must be inside.
+    pub file: Option<PathBuf>,
+    pub text: Option<String>,
+    pub soft_checkpoint: Option<String>,
+    pub zone: Zone,
+    #[serde(default)]
+    pub priority: i32,
+    /// Inject only on the first request of a conversation.
+    #[serde(default)]
+    pub session_start: bool,
+    pub keyword: Option<String>,
+    pub pattern: Option<String>,
+}
+
+impl StagingSpec {
+    fn condition(&self) -> InjectionCondition {
+        match (&self.keyword, &self.pattern) {
+            (Some(keyword), _) => InjectionCondition::Keyword(keyword.clone()),
+            (_, Some(pattern)) => InjectionCondition::Pattern(pattern.clone()),
+            _ if self.session_start => InjectionCondition::OnSessionStart,
+            _ => InjectionCondition::Always,
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let sources = [
+            self.file.is_some(),
+            self.text.is_some(),
+            self.soft_checkpoint.is_some(),
+        ];
+        if sources.iter().filter(|set| **set).count() != 1 {
+            return Err("set exactly one of `file`, `text` or `soft_checkpoint`".to_string());
+        }
+        let conditions = [
+            self.session_start,
+            self.keyword.is_some(),
+            self.pattern.is_some(),
+        ];
+        if conditions.iter().filter(|set| **set).count() > 1 {
+            return Err("set at most one of `session_start`, `keyword` or `pattern`".to_string());
+        }
+        self.condition().validate().map_err(|e| e.to_string())
+    }
+
+    /// The item to stage for preset `preset` of a profile in `root`.
+    fn to_new_item(&self, preset: &str, root: &Path) -> Result<NewStagedItem, ProfileError> {
+        let source = if let Some(path) = &self.file {
+            let joined = root.join(path);
+            let read_error = |e: std::io::Error| ProfileError::Staging {
+                preset: preset.to_string(),
+                source: StagingError::FileRead {
+                    path: joined.display().to_string(),
+                    message: e.to_string(),
+                },
+            };
+            // Resolves `..` and symlinks before the containment check.
+            let path = joined.canonicalize().map_err(read_error)?;
+            if !path.starts_with(root.canonicalize().map_err(read_error)?) {
+                return Err(ProfileError::OutsideProfile {
+                    preset: preset.to_string(),
+                    path: joined.display().to_string(),
+                });
+            }
+            StagedSource::File { path }
+        } else if let Some(checkpoint_id) = &self.soft_checkpoint {
+            StagedSource::SoftCheckpoint {
+                checkpoint_id: checkpoint_id.clone(),
+            }
+        } else {
+            StagedSource::Text {
+                content: self.text.clone().unwrap_or_default(),
+            }
+        };
+        let name = self
+            .name
+            .clone()
+            .unwrap_or_else(|| match (&self.file, &self.soft_checkpoint) {
+                (Some(path), _) => path.display().to_string(),
+                (_, Some(checkpoint_id)) => checkpoint_id.clone(),
+                _ => "text".to_string(),
+            });
+        Ok(NewStagedItem {
+            name,
+            source,
+            target_zone: self.zone.clone(),
+            priority: self.priority,
+            condition: self.condition(),
+        })
+    }
+}
+
+/// One named preset. Unset fields come from the preset it `extends`, then
+/// from the engine defaults.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PresetSettings {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extends: Option<String>,
+    #[serde(default)]
+    pub zones: ZoneSettings,
+    #[serde(default)]
+    pub compression: CompressionSettings,
+    #[serde(default)]
+    pub routing: RoutingSettings,
+    pub staging: Option<Vec<StagingSpec>>,
+    pub rules: Option<Vec<Rule>>,
+}
+
+impl PresetSettings {
+    fn inherit(self, parent: &Self) -> Self {
+        Self {
+            extends: self.extends,
+            zones: self.zones.inherit(&parent.zones),
+            compression: self.compression.inherit(&parent.compression),
+            routing: self.routing.inherit(&parent.routing),
+            staging: self.staging.or_else(|| parent.staging.clone()),
+            rules: self.rules.or_else(|| parent.rules.clone()),
+        }
+    }
+}
+
+/// A preset with its inheritance applied.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResolvedPreset {
+    pub name: String,
+    /// The preset and its ancestors, nearest first.
+    pub chain: Vec<String>,
+    pub settings: PresetSettings,
+}
+
+impl ResolvedPreset {
+    pub fn recency_turns(&self) -> u32 {
+        self.settings
+            .zones
+            .recency_turns
+            .unwrap_or(DEFAULT_RECENCY_TURNS)
+    }
+
+    pub fn auto_compression(&self) -> bool {
+        self.settings.compression.auto.unwrap_or(true)
+    }
+
+    pub fn budget_config(&self) -> BudgetConfig {
+        let settings = &self.settings.compression;
+        let mut config =
+            BudgetConfig::new(settings.context_window.unwrap_or(DEFAULT_CONTEXT_WINDOW));
+        config.recency_turns = self.recency_turns();
+        let quality = QualityConfig::default();
+        config.pressure_threshold = settings
+            .pressure_threshold
+            .unwrap_or(config.pressure_threshold);
+        config.quality = QualityConfig {
+            max_ratio: settings.max_ratio.unwrap_or(quality.max_ratio),
+            min_confidence: settings.min_confidence.unwrap_or(quality.min_confidence),
+            self_check: settings.self_check.unwrap_or(quality.self_check),
+        };
+        config
+    }
+}
+
+/// Contents of a `.aperture.yml`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProjectProfile {
+    pub project: Option<String>,
+    /// Preset applied when the profile is loaded.
+    pub default_preset: Option<String>,
+    #[serde(default)]
+    pub presets: BTreeMap<String, PresetSettings>,
+}
+
+impl ProjectProfile {
+    /// Parse and validate a profile read from `path`.
+    pub fn parse(text: &str, path: &Path) -> Result<Self, ProfileError> {
+        let invalid = |message: String| ProfileError::Invalid {
+            path: path.display().to_string(),
+            message,
+        };
+        if text.trim().is_empty() {
+            return Ok(Self::default());
+        }
+        let profile: Self = serde_yaml::from_str(text).map_err(|e| invalid(e.to_string()))?;
+        profile.validate().map_err(invalid)?;
+        Ok(profile)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if let Some(name) = &self.default_preset {
+            if !self.presets.contains_key(name) {
+                return Err(format!("default_preset: unknown preset `{name}`"));
+            }
+        }
+        for (name, preset) in &self.presets {
+            if let Some(parent) = &preset.extends {
+                if !self.presets.contains_key(parent) {
+                    return Err(format!("presets.{name}.extends: unknown preset `{parent}`"));
+                }
+            }
+            let compression = &preset.compression;
+            let fractions = [
+                ("pressure_threshold", compression.pressure_threshold),
+                ("min_confidence", compression.min_confidence),
+            ];
+            for (key, value) in fractions {
+                if let Some(value) = value.filter(|v| !(0.0..=1.0).contains(v)) {
+                    return Err(format!(
+                        "presets.{name}.compression.{key}: must be between 0 and 1, got {value}"
+                    ));
+                }
+            }
+            for (i, spec) in preset.staging.iter().flatten().enumerate() {
+                spec.validate()
+                    .map_err(|message| format!("presets.{name}.staging[{i}]: {message}"))?;
+            }
+            for (i, rule) in preset.rules.iter().flatten().enumerate() {
+                rule.validate()
+                    .map_err(|e| format!("presets.{name}.rules[{i}]: {e}"))?;
+            }
+            self.resolve(name)
+                .map_err(|e| format!("presets.{name}.extends: {e}"))?;
+        }
+        Ok(())
+    }
+
+    /// `name` with the settings it inherits filled in.
+    pub fn resolve(&self, name: &str) -> Result<ResolvedPreset, ProfileError> {
+        let preset = |name: &str| {
+            self.presets
+                .get(name)
+                .ok_or_else(|| ProfileError::PresetNotFound(name.to_string()))
+        };
+        let mut settings = preset(name)?.clone();
+        let mut chain = vec![name.to_string()];
+        let mut parent = settings.extends.clone();
+        while let Some(parent_name) = parent {
+            let is_cycle = chain.contains(&parent_name);
+            chain.push(parent_name.clone());
+            if is_cycle {
+                return Err(ProfileError::InheritanceCycle(chain));
+            }
+            let parent_settings = preset(&parent_name)?;
+            settings = settings.inherit(parent_settings);
+            parent = parent_settings.extends.clone();
+        }
+        settings.extends = None;
+        Ok(ResolvedPreset {
+            name: name.to_string(),
+            chain,
+            settings,
+        })
+    }
+}
+
+/// The profile file nearest `start`, looking upward until the repository
+/// root (the first directory containing `.git`).
+pub fn find_profile(start: &Path) -> Option<PathBuf> {
+    for dir in start.ancestors() {
+        let found = PROFILE_FILE_NAMES
+            .iter()
+            .map(|name| dir.join(name))
+            .find(|path| path.is_file());
+        if found.is_some() {
+            return found;
+        }
+        if dir.join(".git").exists() {
+            break;
+        }
+    }
+    None
+}
+
+/// A profile and the file it came from.
+#[derive(Debug, Clone)]
+pub struct LoadedProfile {
+    pub path: PathBuf,
+    pub profile: ProjectProfile,
+}
+
+impl LoadedProfile {
+    pub fn load(path: &Path) -> Result<Self, ProfileError> {
+        let text = fs::read_to_string(path).map_err(|e| ProfileError::Read {
+            path: path.display().to_string(),
+            message: e.to_string(),
+        })?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            profile: ProjectProfile::parse(&text, path)?,
+        })
+    }
+
+    /// Directory relative paths in the profile resolve against.
+    pub fn root(&self) -> &Path {
+        self.path.parent().unwrap_or(Path::new("."))
+    }
+}
+
+/// A preset as listed to the user.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PresetInfo {
+    pub name: String,
+    pub extends: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProfileSummary {
+    pub path: String,
+    pub project: Option<String>,
+    pub default_preset: Option<String>,
+    pub active_preset: Option<String>,
+    pub presets: Vec<PresetInfo>,
+}
+
+/// The session's loaded profile and what its active preset applied.
+#[derive(Debug, Clone, Default)]
+pub struct ProfileState {
+    loaded: Option<LoadedProfile>,
+    active: Option<ResolvedPreset>,
+    /// Staged items added by the active preset.
+    staged_ids: Vec<String>,
+    /// Whether `EngineSession::rules` came from the active preset.
+    owns_rules: bool,
+    /// Upstream base URLs presets may route to, approved by the user.
+    allowed_upstreams: Vec<String>,
+}
+
+impl ProfileState {
+    pub fn active(&self) -> Option<&ResolvedPreset> {
+        self.active.as_ref()
+    }
+
+    /// Budget of the active preset, or the engine defaults.
+    pub fn budget_config(&self) -> BudgetConfig {
+        self.active
+            .as_ref()
+            .map(ResolvedPreset::budget_config)
+            .unwrap_or_else(|| BudgetConfig::new(DEFAULT_CONTEXT_WINDOW))
+    }
+
+    /// Approve `urls` as routing targets for presets applied from now on.
+    pub fn set_allowed_upstreams(&mut self, urls: Vec<String>) {
+        self.allowed_upstreams = urls
+            .iter()
+            .map(|url| normalize_url(url).to_string())
+            .collect();
+    }
+
+    /// `defaults` with the active preset's routing applied.
+    pub fn upstream_config(&self, defaults: &UpstreamConfig) -> UpstreamConfig {
+        let routing = self.active.as_ref().map(|p| &p.settings.routing);
+        let url = |pick: fn(&RoutingSettings) -> &Option<String>, default: &String| {
+            routing
+                .and_then(|r| pick(r).as_deref())
+                .map_or_else(|| default.clone(), |url| normalize_url(url).to_string())
+        };
+        UpstreamConfig {
+            anthropic_url: url(|r| &r.anthropic_url, &defaults.anthropic_url),
+            openai_url: url(|r| &r.openai_url, &defaults.openai_url),
+        }
+    }
+
+    /// Whether requests are compressed under budget pressure.
+    pub fn auto_compression(&self) -> bool {
+        self.active
+            .as_ref()
+            .is_none_or(ResolvedPreset::auto_compression)
+    }
+
+    pub fn summary(&self) -> Option<ProfileSummary> {
+        let loaded = self.loaded.as_ref()?;
+        Some(ProfileSummary {
+            path: loaded.path.display().to_string(),
+            project: loaded.profile.project.clone(),
+            default_preset: loaded.profile.default_preset.clone(),
+            active_preset: self.active.as_ref().map(|p| p.name.clone()),
+            presets: loaded
+                .profile
+                .presets
+                .iter()
+                .map(|(name, preset)| PresetInfo {
+                    name: name.clone(),
+                    extends: preset.extends.clone(),
+                })
+                .collect(),
+        })
+    }
+}
+
+/// Undo what the active preset applied.
+fn clear_preset(session: &mut EngineSession) {
+    for id in std::mem::take(&mut session.profile.staged_ids) {
+        // The user may have removed it already.
+        let _ = session.staging.remove(&id);
+    }
+    if std::mem::take(&mut session.profile.owns_rules) {
+        session.rules.clear();
+    }
+    session.profile.active = None;
+}
+
+/// Switch the session to preset `name` of the loaded profile. Staged items
+/// and rules from the previous preset are replaced; if the new preset's
+/// items fail to load or it routes to an upstream the user has not
+/// approved, the previous preset stays active.
+pub fn apply_preset(
+    session: &mut EngineSession,
+    store: &CheckpointStore,
+    name: &str,
+) -> Result<ResolvedPreset, ProfileError> {
+    let loaded = session
+        .profile
+        .loaded
+        .as_ref()
+        .ok_or(ProfileError::NotLoaded)?;
+    let resolved = loaded.profile.resolve(name)?;
+    let allowed = &session.profile.allowed_upstreams;
+    if let Some(url) = resolved
+        .settings
+        .routing
+        .urls()
+        .find(|url| !allowed.iter().any(|a| a == normalize_url(url)))
+    {
+        return Err(ProfileError::UpstreamNotAllowed {
+            preset: name.to_string(),
+            url: url.clone(),
+        });
+    }
+    let items = resolved
+        .settings
+        .staging
+        .iter()
+        .flatten()
+        .map(|spec| spec.to_new_item(name, loaded.root()))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut staging = session.staging.clone();
+    for id in &session.profile.staged_ids {
+        let _ = staging.remove(id);
+    }
+    let mut staged_ids = Vec::new();
+    for item in items {
+        let staged = staging
+            .add(item, store)
+            .map_err(|source| ProfileError::Staging {
+                preset: name.to_string(),
+                source,
+            })?;
+        staged_ids.push(staged.id);
+    }
+
+    session.staging = staging;
+    session.profile.staged_ids = staged_ids;
+    match &resolved.settings.rules {
+        Some(rules) => {
+            session.rules = rules.clone();
+            session.profile.owns_rules = true;
+        }
+        None if std::mem::take(&mut session.profile.owns_rules) => session.rules.clear(),
+        None => {}
+    }
+    session.profile.active = Some(resolved.clone());
+    info!("Applied preset {} ({})", name, resolved.chain.join(" -> "));
+    Ok(resolved)
+}
+
+/// Load the profile for `cwd` and apply its default preset, replacing any
+/// profile loaded before. Returns `None` if no profile applies to `cwd`.
+pub fn load_for_dir(
+    session: &mut EngineSession,
+    store: &CheckpointStore,
+    cwd: &Path,
+) -> Result<Option<ProfileSummary>, ProfileError> {
+    let loaded = find_profile(cwd)
+        .map(|path| LoadedProfile::load(&path))
+        .transpose()?;
+    clear_preset(session);
+    let default_preset = loaded
+        .as_ref()
+        .and_then(|l| l.profile.default_preset.clone());
+    session.profile.loaded = loaded;
+    if let Some(name) = default_preset {
+        apply_preset(session, store, &name)?;
+    }
+    Ok(session.profile.summary())
+}
+
+/// Base URLs listed in [`ALLOWED_UPSTREAMS_ENV`].
+pub fn allowed_upstreams_from_env() -> Vec<String> {
+    std::env::var(ALLOWED_UPSTREAMS_ENV)
+        .map(|raw| {
+            raw.split(',')
+                .map(str::trim)
+                .filter(|url| !url.is_empty())
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn normalize_url(url: &str) -> &str {
+    url.trim().trim_end_matches('/')
+}
+
+/// Load the profile for the process's working directory at startup, with
+/// the upstreams approved in the environment. Failures are logged.
+pub fn load_startup_profile(session: &SharedSession, store: &CheckpointStore) {
+    let mut session = session.lock();
+    session
+        .profile
+        .set_allowed_upstreams(allowed_upstreams_from_env());
+    let Ok(cwd) = std::env::current_dir() else {
+        return;
+    };
+    match load_for_dir(&mut session, store, &cwd) {
+        Ok(Some(summary)) => info!("Loaded project profile {}", summary.path),
+        Ok(None) => {}
+        Err(e) => warn!("Project profile not loaded: {e}"),
+    }
+}
+
+/// Load the profile that applies to `cwd`, e.g. a terminal's directory.
+#[tauri::command]
+pub fn profile_load(
+    store: State<'_, CheckpointStore>,
+    session: State<'_, SharedSession>,
+    cwd: String,
+) -> Result<Option<ProfileSummary>, ProfileError> {
+    load_for_dir(&mut session.lock(), &store, Path::new(&cwd))
+}
+
+#[tauri::command]
+pub fn profile_current(session: State<'_, SharedSession>) -> Option<ProfileSummary> {
+    session.lock().profile.summary()
+}
+
+#[tauri::command]
+pub fn preset_switch(
+    store: State<'_, CheckpointStore>,
+    session: State<'_, SharedSession>,
+    name: String,
+) -> Result<ResolvedPreset, ProfileError> {
+    apply_preset(&mut session.lock(), &store, &name)
+}
+
+#[tauri::command]
+pub fn preset_active(session: State<'_, SharedSession>) -> Option<ResolvedPreset> {
+    session.lock().profile.active().cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::paths::TestDir;
+
+    const PROFILE: &str = "\
+project: billing
+default_preset: focused
+presets:
+  base:
+    zones:
+      recency_turns: 8
+    compression:
+      pressure_threshold: 0.8
+    rules:
+      - id: r1
+        name: pin errors
+        trigger: { field: content, operator: contains, value: error }
+        action: pin
+  focused:
+    extends: base
+    compression:
+      context_window: 100000
+    staging:
+      - file: notes.md
+        zone: primacy
+        keyword: billing
+  bare:
+    compression:
+      auto: false
+";
+
+    #[test]
+    fn test_presets_inherit_unset_settings() {
+        let profile = ProjectProfile::parse(PROFILE, Path::new(".aperture.yml")).unwrap();
+        let focused = profile.resolve("focused").unwrap();
+
+        assert_eq!(focused.chain, ["focused", "base"]);
+        assert_eq!(focused.recency_turns(), 8);
+        let budget = focused.budget_config();
+        assert_eq!(budget.context_window, 100_000);
+        assert_eq!(budget.pressure_threshold, 0.8);
+        assert_eq!(focused.settings.rules.as_ref().map(Vec::len), Some(1));
+        assert_eq!(
+            focused.settings.staging.as_ref().unwrap()[0].condition(),
+            InjectionCondition::Keyword("billing".to_string())
+        );
+
+        let bare = profile.resolve("bare").unwrap();
+        assert!(!bare.auto_compression());
+        assert_eq!(bare.recency_turns(), DEFAULT_RECENCY_TURNS);
+    }
+
+    #[test]
+    fn test_profile_errors_name_the_offending_key() {
+        let path = Path::new(".aperture.yml");
+        let unknown = "presets:\n  base:\n    zones:\n      recency_turn: 3\n";
+        let err = ProjectProfile::parse(unknown, path)
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.contains("presets.base.zones: unknown field `recency_turn`"),
+            "{err}"
+        );
+        assert!(err.contains("line 4"), "{err}");
+
+        let cycle = "presets:\n  a:\n    extends: b\n  b:\n    extends: a\n";
+        let err = ProjectProfile::parse(cycle, path).unwrap_err().to_string();
+        assert!(err.contains("cycle: a -> b -> a"), "{err}");
+
+        let two_sources = "presets:\n  a:\n    staging:\n      - text: x\n        file: y\n        zone: middle\n";
+        let err = ProjectProfile::parse(two_sources, path)
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.contains("presets.a.staging[0]: set exactly one"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn test_profile_found_up_to_repo_root_and_presets_switch() {
+        let dir = TestDir::new("profile");
+        let repo = dir.path().to_path_buf();
+        let nested = repo.join("src/deep");
+        fs::create_dir_all(&nested).unwrap();
+        fs::create_dir(repo.join(".git")).unwrap();
+        assert_eq!(find_profile(&nested), None);
+
+        fs::write(repo.join(".aperture.yml"), PROFILE).unwrap();
+        fs::write(repo.join("notes.md"), "billing notes").unwrap();
+        assert_eq!(find_profile(&nested), Some(repo.join(".aperture.yml")));
+
+        let store = CheckpointStore::new(repo.join("checkpoints"));
+        let mut session = EngineSession::default();
+        let summary = load_for_dir(&mut session, &store, &nested)
+            .unwrap()
+            .unwrap();
+        assert_eq!(summary.active_preset.as_deref(), Some("focused"));
+        assert_eq!(session.staging.items().len(), 1);
+        assert_eq!(session.staging.items()[0].content, "billing notes");
+        assert_eq!(session.rules.len(), 1);
+
+        apply_preset(&mut session, &store, "bare").unwrap();
+        assert!(session.staging.items().is_empty());
+        assert!(session.rules.is_empty());
+        assert!(matches!(
+            apply_preset(&mut session, &store, "missing"),
+            Err(ProfileError::PresetNotFound(_))
+        ));
+        assert_eq!(session.profile.active().unwrap().name, "bare");
+    }
+
+    #[test]
+    fn test_routing_needs_approved_upstreams() {
+        let dir = TestDir::new("profile");
+        fs::create_dir_all(dir.join(".git")).unwrap();
+        let routing = "default_preset: local\npresets:\n  local:\n    routing:\n      anthropic_url: http://127.0.0.1:8080/\n  remote:\n    extends: local\n    routing:\n      openai_url: http://evil.test\n";
+        fs::write(dir.join(".aperture.yml"), routing).unwrap();
+        let store = CheckpointStore::new(dir.join("checkpoints"));
+        let defaults = UpstreamConfig::default();
+
+        let mut session = EngineSession::default();
+        assert!(matches!(
+            load_for_dir(&mut session, &store, dir.path()),
+            Err(ProfileError::UpstreamNotAllowed { .. })
+        ));
+        assert!(session.profile.active().is_none());
+        assert_eq!(session.profile.upstream_config(&defaults), defaults);
+
+        session
+            .profile
+            .set_allowed_upstreams(vec!["http://127.0.0.1:8080".to_string()]);
+        load_for_dir(&mut session, &store, dir.path()).unwrap();
+        let routed = session.profile.upstream_config(&defaults);
+        assert_eq!(routed.anthropic_url, "http://127.0.0.1:8080");
+        assert_eq!(routed.openai_url, defaults.openai_url);
+
+        let err = apply_preset(&mut session, &store, "remote").unwrap_err();
+        assert!(err.to_string().contains("http://evil.test"), "{err}");
+        assert_eq!(session.profile.active().unwrap().name, "local");
+    }
+
+    #[test]
+    fn test_profiles_cannot_stage_outside_their_directory() {
+        let dir = TestDir::new("profile");
+        let repo = dir.join("repo");
+        fs::create_dir_all(repo.join(".git")).unwrap();
+        fs::write(dir.join("secret.txt"), "secret").unwrap();
+        let escaping = "default_preset: a\npresets:\n  a:\n    staging:\n      - file: ../secret.txt\n        zone: primacy\n";
+        fs::write(repo.join(".aperture.yml"), escaping).unwrap();
+
+        let store = CheckpointStore::new(dir.join("checkpoints"));
+        let mut session = EngineSession::default();
+        assert!(matches!(
+            load_for_dir(&mut session, &store, &repo),
+            Err(ProfileError::OutsideProfile { .. })
+        ));
+        assert!(session.staging.items().is_empty());
+    }
+}
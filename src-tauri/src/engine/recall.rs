@@ -0,0 +1,283 @@
+//! Bring cold and archived blocks back into the active context.
+//!
+//! A recall names a block id, a topic, or free text. Free text is matched
+//! against each inactive block's recall metadata, topic keywords and
+//! original content, and the best matches are recalled.
+
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+
+use super::action_log::{ActionLog, ActionReason};
+use super::block::Block;
+use super::clustering::TopicClusters;
+use super::keywords::tokenize;
+use super::memory::{transition, MemoryError, MemoryState};
+use super::types::CompressionLevel;
+
+/// Free-text recall limits.
+#[derive(Debug, Clone)]
+pub struct RecallConfig {
+    /// Most blocks a free-text query recalls.
+    pub max_blocks: usize,
+    /// Share of the query's tokens a block must contain to be recalled.
+    pub min_score: f64,
+}
+
+impl Default for RecallConfig {
+    fn default() -> Self {
+        Self {
+            max_blocks: 5,
+            min_score: 0.5,
+        }
+    }
+}
+
+/// What to recall.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "value", rename_all = "snake_case")]
+pub enum RecallQuery {
+    BlockId(String),
+    /// A topic id or label.
+    Topic(String),
+    Query(String),
+}
+
+impl RecallQuery {
+    /// Parse `block:<id>`, `topic:<id or label>`, or free text.
+    pub fn parse(input: &str) -> Self {
+        let input = input.trim();
+        if let Some(id) = input.strip_prefix("block:") {
+            Self::BlockId(id.trim().to_string())
+        } else if let Some(topic) = input.strip_prefix("topic:") {
+            Self::Topic(topic.trim().to_string())
+        } else {
+            Self::Query(input.to_string())
+        }
+    }
+}
+
+/// Blocks brought back by a recall.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecallReport {
+    pub recalled: Vec<String>,
+    pub action_ids: Vec<String>,
+}
+
+/// Recall the blocks matching `query` into the active context.
+///
+/// Blocks that were compressed when they went cold come back warm at the
+/// same level; the rest come back hot. Fails only when a `BlockId` query
+/// names a block that does not exist.
+pub fn recall(
+    blocks: &mut [Block],
+    query: &RecallQuery,
+    clusters: &TopicClusters,
+    config: &RecallConfig,
+    log: &mut ActionLog,
+) -> Result<RecallReport, MemoryError> {
+    let ids: Vec<String> = match query {
+        RecallQuery::BlockId(id) => {
+            if !blocks.iter().any(|b| b.id == *id) {
+                return Err(MemoryError::BlockNotFound(id.clone()));
+            }
+            vec![id.clone()]
+        }
+        RecallQuery::Topic(topic) => {
+            let topic_ids = matching_topics(topic, clusters);
+            blocks
+                .iter()
+                .filter(|b| topic_of(b).is_some_and(|t| topic_ids.iter().any(|id| id == t)))
+                .map(|b| b.id.clone())
+                .collect()
+        }
+        RecallQuery::Query(text) => best_matches(blocks, text, config),
+    };
+
+    let mut report = RecallReport::default();
+    for block in blocks.iter_mut().filter(|b| ids.contains(&b.id)) {
+        if block.memory_state.is_active() {
+            continue;
+        }
+        let to = if block.compression_level == CompressionLevel::Original {
+            MemoryState::Hot
+        } else {
+            MemoryState::Warm
+        };
+        if let Some(action_id) = transition(block, to, None, ActionReason::Recall, log)? {
+            report.recalled.push(block.id.clone());
+            report.action_ids.push(action_id);
+        }
+    }
+    Ok(report)
+}
+
+fn topic_of(block: &Block) -> Option<&str> {
+    block
+        .recall
+        .as_ref()
+        .and_then(|r| r.topic.as_deref())
+        .or(block.topic_cluster.as_deref())
+}
+
+/// Topic ids whose id or label matches `topic`, case-insensitively.
+fn matching_topics(topic: &str, clusters: &TopicClusters) -> Vec<String> {
+    let wanted = topic.to_lowercase();
+    let mut ids = vec![topic.to_string()];
+    ids.extend(
+        clusters
+            .clusters()
+            .iter()
+            .filter(|c| c.id == topic || c.display_label().to_lowercase() == wanted)
+            .map(|c| c.id.clone()),
+    );
+    ids
+}
+
+/// Inactive blocks containing at least `min_score` of the query's tokens,
+/// best first.
+fn best_matches(blocks: &[Block], text: &str, config: &RecallConfig) -> Vec<String> {
+    let wanted: HashSet<String> = tokenize(text).into_iter().collect();
+    if wanted.is_empty() {
+        return Vec::new();
+    }
+    let mut scored: Vec<(f64, &str)> = blocks
+        .iter()
+        .filter(|b| !b.memory_state.is_active())
+        .filter_map(|block| {
+            let found: HashSet<String> = searchable_text(block)
+                .iter()
+                .flat_map(|text| tokenize(text))
+                .filter(|token| wanted.contains(token))
+                .collect();
+            let score = found.len() as f64 / wanted.len() as f64;
+            (score >= config.min_score).then_some((score, block.id.as_str()))
+        })
+        .collect();
+    scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+    scored
+        .into_iter()
+        .take(config.max_blocks)
+        .map(|(_, id)| id.to_string())
+        .collect()
+}
+
+fn searchable_text(block: &Block) -> Vec<&str> {
+    let mut texts = vec![block.compressed_versions.original.content.as_str()];
+    texts.extend(block.topic_keywords.iter().map(String::as_str));
+    if let Some(recall) = &block.recall {
+        texts.push(&recall.summary);
+        texts.extend(recall.files.iter().map(String::as_str));
+    }
+    texts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::block::{test_block, CompressionVersion};
+    use crate::engine::clustering::ClusterConfig;
+    use crate::engine::types::Role;
+
+    fn archive(blocks: &mut [Block], log: &mut ActionLog) {
+        for block in blocks.iter_mut() {
+            transition(
+                block,
+                MemoryState::Archived,
+                None,
+                ActionReason::Lifecycle,
+                log,
+            )
+            .expect("archive");
+        }
+    }
+
+    fn session() -> (Vec<Block>, ActionLog) {
+        let mut blocks = vec![
+            test_block("auth", Role::User, "Why does the login token refresh fail?"),
+            test_block("build", Role::ToolResult, "error: linker `cc` not found"),
+            test_block("css", Role::Assistant, "The sidebar flexbox overflows."),
+        ];
+        blocks[0].topic_cluster = Some("topic-1".to_string());
+        blocks[1].metadata.file_paths = vec!["src-tauri/Cargo.toml".to_string()];
+        blocks[1].compressed_versions.trimmed =
+            Some(CompressionVersion::new("linker `cc` not found".to_string()));
+        blocks[1].set_compression_level(CompressionLevel::Trimmed);
+        let mut log = ActionLog::new();
+        archive(&mut blocks, &mut log);
+        (blocks, log)
+    }
+
+    #[test]
+    fn test_parse_recall_query_prefixes() {
+        assert_eq!(
+            RecallQuery::parse("block: b-1"),
+            RecallQuery::BlockId("b-1".to_string())
+        );
+        assert_eq!(
+            RecallQuery::parse("topic:auth"),
+            RecallQuery::Topic("auth".to_string())
+        );
+        assert_eq!(
+            RecallQuery::parse("linker error"),
+            RecallQuery::Query("linker error".to_string())
+        );
+    }
+
+    #[test]
+    fn test_recall_by_block_id_restores_previous_level() {
+        let (mut blocks, mut log) = session();
+        let clusters = TopicClusters::new(ClusterConfig::default());
+
+        let report = recall(
+            &mut blocks,
+            &RecallQuery::BlockId("build".to_string()),
+            &clusters,
+            &RecallConfig::default(),
+            &mut log,
+        )
+        .expect("recall");
+        assert_eq!(report.recalled, vec!["build"]);
+        assert_eq!(blocks[1].memory_state, MemoryState::Warm);
+        assert_eq!(blocks[1].compression_level, CompressionLevel::Trimmed);
+        assert!(blocks[1].recall.is_none());
+
+        let missing = recall(
+            &mut blocks,
+            &RecallQuery::BlockId("nope".to_string()),
+            &clusters,
+            &RecallConfig::default(),
+            &mut log,
+        );
+        assert!(matches!(missing, Err(MemoryError::BlockNotFound(_))));
+    }
+
+    #[test]
+    fn test_recall_by_topic_and_query() {
+        let (mut blocks, mut log) = session();
+        let clusters = TopicClusters::new(ClusterConfig::default());
+
+        let report = recall(
+            &mut blocks,
+            &RecallQuery::Topic("topic-1".to_string()),
+            &clusters,
+            &RecallConfig::default(),
+            &mut log,
+        )
+        .expect("recall");
+        assert_eq!(report.recalled, vec!["auth"]);
+        assert_eq!(blocks[0].memory_state, MemoryState::Hot);
+
+        let report = recall(
+            &mut blocks,
+            &RecallQuery::parse("linker Cargo.toml"),
+            &clusters,
+            &RecallConfig::default(),
+            &mut log,
+        )
+        .expect("recall");
+        assert_eq!(report.recalled, vec!["build"]);
+        assert_eq!(blocks[2].memory_state, MemoryState::Archived);
+    }
+}
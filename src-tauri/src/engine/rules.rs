@@ -0,0 +1,975 @@
+//! Rule engine: user-defined triggers and actions over blocks.
+//!
+//! A [`Rule`] has the shape of the frontend's `Rule`: one trigger, an
+//! action and action config, plus optional further `conditions` that must
+//! also hold. Triggers read either the request (token pressure, turn
+//! count) or a block (age, role, type, tool name, content, tokens, zone).
+//! Rules run in order on each request; each firing changes matching
+//! blocks through the [`ActionLog`] or the trash, so it can be undone, and
+//! is kept in a [`RuleAudit`] of the latest firings. A removal others depend on is refused. A dry
+//! run makes the same pass over a copy of the blocks, checking removals
+//! against the trash, and reports what would change.
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use regex::{Regex, RegexBuilder};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use tauri::State;
+use thiserror::Error;
+use tracing::info;
+use uuid::Uuid;
+
+use super::action_log::{ActionLog, ActionReason, BlockChange};
+use super::block::Block;
+use super::budget::{projected_tokens, BudgetConfig};
+use super::dependency::RemovalPolicy;
+use super::memory::{self, MemoryState};
+use super::session::{EngineSession, SharedSession};
+use super::trash::TrashStore;
+use super::types::{CompressionLevel, PinPosition, Zone};
+use crate::events::timeline::now_ms;
+
+#[derive(Debug, Error)]
+pub enum RuleError {
+    #[error("Invalid rule {rule_id}: {message}")]
+    InvalidRule { rule_id: String, message: String },
+}
+
+impl serde::Serialize for RuleError {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+/// What a trigger reads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TriggerField {
+    /// Projected prompt tokens as a fraction of the context window.
+    TokenPressure,
+    /// Current turn number.
+    Turn,
+    /// Turns since the block was added.
+    Age,
+    Tokens,
+    Role,
+    BlockType,
+    ToolName,
+    Content,
+    Zone,
+}
+
+impl TriggerField {
+    fn is_numeric(self) -> bool {
+        matches!(
+            self,
+            Self::TokenPressure | Self::Turn | Self::Age | Self::Tokens
+        )
+    }
+
+    /// Whether the field is read from a block rather than the request.
+    fn is_block_field(self) -> bool {
+        !matches!(self, Self::TokenPressure | Self::Turn)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TriggerOperator {
+    Eq,
+    Gt,
+    Lt,
+    /// Substring, ignoring case.
+    Contains,
+    /// Regex, ignoring case.
+    Matches,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum TriggerValue {
+    Number(f64),
+    Text(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RuleTrigger {
+    pub field: TriggerField,
+    pub operator: TriggerOperator,
+    pub value: TriggerValue,
+}
+
+/// What a rule does to each matching block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RuleAction {
+    /// Step down to the next compression level that exists.
+    Condense,
+    /// Switch to `config.level`.
+    Compress,
+    /// Move to the trash.
+    Remove,
+    /// Pin at `config.position` (default top).
+    Pin,
+    Unpin,
+    /// Move to `config.zone`.
+    MoveZone,
+    /// Take out of the prompt, keeping it recallable.
+    Archive,
+    /// Record a warning with `config.message`; changes nothing.
+    Warn,
+}
+
+fn enabled_by_default() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    pub id: String,
+    pub name: String,
+    pub trigger: RuleTrigger,
+    /// Further triggers that must all hold too.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub conditions: Vec<RuleTrigger>,
+    pub action: RuleAction,
+    #[serde(default)]
+    pub config: Map<String, Value>,
+    #[serde(default = "enabled_by_default")]
+    pub enabled: bool,
+}
+
+/// A trigger ready to evaluate.
+struct Matcher {
+    field: TriggerField,
+    operator: TriggerOperator,
+    number: f64,
+    text: String,
+    regex: Option<Regex>,
+}
+
+impl RuleTrigger {
+    fn compile(&self) -> Result<Matcher, String> {
+        let field = serde_name(&self.field);
+        let operator = serde_name(&self.operator);
+        let text = match &self.value {
+            TriggerValue::Number(n) => n.to_string(),
+            TriggerValue::Text(t) => t.clone(),
+        };
+        let mut matcher = Matcher {
+            field: self.field,
+            operator: self.operator,
+            number: f64::NAN,
+            text: text.to_lowercase(),
+            regex: None,
+        };
+        match (self.field.is_numeric(), self.operator) {
+            (true, TriggerOperator::Eq | TriggerOperator::Gt | TriggerOperator::Lt) => {
+                matcher.number = text
+                    .trim()
+                    .parse()
+                    .map_err(|_| format!("`{field}` compares against a number, got {text:?}"))?;
+            }
+            (false, TriggerOperator::Matches) => {
+                let regex = RegexBuilder::new(&text)
+                    .case_insensitive(true)
+                    .build()
+                    .map_err(|e| format!("invalid pattern {text:?}: {e}"))?;
+                matcher.regex = Some(regex);
+            }
+            (false, TriggerOperator::Eq | TriggerOperator::Contains) => {}
+            (true, _) => return Err(format!("`{operator}` does not apply to number `{field}`")),
+            (false, _) => return Err(format!("`{operator}` does not apply to text `{field}`")),
+        }
+        Ok(matcher)
+    }
+}
+
+impl Matcher {
+    fn holds(&self, block: Option<&Block>, context: &RuleContext) -> bool {
+        if self.field.is_numeric() {
+            let value = match (self.field, block) {
+                (TriggerField::TokenPressure, _) => context.token_pressure,
+                (TriggerField::Turn, _) => context.current_turn as f64,
+                (TriggerField::Age, Some(block)) => context
+                    .current_turn
+                    .saturating_sub(block.metadata.turn_index)
+                    as f64,
+                (TriggerField::Tokens, Some(block)) => block.tokens as f64,
+                _ => return false,
+            };
+            return match self.operator {
+                TriggerOperator::Gt => value > self.number,
+                TriggerOperator::Lt => value < self.number,
+                _ => (value - self.number).abs() < f64::EPSILON,
+            };
+        }
+        let Some(block) = block else {
+            return false;
+        };
+        let value = match self.field {
+            TriggerField::Role => serde_name(&block.role),
+            TriggerField::BlockType => block.block_type.clone().unwrap_or_default(),
+            TriggerField::ToolName => block.metadata.tool_name.clone().unwrap_or_default(),
+            TriggerField::Zone => serde_name(&block.zone),
+            _ => block.content.clone(),
+        };
+        match &self.regex {
+            Some(regex) => regex.is_match(&value),
+            None if self.operator == TriggerOperator::Contains => {
+                value.to_lowercase().contains(&self.text)
+            }
+            None => value.to_lowercase() == self.text,
+        }
+    }
+}
+
+/// The name serde gives a unit variant, e.g. `tool_result`.
+fn serde_name<T: Serialize>(value: &T) -> String {
+    match serde_json::to_value(value) {
+        Ok(Value::String(name)) => name,
+        _ => String::new(),
+    }
+}
+
+/// A rule with its triggers compiled and config read.
+struct CompiledRule<'a> {
+    rule: &'a Rule,
+    matchers: Vec<Matcher>,
+    level: Option<CompressionLevel>,
+    zone: Option<Zone>,
+    position: PinPosition,
+}
+
+impl CompiledRule<'_> {
+    fn is_block_rule(&self) -> bool {
+        self.matchers.iter().any(|m| m.field.is_block_field())
+    }
+}
+
+impl Rule {
+    fn config<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>, String> {
+        self.config
+            .get(key)
+            .map(|value| serde_json::from_value(value.clone()))
+            .transpose()
+            .map_err(|e| format!("config.{key}: {e}"))
+    }
+
+    fn compile(&self) -> Result<CompiledRule<'_>, RuleError> {
+        let invalid = |message: String| RuleError::InvalidRule {
+            rule_id: self.id.clone(),
+            message,
+        };
+        let mut matchers = vec![self
+            .trigger
+            .compile()
+            .map_err(|m| invalid(format!("trigger: {m}")))?];
+        for (i, condition) in self.conditions.iter().enumerate() {
+            matchers.push(
+                condition
+                    .compile()
+                    .map_err(|m| invalid(format!("conditions[{i}]: {m}")))?,
+            );
+        }
+        let compiled = CompiledRule {
+            rule: self,
+            matchers,
+            level: self.config("level").map_err(invalid)?,
+            zone: self.config("zone").map_err(invalid)?,
+            position: self
+                .config("position")
+                .map_err(invalid)?
+                .unwrap_or(PinPosition::Top),
+        };
+        let action = serde_name(&self.action);
+        if self.action != RuleAction::Warn && !compiled.is_block_rule() {
+            return Err(invalid(format!(
+                "`{action}` needs a trigger on a block field"
+            )));
+        }
+        match self.action {
+            RuleAction::Compress if compiled.level.is_none() => {
+                Err(invalid("`compress` needs config.level".to_string()))
+            }
+            RuleAction::MoveZone if compiled.zone.is_none() => {
+                Err(invalid("`move_zone` needs config.zone".to_string()))
+            }
+            _ => Ok(compiled),
+        }
+    }
+
+    /// Check the triggers and the config the action needs.
+    pub fn validate(&self) -> Result<(), RuleError> {
+        self.compile().map(|_| ())
+    }
+}
+
+/// Request-level values triggers can read.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RuleContext {
+    pub current_turn: u32,
+    pub token_pressure: f64,
+}
+
+impl RuleContext {
+    /// Context for a request adding `incoming_tokens` to `blocks`.
+    pub fn new(blocks: &[Block], incoming_tokens: u32, config: &BudgetConfig) -> Self {
+        let projected = projected_tokens(blocks, incoming_tokens);
+        Self {
+            current_turn: blocks
+                .iter()
+                .map(|b| b.metadata.turn_index)
+                .max()
+                .unwrap_or(0),
+            token_pressure: projected as f64 / config.context_window.max(1) as f64,
+        }
+    }
+}
+
+/// One rule firing, on a block or, for `warn`, on the request.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RuleFiring {
+    pub id: String,
+    /// Unix epoch milliseconds.
+    pub at_ms: u64,
+    pub rule_id: String,
+    pub rule_name: String,
+    pub action: RuleAction,
+    pub block_id: Option<String>,
+    pub detail: String,
+    pub tokens_before: u32,
+    pub tokens_after: u32,
+    /// The [`ActionLog`] entry that can undo it, if any.
+    pub action_id: Option<String>,
+}
+
+/// Firings kept in a session's audit log unless configured otherwise.
+pub const DEFAULT_RULE_AUDIT_ENTRIES: usize = 1_000;
+
+/// The latest rule firings of a session, oldest first. Once full, each new
+/// firing evicts the oldest.
+#[derive(Debug, Clone)]
+pub struct RuleAudit {
+    entries: VecDeque<RuleFiring>,
+    max_entries: usize,
+}
+
+impl Default for RuleAudit {
+    fn default() -> Self {
+        Self::new(DEFAULT_RULE_AUDIT_ENTRIES)
+    }
+}
+
+impl RuleAudit {
+    pub fn new(max_entries: usize) -> Self {
+        Self {
+            entries: VecDeque::new(),
+            max_entries,
+        }
+    }
+
+    pub fn max_entries(&self) -> usize {
+        self.max_entries
+    }
+
+    /// Change the cap, evicting the oldest firings over it.
+    pub fn set_max_entries(&mut self, max_entries: usize) {
+        self.max_entries = max_entries;
+        self.evict();
+    }
+
+    pub fn extend(&mut self, firings: impl IntoIterator<Item = RuleFiring>) {
+        self.entries.extend(firings);
+        self.evict();
+    }
+
+    /// Replace the firings, keeping the newest under the cap.
+    pub fn replace(&mut self, firings: Vec<RuleFiring>) {
+        self.entries = firings.into();
+        self.evict();
+    }
+
+    pub fn entries(&self) -> Vec<RuleFiring> {
+        self.entries.iter().cloned().collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn evict(&mut self) {
+        let excess = self.entries.len().saturating_sub(self.max_entries);
+        self.entries.drain(..excess);
+    }
+}
+
+/// A match whose action could not be applied.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SkippedFiring {
+    pub rule_id: String,
+    pub block_id: String,
+    pub reason: String,
+}
+
+/// What one pass over the rules did, or would do on a dry run.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RuleRun {
+    pub dry_run: bool,
+    pub context: RuleContext,
+    pub firings: Vec<RuleFiring>,
+    pub skipped: Vec<SkippedFiring>,
+    /// Projected prompt tokens before and after the pass.
+    pub tokens_before: u32,
+    pub tokens_after: u32,
+}
+
+/// Outcome of applying an action to one block.
+enum Applied {
+    Changed {
+        detail: String,
+        action_id: Option<String>,
+    },
+    Unchanged,
+    Skipped(String),
+}
+
+/// Step `block` down to the next level it has a version for.
+fn next_level(block: &Block) -> Option<CompressionLevel> {
+    let mut level = block.compression_level;
+    while let Some(deeper) = level.deeper() {
+        level = deeper;
+        if block.compressed_versions.get(level).is_some() {
+            return Some(level);
+        }
+    }
+    None
+}
+
+/// Where a pass sends removed blocks.
+struct Removal<'a> {
+    trash: &'a TrashStore,
+    /// Fork branch the blocks belong to.
+    branch: &'a str,
+    /// Check each removal against the trash without changing it.
+    dry_run: bool,
+}
+
+/// Move the block at `index` to the trash unless others depend on it, and
+/// log the removal so it can be undone.
+fn remove(
+    blocks: &mut Vec<Block>,
+    index: usize,
+    log: &mut ActionLog,
+    removal: &Removal<'_>,
+    detail: String,
+) -> Applied {
+    if blocks[index].pinned.is_some() {
+        return Applied::Skipped("pinned".to_string());
+    }
+    let id = blocks[index].id.clone();
+    let (trash, branch) = (removal.trash, removal.branch);
+    let result = if removal.dry_run {
+        trash.preview_remove(blocks, branch, &id, RemovalPolicy::Refuse)
+    } else {
+        trash.remove(
+            blocks,
+            branch,
+            &id,
+            Some(detail.clone()),
+            RemovalPolicy::Refuse,
+        )
+    };
+    let removed = match result {
+        Ok(removed) => removed,
+        Err(e) => return Applied::Skipped(e.to_string()),
+    };
+    let mut action_id = None;
+    for entry in removed {
+        let is_target = entry.block.id == id;
+        let action = log.record_removal(
+            entry.block,
+            entry.ghost.index,
+            entry.ghost.after_block_id,
+            ActionReason::Rule,
+            detail.clone(),
+        );
+        if is_target {
+            action_id = Some(action.id.clone());
+        }
+    }
+    Applied::Changed {
+        detail: format!("{detail}, moved to trash"),
+        action_id,
+    }
+}
+
+fn apply(
+    compiled: &CompiledRule<'_>,
+    blocks: &mut Vec<Block>,
+    index: usize,
+    log: &mut ActionLog,
+    removal: &Removal<'_>,
+) -> Applied {
+    let rule = compiled.rule;
+    let detail = format!("rule {}", rule.name);
+    if rule.action == RuleAction::Remove {
+        return remove(blocks, index, log, removal, detail);
+    }
+    let block = &mut blocks[index];
+    let before = block.tokens;
+    let change = match rule.action {
+        RuleAction::Condense | RuleAction::Compress => {
+            let target = match rule.action {
+                RuleAction::Compress => compiled.level,
+                _ => next_level(block),
+            };
+            let Some(level) = target else {
+                return Applied::Skipped("no deeper version".to_string());
+            };
+            if block.compression_level == level {
+                return Applied::Unchanged;
+            }
+            if block.is_rewritten() {
+                return Applied::Skipped("content was rewritten".to_string());
+            }
+            let from = block.compression_level;
+            if !block.set_compression_level(level) {
+                return Applied::Skipped(format!("no {} version", serde_name(&level)));
+            }
+            BlockChange::CompressionLevel { from, to: level }
+        }
+        RuleAction::Pin | RuleAction::Unpin => {
+            let to = (rule.action == RuleAction::Pin).then_some(compiled.position);
+            if block.pinned == to {
+                return Applied::Unchanged;
+            }
+            let from = std::mem::replace(&mut block.pinned, to);
+            BlockChange::Pin { from, to }
+        }
+        RuleAction::MoveZone => match &compiled.zone {
+            Some(to) if block.zone != *to => {
+                let from = std::mem::replace(&mut block.zone, to.clone());
+                BlockChange::Zone {
+                    from,
+                    to: to.clone(),
+                }
+            }
+            _ => return Applied::Unchanged,
+        },
+        RuleAction::Archive => {
+            return match memory::transition(
+                block,
+                MemoryState::Archived,
+                None,
+                ActionReason::Rule,
+                log,
+            ) {
+                Ok(Some(id)) => Applied::Changed {
+                    detail,
+                    action_id: Some(id),
+                },
+                Ok(None) => Applied::Unchanged,
+                Err(e) => Applied::Skipped(e.to_string()),
+            };
+        }
+        RuleAction::Remove | RuleAction::Warn => return Applied::Unchanged,
+    };
+    let action_id = log
+        .record(block, change, before, ActionReason::Rule, detail.clone())
+        .id
+        .clone();
+    Applied::Changed {
+        detail,
+        action_id: Some(action_id),
+    }
+}
+
+/// Run `rules` in order over `blocks`, sending removed blocks through
+/// `removal`.
+fn run_pass(
+    rules: &[CompiledRule<'_>],
+    blocks: &mut Vec<Block>,
+    log: &mut ActionLog,
+    removal: &Removal<'_>,
+    context: &RuleContext,
+) -> (Vec<RuleFiring>, Vec<SkippedFiring>) {
+    let mut firings = Vec::new();
+    let mut skipped = Vec::new();
+    for compiled in rules.iter().filter(|c| c.rule.enabled) {
+        let rule = compiled.rule;
+        let firing = |block_id: Option<String>, detail, before, after, action_id| RuleFiring {
+            id: Uuid::new_v4().to_string(),
+            at_ms: now_ms(),
+            rule_id: rule.id.clone(),
+            rule_name: rule.name.clone(),
+            action: rule.action,
+            block_id,
+            detail,
+            tokens_before: before,
+            tokens_after: after,
+            action_id,
+        };
+
+        if !compiled.is_block_rule() {
+            if compiled.matchers.iter().all(|m| m.holds(None, context)) {
+                let message = rule.config::<String>("message").ok().flatten();
+                let tokens = projected_tokens(blocks, 0);
+                let detail = message.unwrap_or_else(|| format!("rule {}", rule.name));
+                firings.push(firing(None, detail, tokens, tokens, None));
+            }
+            continue;
+        }
+
+        let matching: Vec<String> = blocks
+            .iter()
+            .filter(|b| compiled.matchers.iter().all(|m| m.holds(Some(b), context)))
+            .map(|b| b.id.clone())
+            .collect();
+        for block_id in matching {
+            let Some(index) = blocks.iter().position(|b| b.id == block_id) else {
+                continue;
+            };
+            let before = blocks[index].tokens;
+            if rule.action == RuleAction::Warn {
+                let message = rule.config::<String>("message").ok().flatten();
+                let detail = message.unwrap_or_else(|| format!("rule {}", rule.name));
+                firings.push(firing(Some(block_id), detail, before, before, None));
+                continue;
+            }
+            match apply(compiled, blocks, index, log, removal) {
+                Applied::Changed { detail, action_id } => {
+                    let after = blocks
+                        .iter()
+                        .find(|b| b.id == block_id)
+                        .map_or(0, |b| b.tokens);
+                    firings.push(firing(Some(block_id), detail, before, after, action_id));
+                }
+                Applied::Unchanged => {}
+                Applied::Skipped(reason) => skipped.push(SkippedFiring {
+                    rule_id: rule.id.clone(),
+                    block_id,
+                    reason,
+                }),
+            }
+        }
+    }
+    (firings, skipped)
+}
+
+/// Evaluate the session's rules for a request. A dry run works on a copy
+/// of the blocks and leaves the session, the trash and the audit log
+/// untouched, but checks removals against the trash as the real run would.
+pub fn run_rules(
+    session: &mut EngineSession,
+    trash: &TrashStore,
+    context: RuleContext,
+    dry_run: bool,
+) -> Result<RuleRun, RuleError> {
+    let rules = std::mem::take(&mut session.rules);
+    let compiled: Result<Vec<_>, _> = rules.iter().map(Rule::compile).collect();
+    let result = compiled.map(|compiled| {
+        let tokens_before = projected_tokens(&session.blocks, 0);
+        let removal = Removal {
+            trash,
+            branch: session.forks.active(),
+            dry_run,
+        };
+        let (firings, skipped, tokens_after) = if dry_run {
+            let mut blocks = session.blocks.clone();
+            let mut log = ActionLog::new();
+            let (firings, skipped) = run_pass(&compiled, &mut blocks, &mut log, &removal, &context);
+            (firings, skipped, projected_tokens(&blocks, 0))
+        } else {
+            let (firings, skipped) = run_pass(
+                &compiled,
+                &mut session.blocks,
+                &mut session.log,
+                &removal,
+                &context,
+            );
+            session.rule_audit.extend(firings.iter().cloned());
+            (firings, skipped, projected_tokens(&session.blocks, 0))
+        };
+        if !dry_run && !firings.is_empty() {
+            info!(
+                "Rules fired {} times ({} -> {} tokens)",
+                firings.len(),
+                tokens_before,
+                tokens_after
+            );
+        }
+        RuleRun {
+            dry_run,
+            context,
+            firings,
+            skipped,
+            tokens_before,
+            tokens_after,
+        }
+    });
+    session.rules = rules;
+    result
+}
+
+/// Replace the session's rules, rejecting the set if any rule is invalid.
+#[tauri::command]
+pub fn rules_set(
+    session: State<'_, SharedSession>,
+    rules: Vec<Rule>,
+) -> Result<Vec<Rule>, RuleError> {
+    for rule in &rules {
+        rule.validate()?;
+    }
+    session.lock().rules = rules.clone();
+    Ok(rules)
+}
+
+#[tauri::command]
+pub fn rules_list(session: State<'_, SharedSession>) -> Vec<Rule> {
+    session.lock().rules.clone()
+}
+
+/// Run the rules now against the session's blocks, or preview the run.
+#[tauri::command]
+pub fn rules_run(
+    trash: State<'_, Arc<TrashStore>>,
+    session: State<'_, SharedSession>,
+    dry_run: bool,
+    incoming_tokens: Option<u32>,
+) -> Result<RuleRun, RuleError> {
+    let mut session = session.lock();
+    let budget = session.profile.budget_config();
+    let context = RuleContext::new(&session.blocks, incoming_tokens.unwrap_or(0), &budget);
+    run_rules(&mut session, &trash, context, dry_run)
+}
+
+/// The session's latest rule firings, oldest first.
+#[tauri::command]
+pub fn rules_audit(session: State<'_, SharedSession>) -> Vec<RuleFiring> {
+    session.lock().rule_audit.entries()
+}
+
+/// Keep at most `max_entries` firings in the session's audit log.
+#[tauri::command]
+pub fn rules_set_audit_limit(session: State<'_, SharedSession>, max_entries: usize) {
+    session.lock().rule_audit.set_max_entries(max_entries);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::block::{test_block, CompressionVersion};
+    use crate::engine::fork::MAIN_BRANCH;
+    use crate::engine::trash::TrashConfig;
+    use crate::engine::types::{BuiltInZone, Role};
+    use crate::paths::TestDir;
+    use serde_json::json;
+
+    fn rule(value: Value) -> Rule {
+        serde_json::from_value(value).expect("rule")
+    }
+
+    fn session() -> EngineSession {
+        let mut old = test_block("old", Role::ToolResult, "line one\n\n\n\nline two");
+        old.metadata.tool_name = Some("read_file".to_string());
+        old.compressed_versions.trimmed =
+            Some(CompressionVersion::new("line one\n\nline two".to_string()));
+        let mut error = test_block("err", Role::ToolResult, "Error: connection refused");
+        error.metadata.turn_index = 9;
+        let mut chat = test_block("chat", Role::User, "hello");
+        chat.metadata.turn_index = 10;
+        EngineSession {
+            blocks: vec![old, error, chat],
+            ..EngineSession::default()
+        }
+    }
+
+    fn trash() -> (TestDir, TrashStore) {
+        let dir = TestDir::new("rules");
+        let trash = TrashStore::open(dir.join("trash.json"), TrashConfig::default());
+        (dir, trash)
+    }
+
+    fn context() -> RuleContext {
+        RuleContext {
+            current_turn: 10,
+            token_pressure: 0.9,
+        }
+    }
+
+    #[test]
+    fn test_invalid_rules_are_reported_precisely() {
+        let bad_operator = rule(json!({
+            "id": "r1", "name": "n", "action": "condense",
+            "trigger": { "field": "age", "operator": "contains", "value": "x" },
+        }));
+        let err = bad_operator.validate().unwrap_err().to_string();
+        assert!(
+            err.contains("trigger: `contains` does not apply to number `age`"),
+            "{err}"
+        );
+
+        let no_block_trigger = rule(json!({
+            "id": "r2", "name": "n", "action": "remove",
+            "trigger": { "field": "token_pressure", "operator": "gt", "value": 0.8 },
+        }));
+        assert!(no_block_trigger.validate().is_err());
+
+        let no_level = rule(json!({
+            "id": "r3", "name": "n", "action": "compress",
+            "trigger": { "field": "role", "operator": "eq", "value": "user" },
+        }));
+        let err = no_level.validate().unwrap_err().to_string();
+        assert!(err.contains("needs config.level"), "{err}");
+    }
+
+    #[test]
+    fn test_dry_run_previews_the_real_run() {
+        let mut session = session();
+        session.rules = vec![
+            rule(json!({
+                "id": "condense-old-reads", "name": "old reads", "action": "condense",
+                "trigger": { "field": "age", "operator": "gt", "value": 5 },
+                "conditions": [
+                    { "field": "token_pressure", "operator": "gt", "value": "0.8" },
+                    { "field": "tool_name", "operator": "eq", "value": "read_file" },
+                ],
+            })),
+            rule(json!({
+                "id": "pin-errors", "name": "errors", "action": "pin",
+                "trigger": { "field": "content", "operator": "matches", "value": "^error:" },
+                "config": { "position": "bottom" },
+            })),
+            rule(json!({
+                "id": "drop-chat", "name": "chat", "action": "remove",
+                "trigger": { "field": "role", "operator": "eq", "value": "user" },
+            })),
+        ];
+        let (_dir, trash) = trash();
+
+        let preview = run_rules(&mut session, &trash, context(), true).unwrap();
+        assert_eq!(preview.firings.len(), 3);
+        assert_eq!(session.blocks.len(), 3);
+        assert!(session.log.entries().is_empty());
+        assert!(session.rule_audit.is_empty());
+
+        let run = run_rules(&mut session, &trash, context(), false).unwrap();
+        let fired = |run: &RuleRun| -> Vec<(String, Option<String>)> {
+            run.firings
+                .iter()
+                .map(|f| (f.rule_id.clone(), f.block_id.clone()))
+                .collect()
+        };
+        assert_eq!(fired(&run), fired(&preview));
+        assert_eq!(run.tokens_after, preview.tokens_after);
+        assert_eq!(
+            session.blocks[0].compression_level,
+            CompressionLevel::Trimmed
+        );
+        assert_eq!(session.blocks[1].pinned, Some(PinPosition::Bottom));
+        assert_eq!(session.blocks.len(), 2);
+        assert_eq!(trash.summary(MAIN_BRANCH).block_count, 1);
+        assert_eq!(session.rule_audit.len(), 3);
+
+        let pin_action = run.firings[1].action_id.clone().unwrap();
+        session.log.undo(&pin_action, &mut session.blocks).unwrap();
+        assert_eq!(session.blocks[1].pinned, None);
+        assert!(run.firings[2].action_id.is_some());
+        assert_eq!(session.log.undo_all(&mut session.blocks), 2);
+        let ids: Vec<&str> = session.blocks.iter().map(|b| b.id.as_str()).collect();
+        assert_eq!(ids, vec!["old", "err", "chat"]);
+    }
+
+    #[test]
+    fn test_dry_run_reports_refused_removals() {
+        let mut session = session();
+        let mut call = test_block("call", Role::ToolUse, "read src/lib.rs");
+        call.metadata.tool_use_id = Some("toolu_1".to_string());
+        session.blocks[0].metadata.tool_use_id = Some("toolu_1".to_string());
+        session.blocks.insert(0, call);
+        session.rules = vec![rule(json!({
+            "id": "drop", "name": "drop", "action": "remove",
+            "trigger": { "field": "role", "operator": "eq", "value": "tool_use" },
+        }))];
+        let (_dir, trash) = trash();
+        let reasons = |run: &RuleRun| -> Vec<String> {
+            run.skipped.iter().map(|s| s.reason.clone()).collect()
+        };
+
+        let preview = run_rules(&mut session, &trash, context(), true).unwrap();
+        let run = run_rules(&mut session, &trash, context(), false).unwrap();
+        assert!(preview.firings.is_empty());
+        assert_eq!(reasons(&preview), vec!["Removing call would break old"]);
+        assert_eq!(reasons(&run), reasons(&preview));
+        assert_eq!(session.blocks.len(), 4);
+
+        // A trash that cannot be saved fails the preview like the run.
+        let dir = TestDir::new("rules");
+        std::fs::write(dir.join("file"), "").unwrap();
+        let broken = TrashStore::open(dir.join("file/trash.json"), TrashConfig::default());
+        session.rules[0] = rule(json!({
+            "id": "drop", "name": "drop", "action": "remove",
+            "trigger": { "field": "role", "operator": "eq", "value": "user" },
+        }));
+        let preview = run_rules(&mut session, &broken, context(), true).unwrap();
+        let run = run_rules(&mut session, &broken, context(), false).unwrap();
+        assert!(preview.firings.is_empty());
+        assert_eq!(preview.skipped.len(), 1);
+        assert_eq!(reasons(&run), reasons(&preview));
+        assert_eq!(session.blocks.len(), 4);
+    }
+
+    #[test]
+    fn test_request_level_warning_fires_once() {
+        let mut session = session();
+        session.rules = vec![
+            rule(json!({
+                "id": "late", "name": "long session", "action": "warn",
+                "trigger": { "field": "turn", "operator": "gt", "value": 8 },
+                "config": { "message": "consider a checkpoint" },
+            })),
+            rule(json!({
+                "id": "off", "name": "disabled", "action": "move_zone", "enabled": false,
+                "trigger": { "field": "zone", "operator": "eq", "value": "middle" },
+                "config": { "zone": "recency" },
+            })),
+        ];
+
+        let (_dir, trash) = trash();
+        let run = run_rules(&mut session, &trash, context(), false).unwrap();
+        assert_eq!(run.firings.len(), 1);
+        assert_eq!(run.firings[0].block_id, None);
+        assert_eq!(run.firings[0].detail, "consider a checkpoint");
+        assert!(session
+            .blocks
+            .iter()
+            .all(|b| b.is_in_zone(BuiltInZone::Middle)));
+
+        // The audit keeps only the newest firings under its cap.
+        session.rule_audit.set_max_entries(2);
+        for _ in 0..3 {
+            run_rules(&mut session, &trash, context(), false).unwrap();
+        }
+        let audit = session.rule_audit.entries();
+        assert_eq!(audit.len(), 2);
+        assert!(audit.iter().all(|f| f.rule_id == "late"));
+        assert_ne!(audit[1].id, run.firings[0].id);
+    }
+}
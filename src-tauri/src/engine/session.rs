@@ -0,0 +1,59 @@
+//! Engine state for one session.
+//!
+//! Shared between the proxy, which ingests every outbound request into it
+//! and rebuilds the request from it, and the Tauri commands, so a change made through a command is what the
+//! next request sees.
+
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use super::action_log::ActionLog;
+use super::block::Block;
+use super::clustering::TopicClusters;
+use super::fork::ForkState;
+use super::manifest::ManifestConfig;
+use super::memory_tool::MemoryToolConfig;
+use super::outbound::Transcript;
+use super::profile::ProfileState;
+use super::rules::{Rule, RuleAudit};
+use super::staging::StagingArea;
+
+/// Blocks and the state derived from them.
+#[derive(Debug, Default)]
+pub struct EngineSession {
+    pub blocks: Vec<Block>,
+    pub clusters: TopicClusters,
+    pub log: ActionLog,
+    /// Rules run on each request, in order.
+    pub rules: Vec<Rule>,
+    /// Latest rule firings, oldest first.
+    pub rule_audit: RuleAudit,
+    /// Branches of the session; `blocks` and `log` belong to the active one.
+    pub forks: ForkState,
+    /// Content injected into requests by condition.
+    pub staging: StagingArea,
+    /// Project profile and the preset applied from it.
+    pub profile: ProfileState,
+    /// Manifest added to each outbound request.
+    pub manifest: ManifestConfig,
+    /// Memory tool offered to the model; off by default.
+    pub memory_tool: MemoryToolConfig,
+    /// Client messages ingested into `blocks`, and the parts they were
+    /// sent as.
+    pub transcript: Transcript,
+}
+
+/// Handle to the session shared across the app.
+#[derive(Debug, Clone, Default)]
+pub struct SharedSession(Arc<Mutex<EngineSession>>);
+
+impl SharedSession {
+    pub fn new(session: EngineSession) -> Self {
+        Self(Arc::new(Mutex::new(session)))
+    }
+
+    /// Lock the session. A panic while it was held leaves the data
+    /// consistent enough to keep serving, so poisoning is ignored.
+    pub fn lock(&self) -> MutexGuard<'_, EngineSession> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
@@ -0,0 +1,675 @@
+//! Staging area: pre-loaded content injected into zones by condition.
+//!
+//! Each staged item has a source (a file, a text snippet or a soft
+//! checkpoint), a target zone, a priority and an [`InjectionCondition`].
+//! At send time the items whose condition holds become blocks in their
+//! target zone, so their tokens count against the budget like any other
+//! block, and [`inject_into_request`] adds them to the outbound body.
+//! File-backed items are re-read when the file changes, up to
+//! [`MAX_STAGED_FILE_BYTES`].
+
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::PathBuf;
+use std::time::UNIX_EPOCH;
+
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tauri::State;
+use thiserror::Error;
+use tracing::warn;
+use uuid::Uuid;
+
+use super::block::Block;
+use super::checkpoint::hard::CheckpointStore;
+use super::checkpoint::CheckpointError;
+use super::manifest::{append_system_text, is_manifest};
+use super::session::SharedSession;
+use super::tokens::count_tokens;
+use super::types::{BuiltInZone, Zone};
+
+/// `block_type` of blocks injected from the staging area.
+pub const STAGED_BLOCK_TYPE: &str = "staged";
+
+/// Largest file a staged item loads.
+pub const MAX_STAGED_FILE_BYTES: u64 = 1024 * 1024;
+
+#[derive(Debug, Error)]
+pub enum StagingError {
+    #[error("Staged item not found: {0}")]
+    ItemNotFound(String),
+
+    #[error("Invalid staging pattern {pattern:?}: {message}")]
+    InvalidPattern { pattern: String, message: String },
+
+    #[error("Failed to read staged file {path}: {message}")]
+    FileRead { path: String, message: String },
+
+    #[error("Staged file {path} is {bytes} bytes, over the {limit} byte limit")]
+    FileTooLarge {
+        path: String,
+        bytes: u64,
+        limit: u64,
+    },
+
+    #[error(transparent)]
+    Checkpoint(#[from] CheckpointError),
+}
+
+impl serde::Serialize for StagingError {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+/// Where a staged item's content comes from.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum StagedSource {
+    File { path: PathBuf },
+    Text { content: String },
+    SoftCheckpoint { checkpoint_id: String },
+}
+
+/// When a staged item is injected.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "value", rename_all = "snake_case")]
+pub enum InjectionCondition {
+    Always,
+    /// Only on the first request of a conversation.
+    OnSessionStart,
+    /// When the latest user message contains the keyword, ignoring case.
+    Keyword(String),
+    /// When the latest user message matches the regex, ignoring case.
+    Pattern(String),
+}
+
+impl InjectionCondition {
+    pub(crate) fn validate(&self) -> Result<(), StagingError> {
+        self.compile().map(drop)
+    }
+
+    /// The regex of a `Pattern` condition.
+    fn compile(&self) -> Result<Option<ConditionPattern>, StagingError> {
+        let Self::Pattern(pattern) = self else {
+            return Ok(None);
+        };
+        RegexBuilder::new(pattern)
+            .case_insensitive(true)
+            .build()
+            .map(|re| Some(ConditionPattern(re)))
+            .map_err(|e| StagingError::InvalidPattern {
+                pattern: pattern.clone(),
+                message: e.to_string(),
+            })
+    }
+}
+
+/// Compiled regex of a [`InjectionCondition::Pattern`], equal by source.
+#[derive(Debug, Clone)]
+struct ConditionPattern(Regex);
+
+impl PartialEq for ConditionPattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+
+/// What the user supplies to stage an item.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NewStagedItem {
+    pub name: String,
+    pub source: StagedSource,
+    pub target_zone: Zone,
+    /// Higher first within a zone.
+    #[serde(default)]
+    pub priority: i32,
+    pub condition: InjectionCondition,
+}
+
+/// A staged item and its last loaded content.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StagedItem {
+    pub id: String,
+    pub name: String,
+    pub source: StagedSource,
+    pub target_zone: Zone,
+    pub priority: i32,
+    pub condition: InjectionCondition,
+    pub enabled: bool,
+    #[serde(default)]
+    pub content: String,
+    #[serde(default)]
+    pub tokens: u32,
+    /// Size and modification time of a file source when it was read.
+    #[serde(skip)]
+    file_stamp: Option<(u64, u128)>,
+    /// Compiled once when staged; `None` for other conditions.
+    #[serde(skip)]
+    pattern: Option<ConditionPattern>,
+}
+
+impl StagedItem {
+    pub fn block_id(&self) -> String {
+        format!("staged-{}", self.id)
+    }
+
+    /// Whether the item applies to a request whose latest user message is
+    /// `latest_user`.
+    pub fn applies(&mut self, latest_user: &str, session_start: bool) -> bool {
+        match &self.condition {
+            InjectionCondition::Always => true,
+            InjectionCondition::OnSessionStart => session_start,
+            InjectionCondition::Keyword(keyword) => {
+                latest_user.to_lowercase().contains(&keyword.to_lowercase())
+            }
+            InjectionCondition::Pattern(_) => {
+                if self.pattern.is_none() {
+                    self.pattern = self.condition.compile().ok().flatten();
+                }
+                self.pattern
+                    .as_ref()
+                    .is_some_and(|p| p.0.is_match(latest_user))
+            }
+        }
+    }
+
+    fn set_content(&mut self, content: String) {
+        self.tokens = count_tokens(&content);
+        self.content = content;
+    }
+
+    /// Load the content, re-reading a file only if it changed since the
+    /// last read. Soft checkpoints are loaded once. Files must be regular
+    /// files of at most [`MAX_STAGED_FILE_BYTES`].
+    pub fn refresh(&mut self, store: &CheckpointStore) -> Result<(), StagingError> {
+        match &self.source {
+            StagedSource::Text { content } => {
+                if self.content != *content {
+                    let content = content.clone();
+                    self.set_content(content);
+                }
+            }
+            StagedSource::SoftCheckpoint { checkpoint_id } => {
+                if self.content.is_empty() {
+                    let markdown = store.load_soft(checkpoint_id)?.to_markdown();
+                    self.set_content(markdown);
+                }
+            }
+            StagedSource::File { path } => {
+                let read_error = |e: std::io::Error| StagingError::FileRead {
+                    path: path.display().to_string(),
+                    message: e.to_string(),
+                };
+                let meta = fs::metadata(path).map_err(read_error)?;
+                if !meta.is_file() {
+                    return Err(StagingError::FileRead {
+                        path: path.display().to_string(),
+                        message: "not a regular file".to_string(),
+                    });
+                }
+                let too_large = |bytes| StagingError::FileTooLarge {
+                    path: path.display().to_string(),
+                    bytes,
+                    limit: MAX_STAGED_FILE_BYTES,
+                };
+                if meta.len() > MAX_STAGED_FILE_BYTES {
+                    return Err(too_large(meta.len()));
+                }
+                let modified = meta
+                    .modified()
+                    .ok()
+                    .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                    .map_or(0, |d| d.as_nanos());
+                let stamp = Some((meta.len(), modified));
+                if self.file_stamp != stamp {
+                    // The file may have grown since the metadata was read.
+                    let mut content = String::new();
+                    File::open(path)
+                        .and_then(|f| {
+                            f.take(MAX_STAGED_FILE_BYTES + 1)
+                                .read_to_string(&mut content)
+                        })
+                        .map_err(read_error)?;
+                    if content.len() as u64 > MAX_STAGED_FILE_BYTES {
+                        return Err(too_large(content.len() as u64));
+                    }
+                    self.set_content(content);
+                    self.file_stamp = stamp;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Every staged item of a session.
+#[derive(Debug, Clone, Default)]
+pub struct StagingArea {
+    items: Vec<StagedItem>,
+}
+
+impl StagingArea {
+    pub fn items(&self) -> &[StagedItem] {
+        &self.items
+    }
+
+    /// Stage `item`, loading its content now so a bad path or checkpoint
+    /// id is reported immediately.
+    pub fn add(
+        &mut self,
+        item: NewStagedItem,
+        store: &CheckpointStore,
+    ) -> Result<StagedItem, StagingError> {
+        let pattern = item.condition.compile()?;
+        let mut staged = StagedItem {
+            id: Uuid::new_v4().to_string(),
+            name: item.name,
+            source: item.source,
+            target_zone: item.target_zone,
+            priority: item.priority,
+            condition: item.condition,
+            enabled: true,
+            content: String::new(),
+            tokens: 0,
+            file_stamp: None,
+            pattern,
+        };
+        staged.refresh(store)?;
+        self.items.push(staged.clone());
+        Ok(staged)
+    }
+
+    pub fn remove(&mut self, id: &str) -> Result<StagedItem, StagingError> {
+        let index = self
+            .items
+            .iter()
+            .position(|i| i.id == id)
+            .ok_or_else(|| StagingError::ItemNotFound(id.to_string()))?;
+        Ok(self.items.remove(index))
+    }
+
+    pub fn set_enabled(&mut self, id: &str, enabled: bool) -> Result<StagedItem, StagingError> {
+        let item = self
+            .items
+            .iter_mut()
+            .find(|i| i.id == id)
+            .ok_or_else(|| StagingError::ItemNotFound(id.to_string()))?;
+        item.enabled = enabled;
+        Ok(item.clone())
+    }
+
+    /// Refresh enabled items and return those whose condition holds,
+    /// highest priority first. An item that fails to load keeps its last
+    /// content.
+    pub fn select(
+        &mut self,
+        store: &CheckpointStore,
+        latest_user: &str,
+        session_start: bool,
+    ) -> Vec<StagedItem> {
+        let mut selected: Vec<StagedItem> = self
+            .items
+            .iter_mut()
+            .filter_map(|item| {
+                if !item.enabled || !item.applies(latest_user, session_start) {
+                    return None;
+                }
+                if let Err(e) = item.refresh(store) {
+                    warn!("Staged item {} not refreshed: {e}", item.name);
+                }
+                (!item.content.is_empty()).then(|| item.clone())
+            })
+            .collect();
+        selected.sort_by_key(|i| std::cmp::Reverse(i.priority));
+        selected
+    }
+}
+
+pub fn is_staged(block: &Block) -> bool {
+    block.block_type.as_deref() == Some(STAGED_BLOCK_TYPE)
+}
+
+/// Replace the staged blocks in `blocks` with `selected`. Primacy items go
+/// after the manifest, other zones at the end, each in priority order.
+pub fn upsert_staged_blocks(blocks: &mut Vec<Block>, selected: &[StagedItem], turn: u32) {
+    blocks.retain(|b| !is_staged(b));
+    let mut at = blocks.iter().take_while(|b| is_manifest(b)).count();
+    for item in selected {
+        let block = Block::generated(
+            item.block_id(),
+            STAGED_BLOCK_TYPE,
+            item.content.clone(),
+            item.target_zone.clone(),
+            turn,
+        );
+        if item.target_zone == Zone::BuiltIn(BuiltInZone::Primacy) {
+            blocks.insert(at, block);
+            at += 1;
+        } else {
+            blocks.push(block);
+        }
+    }
+}
+
+/// Text of the last user message that has any, in either provider format.
+pub fn latest_user_text(body: &Value) -> String {
+    let Some(messages) = body["messages"].as_array() else {
+        return String::new();
+    };
+    messages
+        .iter()
+        .rev()
+        .filter(|m| m["role"] == "user")
+        .map(|m| match &m["content"] {
+            Value::String(text) => text.clone(),
+            Value::Array(parts) => parts
+                .iter()
+                .filter_map(|p| p["text"].as_str())
+                .collect::<Vec<_>>()
+                .join("\n"),
+            _ => String::new(),
+        })
+        .find(|text| !text.is_empty())
+        .unwrap_or_default()
+}
+
+/// Whether the request opens a conversation: one user message so far.
+pub fn is_session_start(body: &Value) -> bool {
+    body["messages"]
+        .as_array()
+        .is_some_and(|m| m.iter().filter(|m| m["role"] == "user").count() == 1)
+}
+
+/// Add `selected` items to a request body: Primacy items to the system
+/// prompt, the rest ahead of the latest user message's content. Returns
+/// `false` if the body is not a JSON object.
+pub fn inject_into_request(body: &mut Value, provider: &str, selected: &[StagedItem]) -> bool {
+    if !body.is_object() {
+        return false;
+    }
+    let (primacy, rest): (Vec<&StagedItem>, Vec<&StagedItem>) = selected
+        .iter()
+        .partition(|i| i.target_zone == Zone::BuiltIn(BuiltInZone::Primacy));
+    for item in primacy {
+        append_system_text(body, provider, &item.content);
+    }
+    if rest.is_empty() {
+        return true;
+    }
+    let text = rest
+        .iter()
+        .map(|i| i.content.as_str())
+        .collect::<Vec<_>>()
+        .join("\n\n");
+    let Some(message) = body["messages"]
+        .as_array_mut()
+        .and_then(|m| m.iter_mut().rev().find(|m| m["role"] == "user"))
+    else {
+        return true;
+    };
+    let content = &mut message["content"];
+    match content {
+        Value::String(existing) => *existing = format!("{text}\n\n{existing}"),
+        Value::Array(parts) => parts.insert(0, json!({ "type": "text", "text": text })),
+        _ => *content = Value::String(text),
+    }
+    true
+}
+
+#[tauri::command]
+pub fn staging_add(
+    store: State<'_, CheckpointStore>,
+    session: State<'_, SharedSession>,
+    item: NewStagedItem,
+) -> Result<StagedItem, StagingError> {
+    session.lock().staging.add(item, &store)
+}
+
+#[tauri::command]
+pub fn staging_remove(
+    session: State<'_, SharedSession>,
+    item_id: String,
+) -> Result<StagedItem, StagingError> {
+    session.lock().staging.remove(&item_id)
+}
+
+#[tauri::command]
+pub fn staging_set_enabled(
+    session: State<'_, SharedSession>,
+    item_id: String,
+    enabled: bool,
+) -> Result<StagedItem, StagingError> {
+    session.lock().staging.set_enabled(&item_id, enabled)
+}
+
+#[tauri::command]
+pub fn staging_list(session: State<'_, SharedSession>) -> Vec<StagedItem> {
+    session.lock().staging.items().to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::block::test_block;
+    use crate::engine::manifest::{upsert_manifest_block, ContextManifest};
+    use crate::engine::types::Role;
+    use crate::paths::TestDir;
+
+    fn store() -> (TestDir, CheckpointStore) {
+        let dir = TestDir::new("staging");
+        let store = CheckpointStore::new(dir.path().to_path_buf());
+        (dir, store)
+    }
+
+    fn text(
+        name: &str,
+        zone: BuiltInZone,
+        priority: i32,
+        condition: InjectionCondition,
+    ) -> NewStagedItem {
+        NewStagedItem {
+            name: name.to_string(),
+            source: StagedSource::Text {
+                content: format!("{name} content"),
+            },
+            target_zone: Zone::BuiltIn(zone),
+            priority,
+            condition,
+        }
+    }
+
+    #[test]
+    fn test_select_by_condition_and_priority() {
+        let (_dir, store) = store();
+        let mut staging = StagingArea::default();
+        staging
+            .add(
+                text("arch", BuiltInZone::Primacy, 1, InjectionCondition::Always),
+                &store,
+            )
+            .expect("add");
+        staging
+            .add(
+                text(
+                    "style",
+                    BuiltInZone::Primacy,
+                    5,
+                    InjectionCondition::OnSessionStart,
+                ),
+                &store,
+            )
+            .expect("add");
+        let db = staging
+            .add(
+                text(
+                    "db",
+                    BuiltInZone::Recency,
+                    0,
+                    InjectionCondition::Pattern(r"migrat(e|ion)".into()),
+                ),
+                &store,
+            )
+            .expect("add");
+        staging
+            .add(
+                text(
+                    "ws",
+                    BuiltInZone::Recency,
+                    0,
+                    InjectionCondition::Keyword("WebSocket".into()),
+                ),
+                &store,
+            )
+            .expect("add");
+        assert!(matches!(
+            staging.add(
+                text(
+                    "bad",
+                    BuiltInZone::Primacy,
+                    0,
+                    InjectionCondition::Pattern("(".into())
+                ),
+                &store
+            ),
+            Err(StagingError::InvalidPattern { .. })
+        ));
+
+        let names = |items: Vec<StagedItem>| items.into_iter().map(|i| i.name).collect::<Vec<_>>();
+        assert_eq!(
+            names(staging.select(&store, "hello", true)),
+            vec!["style", "arch"]
+        );
+        assert_eq!(
+            names(staging.select(&store, "Run the MIGRATION and fix the websocket", false)),
+            vec!["arch", "db", "ws"]
+        );
+
+        staging.set_enabled(&db.id, false).expect("disable");
+        assert_eq!(
+            names(staging.select(&store, "migrate", false)),
+            vec!["arch"]
+        );
+    }
+
+    #[test]
+    fn test_file_items_reload_when_changed() {
+        let (dir, store) = store();
+        let path = dir.join("staged.md");
+        fs::write(&path, "v1").expect("write");
+        let mut staging = StagingArea::default();
+        let item = staging
+            .add(
+                NewStagedItem {
+                    name: "doc".to_string(),
+                    source: StagedSource::File { path: path.clone() },
+                    target_zone: Zone::BuiltIn(BuiltInZone::Primacy),
+                    priority: 0,
+                    condition: InjectionCondition::Always,
+                },
+                &store,
+            )
+            .expect("add");
+        assert_eq!(item.content, "v1");
+
+        fs::write(&path, "version two").expect("rewrite");
+        let selected = staging.select(&store, "", false);
+        assert_eq!(selected[0].content, "version two");
+        assert_eq!(selected[0].tokens, count_tokens("version two"));
+
+        fs::remove_file(&path).expect("remove");
+        assert_eq!(staging.select(&store, "", false)[0].content, "version two");
+        assert!(matches!(
+            staging.add(
+                NewStagedItem {
+                    name: "gone".to_string(),
+                    source: StagedSource::File { path },
+                    target_zone: Zone::BuiltIn(BuiltInZone::Primacy),
+                    priority: 0,
+                    condition: InjectionCondition::Always,
+                },
+                &store,
+            ),
+            Err(StagingError::FileRead { .. })
+        ));
+
+        let file = |path: PathBuf| NewStagedItem {
+            name: "bad".to_string(),
+            source: StagedSource::File { path },
+            target_zone: Zone::BuiltIn(BuiltInZone::Primacy),
+            priority: 0,
+            condition: InjectionCondition::Always,
+        };
+        assert!(matches!(
+            staging.add(file(dir.path().to_path_buf()), &store),
+            Err(StagingError::FileRead { .. })
+        ));
+        let large = dir.join("large.md");
+        fs::write(&large, vec![b'x'; MAX_STAGED_FILE_BYTES as usize + 1]).expect("write");
+        assert!(matches!(
+            staging.add(file(large), &store),
+            Err(StagingError::FileTooLarge { .. })
+        ));
+    }
+
+    #[test]
+    fn test_selected_items_become_blocks_and_request_content() {
+        let (_dir, store) = store();
+        let mut staging = StagingArea::default();
+        staging
+            .add(
+                text("arch", BuiltInZone::Primacy, 0, InjectionCondition::Always),
+                &store,
+            )
+            .expect("add");
+        staging
+            .add(
+                text("notes", BuiltInZone::Recency, 0, InjectionCondition::Always),
+                &store,
+            )
+            .expect("add");
+        let selected = staging.select(&store, "", false);
+
+        let mut blocks = vec![test_block("u", Role::User, "hi")];
+        let manifest = ContextManifest {
+            text: "MANIFEST".to_string(),
+            tokens: 1,
+            omitted_lines: 0,
+        };
+        upsert_manifest_block(&mut blocks, Some(&manifest), 0);
+        upsert_staged_blocks(&mut blocks, &selected, 0);
+        upsert_staged_blocks(&mut blocks, &selected, 0);
+        let ids: Vec<&str> = blocks.iter().map(|b| b.id.as_str()).collect();
+        assert_eq!(
+            ids,
+            vec![
+                "context-manifest",
+                selected[0].block_id().as_str(),
+                "u",
+                selected[1].block_id().as_str()
+            ]
+        );
+        assert_eq!(blocks[3].zone, Zone::BuiltIn(BuiltInZone::Recency));
+
+        let mut body = json!({
+            "system": "Be brief.",
+            "messages": [
+                { "role": "user", "content": "first" },
+                { "role": "assistant", "content": "ok" },
+                { "role": "user", "content": [{ "type": "text", "text": "second" }] }
+            ]
+        });
+        assert_eq!(latest_user_text(&body), "second");
+        assert!(!is_session_start(&body));
+        assert!(inject_into_request(&mut body, "anthropic", &selected));
+        assert_eq!(body["system"], "Be brief.\n\narch content");
+        assert_eq!(body["messages"][2]["content"][0]["text"], "notes content");
+        assert_eq!(body["messages"][0]["content"], "first");
+    }
+}
@@ -0,0 +1,83 @@
+//! Block staleness scoring.
+//!
+//! ```text
+//! staleness = (turns_since_created × token_cost) / relevance_boost
+//! ```
+//!
+//! Old, expensive blocks that nothing refers back to score highest and are
+//! the first candidates for compression under budget pressure.
+
+use super::block::Block;
+
+/// Boost added per recorded reference.
+const REFERENCE_WEIGHT: f64 = 0.5;
+
+/// Extra boost for a reference in the current turn; decays with each turn
+/// since the last reference.
+const RECENT_REFERENCE_WEIGHT: f64 = 4.0;
+
+/// Staleness of `block` at `current_turn`; 0.0 for blocks from this turn.
+///
+/// Token cost is the block's current size, so compressing a block lowers
+/// its score and moves pressure on to the next offender.
+pub fn calculate_staleness(block: &Block, current_turn: u32) -> f64 {
+    let age = current_turn.saturating_sub(block.metadata.turn_index);
+    (age as f64 * block.tokens as f64) / relevance_boost(block, current_turn)
+}
+
+/// 1.0 for a never-referenced block, growing with each reference and with
+/// how recently the last one happened.
+fn relevance_boost(block: &Block, current_turn: u32) -> f64 {
+    if block.reference_count == 0 {
+        return 1.0;
+    }
+    let since_reference = current_turn.saturating_sub(block.last_referenced_turn);
+    1.0 + REFERENCE_WEIGHT * block.reference_count as f64
+        + RECENT_REFERENCE_WEIGHT / (1.0 + since_reference as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::block::test_block;
+    use crate::engine::types::Role;
+
+    fn block_at(turn: u32, tokens: u32) -> Block {
+        let mut block = test_block("b", Role::ToolResult, "output");
+        block.metadata.turn_index = turn;
+        block.tokens = tokens;
+        block
+    }
+
+    #[test]
+    fn test_staleness_grows_with_age_and_tokens() {
+        let recent = block_at(9, 500);
+        let old = block_at(2, 500);
+        let old_large = block_at(2, 5000);
+
+        assert_eq!(calculate_staleness(&recent, 9), 0.0);
+        assert!(calculate_staleness(&old, 10) > calculate_staleness(&recent, 10));
+        assert!(calculate_staleness(&old_large, 10) > calculate_staleness(&old, 10));
+    }
+
+    #[test]
+    fn test_staleness_drops_with_references() {
+        let unused = block_at(2, 1000);
+        let mut referenced = block_at(2, 1000);
+        referenced.reference_count = 2;
+        referenced.last_referenced_turn = 4;
+        let mut just_referenced = referenced.clone();
+        just_referenced.last_referenced_turn = 10;
+
+        let unused_score = calculate_staleness(&unused, 10);
+        let referenced_score = calculate_staleness(&referenced, 10);
+        assert!(referenced_score < unused_score);
+        assert!(calculate_staleness(&just_referenced, 10) < referenced_score);
+    }
+
+    #[test]
+    fn test_staleness_tolerates_future_turn_index() {
+        let block = block_at(12, 1000);
+        assert_eq!(calculate_staleness(&block, 10), 0.0);
+    }
+}
@@ -0,0 +1,51 @@
+//! Token counting for block content.
+//!
+//! Uses the `cl100k_base` BPE, which is close enough to every supported
+//! provider's tokenizer for budgeting and compression savings.
+
+use std::sync::OnceLock;
+
+use tiktoken_rs::CoreBPE;
+use tracing::warn;
+
+fn bpe() -> Option<&'static CoreBPE> {
+    static BPE: OnceLock<Option<CoreBPE>> = OnceLock::new();
+    BPE.get_or_init(|| match tiktoken_rs::cl100k_base() {
+        Ok(bpe) => Some(bpe),
+        Err(e) => {
+            warn!("Failed to load cl100k_base tokenizer, estimating tokens: {e}");
+            None
+        }
+    })
+    .as_ref()
+}
+
+/// Number of tokens in `text`.
+///
+/// Falls back to a ~4 characters per token estimate if the tokenizer could
+/// not be loaded.
+pub fn count_tokens(text: &str) -> u32 {
+    let count = match bpe() {
+        Some(bpe) => bpe.encode_ordinary(text).len(),
+        None => text.chars().count().div_ceil(4),
+    };
+    u32::try_from(count).unwrap_or(u32::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_count_tokens_empty_is_zero() {
+        assert_eq!(count_tokens(""), 0);
+    }
+
+    #[test]
+    fn test_count_tokens_grows_with_text() {
+        let short = count_tokens("hello world");
+        let long = count_tokens(&"hello world ".repeat(50));
+        assert!(short > 0);
+        assert!(long > short * 10);
+    }
+}
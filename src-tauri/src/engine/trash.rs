@@ -0,0 +1,673 @@
+//! Trash: soft-deleted blocks, kept restorable.
+//!
+//! Removing a block moves it here with its [`Ghost`], along with whatever
+//! the [`DependencyGraph`] says must go with it. It can be restored to its
+//! original position until it is purged or its retention period runs out.
+//! Requests are rebuilt from the session's blocks, so a removed block and
+//! its dependents stop reaching the model even though the client keeps
+//! sending them, and come back with a restore.
+//! Entries are kept per fork branch, since branches share block ids. The
+//! trash is written to disk after every change.
+
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use serde::{Deserialize, Serialize};
+use tauri::State;
+use thiserror::Error;
+use tracing::{info, warn};
+
+use super::block::Block;
+use super::dependency::{DependencyError, DependencyGraph, RemovalPolicy};
+use super::fork::MAIN_BRANCH;
+use super::ghost::{generate_ghost, Ghost};
+use super::session::SharedSession;
+use crate::events::timeline::now_ms;
+use crate::paths;
+
+/// Default retention: one week.
+const DEFAULT_RETENTION_MS: u64 = 7 * 24 * 60 * 60 * 1000;
+
+#[derive(Debug, Error)]
+pub enum TrashError {
+    #[error("Block not found: {0}")]
+    BlockNotFound(String),
+
+    #[error("Block not in trash: {0}")]
+    NotInTrash(String),
+
+    #[error("Block {0} is already in the context")]
+    AlreadyPresent(String),
+
+    #[error("Failed to save trash: {0}")]
+    StorageFailed(String),
+
+    #[error(transparent)]
+    Dependency(#[from] DependencyError),
+}
+
+impl serde::Serialize for TrashError {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+/// Trash settings.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrashConfig {
+    /// How long removed blocks are kept; `None` keeps them until purged.
+    pub retention_ms: Option<u64>,
+    /// Whether the UI shows ghosts in the removed blocks' positions.
+    pub show_ghosts: bool,
+}
+
+impl Default for TrashConfig {
+    fn default() -> Self {
+        Self {
+            retention_ms: Some(DEFAULT_RETENTION_MS),
+            show_ghosts: true,
+        }
+    }
+}
+
+/// A removed block and its placeholder.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrashEntry {
+    /// Fork branch the block was removed from.
+    #[serde(default = "main_branch")]
+    pub branch: String,
+    pub ghost: Ghost,
+    pub block: Block,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+fn main_branch() -> String {
+    MAIN_BRANCH.to_string()
+}
+
+/// What the trash holds, e.g. "42 blocks, 12,400 tokens recoverable".
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrashSummary {
+    pub block_count: usize,
+    pub tokens: u32,
+    pub show_ghosts: bool,
+    /// Newest first.
+    pub ghosts: Vec<Ghost>,
+}
+
+/// Persisted trash.
+#[derive(Debug)]
+pub struct TrashStore {
+    path: PathBuf,
+    config: TrashConfig,
+    entries: Mutex<Vec<TrashEntry>>,
+}
+
+impl Default for TrashStore {
+    fn default() -> Self {
+        Self::open(paths::trash_path(), TrashConfig::default())
+    }
+}
+
+impl TrashStore {
+    /// Load the trash at `path`. A missing file is an empty trash; an
+    /// unreadable one is moved aside rather than overwritten.
+    pub fn open(path: PathBuf, config: TrashConfig) -> Self {
+        let entries = match fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_else(|e| {
+                let aside = path.with_extension("json.corrupt");
+                warn!(
+                    "Trash file is corrupt, moving it to {}: {e}",
+                    aside.display()
+                );
+                if let Err(e) = fs::rename(&path, &aside) {
+                    warn!("Failed to move corrupt trash file: {e}");
+                }
+                Vec::new()
+            }),
+            Err(e) if e.kind() == ErrorKind::NotFound => Vec::new(),
+            Err(e) => {
+                warn!("Failed to read trash file: {e}");
+                Vec::new()
+            }
+        };
+        Self {
+            path,
+            config,
+            entries: Mutex::new(entries),
+        }
+    }
+
+    pub fn config(&self) -> &TrashConfig {
+        &self.config
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Vec<TrashEntry>> {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn save(&self, entries: &[TrashEntry]) -> Result<(), TrashError> {
+        let storage = |e: std::io::Error| TrashError::StorageFailed(e.to_string());
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).map_err(storage)?;
+        }
+        let bytes =
+            serde_json::to_vec(entries).map_err(|e| TrashError::StorageFailed(e.to_string()))?;
+        let tmp = self.path.with_extension("json.tmp");
+        fs::write(&tmp, bytes).map_err(storage)?;
+        fs::rename(&tmp, &self.path).map_err(storage)
+    }
+
+    /// Drop entries past the retention period; returns how many.
+    fn expire(&self, entries: &mut Vec<TrashEntry>, now: u64) -> usize {
+        let Some(retention) = self.config.retention_ms else {
+            return 0;
+        };
+        let before = entries.len();
+        entries.retain(|e| now.saturating_sub(e.ghost.removed_at_ms) < retention);
+        before - entries.len()
+    }
+
+    /// Move `block_id` from `blocks` on `branch` to the trash, with the
+    /// blocks it cannot be sent without under `policy`. Returns an entry per
+    /// removed block, in context order. `blocks` is unchanged if the trash
+    /// cannot be saved.
+    pub fn remove(
+        &self,
+        blocks: &mut Vec<Block>,
+        branch: &str,
+        block_id: &str,
+        reason: Option<String>,
+        policy: RemovalPolicy,
+    ) -> Result<Vec<TrashEntry>, TrashError> {
+        let now = now_ms();
+        let (kept, removed) = detach(blocks, branch, block_id, reason, policy, now)?;
+
+        let mut entries = self.lock();
+        let mut updated = entries.clone();
+        self.expire(&mut updated, now);
+        updated.retain(|e| {
+            e.branch != branch || !removed.iter().any(|r| r.ghost.block_id == e.ghost.block_id)
+        });
+        updated.extend(removed.iter().cloned());
+        self.save(&updated)?;
+        *entries = updated;
+        *blocks = kept;
+        info!(
+            "Moved block {block_id} to trash ({} with dependents)",
+            removed.len()
+        );
+        Ok(removed)
+    }
+
+    /// What [`Self::remove`] would do, applied to `blocks` alone. Fails as
+    /// the removal would, including when the trash cannot be written.
+    pub fn preview_remove(
+        &self,
+        blocks: &mut Vec<Block>,
+        branch: &str,
+        block_id: &str,
+        policy: RemovalPolicy,
+    ) -> Result<Vec<TrashEntry>, TrashError> {
+        let (kept, removed) = detach(blocks, branch, block_id, None, policy, now_ms())?;
+        self.check_writable()?;
+        *blocks = kept;
+        Ok(removed)
+    }
+
+    /// Fail as [`Self::save`] would if the trash file cannot be written.
+    fn check_writable(&self) -> Result<(), TrashError> {
+        let storage = |e: std::io::Error| TrashError::StorageFailed(e.to_string());
+        // Held so no save replaces the probe file meanwhile.
+        let _entries = self.lock();
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).map_err(storage)?;
+        }
+        let tmp = self.path.with_extension("json.tmp");
+        fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&tmp)
+            .map_err(storage)?;
+        fs::remove_file(&tmp).map_err(storage)
+    }
+
+    /// Put `block_ids` removed from `branch` back where they were: after
+    /// the block that preceded them, else at their old index. Restores
+    /// nothing unless all can be.
+    pub fn restore(
+        &self,
+        blocks: &mut Vec<Block>,
+        branch: &str,
+        block_ids: &[String],
+    ) -> Result<Vec<Ghost>, TrashError> {
+        let mut entries = self.lock();
+        for id in block_ids {
+            if !entries
+                .iter()
+                .any(|e| e.branch == branch && &e.ghost.block_id == id)
+            {
+                return Err(TrashError::NotInTrash(id.clone()));
+            }
+            if blocks.iter().any(|b| &b.id == id) {
+                return Err(TrashError::AlreadyPresent(id.clone()));
+            }
+        }
+        let (mut restored, kept): (Vec<TrashEntry>, Vec<TrashEntry>) = entries
+            .iter()
+            .cloned()
+            .partition(|e| e.branch == branch && block_ids.contains(&e.ghost.block_id));
+        self.save(&kept)?;
+        *entries = kept;
+
+        // Entries are in removal order. Last removed first, so blocks
+        // removed in a run line up again.
+        restored.reverse();
+        let mut ghosts = Vec::with_capacity(restored.len());
+        for entry in restored {
+            let at = entry
+                .ghost
+                .after_block_id
+                .as_ref()
+                .and_then(|prev| blocks.iter().position(|b| &b.id == prev))
+                .map_or(entry.ghost.index.min(blocks.len()), |i| i + 1);
+            blocks.insert(at, entry.block);
+            ghosts.push(entry.ghost);
+        }
+        info!("Restored {} blocks from trash", ghosts.len());
+        Ok(ghosts)
+    }
+
+    /// Permanently delete `block_ids` removed from `branch`, or all of the
+    /// branch's entries when `None`. Returns how many entries were deleted.
+    pub fn purge(&self, branch: &str, block_ids: Option<&[String]>) -> Result<usize, TrashError> {
+        let mut entries = self.lock();
+        let mut kept = entries.clone();
+        kept.retain(|e| {
+            e.branch != branch || block_ids.is_some_and(|ids| !ids.contains(&e.ghost.block_id))
+        });
+        let purged = entries.len() - kept.len();
+        self.save(&kept)?;
+        *entries = kept;
+        Ok(purged)
+    }
+
+    /// The block removed from `branch` with its ghost.
+    pub fn entry(&self, branch: &str, block_id: &str) -> Option<TrashEntry> {
+        self.lock()
+            .iter()
+            .find(|e| e.branch == branch && e.ghost.block_id == block_id)
+            .cloned()
+    }
+
+    /// What `branch` has in the trash, after dropping expired entries.
+    pub fn summary(&self, branch: &str) -> TrashSummary {
+        let mut entries = self.lock();
+        let mut kept = entries.clone();
+        if self.expire(&mut kept, now_ms()) > 0 {
+            match self.save(&kept) {
+                Ok(()) => *entries = kept,
+                Err(e) => warn!("Failed to save expired trash: {e}"),
+            }
+        }
+        let mut ghosts: Vec<Ghost> = entries
+            .iter()
+            .filter(|e| e.branch == branch)
+            .map(|e| e.ghost.clone())
+            .collect();
+        ghosts.sort_by_key(|g| std::cmp::Reverse(g.removed_at_ms));
+        TrashSummary {
+            block_count: ghosts.len(),
+            tokens: ghosts.iter().map(|g| g.tokens).sum(),
+            show_ghosts: self.config.show_ghosts,
+            ghosts,
+        }
+    }
+}
+
+/// Plan the removal of `block_id` under `policy` and take the planned
+/// blocks out of a copy of `blocks`, one at a time in context order so each
+/// ghost points at a block that is still there when restoring in reverse.
+fn detach(
+    blocks: &[Block],
+    branch: &str,
+    block_id: &str,
+    reason: Option<String>,
+    policy: RemovalPolicy,
+    now: u64,
+) -> Result<(Vec<Block>, Vec<TrashEntry>), TrashError> {
+    if !blocks.iter().any(|b| b.id == block_id) {
+        return Err(TrashError::BlockNotFound(block_id.to_string()));
+    }
+    let ids = DependencyGraph::build(blocks).plan_removal(&[block_id], policy)?;
+    let mut kept = blocks.to_vec();
+    let mut removed = Vec::with_capacity(ids.len());
+    while let Some(index) = kept.iter().position(|b| ids.contains(&b.id)) {
+        removed.push(TrashEntry {
+            branch: branch.to_string(),
+            ghost: generate_ghost(&kept, index, now),
+            block: kept.remove(index),
+            reason: reason.clone(),
+        });
+    }
+    Ok((kept, removed))
+}
+
+/// Soft-delete a block from the session's active branch; later requests
+/// leave it out. A block others cannot be sent without is refused unless
+/// `cascade` takes them too.
+#[tauri::command]
+pub fn trash_remove(
+    trash: State<'_, Arc<TrashStore>>,
+    session: State<'_, SharedSession>,
+    block_id: String,
+    reason: Option<String>,
+    cascade: Option<bool>,
+) -> Result<Vec<Ghost>, TrashError> {
+    let policy = match cascade {
+        Some(true) => RemovalPolicy::Cascade,
+        _ => RemovalPolicy::Refuse,
+    };
+    let mut session = session.lock();
+    let session = &mut *session;
+    let branch = session.forks.active();
+    let removed = trash.remove(&mut session.blocks, branch, &block_id, reason, policy)?;
+    Ok(removed.into_iter().map(|e| e.ghost).collect())
+}
+
+/// Restore blocks to their original positions.
+#[tauri::command]
+pub fn trash_restore(
+    trash: State<'_, Arc<TrashStore>>,
+    session: State<'_, SharedSession>,
+    block_ids: Vec<String>,
+) -> Result<Vec<Ghost>, TrashError> {
+    let mut session = session.lock();
+    let session = &mut *session;
+    trash.restore(&mut session.blocks, session.forks.active(), &block_ids)
+}
+
+/// Permanently delete blocks from the active branch's trash, or empty it.
+#[tauri::command]
+pub fn trash_purge(
+    trash: State<'_, Arc<TrashStore>>,
+    session: State<'_, SharedSession>,
+    block_ids: Option<Vec<String>>,
+) -> Result<usize, TrashError> {
+    let branch = session.lock().forks.active().to_string();
+    trash.purge(&branch, block_ids.as_deref())
+}
+
+#[tauri::command]
+pub fn trash_list(
+    trash: State<'_, Arc<TrashStore>>,
+    session: State<'_, SharedSession>,
+) -> TrashSummary {
+    let branch = session.lock().forks.active().to_string();
+    trash.summary(&branch)
+}
+
+#[tauri::command]
+pub fn trash_entry(
+    trash: State<'_, Arc<TrashStore>>,
+    session: State<'_, SharedSession>,
+    block_id: String,
+) -> Result<TrashEntry, TrashError> {
+    let branch = session.lock().forks.active().to_string();
+    trash
+        .entry(&branch, &block_id)
+        .ok_or(TrashError::NotInTrash(block_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::engine::block::test_block;
+    use crate::engine::session::EngineSession;
+    use crate::engine::types::Role;
+    use crate::paths::TestDir;
+
+    fn blocks() -> Vec<Block> {
+        ["a", "b", "c", "d"]
+            .iter()
+            .map(|id| test_block(id, Role::User, &format!("block {id}")))
+            .collect()
+    }
+
+    fn ids(blocks: &[Block]) -> Vec<&str> {
+        blocks.iter().map(|b| b.id.as_str()).collect()
+    }
+
+    #[test]
+    fn test_remove_and_restore_round_trip_through_disk() {
+        let dir = TestDir::new("trash");
+        let path = dir.join("trash.json");
+        let trash = TrashStore::open(path.clone(), TrashConfig::default());
+        let mut blocks = blocks();
+        let removed_tokens: u32 = blocks[1..3].iter().map(|b| b.tokens).sum();
+        trash
+            .remove(&mut blocks, MAIN_BRANCH, "b", None, RemovalPolicy::Refuse)
+            .expect("remove b");
+        trash
+            .remove(
+                &mut blocks,
+                MAIN_BRANCH,
+                "c",
+                Some("noise".into()),
+                RemovalPolicy::Refuse,
+            )
+            .expect("remove c");
+        assert_eq!(ids(&blocks), vec!["a", "d"]);
+        assert!(matches!(
+            trash.remove(&mut blocks, MAIN_BRANCH, "b", None, RemovalPolicy::Refuse),
+            Err(TrashError::BlockNotFound(_))
+        ));
+
+        // A fresh store sees what was saved.
+        let reopened = TrashStore::open(path, TrashConfig::default());
+        let summary = reopened.summary(MAIN_BRANCH);
+        assert_eq!(summary.block_count, 2);
+        assert_eq!(summary.tokens, removed_tokens);
+        assert_eq!(
+            reopened
+                .entry(MAIN_BRANCH, "c")
+                .expect("entry")
+                .reason
+                .as_deref(),
+            Some("noise")
+        );
+
+        let restored = reopened
+            .restore(
+                &mut blocks,
+                MAIN_BRANCH,
+                &["b".to_string(), "c".to_string()],
+            )
+            .expect("restore");
+        assert_eq!(restored.len(), 2);
+        assert_eq!(ids(&blocks), vec!["a", "b", "c", "d"]);
+        assert_eq!(reopened.summary(MAIN_BRANCH).block_count, 0);
+        assert!(matches!(
+            reopened.restore(&mut blocks, MAIN_BRANCH, &["b".to_string()]),
+            Err(TrashError::NotInTrash(_))
+        ));
+    }
+
+    #[test]
+    fn test_purge_and_retention_drop_entries() {
+        let mut blocks = blocks();
+        let dir = TestDir::new("trash");
+        let trash = TrashStore::open(dir.join("trash.json"), TrashConfig::default());
+        for id in ["a", "b", "c"] {
+            trash
+                .remove(&mut blocks, MAIN_BRANCH, id, None, RemovalPolicy::Refuse)
+                .expect("remove");
+        }
+        assert_eq!(
+            trash
+                .purge(MAIN_BRANCH, Some(&["a".to_string()]))
+                .expect("purge"),
+            1
+        );
+        assert_eq!(trash.summary(MAIN_BRANCH).block_count, 2);
+        assert_eq!(trash.purge(MAIN_BRANCH, None).expect("purge all"), 2);
+
+        let expiring = TrashStore::open(
+            dir.join("expiring.json"),
+            TrashConfig {
+                retention_ms: Some(0),
+                show_ghosts: false,
+            },
+        );
+        expiring
+            .remove(&mut blocks, MAIN_BRANCH, "d", None, RemovalPolicy::Refuse)
+            .expect("remove");
+        let summary = expiring.summary(MAIN_BRANCH);
+        assert_eq!(summary.block_count, 0);
+        assert!(!summary.show_ghosts);
+    }
+
+    #[test]
+    fn test_remove_refuses_or_cascades_dependents() {
+        let dir = TestDir::new("trash");
+        let trash = TrashStore::open(dir.join("trash.json"), TrashConfig::default());
+        let mut call = test_block("use", Role::ToolUse, "read src/lib.rs");
+        call.metadata.tool_use_id = Some("toolu_1".to_string());
+        let mut result = test_block("result", Role::ToolResult, "fn main() {}");
+        result.metadata.tool_use_id = Some("toolu_1".to_string());
+        let mut blocks = vec![call, result, test_block("d", Role::User, "block d")];
+
+        assert!(matches!(
+            trash.remove(&mut blocks, MAIN_BRANCH, "use", None, RemovalPolicy::Refuse),
+            Err(TrashError::Dependency(_))
+        ));
+        assert_eq!(blocks.len(), 3);
+
+        let removed = trash
+            .remove(
+                &mut blocks,
+                MAIN_BRANCH,
+                "use",
+                None,
+                RemovalPolicy::Cascade,
+            )
+            .expect("cascade");
+        assert_eq!(removed.len(), 2);
+        assert_eq!(ids(&blocks), vec!["d"]);
+        trash
+            .restore(
+                &mut blocks,
+                MAIN_BRANCH,
+                &["use".to_string(), "result".to_string()],
+            )
+            .expect("restore");
+        assert_eq!(ids(&blocks), vec!["use", "result", "d"]);
+    }
+
+    #[test]
+    fn test_removed_blocks_leave_later_requests() {
+        let dir = TestDir::new("trash");
+        let trash = TrashStore::open(dir.join("trash.json"), TrashConfig::default());
+        let mut session = EngineSession::default();
+        let body = json!({ "messages": [
+            { "role": "user", "content": "What is in lib.rs?" },
+            { "role": "assistant", "content": [
+                { "type": "tool_use", "id": "toolu_1", "name": "Read",
+                  "input": { "file_path": "src/lib.rs" } },
+            ] },
+            { "role": "user", "content": [
+                { "type": "tool_result", "tool_use_id": "toolu_1", "content": "fn main() {}" },
+            ] },
+            { "role": "assistant", "content": "It holds `main`." },
+        ] });
+        session
+            .transcript
+            .ingest(&mut session.blocks, &body, "anthropic");
+        let call = session
+            .blocks
+            .iter()
+            .find(|b| b.role == Role::ToolUse)
+            .map(|b| b.id.clone())
+            .expect("tool call block");
+
+        let send = |session: &EngineSession| {
+            let mut outbound = body.clone();
+            session
+                .transcript
+                .rebuild(&session.blocks, &mut outbound, "anthropic");
+            outbound["messages"].clone()
+        };
+        assert!(matches!(
+            trash.remove(
+                &mut session.blocks,
+                MAIN_BRANCH,
+                &call,
+                None,
+                RemovalPolicy::Refuse
+            ),
+            Err(TrashError::Dependency(_))
+        ));
+        assert_eq!(send(&session), body["messages"]);
+
+        let removed = trash
+            .remove(
+                &mut session.blocks,
+                MAIN_BRANCH,
+                &call,
+                None,
+                RemovalPolicy::Cascade,
+            )
+            .expect("cascade");
+        assert_eq!(removed.len(), 2);
+        let messages = send(&session);
+        assert!(!messages.to_string().contains("toolu_1"));
+        assert_eq!(messages[0]["content"], "What is in lib.rs?");
+        assert_eq!(messages[1]["content"], "It holds `main`.");
+
+        let ids: Vec<String> = removed.iter().map(|e| e.ghost.block_id.clone()).collect();
+        trash
+            .restore(&mut session.blocks, MAIN_BRANCH, &ids)
+            .expect("restore");
+        assert_eq!(send(&session), body["messages"]);
+    }
+
+    #[test]
+    fn test_entries_are_kept_per_branch() {
+        let dir = TestDir::new("trash");
+        let trash = TrashStore::open(dir.join("trash.json"), TrashConfig::default());
+        let mut main = blocks();
+        let mut child = blocks();
+        child[1].content = "child b".to_string();
+        trash
+            .remove(&mut main, MAIN_BRANCH, "b", None, RemovalPolicy::Refuse)
+            .expect("remove on main");
+        trash
+            .remove(&mut child, "child", "b", None, RemovalPolicy::Refuse)
+            .expect("remove on child");
+
+        assert_eq!(trash.summary(MAIN_BRANCH).block_count, 1);
+        assert_eq!(
+            trash.entry("child", "b").expect("entry").block.content,
+            "child b"
+        );
+        assert_eq!(trash.purge("child", None).expect("purge"), 1);
+        trash
+            .restore(&mut main, MAIN_BRANCH, &["b".to_string()])
+            .expect("restore");
+        assert_eq!(main[1].content, "block b");
+        assert!(matches!(
+            trash.restore(&mut child, "child", &["b".to_string()]),
+            Err(TrashError::NotInTrash(_))
+        ));
+    }
+}
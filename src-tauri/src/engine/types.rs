@@ -43,6 +43,18 @@ pub enum CompressionLevel {
     Minimal,
 }
 
+impl CompressionLevel {
+    /// The next, more compressed level; `None` for `Minimal`.
+    pub fn deeper(self) -> Option<Self> {
+        match self {
+            Self::Original => Some(Self::Trimmed),
+            Self::Trimmed => Some(Self::Summarized),
+            Self::Summarized => Some(Self::Minimal),
+            Self::Minimal => None,
+        }
+    }
+}
+
 /// Pin position within a zone.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
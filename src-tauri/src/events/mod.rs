@@ -4,4 +4,26 @@
 //! Events are emitted via Tauri's event system and consumed by the
 //! Svelte frontend for real-time updates.
 
+pub mod timeline;
 pub mod types;
+
+use std::sync::Arc;
+
+use tauri::{AppHandle, Emitter};
+use tracing::warn;
+
+use types::{channels, ApertureEvent};
+
+/// Callback that delivers backend events, to the frontend via Tauri or to a
+/// collector in tests.
+pub type EventSink = Arc<dyn Fn(ApertureEvent) + Send + Sync>;
+
+/// Sink that emits each event to the frontend on
+/// [`channels::APERTURE_EVENTS`].
+pub fn tauri_sink(app: AppHandle) -> EventSink {
+    Arc::new(move |event| {
+        if let Err(e) = app.emit(channels::APERTURE_EVENTS, &event) {
+            warn!("Failed to emit {event:?}: {e}");
+        }
+    })
+}
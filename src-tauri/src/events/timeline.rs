@@ -0,0 +1,129 @@
+//! Wall-clock timeline of captured proxy requests.
+//!
+//! The proxy records a mark for every request it forwards. Other subsystems
+//! (e.g. terminal recordings) use it to line their own timestamps up with the
+//! context history without the proxy knowing about them.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+/// Marks retained before the oldest are dropped.
+pub const DEFAULT_TIMELINE_CAPACITY: usize = 10_000;
+
+/// A single captured request on the timeline.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestMark {
+    pub request_id: String,
+    pub method: String,
+    pub path: String,
+    /// Unix epoch milliseconds when the proxy received the request.
+    pub at_ms: u64,
+}
+
+/// Bounded, thread-safe log of request marks in arrival order.
+#[derive(Debug)]
+pub struct RequestTimeline {
+    marks: Mutex<VecDeque<RequestMark>>,
+    capacity: usize,
+}
+
+impl Default for RequestTimeline {
+    fn default() -> Self {
+        Self::with_capacity(DEFAULT_TIMELINE_CAPACITY)
+    }
+}
+
+impl RequestTimeline {
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            marks: Mutex::new(VecDeque::new()),
+            capacity,
+        }
+    }
+
+    /// Record a request received now.
+    pub fn record(&self, request_id: &str, method: &str, path: &str) {
+        self.push(RequestMark {
+            request_id: request_id.to_string(),
+            method: method.to_string(),
+            path: path.to_string(),
+            at_ms: now_ms(),
+        });
+    }
+
+    pub fn push(&self, mark: RequestMark) {
+        let mut marks = self
+            .marks
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        marks.push_back(mark);
+        while marks.len() > self.capacity {
+            marks.pop_front();
+        }
+    }
+
+    /// Marks with `start_ms <= at_ms <= end_ms`, oldest first.
+    pub fn between(&self, start_ms: u64, end_ms: u64) -> Vec<RequestMark> {
+        let marks = self
+            .marks
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        marks
+            .iter()
+            .filter(|m| m.at_ms >= start_ms && m.at_ms <= end_ms)
+            .cloned()
+            .collect()
+    }
+}
+
+/// Current time as Unix epoch milliseconds.
+pub fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mark(id: &str, at_ms: u64) -> RequestMark {
+        RequestMark {
+            request_id: id.to_string(),
+            method: "POST".to_string(),
+            path: "/v1/messages".to_string(),
+            at_ms,
+        }
+    }
+
+    #[test]
+    fn test_timeline_between_filters_by_time() {
+        let timeline = RequestTimeline::default();
+        timeline.push(mark("a", 100));
+        timeline.push(mark("b", 200));
+        timeline.push(mark("c", 300));
+
+        let ids: Vec<_> = timeline
+            .between(150, 300)
+            .into_iter()
+            .map(|m| m.request_id)
+            .collect();
+        assert_eq!(ids, vec!["b", "c"]);
+    }
+
+    #[test]
+    fn test_timeline_over_capacity_drops_oldest() {
+        let timeline = RequestTimeline::with_capacity(2);
+        timeline.push(mark("a", 1));
+        timeline.push(mark("b", 2));
+        timeline.push(mark("c", 3));
+
+        assert_eq!(timeline.between(0, u64::MAX).len(), 2);
+        assert_eq!(timeline.between(0, 1).len(), 0);
+    }
+}
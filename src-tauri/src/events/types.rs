@@ -2,6 +2,9 @@
 
 use serde::{Deserialize, Serialize};
 
+use crate::engine::compression::queue::{CompressionPriority, CompressionTaskStatus};
+use crate::engine::types::CompressionLevel;
+
 /// Events emitted by the Aperture backend.
 ///
 /// These are sent to the frontend via Tauri's event system
@@ -39,6 +42,21 @@ pub enum ApertureEvent {
         request_id: Option<String>,
         message: String,
     },
+
+    /// The background compression queue's depth changed.
+    CompressionQueueChanged { pending: u32, running: u32 },
+
+    /// A compression task moved through its lifecycle.
+    CompressionProgress {
+        block_id: String,
+        level: CompressionLevel,
+        priority: CompressionPriority,
+        status: CompressionTaskStatus,
+        /// 1-based attempt number.
+        attempt: u32,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
 }
 
 /// Event channel names used with Tauri's event system.
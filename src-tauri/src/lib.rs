@@ -7,11 +7,14 @@
 
 pub mod engine;
 pub mod events;
+pub mod paths;
 pub mod proxy;
 pub mod terminal;
 
 use std::env;
-use tracing::{error, info};
+use std::sync::Arc;
+use tauri::Manager;
+use tracing::{error, info, warn};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
 
 /// Load environment from .env file (if present).
@@ -77,32 +80,86 @@ pub fn run() {
     init_logging();
 
     let port = get_proxy_port();
+    let timeline = Arc::new(events::timeline::RequestTimeline::default());
+    let proxy_timeline = Arc::clone(&timeline);
+    let session = engine::session::SharedSession::default();
+    let checkpoints = engine::checkpoint::hard::CheckpointStore::default();
+    engine::profile::load_startup_profile(&session, &checkpoints);
+    let proxy_session = session.clone();
+    let proxy_checkpoints = checkpoints.clone();
+    let trash = Arc::new(engine::trash::TrashStore::default());
+    let proxy_trash = Arc::clone(&trash);
+    let compression_backend = match engine::compression::config::backend_from_env() {
+        Ok(Some(backend)) => {
+            info!(
+                "Compression backend: {} ({})",
+                backend.name(),
+                backend.model()
+            );
+            Some(backend)
+        }
+        Ok(None) => None,
+        Err(e) => {
+            warn!("Compression backend disabled: {}", e);
+            None
+        }
+    };
+
+    let worker_backend = compression_backend.clone();
 
     info!("Starting Aperture");
     info!("Transparent proxy mode — tools' API keys pass through, no Aperture key needed");
 
-    // Start proxy server in background
-    std::thread::spawn(move || {
-        let rt = match tokio::runtime::Runtime::new() {
-            Ok(rt) => rt,
-            Err(e) => {
-                error!("Failed to create tokio runtime: {}", e);
-                return;
-            }
-        };
-        rt.block_on(async move {
-            if let Err(e) = proxy::start_proxy(port).await {
-                error!("Proxy server error: {}", e);
-            }
-        });
-    });
-
-    info!("Proxy listening on http://127.0.0.1:{}", port);
-    info!("Usage: ANTHROPIC_BASE_URL=http://localhost:{} claude", port);
-
     tauri::Builder::default()
         .plugin(tauri_plugin_opener::init())
+        .setup(move |app| {
+            // Compression events go to the frontend, so the queue needs the app handle.
+            let queue = engine::compression::CompressionQueue::new(
+                engine::compression::queue::QueueConfig::default(),
+                events::tauri_sink(app.app_handle().clone()),
+            );
+            app.manage(queue.clone());
+
+            // Start proxy server and compression worker in background
+            std::thread::spawn(move || {
+                let rt = match tokio::runtime::Runtime::new() {
+                    Ok(rt) => rt,
+                    Err(e) => {
+                        error!("Failed to create tokio runtime: {}", e);
+                        return;
+                    }
+                };
+                rt.block_on(async move {
+                    // Without a backend nothing would drain the queue.
+                    let proxy_queue = worker_backend.map(|backend| {
+                        engine::compression::start_worker(&queue, backend, proxy_session.clone());
+                        queue
+                    });
+                    if let Err(e) = proxy::start_proxy(
+                        port,
+                        proxy_timeline,
+                        proxy_session,
+                        proxy_checkpoints,
+                        proxy_trash,
+                        proxy_queue,
+                    )
+                    .await
+                    {
+                        error!("Proxy server error: {}", e);
+                    }
+                });
+            });
+
+            info!("Proxy listening on http://127.0.0.1:{}", port);
+            info!("Usage: ANTHROPIC_BASE_URL=http://localhost:{} claude", port);
+            Ok(())
+        })
         .manage(terminal::TerminalState::new())
+        .manage(timeline)
+        .manage(session)
+        .manage(checkpoints)
+        .manage(trash)
+        .manage(compression_backend)
         .invoke_handler(tauri::generate_handler![
             get_proxy_address,
             is_proxy_running,
@@ -110,7 +167,53 @@ pub fn run() {
             terminal::send_input,
             terminal::resize_terminal,
             terminal::kill_session,
+            terminal::list_sessions,
+            terminal::attach_session,
+            terminal::get_recording,
+            terminal::load_recording,
+            engine::checkpoint::checkpoint_create,
+            engine::checkpoint::checkpoint_restore,
+            engine::checkpoint::checkpoint_list,
+            engine::checkpoint::soft_checkpoint_create,
+            engine::checkpoint::soft_checkpoint_export,
+            engine::checkpoint::soft_checkpoint_inject,
+            engine::fork::fork_create,
+            engine::fork::fork_list,
+            engine::fork::fork_switch,
+            engine::fork::fork_delete,
+            engine::fork::fork_diff,
+            engine::fork::fork_merge,
+            engine::history::block_edit,
+            engine::history::block_history,
+            engine::history::block_version_diff,
+            engine::history::block_revert_edit,
+            engine::trash::trash_remove,
+            engine::trash::trash_restore,
+            engine::trash::trash_purge,
+            engine::trash::trash_list,
+            engine::trash::trash_entry,
+            engine::staging::staging_add,
+            engine::staging::staging_remove,
+            engine::staging::staging_set_enabled,
+            engine::staging::staging_list,
+            engine::profile::profile_load,
+            engine::profile::profile_current,
+            engine::profile::preset_switch,
+            engine::profile::preset_active,
+            engine::rules::rules_set,
+            engine::rules::rules_list,
+            engine::rules::rules_run,
+            engine::rules::rules_audit,
+            engine::rules::rules_set_audit_limit,
+            engine::manifest::manifest_set_enabled,
+            engine::memory_tool::memory_tool_set_enabled,
         ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(|app, event| {
+            // Sessions outlive webview reloads, so they are only ended here.
+            if let tauri::RunEvent::Exit = event {
+                app.state::<terminal::TerminalState>().kill_all();
+            }
+        });
 }
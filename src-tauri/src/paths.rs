@@ -0,0 +1,67 @@
+//! Filesystem locations for Aperture's local data.
+//!
+//! Everything Aperture persists stays on this machine (see the security
+//! section of `docs/ARCHITECTURE.md`), under a single data directory.
+
+use std::env;
+use std::path::PathBuf;
+
+/// Override for the data directory (useful for tests and portable installs).
+pub const DATA_DIR_ENV: &str = "APERTURE_DATA_DIR";
+
+/// Root directory for persisted data.
+///
+/// `$APERTURE_DATA_DIR` if set, otherwise the platform data directory
+/// (e.g. `~/.local/share/aperture`), falling back to `./.aperture`.
+pub fn data_dir() -> PathBuf {
+    if let Some(dir) = env::var_os(DATA_DIR_ENV).filter(|d| !d.is_empty()) {
+        return PathBuf::from(dir);
+    }
+    dirs::data_dir()
+        .map(|d| d.join("aperture"))
+        .unwrap_or_else(|| PathBuf::from(".aperture"))
+}
+
+/// Directory holding terminal session recordings.
+pub fn recordings_dir() -> PathBuf {
+    data_dir().join("recordings")
+}
+
+/// File holding soft-deleted blocks.
+pub fn trash_path() -> PathBuf {
+    data_dir().join("trash.json")
+}
+
+/// Directory holding session checkpoints.
+pub fn checkpoints_dir() -> PathBuf {
+    data_dir().join("checkpoints")
+}
+
+/// Unique directory under the system temp dir, removed with everything in
+/// it when dropped.
+#[cfg(test)]
+pub(crate) struct TestDir(PathBuf);
+
+#[cfg(test)]
+impl TestDir {
+    pub(crate) fn new(prefix: &str) -> Self {
+        let dir = env::temp_dir().join(format!("aperture-{prefix}-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).expect("create test dir");
+        Self(dir)
+    }
+
+    pub(crate) fn path(&self) -> &std::path::Path {
+        &self.0
+    }
+
+    pub(crate) fn join(&self, path: impl AsRef<std::path::Path>) -> PathBuf {
+        self.0.join(path)
+    }
+}
+
+#[cfg(test)]
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
@@ -0,0 +1,451 @@
+//! Engine pass over outbound requests.
+//!
+//! Messages and Chat Completions bodies are run through the shared session
+//! before they are forwarded, so what the model sees follows the session's
+//! current state. Each request's new messages are ingested as blocks; then
+//! the staged items whose condition holds are added, the session's rules
+//! run, the topic clusters are updated and budget pressure is relieved
+//! under the active preset's budget and auto-compression policy. The
+//! request's messages are rebuilt from the resulting blocks (see
+//! [`crate::engine::outbound`]) and the context manifest and staged items
+//! are injected. Each response is reassembled, streamed or not, and matched
+//! back to the blocks for usage heat.
+
+use axum::body::Body;
+use futures_util::StreamExt;
+use serde_json::Value;
+use tracing::warn;
+
+use crate::engine::block::Block;
+use crate::engine::budget::relieve_pressure;
+use crate::engine::checkpoint::hard::CheckpointStore;
+use crate::engine::compression::preserve::PreserveKeys;
+use crate::engine::compression::CompressionQueue;
+use crate::engine::heat::{analyze_response, HeatConfig};
+use crate::engine::manifest::{
+    build_manifest, inject_into_request, upsert_manifest_block, ManifestPlacement,
+};
+use crate::engine::rules::{run_rules, RuleContext};
+use crate::engine::session::SharedSession;
+use crate::engine::staging::{self, is_session_start, latest_user_text, upsert_staged_blocks};
+use crate::engine::tokens::count_tokens;
+use crate::engine::trash::TrashStore;
+
+/// Apply `session` to a request `body` for `provider` (`"anthropic"` or
+/// `"openai"`), loading soft checkpoints for staged items from
+/// `checkpoints`, moving blocks removed by rules to `trash` and queueing
+/// model compressions the budget asks for on `queue`. Returns
+/// whether the body was rewritten, which it is unless it has no messages.
+pub(crate) fn prepare_request(
+    session: &SharedSession,
+    checkpoints: &CheckpointStore,
+    trash: &TrashStore,
+    queue: Option<&CompressionQueue>,
+    body: &mut Value,
+    provider: &str,
+) -> bool {
+    if !body["messages"].is_array() {
+        return false;
+    }
+    let mut guard = session.lock();
+    let session = &mut *guard;
+    session
+        .transcript
+        .ingest(&mut session.blocks, body, provider);
+    let turn = current_turn(&session.blocks);
+
+    // Staged blocks count against the budget before pressure is relieved.
+    let staged =
+        session
+            .staging
+            .select(checkpoints, &latest_user_text(body), is_session_start(body));
+    upsert_staged_blocks(&mut session.blocks, &staged, turn);
+
+    // Read per request, so a preset switch applies to the next one.
+    let budget = session.profile.budget_config();
+    let incoming = overhead_tokens(body);
+    if !session.rules.is_empty() {
+        let context = RuleContext::new(&session.blocks, incoming, &budget);
+        if let Err(e) = run_rules(session, trash, context, false) {
+            warn!("Rules not run: {e}");
+        }
+    }
+
+    session.clusters.update(&mut session.blocks);
+    if session.profile.auto_compression() {
+        relieve_pressure(
+            &mut session.blocks,
+            incoming,
+            turn,
+            &budget,
+            &PreserveKeys::default(),
+            &mut session.log,
+            queue,
+        );
+    }
+
+    let manifest = build_manifest(
+        &session.blocks,
+        &session.clusters,
+        &budget,
+        &session.manifest,
+    );
+    let in_system = session.manifest.placement == ManifestPlacement::SystemPrompt;
+    upsert_manifest_block(
+        &mut session.blocks,
+        manifest.as_ref().filter(|_| !in_system),
+        turn,
+    );
+
+    session.transcript.rebuild(&session.blocks, body, provider);
+    if let Some(manifest) = manifest.filter(|_| in_system) {
+        inject_into_request(body, provider, &manifest);
+    }
+    if !staged.is_empty() {
+        staging::inject_into_request(body, provider, &staged);
+    }
+    true
+}
+
+/// Match a finished response's assistant text back to the session's blocks.
+pub(crate) fn record_response(session: &SharedSession, text: &str) {
+    if text.is_empty() {
+        return;
+    }
+    let mut session = session.lock();
+    let turn = current_turn(&session.blocks);
+    analyze_response(&mut session.blocks, text, turn, &HeatConfig::default());
+}
+
+/// Pass a streamed response through unchanged, recording its text once the
+/// stream ends.
+pub(crate) fn observe_stream(body: Body, session: SharedSession) -> Body {
+    let state = Some((body.into_data_stream(), ResponseText::default(), session));
+    Body::from_stream(futures_util::stream::unfold(state, |state| async move {
+        let (mut stream, mut text, session) = state?;
+        match stream.next().await {
+            Some(Ok(chunk)) => {
+                text.push_sse(&chunk);
+                Some((Ok(chunk), Some((stream, text, session))))
+            }
+            Some(Err(e)) => Some((Err(e), None)),
+            None => {
+                record_response(&session, &text.text);
+                None
+            }
+        }
+    }))
+}
+
+/// Assistant text of a non-streaming Messages or Chat Completions response.
+pub(crate) fn response_text(body: &[u8]) -> String {
+    let Ok(json) = serde_json::from_slice::<Value>(body) else {
+        return String::new();
+    };
+    let mut text = String::new();
+    for part in json["content"].as_array().into_iter().flatten() {
+        if part["type"] == "text" {
+            text.push_str(part["text"].as_str().unwrap_or_default());
+        }
+    }
+    for choice in json["choices"].as_array().into_iter().flatten() {
+        text.push_str(choice["message"]["content"].as_str().unwrap_or_default());
+    }
+    text
+}
+
+/// Assistant text reassembled from SSE chunks.
+#[derive(Debug, Default)]
+struct ResponseText {
+    buffer: Vec<u8>,
+    text: String,
+}
+
+impl ResponseText {
+    fn push_sse(&mut self, chunk: &[u8]) {
+        self.buffer.extend(chunk.iter().filter(|&&b| b != b'\r'));
+        while let Some(end) = self.buffer.windows(2).position(|w| w == b"\n\n") {
+            let event: Vec<u8> = self.buffer.drain(..end + 2).collect();
+            let event = String::from_utf8_lossy(&event);
+            for data in event.lines().filter_map(|l| l.strip_prefix("data:")) {
+                let Ok(json) = serde_json::from_str::<Value>(data.trim()) else {
+                    continue;
+                };
+                if json["delta"]["type"] == "text_delta" {
+                    self.text
+                        .push_str(json["delta"]["text"].as_str().unwrap_or_default());
+                }
+                for choice in json["choices"].as_array().into_iter().flatten() {
+                    self.text
+                        .push_str(choice["delta"]["content"].as_str().unwrap_or_default());
+                }
+            }
+        }
+    }
+}
+
+fn current_turn(blocks: &[Block]) -> u32 {
+    blocks
+        .iter()
+        .map(|b| b.metadata.turn_index)
+        .max()
+        .unwrap_or(0)
+}
+
+/// Tokens a request sends besides its blocks: the system prompt, leading
+/// system messages and tool definitions.
+fn overhead_tokens(body: &Value) -> u32 {
+    let system = body["messages"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .take_while(|m| matches!(m["role"].as_str(), Some("system" | "developer")))
+        .fold(text_tokens(&body["system"]), |total, message| {
+            total.saturating_add(text_tokens(&message["content"]))
+        });
+    match body.get("tools") {
+        Some(tools) => system.saturating_add(count_tokens(&tools.to_string())),
+        None => system,
+    }
+}
+
+fn text_tokens(content: &Value) -> u32 {
+    match content {
+        Value::String(text) => count_tokens(text),
+        Value::Array(parts) => parts
+            .iter()
+            .filter_map(|part| part["text"].as_str())
+            .fold(0, |total, text| total.saturating_add(count_tokens(text))),
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::engine::block::test_block;
+    use crate::engine::manifest::{is_manifest, ManifestConfig};
+    use crate::engine::profile::{apply_preset, load_for_dir};
+    use crate::engine::session::EngineSession;
+    use crate::engine::staging::{is_staged, InjectionCondition, NewStagedItem, StagedSource};
+    use crate::engine::trash::TrashConfig;
+    use crate::engine::types::{BuiltInZone, Role, Zone};
+    use crate::paths::TestDir;
+
+    fn stores() -> (TestDir, CheckpointStore, TrashStore) {
+        let dir = TestDir::new("context");
+        let store = CheckpointStore::new(dir.path().to_path_buf());
+        let trash = TrashStore::open(dir.join("trash.json"), TrashConfig::default());
+        (dir, store, trash)
+    }
+
+    fn session() -> SharedSession {
+        SharedSession::new(EngineSession {
+            blocks: vec![test_block("q", Role::User, "Why does the login fail?")],
+            ..EngineSession::default()
+        })
+    }
+
+    #[test]
+    fn test_prepare_request_follows_manifest_toggle() {
+        let session = session();
+        let (_dir, store, trash) = stores();
+        let mut body = json!({ "system": "Be brief.", "messages": [] });
+        prepare_request(&session, &store, &trash, None, &mut body, "anthropic");
+        assert_eq!(body["system"], "Be brief.");
+
+        session.lock().manifest.enabled = true;
+        let mut body = json!({ "system": "Be brief.", "messages": [] });
+        assert!(prepare_request(
+            &session,
+            &store,
+            &trash,
+            None,
+            &mut body,
+            "anthropic"
+        ));
+        assert!(body["system"]
+            .as_str()
+            .expect("system")
+            .contains("[CONTEXT MANIFEST — 1 blocks"));
+
+        session.lock().manifest.enabled = false;
+        let mut body = json!({ "messages": [] });
+        prepare_request(&session, &store, &trash, None, &mut body, "anthropic");
+        assert!(body.get("system").is_none());
+        assert_eq!(body["messages"][0]["content"], "Why does the login fail?");
+
+        let mut other = json!({ "prompt": "hi" });
+        assert!(!prepare_request(
+            &session,
+            &store,
+            &trash,
+            None,
+            &mut other,
+            "anthropic"
+        ));
+    }
+
+    #[test]
+    fn test_prepare_request_clusters_and_relieves_pressure() {
+        let session = session();
+        let (dir, store, trash) = stores();
+        let presets = "default_preset: manual\npresets:\n  manual:\n    compression: { auto: false }\n  auto: {}\n";
+        fs::write(dir.join(".aperture.yml"), presets).expect("write profile");
+        {
+            let mut session = session.lock();
+            let mut old = test_block("old", Role::ToolResult, &"npm install ok\n".repeat(400));
+            // Over the default budget without tokenizing that much text.
+            old.tokens = 190_000;
+            session.blocks.push(old);
+            // Past the presets' recency window.
+            session.blocks[0].metadata.turn_index = 10;
+            load_for_dir(&mut session, &store, dir.path()).expect("load profile");
+        }
+        let body = json!({ "messages": [{ "role": "user", "content": "Next?" }] });
+        prepare_request(
+            &session,
+            &store,
+            &trash,
+            None,
+            &mut body.clone(),
+            "anthropic",
+        );
+        assert!(session.lock().log.entries().is_empty());
+
+        apply_preset(&mut session.lock(), &store, "auto").expect("switch");
+        prepare_request(
+            &session,
+            &store,
+            &trash,
+            None,
+            &mut body.clone(),
+            "anthropic",
+        );
+        let session = session.lock();
+        assert!(session.blocks.iter().all(|b| b.topic_cluster.is_some()));
+        assert!(session
+            .log
+            .entries()
+            .iter()
+            .any(|entry| entry.block_id == "old"));
+    }
+
+    #[test]
+    fn test_streamed_response_text_feeds_heat() {
+        let session = session();
+        session.lock().blocks[0] =
+            test_block("code", Role::ToolResult, "fn refresh_login_token() {}");
+
+        let mut text = ResponseText::default();
+        for chunk in [
+            "event: content_block_delta\ndata: {\"type\":\"content_block_delta\",",
+            "\"delta\":{\"type\":\"text_delta\",\"text\":\"Call refresh_login_token\"}}\n\n",
+            "data: {\"choices\":[{\"delta\":{\"content\":\" first.\"}}]}\n\ndata: [DONE]\n\n",
+        ] {
+            text.push_sse(chunk.as_bytes());
+        }
+        assert_eq!(text.text, "Call refresh_login_token first.");
+
+        record_response(&session, &text.text);
+        assert_eq!(session.lock().blocks[0].reference_count, 1);
+
+        let body = json!({ "content": [{ "type": "text", "text": "done" }] });
+        assert_eq!(response_text(body.to_string().as_bytes()), "done");
+    }
+
+    #[test]
+    fn test_primacy_placement_keeps_manifest_block() {
+        let session = session();
+        let (_dir, store, trash) = stores();
+        session.lock().manifest = ManifestConfig {
+            enabled: true,
+            placement: ManifestPlacement::PrimacyBlock,
+            ..ManifestConfig::default()
+        };
+        let mut body = json!({ "messages": [] });
+        assert!(prepare_request(
+            &session, &store, &trash, None, &mut body, "openai"
+        ));
+        assert_eq!(body["messages"].as_array().map(Vec::len), Some(1));
+        assert!(body["messages"][0]["content"]
+            .as_str()
+            .expect("content")
+            .starts_with("[CONTEXT MANIFEST — 1 blocks"));
+        assert!(session.lock().blocks.iter().any(is_manifest));
+    }
+
+    #[test]
+    fn test_prepare_request_injects_matching_staged_items() {
+        let session = session();
+        let (_dir, store, trash) = stores();
+        session.lock().blocks.clear();
+        session
+            .lock()
+            .staging
+            .add(
+                NewStagedItem {
+                    name: "db".to_string(),
+                    source: StagedSource::Text {
+                        content: "Migrations live in db/migrate.".to_string(),
+                    },
+                    target_zone: Zone::BuiltIn(BuiltInZone::Recency),
+                    priority: 0,
+                    condition: InjectionCondition::Keyword("migration".into()),
+                },
+                &store,
+            )
+            .expect("stage");
+
+        let mut body = json!({ "messages": [{ "role": "user", "content": "hi" }] });
+        prepare_request(&session, &store, &trash, None, &mut body, "anthropic");
+        assert_eq!(body["messages"][0]["content"], "hi");
+        assert!(!session.lock().blocks.iter().any(is_staged));
+
+        let mut body = json!({
+            "messages": [{ "role": "user", "content": "Add a migration" }]
+        });
+        assert!(prepare_request(
+            &session,
+            &store,
+            &trash,
+            None,
+            &mut body,
+            "anthropic"
+        ));
+        assert_eq!(
+            body["messages"][0]["content"],
+            "Migrations live in db/migrate.\n\nAdd a migration"
+        );
+        assert_eq!(
+            session
+                .lock()
+                .blocks
+                .iter()
+                .filter(|b| is_staged(b))
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_prepare_request_runs_rules() {
+        let session = session();
+        let (_dir, store, trash) = stores();
+        session.lock().rules = vec![serde_json::from_value(json!({
+            "id": "pin-login", "name": "login", "action": "pin",
+            "trigger": { "field": "content", "operator": "contains", "value": "login" },
+        }))
+        .expect("rule")];
+
+        let mut body = json!({ "messages": [{ "role": "user", "content": "Next?" }] });
+        prepare_request(&session, &store, &trash, None, &mut body, "anthropic");
+        let session = session.lock();
+        assert!(session.blocks[0].pinned.is_some());
+        assert_eq!(session.rule_audit.len(), 1);
+    }
+}
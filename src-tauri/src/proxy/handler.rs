@@ -6,31 +6,68 @@ use axum::{
     http::{header, HeaderMap, Request, StatusCode},
     response::{IntoResponse, Response},
 };
+use serde_json::Value;
 use std::sync::Arc;
-use tracing::{debug, error, info, instrument};
+use tracing::{debug, error, info, instrument, Span};
 use uuid::Uuid;
 
+use super::context;
+use super::intercept::{self, MemoryTool};
 use super::{error::ProxyError, ProxyState, UpstreamConfig, MAX_BODY_SIZE};
 
 /// Main proxy handler for all requests.
-#[instrument(skip_all, fields(request_id = %Uuid::new_v4()))]
+#[instrument(skip_all, fields(request_id = tracing::field::Empty))]
 pub(crate) async fn proxy_handler(
     State(state): State<Arc<ProxyState>>,
     req: Request<Body>,
 ) -> impl IntoResponse {
+    let request_id = Uuid::new_v4().to_string();
+    Span::current().record("request_id", request_id.as_str());
+
     let method = req.method().clone();
     let uri = req.uri().clone();
     let path = uri.path();
 
     info!("--> {} {}", method, path);
+    state.timeline.record(&request_id, method.as_str(), path);
     log_headers("Request", req.headers());
 
-    let upstream_base = determine_upstream(&state.config, req.headers(), path);
+    // The active preset may route to an approved upstream.
+    let config = state.session.lock().profile.upstream_config(&state.config);
+    let upstream_base = determine_upstream(&config, req.headers(), path);
     let upstream_url = format!("{}{}", upstream_base, path);
 
     debug!("Forwarding to: {}", upstream_url);
 
-    match forward_request(&state.client, req, &upstream_url).await {
+    // The engine only rewrites Messages and Chat Completions requests, and
+    // the memory tool is only offered on Anthropic Messages requests.
+    let provider = if upstream_base == config.openai_url {
+        "openai"
+    } else {
+        "anthropic"
+    };
+    let engine_pass = match provider {
+        "openai" => path.ends_with("/v1/chat/completions"),
+        _ => path.ends_with("/v1/messages"),
+    };
+    let memory_tool = state
+        .memory_executor
+        .as_ref()
+        .filter(|_| engine_pass && provider == "anthropic")
+        .and_then(|executor| {
+            let config = state.session.lock().memory_tool.clone();
+            config.enabled.then(|| MemoryTool {
+                config,
+                executor: Arc::clone(executor),
+            })
+        });
+
+    let outbound = Outbound {
+        upstream_url: &upstream_url,
+        provider: engine_pass.then_some(provider),
+        memory_tool,
+    };
+    match forward_request(&state, req, outbound).await {
         Ok(response) => {
             let status = response.status();
             info!("<-- {} {} -> {}", method, path, status);
@@ -76,12 +113,26 @@ fn determine_upstream<'a>(config: &'a UpstreamConfig, headers: &HeaderMap, path:
     &config.anthropic_url
 }
 
+/// Where a request goes and how it is rewritten on the way.
+struct Outbound<'a> {
+    upstream_url: &'a str,
+    /// API of a request the engine pass applies to, if it does.
+    provider: Option<&'static str>,
+    memory_tool: Option<MemoryTool>,
+}
+
 /// Forward a request to the upstream server.
 async fn forward_request(
-    client: &reqwest::Client,
+    state: &ProxyState,
     req: Request<Body>,
-    upstream_url: &str,
+    outbound: Outbound<'_>,
 ) -> Result<Response, ProxyError> {
+    let Outbound {
+        upstream_url,
+        provider,
+        memory_tool,
+    } = outbound;
+    let client = &state.client;
     let (parts, body) = req.into_parts();
 
     // Read body for logging (we'll need to capture this later for context analysis)
@@ -113,28 +164,55 @@ async fn forward_request(
         debug!("Request body: {}", preview);
     }
 
+    // Apply the session and offer the memory tool on streaming requests;
+    // either rewrites the body.
+    let mut body_bytes = body_bytes.to_vec();
+    let mut intercepting = None;
+    if let Some(provider) = provider {
+        if let Ok(mut json) = serde_json::from_slice::<Value>(&body_bytes) {
+            let rewritten = context::prepare_request(
+                &state.session,
+                &state.checkpoints,
+                &state.trash,
+                state.queue.as_ref(),
+                &mut json,
+                provider,
+            );
+            let tool =
+                memory_tool.filter(|tool| intercept::prepare_request(&mut json, &tool.config));
+            if rewritten || tool.is_some() {
+                body_bytes = json.to_string().into_bytes();
+            }
+            intercepting = tool.map(|tool| (tool, json));
+        }
+    }
+    let forward_headers = match intercepting {
+        Some(_) => intercept::upstream_headers(&parts.headers),
+        None => {
+            let mut headers = parts.headers.clone();
+            headers.remove(header::CONTENT_LENGTH);
+            headers
+        }
+    };
+
     // Build upstream request
     let mut upstream_req = client.request(parts.method, upstream_url);
 
     // Forward headers (except host)
-    for (key, value) in parts.headers.iter() {
+    for (key, value) in forward_headers.iter() {
         if key != header::HOST {
             upstream_req = upstream_req.header(key, value);
         }
     }
 
     // Send request
-    let upstream_response = upstream_req
-        .body(body_bytes.to_vec())
-        .send()
-        .await
-        .map_err(|e| {
-            if e.is_timeout() {
-                ProxyError::UpstreamTimeout
-            } else {
-                ProxyError::UpstreamFailed(e)
-            }
-        })?;
+    let upstream_response = upstream_req.body(body_bytes).send().await.map_err(|e| {
+        if e.is_timeout() {
+            ProxyError::UpstreamTimeout
+        } else {
+            ProxyError::UpstreamFailed(e)
+        }
+    })?;
 
     let status = upstream_response.status();
     let headers = upstream_response.headers().clone();
@@ -150,8 +228,20 @@ async fn forward_request(
 
     if is_streaming {
         debug!("Streaming SSE response");
-        let stream = upstream_response.bytes_stream();
-        let body = Body::from_stream(stream);
+        let mut body = match intercepting {
+            Some((tool, json)) => intercept::intercepting_body(
+                client.clone(),
+                upstream_url.to_string(),
+                forward_headers,
+                json,
+                upstream_response,
+                tool,
+            ),
+            None => Body::from_stream(upstream_response.bytes_stream()),
+        };
+        if provider.is_some() && status.is_success() {
+            body = context::observe_stream(body, state.session.clone());
+        }
 
         let mut response = Response::new(body);
         *response.status_mut() = status;
@@ -173,6 +263,10 @@ async fn forward_request(
         };
         debug!("Response body: {}", preview);
 
+        if provider.is_some() && status.is_success() {
+            context::record_response(&state.session, &context::response_text(&response_bytes));
+        }
+
         let mut response = Response::new(Body::from(response_bytes.to_vec()));
         *response.status_mut() = status;
         *response.headers_mut() = convert_headers(&headers);
@@ -207,7 +301,130 @@ fn log_headers(label: &str, headers: &HeaderMap) {
 
 #[cfg(test)]
 mod tests {
+    use axum::Router;
+    use serde_json::json;
+
     use super::*;
+    use crate::engine::action_log::ActionReason;
+    use crate::engine::block::CompressionVersion;
+    use crate::engine::memory::{transition, MemoryState};
+    use crate::engine::session::SharedSession;
+    use crate::engine::types::CompressionLevel;
+
+    /// Send a Messages request through the proxy and return what the
+    /// upstream received.
+    async fn send(state: &Arc<ProxyState>, body: &Value) -> Value {
+        let req = Request::post("/v1/messages")
+            .header("anthropic-version", "2023-06-01")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(header::CONTENT_LENGTH, body.to_string().len())
+            .body(Body::from(body.to_string()))
+            .expect("request");
+        let response = proxy_handler(State(Arc::clone(state)), req)
+            .await
+            .into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = axum::body::to_bytes(response.into_body(), MAX_BODY_SIZE)
+            .await
+            .expect("body");
+        serde_json::from_slice(&bytes).expect("json")
+    }
+
+    /// Start an upstream that answers every request with the body it got.
+    async fn echo_upstream() -> String {
+        let app = Router::new().fallback(|body: axum::body::Bytes| async move { body });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind");
+        let addr = listener.local_addr().expect("addr");
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        format!("http://{addr}")
+    }
+
+    #[tokio::test]
+    async fn test_forwarded_messages_are_rebuilt_from_blocks() {
+        let config = UpstreamConfig {
+            anthropic_url: echo_upstream().await,
+            openai_url: "http://127.0.0.1:9".to_string(),
+        };
+        let session = SharedSession::default();
+        let state = Arc::new(
+            ProxyState::with_config(config)
+                .expect("client")
+                .with_session(session.clone()),
+        );
+
+        let body = json!({
+            "model": "claude-test",
+            "system": "Be brief.",
+            "messages": [
+                { "role": "user", "content": "Why does the login fail?" },
+                { "role": "assistant", "content": "The token expired." },
+                { "role": "user", "content": "How do I fix it?" },
+            ],
+        });
+        let forwarded = send(&state, &body).await;
+        assert_eq!(forwarded["system"], "Be brief.");
+        assert_eq!(forwarded["messages"], body["messages"]);
+
+        {
+            let mut session = session.lock();
+            let answer = &mut session.blocks[1];
+            answer.compressed_versions.trimmed =
+                Some(CompressionVersion::new("Token expired.".to_string()));
+            answer.set_compression_level(CompressionLevel::Trimmed);
+        }
+        let forwarded = send(&state, &body).await;
+        assert_eq!(forwarded["messages"][1]["content"], "Token expired.");
+        assert_eq!(forwarded["messages"][2], body["messages"][2]);
+    }
+
+    #[tokio::test]
+    async fn test_cold_and_archived_blocks_are_not_forwarded() {
+        let config = UpstreamConfig {
+            anthropic_url: echo_upstream().await,
+            openai_url: "http://127.0.0.1:9".to_string(),
+        };
+        let session = SharedSession::default();
+        let state = Arc::new(
+            ProxyState::with_config(config)
+                .expect("client")
+                .with_session(session.clone()),
+        );
+
+        let body = json!({
+            "model": "claude-test",
+            "messages": [
+                { "role": "user", "content": "Here is the build log." },
+                { "role": "assistant", "content": "The linker failed." },
+                { "role": "user", "content": "And the test log?" },
+                { "role": "assistant", "content": "Two tests timed out." },
+                { "role": "user", "content": "Fix the linker first." },
+            ],
+        });
+        send(&state, &body).await;
+        {
+            let mut guard = session.lock();
+            let session = &mut *guard;
+            for (index, to) in [(1, MemoryState::Cold), (3, MemoryState::Archived)] {
+                transition(
+                    &mut session.blocks[index],
+                    to,
+                    None,
+                    ActionReason::Lifecycle,
+                    &mut session.log,
+                )
+                .expect("transition");
+            }
+        }
+
+        let forwarded = send(&state, &body).await;
+        let sent = forwarded["messages"].to_string();
+        assert!(!sent.contains("The linker failed."));
+        assert!(!sent.contains("Two tests timed out."));
+        assert_eq!(forwarded["messages"].as_array().map(Vec::len), Some(1));
+        assert!(sent.contains("Fix the linker first."));
+    }
 
     #[test]
     fn test_determine_upstream_anthropic_header() {
@@ -0,0 +1,598 @@
+//! Interception of the synthetic memory tool in Anthropic streams.
+//!
+//! The tool definition is added to streaming `/v1/messages` requests. The
+//! response stream is relayed to the client with the tool's `tool_use`
+//! blocks removed. When the model stops to wait for them, Aperture runs the
+//! commands, sends the conversation back upstream with the `tool_result`s,
+//! and splices the continuation into the same client stream, renumbering
+//! content blocks so the client sees one uninterrupted message.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use axum::body::{Body, Bytes};
+use axum::http::{header, HeaderMap};
+use futures_util::StreamExt;
+use serde_json::{json, Value};
+use tokio::sync::mpsc;
+use tracing::{debug, info, warn};
+
+use crate::engine::memory_tool::{MemoryToolConfig, MemoryToolExecutor, MEMORY_TOOL_NAME};
+
+/// The memory tool as wired into the proxy.
+#[derive(Clone)]
+pub(crate) struct MemoryTool {
+    pub(crate) config: MemoryToolConfig,
+    pub(crate) executor: Arc<dyn MemoryToolExecutor>,
+}
+
+/// Add the tool to a streaming request body.
+///
+/// Returns `false`, leaving the body alone, for non-streaming requests and
+/// for bodies that already define a tool with the same name.
+pub(crate) fn prepare_request(body: &mut Value, config: &MemoryToolConfig) -> bool {
+    let Some(object) = body.as_object_mut() else {
+        return false;
+    };
+    if object.get("stream") != Some(&Value::Bool(true)) || !object["messages"].is_array() {
+        return false;
+    }
+    let tools = object.entry("tools").or_insert_with(|| json!([]));
+    let Some(tools) = tools.as_array_mut() else {
+        return false;
+    };
+    if tools.iter().any(|t| t["name"] == MEMORY_TOOL_NAME) {
+        return false;
+    }
+    tools.push(config.tool_definition());
+    true
+}
+
+/// Headers for requests Aperture sends upstream on the client's behalf.
+///
+/// The body is re-serialized, so its length changes, and the stream must
+/// arrive uncompressed to be parsed.
+pub(crate) fn upstream_headers(headers: &HeaderMap) -> HeaderMap {
+    let mut headers = headers.clone();
+    for name in [
+        header::HOST,
+        header::CONTENT_LENGTH,
+        header::ACCEPT_ENCODING,
+    ] {
+        headers.remove(name);
+    }
+    headers
+}
+
+/// An intercepted call to the memory tool.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ToolCall {
+    pub(crate) id: String,
+    pub(crate) input: Value,
+}
+
+/// End of one upstream response.
+#[derive(Debug)]
+pub(crate) struct RoundEnd {
+    /// Every content block of the response, intercepted ones included, as
+    /// it must be replayed in the assistant turn.
+    pub(crate) assistant_content: Vec<Value>,
+    pub(crate) calls: Vec<ToolCall>,
+    /// Whether the response also called tools the client owns.
+    pub(crate) client_tool_calls: bool,
+    /// The held-back `message_delta` event.
+    pub(crate) message_delta: Option<Value>,
+}
+
+impl RoundEnd {
+    fn stop_reason(&self) -> Option<&str> {
+        self.message_delta.as_ref()?["delta"]["stop_reason"].as_str()
+    }
+
+    /// What follows this round, after `continuations` earlier ones.
+    ///
+    /// Memory tool results only reach the model through a continuation,
+    /// which is not possible when the model stopped for another reason or
+    /// the client has tool calls of its own to answer; such calls are not
+    /// run at all.
+    fn next_round(&self, continuations: u32, max_continuations: u32) -> NextRound {
+        let waiting = self.stop_reason() == Some("tool_use") && !self.client_tool_calls;
+        if self.calls.is_empty() || !waiting {
+            NextRound::Finish
+        } else if continuations >= max_continuations {
+            NextRound::EndTurn
+        } else {
+            NextRound::Continue
+        }
+    }
+}
+
+/// How a relay proceeds after an upstream round.
+#[derive(Debug, PartialEq, Eq)]
+enum NextRound {
+    /// Close the client's message as upstream ended it.
+    Finish,
+    /// Run the memory calls and ask upstream to continue.
+    Continue,
+    /// Out of continuations; close the message as a finished turn.
+    EndTurn,
+}
+
+#[derive(Debug)]
+pub(crate) enum SpliceStep {
+    /// Send this text to the client.
+    Forward(String),
+    /// The upstream message ended; `message_delta` and `message_stop` have
+    /// not been forwarded.
+    RoundEnd(RoundEnd),
+}
+
+#[derive(Debug)]
+struct RoundBlock {
+    content: Value,
+    partial_json: String,
+    /// Index the client sees; `None` for intercepted blocks.
+    client_index: Option<u64>,
+}
+
+/// Rewrites an Anthropic event stream across one or more upstream rounds.
+#[derive(Debug, Default)]
+pub(crate) struct SseSplicer {
+    buffer: Vec<u8>,
+    round: u32,
+    next_client_index: u64,
+    blocks: BTreeMap<u64, RoundBlock>,
+    message_delta: Option<Value>,
+}
+
+impl SseSplicer {
+    /// Feed raw stream bytes; returns what complete events produced.
+    pub(crate) fn push(&mut self, chunk: &[u8]) -> Vec<SpliceStep> {
+        self.buffer.extend(chunk.iter().filter(|&&b| b != b'\r'));
+        let mut steps = Vec::new();
+        while let Some(end) = self.buffer.windows(2).position(|w| w == b"\n\n") {
+            let event: Vec<u8> = self.buffer.drain(..end + 2).collect();
+            let event = String::from_utf8_lossy(&event[..end]).into_owned();
+            steps.extend(self.handle_event(event));
+        }
+        steps
+    }
+
+    /// Start splicing the continuation of the same client message.
+    pub(crate) fn next_round(&mut self) {
+        self.round += 1;
+        self.blocks.clear();
+        self.message_delta = None;
+    }
+
+    fn handle_event(&mut self, raw: String) -> Option<SpliceStep> {
+        let mut name = None;
+        let mut data = Vec::new();
+        for line in raw.lines() {
+            if let Some(value) = line.strip_prefix("event:") {
+                name = Some(value.trim().to_string());
+            } else if let Some(value) = line.strip_prefix("data:") {
+                data.push(value.trim_start());
+            }
+        }
+        let Ok(mut data) = serde_json::from_str::<Value>(&data.join("\n")) else {
+            return Some(SpliceStep::Forward(format!("{raw}\n\n")));
+        };
+        let kind = data["type"]
+            .as_str()
+            .map(str::to_string)
+            .or(name)
+            .unwrap_or_default();
+        let index = data["index"].as_u64();
+
+        match kind.as_str() {
+            "message_start" if self.round > 0 => None,
+            "content_block_start" => {
+                let content = data["content_block"].clone();
+                let intercepted =
+                    content["type"] == "tool_use" && content["name"] == MEMORY_TOOL_NAME;
+                let client_index = (!intercepted).then(|| {
+                    self.next_client_index += 1;
+                    self.next_client_index - 1
+                });
+                self.blocks.insert(
+                    index?,
+                    RoundBlock {
+                        content,
+                        partial_json: String::new(),
+                        client_index,
+                    },
+                );
+                self.forward_block_event(&kind, index?, &mut data)
+            }
+            "content_block_delta" => {
+                let block = self.blocks.get_mut(&index?)?;
+                apply_delta(block, &data["delta"]);
+                self.forward_block_event(&kind, index?, &mut data)
+            }
+            "content_block_stop" => {
+                let block = self.blocks.get_mut(&index?)?;
+                if block.content["type"] == "tool_use" {
+                    let json = std::mem::take(&mut block.partial_json);
+                    block.content["input"] = if json.trim().is_empty() {
+                        json!({})
+                    } else {
+                        serde_json::from_str(&json).unwrap_or_else(|_| json!({}))
+                    };
+                }
+                self.forward_block_event(&kind, index?, &mut data)
+            }
+            "message_delta" => {
+                self.message_delta = Some(data);
+                None
+            }
+            "message_stop" => Some(SpliceStep::RoundEnd(self.round_end())),
+            _ => Some(SpliceStep::Forward(format!("{raw}\n\n"))),
+        }
+    }
+
+    fn forward_block_event(&self, kind: &str, index: u64, data: &mut Value) -> Option<SpliceStep> {
+        let client_index = self.blocks.get(&index)?.client_index?;
+        data["index"] = json!(client_index);
+        Some(SpliceStep::Forward(render_event(kind, data)))
+    }
+
+    fn round_end(&mut self) -> RoundEnd {
+        let mut calls = Vec::new();
+        let mut client_tool_calls = false;
+        for block in self.blocks.values() {
+            if block.content["type"] != "tool_use" {
+                continue;
+            }
+            if block.client_index.is_some() {
+                client_tool_calls = true;
+            } else {
+                calls.push(ToolCall {
+                    id: block.content["id"].as_str().unwrap_or_default().to_string(),
+                    input: block.content["input"].clone(),
+                });
+            }
+        }
+        RoundEnd {
+            assistant_content: self.blocks.values().map(|b| b.content.clone()).collect(),
+            calls,
+            client_tool_calls,
+            message_delta: self.message_delta.take(),
+        }
+    }
+}
+
+fn apply_delta(block: &mut RoundBlock, delta: &Value) {
+    let append = |content: &mut Value, field: &str, text: &Value| {
+        let existing = content[field].as_str().unwrap_or_default();
+        content[field] = json!(format!("{existing}{}", text.as_str().unwrap_or_default()));
+    };
+    match delta["type"].as_str() {
+        Some("text_delta") => append(&mut block.content, "text", &delta["text"]),
+        Some("thinking_delta") => append(&mut block.content, "thinking", &delta["thinking"]),
+        Some("signature_delta") => block.content["signature"] = delta["signature"].clone(),
+        Some("input_json_delta") => block
+            .partial_json
+            .push_str(delta["partial_json"].as_str().unwrap_or_default()),
+        Some("citations_delta") => {
+            if !block.content["citations"].is_array() {
+                block.content["citations"] = json!([]);
+            }
+            if let Some(citations) = block.content["citations"].as_array_mut() {
+                citations.push(delta["citation"].clone());
+            }
+        }
+        _ => {}
+    }
+}
+
+fn render_event(kind: &str, data: &Value) -> String {
+    format!("event: {kind}\ndata: {data}\n\n")
+}
+
+/// `message_delta` (with `stop_reason` replaced if asked) and
+/// `message_stop`, ending the client's message.
+pub(crate) fn closing_events(message_delta: Option<Value>, stop_reason: Option<&str>) -> String {
+    let mut out = String::new();
+    if let Some(mut delta) = message_delta {
+        if let Some(reason) = stop_reason {
+            delta["delta"]["stop_reason"] = json!(reason);
+        }
+        out.push_str(&render_event("message_delta", &delta));
+    }
+    out.push_str(&render_event(
+        "message_stop",
+        &json!({ "type": "message_stop" }),
+    ));
+    out
+}
+
+fn error_event(message: &str) -> String {
+    render_event(
+        "error",
+        &json!({ "type": "error", "error": { "type": "api_error", "message": message } }),
+    )
+}
+
+/// Relay `first` to the client, handling memory tool calls along the way.
+pub(crate) fn intercepting_body(
+    client: reqwest::Client,
+    upstream_url: String,
+    headers: HeaderMap,
+    body: Value,
+    first: reqwest::Response,
+    tool: MemoryTool,
+) -> Body {
+    let (tx, rx) = mpsc::channel::<Result<Bytes, std::io::Error>>(32);
+    tokio::spawn(async move {
+        let upstream = Upstream {
+            client,
+            url: upstream_url,
+            headers,
+        };
+        if let Err(message) = relay(upstream, body, first, tool, &tx).await {
+            warn!("Memory tool relay failed: {message}");
+            let _ = tx.send(Ok(Bytes::from(error_event(&message)))).await;
+        }
+    });
+    Body::from_stream(futures_util::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|item| (item, rx))
+    }))
+}
+
+struct Upstream {
+    client: reqwest::Client,
+    url: String,
+    headers: HeaderMap,
+}
+
+async fn relay(
+    upstream: Upstream,
+    mut body: Value,
+    first: reqwest::Response,
+    tool: MemoryTool,
+    tx: &mpsc::Sender<Result<Bytes, std::io::Error>>,
+) -> Result<(), String> {
+    let mut splicer = SseSplicer::default();
+    let mut response = first;
+    let mut calls_made = 0;
+    let mut continuations = 0;
+    loop {
+        let mut stream = response.bytes_stream();
+        let mut end = None;
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk.map_err(|e| format!("upstream stream failed: {e}"))?;
+            for step in splicer.push(&chunk) {
+                match step {
+                    SpliceStep::Forward(text) => {
+                        if tx.send(Ok(Bytes::from(text))).await.is_err() {
+                            debug!("Client disconnected during memory tool relay");
+                            return Ok(());
+                        }
+                    }
+                    SpliceStep::RoundEnd(round) => end = Some(round),
+                }
+            }
+        }
+        let Some(end) = end else {
+            return Err("upstream stream ended without message_stop".to_string());
+        };
+
+        match end.next_round(continuations, tool.config.max_continuations) {
+            NextRound::Finish => {
+                if !end.calls.is_empty() {
+                    warn!(
+                        "Skipping {} memory tool call(s) that cannot be continued",
+                        end.calls.len()
+                    );
+                }
+                let closing = closing_events(end.message_delta, None);
+                let _ = tx.send(Ok(Bytes::from(closing))).await;
+                return Ok(());
+            }
+            NextRound::EndTurn => {
+                warn!("Memory tool continuation limit reached; ending the turn");
+                let closing = closing_events(end.message_delta, Some("end_turn"));
+                let _ = tx.send(Ok(Bytes::from(closing))).await;
+                return Ok(());
+            }
+            NextRound::Continue => {}
+        }
+
+        let mut results = Vec::new();
+        for call in &end.calls {
+            let outcome = match tool.config.admit(&call.input, calls_made) {
+                Ok(command) => {
+                    calls_made += 1;
+                    tool.executor.execute(&command)
+                }
+                Err(e) => Err(e),
+            };
+            info!("Memory tool call {}: {:?}", call.id, outcome);
+            let (content, is_error) = match outcome {
+                Ok(text) => (text, false),
+                Err(e) => (e.to_string(), true),
+            };
+            results.push(json!({
+                "type": "tool_result",
+                "tool_use_id": call.id,
+                "content": content,
+                "is_error": is_error,
+            }));
+        }
+
+        let messages = body["messages"]
+            .as_array_mut()
+            .ok_or("request has no messages")?;
+        messages.push(json!({ "role": "assistant", "content": end.assistant_content }));
+        messages.push(json!({ "role": "user", "content": results }));
+        continuations += 1;
+
+        response = upstream
+            .client
+            .post(&upstream.url)
+            .headers(upstream.headers.clone())
+            .body(body.to_string())
+            .send()
+            .await
+            .map_err(|e| format!("continuation request failed: {e}"))?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            return Err(format!("continuation returned {status}: {text}"));
+        }
+        splicer.next_round();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(data: Value) -> String {
+        render_event(data["type"].as_str().expect("type"), &data)
+    }
+
+    fn first_round() -> String {
+        [
+            json!({ "type": "message_start", "message": { "id": "msg_1" } }),
+            json!({ "type": "content_block_start", "index": 0,
+                    "content_block": { "type": "text", "text": "" } }),
+            json!({ "type": "content_block_delta", "index": 0,
+                    "delta": { "type": "text_delta", "text": "Let me check." } }),
+            json!({ "type": "content_block_stop", "index": 0 }),
+            json!({ "type": "content_block_start", "index": 1,
+                    "content_block": { "type": "tool_use", "id": "toolu_1",
+                                       "name": MEMORY_TOOL_NAME, "input": {} } }),
+            json!({ "type": "content_block_delta", "index": 1,
+                    "delta": { "type": "input_json_delta",
+                               "partial_json": "{\"operation\":\"recall\"," } }),
+            json!({ "type": "content_block_delta", "index": 1,
+                    "delta": { "type": "input_json_delta",
+                               "partial_json": "\"query\":\"topic:auth\"}" } }),
+            json!({ "type": "content_block_stop", "index": 1 }),
+            json!({ "type": "message_delta", "delta": { "stop_reason": "tool_use" } }),
+            json!({ "type": "message_stop" }),
+        ]
+        .into_iter()
+        .map(event)
+        .collect()
+    }
+
+    fn forwarded(steps: &[SpliceStep]) -> Vec<Value> {
+        steps
+            .iter()
+            .filter_map(|s| match s {
+                SpliceStep::Forward(text) => text
+                    .lines()
+                    .find_map(|l| l.strip_prefix("data: "))
+                    .map(|d| serde_json::from_str(d).expect("json")),
+                SpliceStep::RoundEnd(_) => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_prepare_request_adds_tool_to_streaming_requests_only() {
+        let config = MemoryToolConfig::default();
+        let mut streaming = json!({ "stream": true, "messages": [] });
+        assert!(prepare_request(&mut streaming, &config));
+        assert_eq!(streaming["tools"][0]["name"], MEMORY_TOOL_NAME);
+        assert!(!prepare_request(&mut streaming, &config));
+
+        let mut blocking = json!({ "messages": [] });
+        assert!(!prepare_request(&mut blocking, &config));
+        assert!(blocking.get("tools").is_none());
+    }
+
+    #[test]
+    fn test_splicer_hides_intercepted_tool_use() {
+        let mut splicer = SseSplicer::default();
+        let raw = first_round();
+        // Split mid-event to exercise buffering.
+        let (a, b) = raw.as_bytes().split_at(raw.len() / 2);
+        let mut steps = splicer.push(a);
+        steps.extend(splicer.push(b));
+
+        let sent = forwarded(&steps);
+        assert_eq!(sent.len(), 4);
+        assert!(sent.iter().all(|e| e["index"] != 1));
+        assert!(sent.iter().all(|e| e["type"] != "message_delta"));
+
+        let Some(SpliceStep::RoundEnd(end)) = steps.pop() else {
+            panic!("expected round end");
+        };
+        assert_eq!(end.stop_reason(), Some("tool_use"));
+        assert!(!end.client_tool_calls);
+        assert_eq!(
+            end.calls,
+            vec![ToolCall {
+                id: "toolu_1".to_string(),
+                input: json!({ "operation": "recall", "query": "topic:auth" }),
+            }]
+        );
+        assert_eq!(end.assistant_content[0]["text"], "Let me check.");
+        assert_eq!(end.assistant_content[1]["input"]["query"], "topic:auth");
+    }
+
+    #[test]
+    fn test_splicer_renumbers_continuation_blocks() {
+        let mut splicer = SseSplicer::default();
+        splicer.push(first_round().as_bytes());
+        splicer.next_round();
+
+        let continuation: String = [
+            json!({ "type": "message_start", "message": { "id": "msg_2" } }),
+            json!({ "type": "content_block_start", "index": 0,
+                    "content_block": { "type": "text", "text": "" } }),
+            json!({ "type": "content_block_stop", "index": 0 }),
+            json!({ "type": "content_block_start", "index": 1,
+                    "content_block": { "type": "tool_use", "id": "toolu_2",
+                                       "name": "Read", "input": {} } }),
+            json!({ "type": "content_block_stop", "index": 1 }),
+            json!({ "type": "message_delta", "delta": { "stop_reason": "tool_use" } }),
+            json!({ "type": "message_stop" }),
+        ]
+        .into_iter()
+        .map(event)
+        .collect();
+        let mut steps = splicer.push(continuation.as_bytes());
+
+        let sent = forwarded(&steps);
+        assert!(sent.iter().all(|e| e["type"] != "message_start"));
+        let indices: Vec<&Value> = sent.iter().map(|e| &e["index"]).collect();
+        assert_eq!(indices, vec![&json!(1), &json!(1), &json!(2), &json!(2)]);
+
+        let Some(SpliceStep::RoundEnd(end)) = steps.pop() else {
+            panic!("expected round end");
+        };
+        assert!(end.client_tool_calls);
+        assert!(end.calls.is_empty());
+        let closing = closing_events(end.message_delta, Some("end_turn"));
+        assert!(closing.contains("\"stop_reason\":\"end_turn\""));
+        assert!(closing.ends_with("event: message_stop\ndata: {\"type\":\"message_stop\"}\n\n"));
+    }
+
+    #[test]
+    fn test_next_round_skips_calls_it_cannot_continue() {
+        let end = |client_tool_calls: bool, stop_reason: &str| RoundEnd {
+            assistant_content: Vec::new(),
+            calls: vec![ToolCall {
+                id: "toolu_1".to_string(),
+                input: json!({ "operation": "recall", "query": "topic:auth" }),
+            }],
+            client_tool_calls,
+            message_delta: Some(json!({ "delta": { "stop_reason": stop_reason } })),
+        };
+
+        assert_eq!(end(false, "tool_use").next_round(0, 3), NextRound::Continue);
+        assert_eq!(end(true, "tool_use").next_round(0, 3), NextRound::Finish);
+        assert_eq!(end(false, "end_turn").next_round(0, 3), NextRound::Finish);
+        assert_eq!(end(false, "tool_use").next_round(2, 3), NextRound::Continue);
+        assert_eq!(end(false, "tool_use").next_round(3, 3), NextRound::EndTurn);
+
+        let mut none = end(false, "tool_use");
+        none.calls.clear();
+        assert_eq!(none.next_round(0, 3), NextRound::Finish);
+    }
+}
@@ -5,8 +5,10 @@
 //! requests and responses for visualization while streaming SSE
 //! responses back to clients.
 
+mod context;
 pub mod error;
 mod handler;
+mod intercept;
 
 use axum::{routing::any, Router};
 use reqwest::Client;
@@ -16,6 +18,12 @@ use tokio::net::TcpListener;
 use tracing::info;
 
 use self::error::ProxyError;
+use crate::engine::checkpoint::hard::CheckpointStore;
+use crate::engine::compression::CompressionQueue;
+use crate::engine::memory_tool::MemoryToolExecutor;
+use crate::engine::session::SharedSession;
+use crate::engine::trash::TrashStore;
+use crate::events::timeline::RequestTimeline;
 
 /// Default port for the proxy server.
 pub const DEFAULT_PORT: u16 = 5400;
@@ -24,7 +32,7 @@ pub const DEFAULT_PORT: u16 = 5400;
 pub(crate) const MAX_BODY_SIZE: usize = 10 * 1024 * 1024;
 
 /// Upstream API configuration.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct UpstreamConfig {
     /// Base URL for Anthropic API.
     pub anthropic_url: String,
@@ -46,6 +54,19 @@ impl Default for UpstreamConfig {
 pub struct ProxyState {
     pub(crate) client: Client,
     pub(crate) config: UpstreamConfig,
+    pub(crate) timeline: Arc<RequestTimeline>,
+    /// Engine state applied to every outbound request.
+    pub(crate) session: SharedSession,
+    /// Where soft checkpoints staged into requests are loaded from.
+    pub(crate) checkpoints: CheckpointStore,
+    /// Where the session's rules move removed blocks; shared with the app.
+    pub(crate) trash: Arc<TrashStore>,
+    /// Runs memory tool calls; the tool is only offered while the
+    /// session's memory tool config is enabled.
+    pub(crate) memory_executor: Option<Arc<dyn MemoryToolExecutor>>,
+    /// Where budget pressure queues model compressions; `None` without a
+    /// compression backend.
+    pub(crate) queue: Option<CompressionQueue>,
 }
 
 impl ProxyState {
@@ -59,19 +80,90 @@ impl ProxyState {
         Ok(Self {
             client,
             config: UpstreamConfig::default(),
+            timeline: Arc::new(RequestTimeline::default()),
+            session: SharedSession::default(),
+            checkpoints: CheckpointStore::default(),
+            trash: Arc::new(TrashStore::default()),
+            memory_executor: None,
+            queue: None,
         })
     }
 
     /// Create proxy state with custom upstream configuration.
     pub fn with_config(config: UpstreamConfig) -> Result<Self, ProxyError> {
         let client = Self::build_client(Client::builder().timeout(Duration::from_secs(120)))?;
-        Ok(Self { client, config })
+        Ok(Self {
+            client,
+            config,
+            timeline: Arc::new(RequestTimeline::default()),
+            session: SharedSession::default(),
+            checkpoints: CheckpointStore::default(),
+            trash: Arc::new(TrashStore::default()),
+            memory_executor: None,
+            queue: None,
+        })
+    }
+
+    /// Record captured requests on a timeline shared with the rest of the app.
+    pub fn with_timeline(mut self, timeline: Arc<RequestTimeline>) -> Self {
+        self.timeline = timeline;
+        self
+    }
+
+    /// Apply `session` to outbound requests instead of an empty one.
+    pub fn with_session(mut self, session: SharedSession) -> Self {
+        self.session = session;
+        self
+    }
+
+    /// Load staged soft checkpoints from `checkpoints`.
+    pub fn with_checkpoints(mut self, checkpoints: CheckpointStore) -> Self {
+        self.checkpoints = checkpoints;
+        self
+    }
+
+    /// Send blocks removed by rules to `trash`.
+    pub fn with_trash(mut self, trash: Arc<TrashStore>) -> Self {
+        self.trash = trash;
+        self
+    }
+
+    /// Queue the model compressions budget pressure asks for on `queue`.
+    pub fn with_queue(mut self, queue: CompressionQueue) -> Self {
+        self.queue = Some(queue);
+        self
+    }
+
+    /// Run the model's memory tool calls with `executor`. The tool is
+    /// offered on requests made while the session enables it.
+    pub fn with_memory_tool(mut self, executor: Arc<dyn MemoryToolExecutor>) -> Self {
+        self.memory_executor = Some(executor);
+        self
     }
 }
 
-/// Start the proxy server.
-pub async fn start_proxy(port: u16) -> Result<(), ProxyError> {
-    let state = Arc::new(ProxyState::new()?);
+/// Start the proxy server, recording every request on `timeline` and
+/// applying `session` to it, with staged soft checkpoints from
+/// `checkpoints`, rule removals going to `trash` and budget pressure
+/// queueing model compressions on `queue`.
+pub async fn start_proxy(
+    port: u16,
+    timeline: Arc<RequestTimeline>,
+    session: SharedSession,
+    checkpoints: CheckpointStore,
+    trash: Arc<TrashStore>,
+    queue: Option<CompressionQueue>,
+) -> Result<(), ProxyError> {
+    let mut state = ProxyState::new()?
+        .with_timeline(timeline)
+        .with_checkpoints(checkpoints)
+        .with_trash(trash)
+        .with_memory_tool(Arc::new(session.clone()))
+        .with_session(session);
+    if let Some(queue) = queue {
+        state = state.with_queue(queue);
+    }
+    let state = Arc::new(state);
 
     let app = Router::new()
         .route("/{*path}", any(handler::proxy_handler))
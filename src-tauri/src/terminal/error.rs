@@ -16,6 +16,18 @@ pub enum TerminalError {
 
     #[error("Failed to initialize terminal reader: {0}")]
     ReaderInitFailed(String),
+
+    #[error("Failed to attach to terminal: {0}")]
+    AttachFailed(String),
+
+    #[error("Spawn rejected by policy: {0}")]
+    PolicyViolation(String),
+
+    #[error("Recording not found: {0}")]
+    RecordingNotFound(String),
+
+    #[error("Failed to access recording: {0}")]
+    RecordingFailed(String),
 }
 
 impl serde::Serialize for TerminalError {
@@ -1,72 +1,132 @@
 mod error;
+mod output;
+mod recording;
+mod scrollback;
 mod session;
+mod spawn;
 
-use std::collections::HashMap;
-use std::sync::Mutex;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-use portable_pty::{native_pty_system, CommandBuilder, PtySize};
+use portable_pty::{native_pty_system, PtySize};
 use tauri::{AppHandle, State};
 use tracing::{debug, info};
 use uuid::Uuid;
 
+use crate::events::timeline::RequestTimeline;
 use error::TerminalError;
-use session::TerminalSession;
+use recording::{Recording, RecordingDetails, SharedRecording};
+use session::{SessionAttachment, SessionInfo, TerminalSession};
+use spawn::SpawnPolicy;
+
+pub use spawn::SpawnOptions;
+
+type SessionMap = Arc<Mutex<HashMap<String, TerminalSession>>>;
+
+/// Finished recordings kept available for replay. Older ones are dropped
+/// from the index; their files stay on disk.
+const MAX_KEPT_RECORDINGS: usize = 32;
+
+/// How long an exited session stays listed, with its scrollback and exit
+/// status, unless `kill_session` closes it first.
+const EXITED_SESSION_TTL: Duration = Duration::from_secs(30 * 60);
 
 pub struct TerminalState {
-    sessions: Mutex<HashMap<String, TerminalSession>>,
+    /// Shared with each session's waiter thread, which removes the session
+    /// [`EXITED_SESSION_TTL`] after its process exits.
+    sessions: SessionMap,
+    /// Recordings outlive their sessions so they can be replayed after
+    /// exit, oldest first.
+    recordings: Mutex<VecDeque<(String, SharedRecording)>>,
+    policy: SpawnPolicy,
 }
 
 impl Default for TerminalState {
     fn default() -> Self {
         Self {
-            sessions: Mutex::new(HashMap::new()),
+            sessions: Arc::new(Mutex::new(HashMap::new())),
+            recordings: Mutex::new(VecDeque::new()),
+            policy: SpawnPolicy::default(),
         }
     }
 }
 
 impl TerminalState {
+    /// Create terminal state with the spawn policy read from the environment.
     pub fn new() -> Self {
-        Self::default()
+        Self {
+            policy: SpawnPolicy::from_env(),
+            ..Self::default()
+        }
     }
-}
 
-/// Detect user's shell from $SHELL or fall back to /bin/sh.
-fn detect_shell() -> String {
-    std::env::var("SHELL").unwrap_or_else(|_| "/bin/sh".to_string())
+    /// Kill every session, waiting out their grace periods together. Called
+    /// when the app exits.
+    pub fn kill_all(&self) {
+        let sessions: Vec<TerminalSession> = self
+            .sessions
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .drain()
+            .map(|(_, session)| session)
+            .collect();
+        if sessions.is_empty() {
+            return;
+        }
+        info!("Killing {} terminal session(s) on exit", sessions.len());
+        std::thread::scope(|scope| {
+            for mut session in sessions {
+                scope.spawn(move || session.kill());
+            }
+        });
+    }
 }
 
+/// Spawn a PTY session. Without `options` this runs the user's shell; with
+/// them it may run any allowlisted program (see [`SpawnOptions`]).
 #[tauri::command]
 pub fn spawn_shell(
     app: AppHandle,
     state: State<'_, TerminalState>,
     cols: Option<u16>,
     rows: Option<u16>,
+    options: Option<SpawnOptions>,
 ) -> Result<String, TerminalError> {
+    let plan = state.policy.resolve(options.unwrap_or_default())?;
+    let (cols, rows) = (cols.unwrap_or(80), rows.unwrap_or(24));
+
     let pty_system = native_pty_system();
 
     let pair = pty_system
         .openpty(PtySize {
-            rows: rows.unwrap_or(24),
-            cols: cols.unwrap_or(80),
+            rows,
+            cols,
             pixel_width: 0,
             pixel_height: 0,
         })
         .map_err(|e| TerminalError::SpawnFailed(e.to_string()))?;
 
-    let shell = detect_shell();
-    info!("Spawning shell: {shell}");
-
-    let mut cmd = CommandBuilder::new(&shell);
-    cmd.env("TERM", "xterm-256color");
+    let session_id = Uuid::new_v4().to_string();
+    let recording = if plan.record {
+        let recording = Recording::start(
+            &crate::paths::recordings_dir(),
+            &session_id,
+            cols,
+            rows,
+            &plan.program,
+        )
+        .map_err(|e| TerminalError::RecordingFailed(e.to_string()))?;
+        Some(Arc::new(Mutex::new(recording)))
+    } else {
+        None
+    };
 
-    // Inherit HOME so shell config loads
-    if let Ok(home) = std::env::var("HOME") {
-        cmd.env("HOME", &home);
-    }
+    info!("Spawning terminal program: {}", plan.program);
 
     let child = pair
         .slave
-        .spawn_command(cmd)
+        .spawn_command(plan.command())
         .map_err(|e| TerminalError::SpawnFailed(e.to_string()))?;
 
     let writer = pair
@@ -74,13 +134,30 @@ pub fn spawn_shell(
         .take_writer()
         .map_err(|e| TerminalError::SpawnFailed(e.to_string()))?;
 
-    let session_id = Uuid::new_v4().to_string();
-    let session = TerminalSession::new(session_id.clone(), child, writer, pair.master, app)?;
+    if let Some(recording) = &recording {
+        let mut recordings = state
+            .recordings
+            .lock()
+            .map_err(|e| TerminalError::RecordingFailed(e.to_string()))?;
+        recordings.push_back((session_id.clone(), Arc::clone(recording)));
+        prune_recordings(&mut recordings);
+    }
 
+    // Hold the map lock until the session is inserted so a process that
+    // exits immediately cannot be reaped before it is tracked.
     let mut sessions = state
         .sessions
         .lock()
         .map_err(|e| TerminalError::SpawnFailed(e.to_string()))?;
+    let session = TerminalSession::new(
+        session_id.clone(),
+        child,
+        writer,
+        pair.master,
+        recording,
+        app,
+        reap_after_exit(&state.sessions, EXITED_SESSION_TTL),
+    )?;
     sessions.insert(session_id.clone(), session);
 
     debug!("Terminal session created: {session_id}");
@@ -135,6 +212,7 @@ pub fn resize_terminal(
     Ok(())
 }
 
+/// Kill a running session, or close an exited one, and drop it.
 #[tauri::command]
 pub fn kill_session(
     state: State<'_, TerminalState>,
@@ -145,7 +223,12 @@ pub fn kill_session(
         .lock()
         .map_err(|e| TerminalError::SessionNotFound(e.to_string()))?;
 
-    if let Some(mut session) = sessions.remove(&session_id) {
+    let session = sessions.remove(&session_id);
+    // Release the map before waiting out the kill grace period so the
+    // session's waiter and other commands aren't blocked behind it.
+    drop(sessions);
+
+    if let Some(mut session) = session {
         session.kill();
         debug!("Terminal session killed: {session_id}");
         Ok(())
@@ -153,3 +236,140 @@ pub fn kill_session(
         Err(TerminalError::SessionNotFound(session_id))
     }
 }
+
+/// List every tracked session, oldest first, so a reloaded UI can reattach.
+#[tauri::command]
+pub fn list_sessions(state: State<'_, TerminalState>) -> Result<Vec<SessionInfo>, TerminalError> {
+    let sessions = state
+        .sessions
+        .lock()
+        .map_err(|e| TerminalError::AttachFailed(e.to_string()))?;
+
+    let mut infos: Vec<SessionInfo> = sessions.values().map(TerminalSession::info).collect();
+    infos.sort_by_key(|info| info.created_at);
+    Ok(infos)
+}
+
+/// Return a session's scrollback and status for replay after a webview reload.
+#[tauri::command]
+pub fn attach_session(
+    state: State<'_, TerminalState>,
+    session_id: String,
+) -> Result<SessionAttachment, TerminalError> {
+    let sessions = state
+        .sessions
+        .lock()
+        .map_err(|e| TerminalError::AttachFailed(e.to_string()))?;
+
+    let session = sessions
+        .get(&session_id)
+        .ok_or_else(|| TerminalError::SessionNotFound(session_id.clone()))?;
+
+    debug!("Reattaching to terminal session: {session_id}");
+    Ok(session.attach())
+}
+
+/// Exit callback that drops a session from `sessions` once `ttl` has
+/// passed since its process exited, so a reloaded UI still sees how it
+/// ended. It runs on the session's waiter thread, which is never joined.
+///
+/// Holds the map weakly so sessions never keep their own state alive, and
+/// drops the removed session outside the lock.
+fn reap_after_exit<S: Send + 'static>(
+    sessions: &Arc<Mutex<HashMap<String, S>>>,
+    ttl: Duration,
+) -> impl FnOnce(&str) + Send + 'static {
+    let sessions = Arc::downgrade(sessions);
+    move |session_id| {
+        std::thread::sleep(ttl);
+        let Some(sessions) = sessions.upgrade() else {
+            return;
+        };
+        let removed = sessions
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .remove(session_id);
+        if removed.is_some() {
+            debug!("Reaped exited terminal session: {session_id}");
+        }
+    }
+}
+
+/// Drop the oldest finished recordings beyond [`MAX_KEPT_RECORDINGS`].
+/// Recordings of live sessions are always kept.
+fn prune_recordings(recordings: &mut VecDeque<(String, SharedRecording)>) {
+    let mut excess = recordings.len().saturating_sub(MAX_KEPT_RECORDINGS);
+    recordings.retain(|(_, recording)| {
+        let finished = recording
+            .lock()
+            .map_or(true, |recording| recording.is_finished());
+        let drop = excess > 0 && finished;
+        excess -= usize::from(drop);
+        !drop
+    });
+}
+
+fn find_recording(
+    state: &TerminalState,
+    session_id: &str,
+) -> Result<SharedRecording, TerminalError> {
+    let recordings = state
+        .recordings
+        .lock()
+        .map_err(|e| TerminalError::RecordingFailed(e.to_string()))?;
+    recordings
+        .iter()
+        .find(|(id, _)| id == session_id)
+        .map(|(_, recording)| Arc::clone(recording))
+        .ok_or_else(|| TerminalError::RecordingNotFound(session_id.to_string()))
+}
+
+/// Recording location, time span and the proxy requests that fell inside it.
+#[tauri::command]
+pub fn get_recording(
+    state: State<'_, TerminalState>,
+    timeline: State<'_, Arc<RequestTimeline>>,
+    session_id: String,
+) -> Result<RecordingDetails, TerminalError> {
+    let recording = find_recording(&state, &session_id)?;
+    let recording = recording
+        .lock()
+        .map_err(|e| TerminalError::RecordingFailed(e.to_string()))?;
+    Ok(recording.details(&timeline))
+}
+
+/// Full asciicast for replay, with each proxy request merged in as a marker.
+#[tauri::command]
+pub fn load_recording(
+    state: State<'_, TerminalState>,
+    timeline: State<'_, Arc<RequestTimeline>>,
+    session_id: String,
+) -> Result<String, TerminalError> {
+    let details = {
+        let recording = find_recording(&state, &session_id)?;
+        let mut recording = recording
+            .lock()
+            .map_err(|e| TerminalError::RecordingFailed(e.to_string()))?;
+        recording.flush();
+        recording.details(&timeline)
+    };
+    recording::load_with_markers(&details.info.path, &details.markers)
+        .map_err(|e| TerminalError::RecordingFailed(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exited_sessions_are_kept_until_the_ttl() {
+        let sessions = Arc::new(Mutex::new(HashMap::from([("a".to_string(), ())])));
+        let reap = reap_after_exit(&sessions, Duration::from_millis(200));
+        let waiter = std::thread::spawn(move || reap("a"));
+
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(sessions.lock().unwrap().contains_key("a"));
+        waiter.join().unwrap();
+        assert!(sessions.lock().unwrap().is_empty());
+    }
+}
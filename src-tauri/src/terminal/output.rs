@@ -0,0 +1,384 @@
+//! Decoding and batching of raw PTY output.
+//!
+//! PTY reads split the byte stream at arbitrary points: a 4096-byte read can
+//! end inside a multi-byte UTF-8 character or halfway through an escape
+//! sequence. [`OutputDecoder`] carries incomplete tails forward so every
+//! emitted chunk is valid text that ends on a sequence boundary, and
+//! [`OutputCoalescer`] merges reads that arrive close together into a single
+//! IPC emit.
+
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+const ESC: u8 = 0x1b;
+const BEL: u8 = 0x07;
+
+/// Longest trailing escape sequence held back waiting for its terminator.
+/// Anything longer is passed through so a stray `ESC ]` cannot stall output.
+const MAX_ESCAPE_HOLDBACK: usize = 256;
+
+/// Default coalescing window for batched emits.
+pub const EMIT_WINDOW: Duration = Duration::from_millis(8);
+
+/// Batch size that triggers an emit before the window closes.
+pub const MAX_BATCH_BYTES: usize = 64 * 1024;
+
+/// How long a held-back partial sequence may wait for more input before it
+/// is flushed as-is.
+pub const HOLDBACK_TIMEOUT: Duration = Duration::from_millis(50);
+
+/// A raw PTY read, stamped on the reader thread when it returned.
+#[derive(Debug)]
+pub struct PtyRead {
+    pub at: Instant,
+    pub bytes: Vec<u8>,
+}
+
+impl PtyRead {
+    pub fn now(bytes: Vec<u8>) -> Self {
+        Self {
+            at: Instant::now(),
+            bytes,
+        }
+    }
+}
+
+/// Streaming decoder that never splits a UTF-8 character or escape sequence.
+#[derive(Debug, Default)]
+pub struct OutputDecoder {
+    pending: Vec<u8>,
+}
+
+impl OutputDecoder {
+    /// Decode as much of `input` (plus carried bytes) as forms complete text.
+    pub fn decode(&mut self, input: &[u8]) -> String {
+        self.pending.extend_from_slice(input);
+        let complete = complete_prefix_len(&self.pending);
+        let rest = self.pending.split_off(complete);
+        let text = String::from_utf8_lossy(&self.pending).into_owned();
+        self.pending = rest;
+        text
+    }
+
+    /// Whether bytes are being carried to the next read.
+    pub fn has_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// Flush carried bytes, replacing any invalid UTF-8.
+    pub fn finish(&mut self) -> String {
+        let text = String::from_utf8_lossy(&self.pending).into_owned();
+        self.pending.clear();
+        text
+    }
+}
+
+/// Length of the prefix of `bytes` that can be emitted now.
+fn complete_prefix_len(bytes: &[u8]) -> usize {
+    let utf8_end = bytes.len() - incomplete_utf8_tail(bytes);
+    incomplete_escape_start(&bytes[..utf8_end]).unwrap_or(utf8_end)
+}
+
+/// Number of trailing bytes that start a UTF-8 character not yet complete.
+fn incomplete_utf8_tail(bytes: &[u8]) -> usize {
+    let len = bytes.len();
+    for back in 1..=len.min(3) {
+        let byte = bytes[len - back];
+        if byte & 0b1100_0000 == 0b1000_0000 {
+            // Continuation byte: keep looking for the lead byte.
+            continue;
+        }
+        let needed = match byte {
+            0xC2..=0xDF => 2,
+            0xE0..=0xEF => 3,
+            0xF0..=0xF4 => 4,
+            // ASCII or an invalid lead byte; nothing to carry.
+            _ => return 0,
+        };
+        return if back < needed { back } else { 0 };
+    }
+    0
+}
+
+/// Start of a trailing escape sequence that has not reached its final byte.
+fn incomplete_escape_start(bytes: &[u8]) -> Option<usize> {
+    let window_start = bytes.len().saturating_sub(MAX_ESCAPE_HOLDBACK);
+    let mut i = window_start;
+    while i < bytes.len() {
+        if bytes[i] != ESC {
+            i += 1;
+            continue;
+        }
+        match escape_len(&bytes[i..]) {
+            Some(len) => i += len,
+            None => return Some(i),
+        }
+    }
+    None
+}
+
+/// Length of the escape sequence at the start of `seq`, or `None` if it is
+/// cut off. `seq[0]` must be ESC.
+fn escape_len(seq: &[u8]) -> Option<usize> {
+    let kind = *seq.get(1)?;
+    match kind {
+        // CSI: parameters and intermediates, then a final byte 0x40..=0x7E.
+        b'[' => seq[2..]
+            .iter()
+            .position(|b| (0x40..=0x7E).contains(b))
+            .map(|p| p + 3),
+        // OSC: terminated by BEL or ST (ESC \).
+        b']' => string_terminator(&seq[2..], true).map(|p| p + 2),
+        // DCS, SOS, PM, APC: terminated by ST.
+        b'P' | b'X' | b'^' | b'_' => string_terminator(&seq[2..], false).map(|p| p + 2),
+        // nF sequences: intermediates 0x20..=0x2F, then a final byte.
+        0x20..=0x2F => seq[2..]
+            .iter()
+            .position(|b| !(0x20..=0x2F).contains(b))
+            .map(|p| p + 3),
+        // Two-byte sequences (ESC 7, ESC M, ESC =, ...).
+        _ => Some(2),
+    }
+}
+
+/// Offset just past the string terminator in `body`, if present.
+fn string_terminator(body: &[u8], allow_bel: bool) -> Option<usize> {
+    let mut i = 0;
+    while i < body.len() {
+        match body[i] {
+            BEL if allow_bel => return Some(i + 1),
+            ESC => return body.get(i + 1).map(|_| i + 2),
+            _ => i += 1,
+        }
+    }
+    None
+}
+
+/// Merges PTY reads arriving within a short window into batched emits.
+#[derive(Debug, Clone, Copy)]
+pub struct OutputCoalescer {
+    pub window: Duration,
+    pub max_batch_bytes: usize,
+    pub holdback_timeout: Duration,
+}
+
+impl Default for OutputCoalescer {
+    fn default() -> Self {
+        Self {
+            window: EMIT_WINDOW,
+            max_batch_bytes: MAX_BATCH_BYTES,
+            holdback_timeout: HOLDBACK_TIMEOUT,
+        }
+    }
+}
+
+impl OutputCoalescer {
+    /// Decode raw reads from `rx` and pass batched text to `emit` until the
+    /// sender hangs up. Any carried bytes are flushed before returning.
+    ///
+    /// `on_text` sees each decoded piece with its read timestamp before it is
+    /// batched, for consumers (like recordings) that need per-read timing.
+    pub fn run(
+        &self,
+        rx: Receiver<PtyRead>,
+        mut on_text: impl FnMut(Instant, &str),
+        mut emit: impl FnMut(String),
+    ) {
+        let mut decoder = OutputDecoder::default();
+        let mut batch = String::new();
+        let mut take = |at: Instant, text: String, batch: &mut String| {
+            if !text.is_empty() {
+                on_text(at, &text);
+                batch.push_str(&text);
+            }
+        };
+
+        loop {
+            // Block for the first read of a batch, unless a partial sequence
+            // is waiting: then give up after the holdback timeout and flush it.
+            let first = if decoder.has_pending() {
+                match rx.recv_timeout(self.holdback_timeout) {
+                    Ok(read) => read,
+                    Err(RecvTimeoutError::Timeout) => {
+                        take(Instant::now(), decoder.finish(), &mut batch);
+                        emit_nonempty(&mut emit, std::mem::take(&mut batch));
+                        continue;
+                    }
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+            } else {
+                match rx.recv() {
+                    Ok(read) => read,
+                    Err(_) => break,
+                }
+            };
+            take(first.at, decoder.decode(&first.bytes), &mut batch);
+
+            let deadline = Instant::now() + self.window;
+            while batch.len() < self.max_batch_bytes {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    break;
+                }
+                match rx.recv_timeout(remaining) {
+                    Ok(read) => take(read.at, decoder.decode(&read.bytes), &mut batch),
+                    Err(RecvTimeoutError::Timeout) => break,
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+            }
+
+            emit_nonempty(&mut emit, std::mem::take(&mut batch));
+        }
+
+        take(Instant::now(), decoder.finish(), &mut batch);
+        emit_nonempty(&mut emit, batch);
+    }
+}
+
+fn emit_nonempty(emit: &mut impl FnMut(String), text: String) {
+    if !text.is_empty() {
+        emit(text);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn test_decoder_multibyte_split_across_reads_is_reassembled() {
+        let bytes = "╭─╮".as_bytes();
+        let mut decoder = OutputDecoder::default();
+
+        let mut out = String::new();
+        for chunk in bytes.chunks(2) {
+            out.push_str(&decoder.decode(chunk));
+        }
+        out.push_str(&decoder.finish());
+
+        assert_eq!(out, "╭─╮");
+        assert!(!out.contains('\u{FFFD}'));
+    }
+
+    #[test]
+    fn test_decoder_four_byte_char_split_carries_three_bytes() {
+        let bytes = "a🦀".as_bytes();
+        let mut decoder = OutputDecoder::default();
+
+        assert_eq!(decoder.decode(&bytes[..4]), "a");
+        assert!(decoder.has_pending());
+        assert_eq!(decoder.decode(&bytes[4..]), "🦀");
+        assert!(!decoder.has_pending());
+    }
+
+    #[test]
+    fn test_decoder_invalid_bytes_become_replacement_chars() {
+        let mut decoder = OutputDecoder::default();
+        assert_eq!(decoder.decode(b"ok\xFFok"), "ok\u{FFFD}ok");
+        assert!(!decoder.has_pending());
+    }
+
+    #[test]
+    fn test_decoder_holds_back_partial_csi_sequence() {
+        let mut decoder = OutputDecoder::default();
+
+        assert_eq!(decoder.decode(b"hi \x1b[38;5"), "hi ");
+        assert_eq!(decoder.decode(b";208mX"), "\x1b[38;5;208mX");
+    }
+
+    #[test]
+    fn test_decoder_holds_back_unterminated_osc() {
+        let mut decoder = OutputDecoder::default();
+
+        assert_eq!(decoder.decode(b"\x1b]0;title"), "");
+        assert_eq!(decoder.decode(b"\x07$ "), "\x1b]0;title\x07$ ");
+
+        assert_eq!(decoder.decode(b"\x1b]8;;http://x\x1b"), "");
+        assert_eq!(decoder.decode(b"\\link"), "\x1b]8;;http://x\x1b\\link");
+    }
+
+    #[test]
+    fn test_decoder_complete_sequences_pass_through() {
+        let mut decoder = OutputDecoder::default();
+        let input = b"\x1b[0m\x1b7\x1b(B\x1b[?25h";
+        assert_eq!(decoder.decode(input).as_bytes(), input);
+        assert!(!decoder.has_pending());
+    }
+
+    #[test]
+    fn test_decoder_oversized_escape_is_not_held_forever() {
+        let mut decoder = OutputDecoder::default();
+        let mut input = b"\x1b]".to_vec();
+        input.extend(vec![b'a'; MAX_ESCAPE_HOLDBACK + 10]);
+
+        let out = decoder.decode(&input);
+        assert_eq!(out.len(), input.len());
+    }
+
+    #[test]
+    fn test_coalescer_merges_reads_within_window() {
+        let (tx, rx) = mpsc::channel();
+        tx.send(PtyRead::now(b"one ".to_vec())).expect("send");
+        tx.send(PtyRead::now(b"two ".to_vec())).expect("send");
+        tx.send(PtyRead::now(b"three".to_vec())).expect("send");
+        drop(tx);
+
+        let mut pieces = Vec::new();
+        let mut emits = Vec::new();
+        OutputCoalescer {
+            window: Duration::from_millis(50),
+            ..OutputCoalescer::default()
+        }
+        .run(
+            rx,
+            |_, text| pieces.push(text.to_string()),
+            |text| emits.push(text),
+        );
+
+        assert_eq!(emits, vec!["one two three"]);
+        assert_eq!(pieces, vec!["one ", "two ", "three"]);
+    }
+
+    #[test]
+    fn test_coalescer_respects_max_batch_size() {
+        let (tx, rx) = mpsc::channel();
+        for _ in 0..4 {
+            tx.send(PtyRead::now(vec![b'x'; 10])).expect("send");
+        }
+        drop(tx);
+
+        let mut emits = Vec::new();
+        OutputCoalescer {
+            window: Duration::from_secs(1),
+            max_batch_bytes: 20,
+            ..OutputCoalescer::default()
+        }
+        .run(rx, |_, _| {}, |text| emits.push(text));
+
+        assert_eq!(emits.len(), 2);
+        assert!(emits.iter().all(|e| e.len() == 20));
+    }
+
+    #[test]
+    fn test_coalescer_flushes_partial_sequence_after_timeout() {
+        let (tx, rx) = mpsc::channel::<PtyRead>();
+        let handle = std::thread::spawn(move || {
+            let mut emits = Vec::new();
+            OutputCoalescer {
+                window: Duration::from_millis(1),
+                max_batch_bytes: MAX_BATCH_BYTES,
+                holdback_timeout: Duration::from_millis(5),
+            }
+            .run(rx, |_, _| {}, |text| emits.push(text));
+            emits
+        });
+
+        tx.send(PtyRead::now(b"$ \x1b[".to_vec())).expect("send");
+        std::thread::sleep(Duration::from_millis(100));
+        drop(tx);
+
+        let emits = handle.join().expect("coalescer thread");
+        assert_eq!(emits, vec!["$ ", "\x1b["]);
+    }
+}
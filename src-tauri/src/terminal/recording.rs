@@ -0,0 +1,377 @@
+//! Asciicast v2 recording of terminal sessions.
+//!
+//! Output is timestamped on the PTY reader thread and written as
+//! `[seconds, "o", text]` events after a JSON header line (see
+//! <https://docs.asciinema.org/manual/asciicast/v2/>). Recordings are lined
+//! up with the proxy's [`RequestTimeline`] so replay can jump to the moment
+//! each request went out.
+
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use serde::Serialize;
+use serde_json::{json, Value};
+use tracing::warn;
+
+use crate::events::timeline::{now_ms, RequestTimeline};
+
+/// Largest recording `load_with_markers` will read into memory.
+pub const MAX_LOAD_BYTES: u64 = 64 * 1024 * 1024;
+
+/// Recording shared between a session's emitter thread and `TerminalState`.
+pub type SharedRecording = Arc<Mutex<Recording>>;
+
+/// Asciicast v2 header line.
+#[derive(Debug, Serialize)]
+struct CastHeader<'a> {
+    version: u8,
+    width: u16,
+    height: u16,
+    /// Unix epoch seconds.
+    timestamp: u64,
+    title: &'a str,
+    env: CastEnv,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "UPPERCASE")]
+struct CastEnv {
+    term: &'static str,
+    shell: String,
+}
+
+/// Writes asciicast v2 events relative to a start instant.
+pub struct AsciicastWriter<W: Write> {
+    out: W,
+    started: Instant,
+}
+
+impl<W: Write> AsciicastWriter<W> {
+    /// Write the header for a session running `program` and return a
+    /// writer whose clock starts at `started`.
+    pub fn new(
+        mut out: W,
+        started: Instant,
+        started_at_ms: u64,
+        cols: u16,
+        rows: u16,
+        program: &str,
+    ) -> io::Result<Self> {
+        let header = CastHeader {
+            version: 2,
+            width: cols,
+            height: rows,
+            timestamp: started_at_ms / 1000,
+            title: program,
+            env: CastEnv {
+                term: "xterm-256color",
+                shell: program.to_string(),
+            },
+        };
+        serde_json::to_writer(&mut out, &header)?;
+        out.write_all(b"\n")?;
+        Ok(Self { out, started })
+    }
+
+    pub fn output(&mut self, at: Instant, data: &str) -> io::Result<()> {
+        self.event(at, "o", data)
+    }
+
+    pub fn resize(&mut self, at: Instant, cols: u16, rows: u16) -> io::Result<()> {
+        self.event(at, "r", &format!("{cols}x{rows}"))
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+
+    fn event(&mut self, at: Instant, code: &str, data: &str) -> io::Result<()> {
+        let elapsed = at.saturating_duration_since(self.started).as_secs_f64();
+        serde_json::to_writer(&mut self.out, &json!([round_secs(elapsed), code, data]))?;
+        self.out.write_all(b"\n")
+    }
+}
+
+/// Where a recording lives and the wall-clock span it covers.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordingInfo {
+    pub session_id: String,
+    pub path: PathBuf,
+    /// Unix epoch milliseconds.
+    pub started_at_ms: u64,
+    /// Unix epoch milliseconds; `None` while the session is still recording.
+    pub ended_at_ms: Option<u64>,
+}
+
+/// A proxy request placed on a recording's clock.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordingMarker {
+    pub request_id: String,
+    pub method: String,
+    pub path: String,
+    /// Seconds since the recording started.
+    pub time: f64,
+}
+
+/// Recording metadata plus the requests that fell inside it.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordingDetails {
+    #[serde(flatten)]
+    pub info: RecordingInfo,
+    pub markers: Vec<RecordingMarker>,
+}
+
+/// An in-progress or finished session recording.
+pub struct Recording {
+    info: RecordingInfo,
+    writer: Option<AsciicastWriter<BufWriter<File>>>,
+}
+
+impl Recording {
+    /// Create `<dir>/<session_id>.cast` and write its header.
+    pub fn start(
+        dir: &Path,
+        session_id: &str,
+        cols: u16,
+        rows: u16,
+        program: &str,
+    ) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let path = dir.join(format!("{session_id}.cast"));
+        let file = BufWriter::new(File::create(&path)?);
+        let started_at_ms = now_ms();
+        let writer =
+            AsciicastWriter::new(file, Instant::now(), started_at_ms, cols, rows, program)?;
+
+        Ok(Self {
+            info: RecordingInfo {
+                session_id: session_id.to_string(),
+                path,
+                started_at_ms,
+                ended_at_ms: None,
+            },
+            writer: Some(writer),
+        })
+    }
+
+    pub fn output(&mut self, at: Instant, data: &str) {
+        self.write_with(|w| w.output(at, data));
+    }
+
+    pub fn resize(&mut self, cols: u16, rows: u16) {
+        self.write_with(|w| w.resize(Instant::now(), cols, rows));
+    }
+
+    pub fn flush(&mut self) {
+        self.write_with(AsciicastWriter::flush);
+    }
+
+    /// Flush and close the file, stamping the end time.
+    pub fn finish(&mut self) {
+        self.flush();
+        self.writer = None;
+        if self.info.ended_at_ms.is_none() {
+            self.info.ended_at_ms = Some(now_ms());
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.info.ended_at_ms.is_some()
+    }
+
+    /// Recording info plus the timeline requests within its span.
+    pub fn details(&self, timeline: &RequestTimeline) -> RecordingDetails {
+        RecordingDetails {
+            info: self.info.clone(),
+            markers: markers(&self.info, timeline),
+        }
+    }
+
+    /// Stop recording after the first I/O error rather than failing the session.
+    fn write_with(
+        &mut self,
+        op: impl FnOnce(&mut AsciicastWriter<BufWriter<File>>) -> io::Result<()>,
+    ) {
+        let Some(writer) = self.writer.as_mut() else {
+            return;
+        };
+        if let Err(e) = op(writer) {
+            warn!(
+                "Recording for session {} stopped: {e}",
+                self.info.session_id
+            );
+            self.writer = None;
+            self.info.ended_at_ms = Some(now_ms());
+        }
+    }
+}
+
+/// Requests from `timeline` that happened while `info` was recording.
+pub fn markers(info: &RecordingInfo, timeline: &RequestTimeline) -> Vec<RecordingMarker> {
+    let end = info.ended_at_ms.unwrap_or_else(now_ms);
+    timeline
+        .between(info.started_at_ms, end)
+        .into_iter()
+        .map(|mark| RecordingMarker {
+            time: round_secs((mark.at_ms - info.started_at_ms) as f64 / 1000.0),
+            request_id: mark.request_id,
+            method: mark.method,
+            path: mark.path,
+        })
+        .collect()
+}
+
+/// Read a cast file and merge `markers` in as asciicast `"m"` events.
+pub fn load_with_markers(path: &Path, markers: &[RecordingMarker]) -> io::Result<String> {
+    let size = fs::metadata(path)?.len();
+    if size > MAX_LOAD_BYTES {
+        return Err(io::Error::other(format!(
+            "recording is {size} bytes, exceeds {MAX_LOAD_BYTES} byte limit"
+        )));
+    }
+
+    let mut lines = BufReader::new(File::open(path)?).lines();
+    let header = lines.next().transpose()?.unwrap_or_default();
+
+    let mut events: Vec<(f64, String)> = Vec::new();
+    for line in lines {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let time = serde_json::from_str::<Value>(&line)
+            .ok()
+            .and_then(|v| v.get(0).and_then(Value::as_f64))
+            .unwrap_or(0.0);
+        events.push((time, line));
+    }
+    for marker in markers {
+        let label = format!("{} {} {}", marker.method, marker.path, marker.request_id);
+        events.push((marker.time, json!([marker.time, "m", label]).to_string()));
+    }
+    // Stable sort keeps output order intact and places markers after
+    // output written at the same instant.
+    events.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let mut out = header;
+    out.push('\n');
+    for (_, line) in events {
+        out.push_str(&line);
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+/// Microsecond precision is plenty for replay and keeps files compact.
+fn round_secs(secs: f64) -> f64 {
+    (secs * 1_000_000.0).round() / 1_000_000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::timeline::RequestMark;
+    use std::time::Duration;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "aperture-recording-{name}-{}",
+            uuid::Uuid::new_v4()
+        ));
+        fs::create_dir_all(&dir).expect("create temp dir");
+        dir
+    }
+
+    #[test]
+    fn test_writer_emits_header_and_relative_events() {
+        let start = Instant::now();
+        let mut buf = Vec::new();
+        {
+            let mut writer =
+                AsciicastWriter::new(&mut buf, start, 1_700_000_000_500, 80, 24, "claude")
+                    .expect("header");
+            writer
+                .output(start + Duration::from_millis(1500), "hi\r\n")
+                .expect("event");
+            writer
+                .resize(start + Duration::from_secs(2), 120, 40)
+                .expect("event");
+        }
+
+        let text = String::from_utf8(buf).expect("utf8");
+        let lines: Vec<Value> = text
+            .lines()
+            .map(|l| serde_json::from_str(l).expect("json line"))
+            .collect();
+
+        assert_eq!(lines[0]["version"], 2);
+        assert_eq!(lines[0]["width"], 80);
+        assert_eq!(lines[0]["timestamp"], 1_700_000_000);
+        assert_eq!(lines[0]["title"], "claude");
+        assert_eq!(lines[0]["env"]["SHELL"], "claude");
+        assert_eq!(lines[1], json!([1.5, "o", "hi\r\n"]));
+        assert_eq!(lines[2], json!([2.0, "r", "120x40"]));
+    }
+
+    #[test]
+    fn test_markers_only_include_requests_within_span() {
+        let info = RecordingInfo {
+            session_id: "s".to_string(),
+            path: PathBuf::from("s.cast"),
+            started_at_ms: 10_000,
+            ended_at_ms: Some(20_000),
+        };
+        let timeline = RequestTimeline::default();
+        for (id, at_ms) in [("early", 9_000), ("inside", 12_500), ("late", 21_000)] {
+            timeline.push(RequestMark {
+                request_id: id.to_string(),
+                method: "POST".to_string(),
+                path: "/v1/messages".to_string(),
+                at_ms,
+            });
+        }
+
+        let markers = markers(&info, &timeline);
+        assert_eq!(markers.len(), 1);
+        assert_eq!(markers[0].request_id, "inside");
+        assert!((markers[0].time - 2.5).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn test_recording_roundtrip_merges_markers_in_time_order() {
+        let dir = temp_dir("roundtrip");
+        let mut recording = Recording::start(&dir, "abc", 80, 24, "/bin/sh").expect("start");
+        let t0 = Instant::now();
+        recording.output(t0, "one");
+        recording.output(t0 + Duration::from_secs(3), "two");
+        recording.finish();
+
+        let info = recording.details(&RequestTimeline::default()).info;
+        let path = info.path.clone();
+        assert!(path.ends_with("abc.cast"));
+
+        let markers = vec![RecordingMarker {
+            request_id: "req-1".to_string(),
+            method: "POST".to_string(),
+            path: "/v1/messages".to_string(),
+            time: 1.0,
+        }];
+        let cast = load_with_markers(&path, &markers).expect("load");
+        let codes: Vec<String> = cast
+            .lines()
+            .skip(1)
+            .map(|l| serde_json::from_str::<Value>(l).expect("json")[1].to_string())
+            .collect();
+
+        assert_eq!(codes, vec!["\"o\"", "\"m\"", "\"o\""]);
+        assert!(info.ended_at_ms.is_some());
+
+        let _ = fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,146 @@
+//! Bounded per-session output history.
+//!
+//! The PTY reader pushes every decoded chunk here before emitting it, so a
+//! reloaded webview can reattach and replay what it missed.
+
+use std::collections::VecDeque;
+
+/// Default scrollback capacity per session (512 KiB of decoded output).
+pub const DEFAULT_SCROLLBACK_BYTES: usize = 512 * 1024;
+
+/// Ring buffer of terminal output, bounded by total byte length.
+///
+/// Chunks are evicted oldest-first. `offset` counts every byte ever pushed,
+/// which lets clients de-duplicate live events against a snapshot.
+#[derive(Debug)]
+pub struct Scrollback {
+    chunks: VecDeque<String>,
+    len: usize,
+    capacity: usize,
+    offset: u64,
+}
+
+impl Scrollback {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            chunks: VecDeque::new(),
+            len: 0,
+            capacity,
+            offset: 0,
+        }
+    }
+
+    /// Append a chunk, evicting old output to stay within capacity.
+    ///
+    /// Returns the stream offset after this chunk.
+    pub fn push(&mut self, chunk: &str) -> u64 {
+        self.offset += chunk.len() as u64;
+        if chunk.is_empty() || self.capacity == 0 {
+            return self.offset;
+        }
+
+        if chunk.len() >= self.capacity {
+            self.chunks.clear();
+            let start = ceil_char_boundary(chunk, chunk.len() - self.capacity);
+            self.chunks.push_back(chunk[start..].to_string());
+            self.len = chunk.len() - start;
+            return self.offset;
+        }
+
+        self.chunks.push_back(chunk.to_string());
+        self.len += chunk.len();
+
+        while self.len > self.capacity {
+            let Some(front) = self.chunks.front_mut() else {
+                break;
+            };
+            let excess = self.len - self.capacity;
+            if front.len() <= excess {
+                self.len -= front.len();
+                self.chunks.pop_front();
+            } else {
+                let cut = ceil_char_boundary(front, excess);
+                front.drain(..cut);
+                self.len -= cut;
+            }
+        }
+
+        self.offset
+    }
+
+    /// Total bytes ever pushed, including evicted output.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Concatenate the retained output.
+    pub fn contents(&self) -> String {
+        let mut out = String::with_capacity(self.len);
+        for chunk in &self.chunks {
+            out.push_str(chunk);
+        }
+        out
+    }
+}
+
+impl Default for Scrollback {
+    fn default() -> Self {
+        Self::new(DEFAULT_SCROLLBACK_BYTES)
+    }
+}
+
+/// Smallest char boundary in `s` at or after `index`.
+fn ceil_char_boundary(s: &str, index: usize) -> usize {
+    let mut i = index.min(s.len());
+    while !s.is_char_boundary(i) {
+        i += 1;
+    }
+    i
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scrollback_under_capacity_keeps_everything() {
+        let mut sb = Scrollback::new(64);
+        sb.push("hello ");
+        let offset = sb.push("world");
+
+        assert_eq!(sb.contents(), "hello world");
+        assert_eq!(offset, 11);
+    }
+
+    #[test]
+    fn test_scrollback_over_capacity_evicts_oldest() {
+        let mut sb = Scrollback::new(8);
+        sb.push("aaaa");
+        sb.push("bbbb");
+        sb.push("cccc");
+
+        assert_eq!(sb.contents(), "bbbbcccc");
+        assert_eq!(sb.offset(), 12);
+    }
+
+    #[test]
+    fn test_scrollback_partial_eviction_respects_char_boundaries() {
+        let mut sb = Scrollback::new(6);
+        sb.push("─┤"); // 6 bytes
+        sb.push("x");
+
+        let contents = sb.contents();
+        assert!(contents.len() <= 6);
+        assert!(contents.ends_with('x'));
+        assert_eq!(contents, "┤x");
+    }
+
+    #[test]
+    fn test_scrollback_oversized_chunk_keeps_tail() {
+        let mut sb = Scrollback::new(4);
+        sb.push("0123456789");
+
+        assert_eq!(sb.contents(), "6789");
+        assert_eq!(sb.offset(), 10);
+    }
+}
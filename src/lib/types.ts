@@ -16,9 +16,40 @@ export type PinPosition = "top" | "bottom";
 
 export type CompressionLevel = "original" | "trimmed" | "summarized" | "minimal";
 
+export type MemoryState = "hot" | "warm" | "cold" | "archived"; // cold/archived leave the prompt
+
+export interface RecallMetadata {
+  topic: string | null;
+  files: string[];
+  summary: string; // One line
+}
+
+export type EditAuthor =
+  | { kind: "user" }
+  | { kind: "rule"; ruleId: string }
+  | { kind: "plugin"; name: string }
+  | { kind: "model_command" };
+
+export interface BlockEdit {
+  id: number; // 1-based; version n is the content after edit n
+  author: EditAuthor;
+  atMs: number;
+  reason?: string;
+  before: string;
+  after: string;
+  reverts?: number; // Edit this one reverted
+}
+
+export interface CompressionQualityReport {
+  confidence: number; // 0.0-1.0; below the configured minimum blocks automatic use
+  ratio: number; // original tokens per compressed token
+  issues: Array<{ kind: string; [detail: string]: unknown }>;
+}
+
 export interface CompressionVersion {
   content: string;
   tokens: number;
+  quality?: CompressionQualityReport;
 }
 
 export interface CompressionVersions {
@@ -33,6 +64,7 @@ export interface BlockMetadata {
   turnIndex: number;
   toolName?: string;
   filePaths: string[];
+  toolUseId?: string; // Links a tool_use block to its tool_result
 }
 
 export interface Block {
@@ -59,6 +91,13 @@ export interface Block {
   topicCluster: string | null;
   topicKeywords: string[];
 
+  // Memory lifecycle
+  memoryState?: MemoryState; // Absent means "hot"
+  recall?: RecallMetadata; // Set while cold or archived
+
+  // Edit history
+  edits?: BlockEdit[]; // Oldest first, append-only
+
   // Metadata
   metadata: BlockMetadata;
 }
@@ -102,6 +141,177 @@ export interface Snapshot {
   parentSnapshotId?: string | null;
 }
 
+export interface CheckpointSummary {
+  id: string;
+  name: string;
+  createdAtMs: number;
+  blockCount: number;
+  totalTokens: number;
+  sizeBytes: number; // Serialized size of every block
+  storedBytes: number; // Bytes new to the content-addressed store
+}
+
+export interface FileReference {
+  path: string;
+  mentions: number;
+  lastTurn: number;
+}
+
+export type SoftCheckpointSource =
+  | { kind: "model"; backend: string; model: string }
+  | { kind: "deterministic" };
+
+export interface SoftCheckpoint {
+  id: string;
+  name: string;
+  createdAtMs: number;
+  taskStatus: string | null;
+  files: FileReference[];
+  decisions: string[];
+  openErrors: string[];
+  summary: string;
+  tokens: number; // Tokens of the Markdown rendering
+  source: SoftCheckpointSource;
+}
+
+export type SoftCheckpointFormat = "markdown" | "json";
+
+export interface BranchSummary {
+  id: string; // "main" for the branch every session starts on
+  name: string;
+  parent: string | null;
+  createdAtMs: number;
+  active: boolean;
+  blockCount: number;
+  totalTokens: number;
+}
+
+export interface BlockDiff {
+  blockId: string;
+  status: "removed" | "modified" | "added";
+  changes: string[]; // e.g. "content", "compression: original → trimmed"
+}
+
+export interface BranchDiff {
+  from: string;
+  to: string;
+  entries: BlockDiff[];
+  tokenDelta: number;
+}
+
+export type MergeResolution = "ours" | "theirs";
+
+export interface MergeConflict {
+  blockId: string;
+  ours: string | null; // null when that side removed the block
+  theirs: string | null;
+}
+
+export type StagedSource =
+  | { kind: "file"; path: string }
+  | { kind: "text"; content: string }
+  | { kind: "soft_checkpoint"; checkpoint_id: string };
+
+export type InjectionCondition =
+  | { kind: "always" }
+  | { kind: "on_session_start" }
+  | { kind: "keyword"; value: string } // In the latest user message, ignoring case
+  | { kind: "pattern"; value: string }; // Regex over the latest user message
+
+export interface NewStagedItem {
+  name: string;
+  source: StagedSource;
+  targetZone: Zone;
+  priority?: number; // Higher first within a zone
+  condition: InjectionCondition;
+}
+
+export interface StagedItem extends Required<NewStagedItem> {
+  id: string;
+  enabled: boolean;
+  content: string; // Last loaded content
+  tokens: number;
+}
+
+// Settings of a preset in a `.aperture.yml` profile, keys as written there
+export interface StagingSpec {
+  name?: string;
+  file?: string; // Relative to the profile's directory
+  text?: string;
+  soft_checkpoint?: string;
+  zone: Zone;
+  priority?: number;
+  session_start?: boolean;
+  keyword?: string;
+  pattern?: string;
+}
+
+export interface PresetSettings {
+  extends?: string;
+  zones: {
+    primacy_max_tokens: number | null;
+    recency_max_tokens: number | null;
+    recency_turns: number | null;
+  };
+  compression: {
+    auto: boolean | null;
+    context_window: number | null;
+    pressure_threshold: number | null;
+    max_ratio: number | null;
+    min_confidence: number | null;
+    self_check: boolean | null;
+  };
+  routing: {
+    anthropic_url: string | null; // Must be listed in APERTURE_ALLOWED_UPSTREAMS
+    openai_url: string | null;
+  };
+  staging: StagingSpec[] | null;
+  rules: Rule[] | null;
+}
+
+export interface ResolvedPreset {
+  name: string;
+  chain: string[]; // The preset and its ancestors, nearest first
+  settings: PresetSettings; // Unset fields take engine defaults
+}
+
+export interface PresetInfo {
+  name: string;
+  extends: string | null;
+}
+
+export interface ProfileSummary {
+  path: string;
+  project: string | null;
+  defaultPreset: string | null;
+  activePreset: string | null;
+  presets: PresetInfo[];
+}
+
+export interface Ghost {
+  blockId: string;
+  role: Role;
+  zone: Zone;
+  tokens: number;
+  summary: string; // One line, e.g. "src/config.py read by read_file"
+  index: number; // Position when removed
+  afterBlockId: string | null; // Preceding block when removed
+  removedAtMs: number;
+}
+
+export interface TrashSummary {
+  blockCount: number;
+  tokens: number; // Recoverable tokens
+  showGhosts: boolean;
+  ghosts: Ghost[]; // Newest first
+}
+
+export interface MergeReport {
+  merged: string[];
+  removed: string[];
+  conflicts: MergeConflict[];
+}
+
 // ============================================================================
 // UI State Types
 // ============================================================================
@@ -137,25 +347,61 @@ export interface Rule {
   id: string;
   name: string;
   trigger: RuleTrigger;
+  conditions?: RuleTrigger[]; // Further triggers that must all hold
   action: RuleAction;
-  config: Record<string, unknown>;
+  config: Record<string, unknown>; // level, zone, position or message, per action
   enabled: boolean;
 }
 
+export type RuleTriggerField =
+  | "token_pressure" // Projected tokens / context window
+  | "turn"
+  | "age" // Turns since the block was added
+  | "tokens"
+  | "role"
+  | "block_type"
+  | "tool_name"
+  | "content"
+  | "zone";
+
 export interface RuleTrigger {
-  field: string;
+  field: RuleTriggerField;
   operator: "eq" | "gt" | "lt" | "contains" | "matches";
   value: string | number;
 }
 
 export type RuleAction =
   | "condense"
+  | "compress"
   | "remove"
   | "pin"
   | "unpin"
   | "move_zone"
+  | "archive"
   | "warn";
 
+export interface RuleFiring {
+  id: string;
+  atMs: number;
+  ruleId: string;
+  ruleName: string;
+  action: RuleAction;
+  blockId: string | null; // null for request-level warnings
+  detail: string;
+  tokensBefore: number;
+  tokensAfter: number;
+  actionId: string | null; // Action log entry that can undo it
+}
+
+export interface RuleRun {
+  dryRun: boolean;
+  context: { currentTurn: number; tokenPressure: number };
+  firings: RuleFiring[];
+  skipped: { ruleId: string; blockId: string; reason: string }[];
+  tokensBefore: number;
+  tokensAfter: number;
+}
+
 // ============================================================================
 // Event Types (for IPC)
 // ============================================================================